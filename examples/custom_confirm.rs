@@ -0,0 +1,47 @@
+//! Embeds the sai pipeline with a custom confirmation UI.
+//!
+//! Instead of a y/N prompt, the command is shown in a box together with an
+//! LLM explanation and the user must type `run` to execute it.
+//!
+//! Usage: cargo run --example custom_confirm -- "list the rust files under src"
+
+use anyhow::{anyhow, Result};
+use sai_core::session::{Outcome, Session, SessionOptions, ValidatedCommand};
+use std::env;
+use std::io::{self, BufRead, Write};
+
+fn main() -> Result<()> {
+    let nl_prompt = env::args()
+        .nth(1)
+        .ok_or_else(|| anyhow!("usage: custom_confirm \"<natural language prompt>\""))?;
+
+    let session = Session::configure(SessionOptions::default())?;
+    let cmd_line = session.generate(&nl_prompt)?;
+    let command = session.validate(&cmd_line)?;
+    let explanation = session.explain(&command)?;
+
+    let mut confirm_ui = |cmd: &ValidatedCommand| -> Result<bool> {
+        let width = cmd.command().chars().count() + 4;
+        println!("┌{}┐", "─".repeat(width));
+        println!("│  {}  │", cmd.command());
+        println!("└{}┘", "─".repeat(width));
+        println!("{}", explanation);
+        if cmd.confirmation_required() {
+            println!("(sai policy requires confirmation for this command)");
+        }
+        print!("Type 'run' to execute: ");
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer)?;
+        Ok(answer.trim() == "run")
+    };
+
+    match session.execute(&command, &mut confirm_ui)? {
+        Outcome::Executed(code) => std::process::exit(code),
+        Outcome::Declined => {
+            println!("Not executed.");
+            Ok(())
+        }
+    }
+}
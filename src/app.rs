@@ -1,20 +1,69 @@
-use crate::cli::Cli;
+use crate::backup;
+use crate::budget::{self, EstimateRow};
+use crate::cassette::Cassette;
+use crate::cli::{self, Cli, SubcommandCli};
+use crate::clipboard::{self, CopyMode};
+use crate::commands;
 use crate::config::{
     find_global_config_path, load_global_config, load_prompt_config, resolve_ai_config,
+    resolve_race_config, PromptConfig,
 };
-use crate::executor::{CommandExecutor, ShellCommandExecutor};
+use crate::deprecation;
+use crate::envinfo;
+use crate::executor::{
+    argument_size, ArgumentSize, CapturedOutput, CommandExecutor, ExecOptions,
+    ShellCommandExecutor, SshExecutor, EXIT_TIMED_OUT,
+};
+use crate::guard::{self, GuardMatch};
 use crate::help;
-use crate::history::{self, HistoryEntry};
-use crate::llm::{ChatClient, CommandGenerator, HttpCommandGenerator};
+use crate::history::{self, CommandOutput, EntrySelector, HistoryEntry};
+use crate::humanize;
+use crate::interrupt::{self, InterruptibleStdin};
+use crate::jail::{self, PathJail};
+use crate::llm::{self, ChatClient, CommandGenerator, HttpCommandGenerator};
+use crate::network::NetworkGuard;
 use crate::ops;
-use crate::peek::build_peek_context;
+use crate::orgpolicy::{self, OrgPolicy};
+use crate::osc52;
+use crate::pager::Pager;
+use crate::peek::{
+    build_output_context, build_peek_context, files_named_in_prompt, with_diff_context,
+    PEEK_MAX_BYTES,
+};
+use crate::progress::Spinner;
+use crate::project::ProjectPath;
+use crate::projectcfg;
 use crate::prompt::build_system_prompt;
-use crate::safety::validate_and_split_command;
+use crate::refine;
+use crate::rerun;
+use crate::respcache::ResponseCache;
+use crate::risk::{self, CommandEffect, ConfirmMode, LlmReview, RiskLevel};
+use crate::safety::{
+    check_read_only, is_read_only_tool, resolve_tool, runs_in_shell, validate_and_split_command,
+};
+use crate::scope::remote_scope_hint;
+use crate::shellcheck::{self, Finding, ShellcheckConfig};
+use crate::tasks;
+use crate::template;
+use crate::tooldocs::{
+    append_tool_docs, distill_tool_docs, failing_tool, fetch_tool_docs, flag_help, is_flag_error,
+};
+use crate::transaction::Transaction;
+use crate::transcript::{self, Decision, TranscriptRecord};
+use crate::tui::{self, TuiBackend};
+use crate::webhook::{self, AuditEvent};
+use crate::workdir;
 use anyhow::{anyhow, Context, Result};
 use clap::Parser;
+use std::collections::BTreeMap;
 use std::env;
+use std::fs;
 use std::io::{self, BufRead, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Exit code when --verify finds that the output does not meet the expectation.
+pub const EXIT_VERIFY_FAILED: i32 = 3;
 
 #[derive(Debug, Clone)]
 pub struct RunSummary {
@@ -26,6 +75,26 @@ pub struct RunSummary {
     pub scope: Option<String>,
     pub peek_files: Vec<String>,
     pub notes: Option<String>,
+    /// Model that generated the command when it was not the configured one
+    /// (the `race` provider won).
+    pub model: Option<String>,
+    /// End of the command's output, when it is kept for history.
+    pub output: Option<CommandOutput>,
+    /// The model's review of the command (--review).
+    pub review: Option<LlmReview>,
+    /// Risk level of the generated command.
+    pub risk: Option<RiskLevel>,
+    /// Whether the command was started.
+    pub executed: bool,
+    /// The model's explanation of the command, when one was shown.
+    pub explanation: Option<String>,
+    /// End of the command's output for the transcript, when one is recorded.
+    pub transcript_output: Option<CommandOutput>,
+    /// The prompt typed in --tui, recorded as if it had been the last
+    /// argument.
+    pub tui_prompt: Option<String>,
+    /// Allowlisted tools the command runs, whose versions history records.
+    pub tools: Vec<String>,
 }
 
 impl RunSummary {
@@ -37,8 +106,17 @@ impl RunSummary {
             confirm: cli.confirm || cli.unsafe_mode || cli.explain,
             explain: cli.explain,
             scope: cli.scope.clone(),
-            peek_files: cli.peek.clone(),
+            peek_files: display_paths(&cli.peek),
             notes: None,
+            model: None,
+            output: None,
+            review: None,
+            risk: None,
+            executed: false,
+            explanation: None,
+            transcript_output: None,
+            tui_prompt: None,
+            tools: Vec::new(),
         }
     }
 
@@ -52,12 +130,21 @@ impl RunSummary {
             scope: None,
             peek_files: Vec::new(),
             notes: None,
+            model: None,
+            output: None,
+            review: None,
+            risk: None,
+            executed: false,
+            explanation: None,
+            transcript_output: None,
+            tui_prompt: None,
+            tools: Vec::new(),
         }
     }
 }
 
 pub fn run() -> Result<()> {
-    let raw_args: Vec<String> = env::args().collect();
+    let raw_args = lossy_args();
     if let Some(help) = help::try_handle_help(&raw_args[1..]) {
         match help {
             Ok(text) => {
@@ -71,19 +158,72 @@ pub fn run() -> Result<()> {
         }
     }
 
-    let cli = Cli::parse();
-    let generator = HttpCommandGenerator::new();
-    let executor = ShellCommandExecutor;
-    let exit_code = run_and_log(cli, &generator, &executor);
+    if cli::is_subcommand(&raw_args[1..]) {
+        let sub = SubcommandCli::parse();
+        let exit_code = match commands::run(sub) {
+            Ok(code) => code,
+            Err(err) => {
+                eprintln!("Error: {:#}", err);
+                1
+            }
+        };
+        std::process::exit(exit_code);
+    }
+
+    let exit_code = run_cli(Cli::parse())?;
     std::process::exit(exit_code);
 }
 
+/// Command line as text for help dispatch and history. Arguments that are not
+/// valid UTF-8 (e.g. file names for --peek) are kept as raw OS strings by clap.
+fn lossy_args() -> Vec<String> {
+    env::args_os()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+fn display_paths(paths: &[PathBuf]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Runs a parsed invocation with the HTTP generator and shell executor, logging
+/// it to history. Also used by `sai fav NAME`.
+pub(crate) fn run_cli(cli: Cli) -> Result<i32> {
+    interrupt::install()?;
+    let cassette = match Cassette::from_env() {
+        Ok(cassette) => cassette,
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            return Ok(1);
+        }
+    };
+    // Recording and replaying cassettes need the provider to be asked, and a
+    // run kept out of history leaves no cached command behind either.
+    let history_off = cli.no_history
+        || load_global_config(&find_global_config_path())
+            .ok()
+            .and_then(|cfg| cfg.history)
+            .is_some_and(|history| !history.enabled);
+    let response_cache =
+        (!cli.no_cache && !history_off && cassette.is_none()).then(ResponseCache::in_config_dir);
+    let generator = HttpCommandGenerator::try_new()?
+        .with_cassette(cassette)
+        .with_response_cache(response_cache);
+    match cli.host.clone() {
+        Some(host) => Ok(run_and_log(cli, &generator, &SshExecutor::new(host))),
+        None => Ok(run_and_log(cli, &generator, &ShellCommandExecutor)),
+    }
+}
+
 fn run_and_log<G, E>(cli: Cli, generator: &G, executor: &E) -> i32
 where
     G: CommandGenerator + ChatClient,
     E: CommandExecutor,
 {
-    let argv: Vec<String> = env::args().collect();
+    let argv = lossy_args();
     let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
 
     let mut exit_code = 1;
@@ -91,9 +231,8 @@ where
     let notes: Option<String>;
 
     let run_result = {
-        let stdin = io::stdin();
-        let mut stdin_lock = stdin.lock();
-        run_with_reader(cli.clone(), generator, executor, &mut stdin_lock)
+        let mut stdin = InterruptibleStdin::new();
+        run_with_reader(cli.clone(), generator, executor, &mut stdin)
     };
 
     match run_result {
@@ -102,12 +241,36 @@ where
             notes = res.notes.clone();
             summary = Some(res);
         }
+        Err(err) if interrupt::is_interrupt_error(&err) => {
+            let stop = interrupt::stop_signal();
+            eprintln!("{}", stop.message());
+            exit_code = stop.exit_code();
+            notes = Some(stop.note().to_string());
+        }
         Err(err) => {
             eprintln!("Error: {:#}", err);
+            if let Some(hint) = llm::error_guidance(&err) {
+                eprintln!("Hint: {}", hint);
+            }
             notes = Some(err.to_string());
         }
     }
 
+    let tui_prompt = summary.as_ref().and_then(|s| s.tui_prompt.clone());
+    let mut argv = argv;
+    if let Some(prompt) = &tui_prompt {
+        if prompt.starts_with('-') {
+            argv.push("--".to_string());
+        }
+        argv.push(prompt.clone());
+    }
+    let race_winner = summary.as_ref().and_then(|s| s.model.clone());
+    let output = summary.as_ref().and_then(|s| s.output.clone());
+    let review = summary.as_ref().and_then(|s| s.review.clone());
+    let audit = summary
+        .as_ref()
+        .filter(|s| s.executed)
+        .and_then(|s| Some((s.generated_command.clone()?, s.risk)));
     let (confirm, explain, unsafe_mode, scope, peek_files, generated_command) =
         if let Some(ref s) = summary {
             (
@@ -124,11 +287,51 @@ where
                 cli.explain,
                 cli.unsafe_mode,
                 cli.scope.clone(),
-                cli.peek.clone(),
+                display_paths(&cli.peek),
                 None,
             )
         };
 
+    let usage = generator.token_usage();
+    let mut model = None;
+    let mut seed = None;
+    let mut budget_cfg = None;
+    if !usage.is_empty() {
+        eprintln!(
+            "Tokens: {} prompt + {} completion = {} total",
+            usage.prompt_tokens,
+            usage.completion_tokens,
+            usage.total()
+        );
+    }
+    let global_cfg = load_global_config(&find_global_config_path()).ok();
+    let history_cfg = global_cfg
+        .as_ref()
+        .and_then(|cfg| cfg.history.clone())
+        .unwrap_or_default();
+    let hooks_cfg = global_cfg.as_ref().and_then(|cfg| cfg.hooks.clone());
+    let budgeted = global_cfg.as_ref().is_some_and(|cfg| cfg.budget.is_some());
+    let redaction = global_cfg
+        .as_ref()
+        .and_then(|cfg| cfg.redaction.clone())
+        .unwrap_or_default();
+    if !usage.is_empty() || generated_command.is_some() {
+        if let Some(global_cfg) = global_cfg {
+            if let Ok(ai) = resolve_ai_for_cli(global_cfg.ai, &cli) {
+                model = (!usage.is_empty())
+                    .then(|| race_winner.unwrap_or_else(|| ai.model_name().to_string()));
+                seed = ai.seed;
+            }
+            budget_cfg = global_cfg.budget;
+        }
+    }
+
+    // Only commands that ran; a declined line may name anything.
+    let environment = summary
+        .as_ref()
+        .filter(|s| s.executed && history_cfg.enabled && !cli.no_history)
+        .map(|s| envinfo::capture(&s.tools));
+
     let entry = HistoryEntry {
         ts: history::now_iso_ts(),
         cwd: cwd.to_string_lossy().to_string(),
@@ -140,11 +343,70 @@ where
         explain,
         scope,
         peek_files,
-        notes,
+        notes: match (notes, generator.cache_hits() > 0) {
+            (Some(notes), true) => Some(format!("{}; cached response", notes)),
+            (None, true) => Some("cached response".to_string()),
+            (notes, false) => notes,
+        },
+        usage: (!usage.is_empty()).then_some(usage),
+        model,
+        seed,
+        extra_tools: cli.allow_tool.clone(),
+        environment,
+        output,
+        review,
     };
 
-    if let Err(err) = history::write_entry(entry) {
-        eprintln!("Warning: failed to write history: {:#}", err);
+    if let (Some((command, risk)), Some(hooks)) = (audit, &hooks_cfg) {
+        let event = AuditEvent {
+            ts: entry.ts.clone(),
+            command,
+            user: webhook::current_user(),
+            host: cli.host.clone().unwrap_or_else(webhook::local_hostname),
+            cwd: entry.cwd.clone(),
+            exit_code,
+            risk,
+            unsafe_mode,
+        };
+        if let Err(err) = webhook::send(hooks, &event) {
+            eprintln!("Warning: audit webhook: {:#}", err);
+        }
+    }
+
+    if let Some(path) = transcript_file(&cli, history_cfg.enabled) {
+        let record = TranscriptRecord {
+            prompt: if cli.tui {
+                tui_prompt
+            } else {
+                cli.prompt_and_configs().1
+            },
+            command: entry.generated_command.clone(),
+            explanation: summary.as_ref().and_then(|s| s.explanation.clone()),
+            decision: match &summary {
+                Some(s) if s.confirm && s.executed => Decision::Confirmed,
+                Some(s) if s.confirm && s.generated_command.is_some() => Decision::Declined,
+                _ => Decision::NotAsked,
+            },
+            exit_code: summary.as_ref().filter(|s| s.executed).map(|_| exit_code),
+            notes: entry.notes.clone(),
+            output: summary.and_then(|s| s.transcript_output),
+        };
+        if let Err(err) = transcript::append(&path, &record, &redaction) {
+            eprintln!("Warning: {:#}", err);
+        }
+    }
+
+    if let Some(mut entry) = history_cfg.entry_to_write(entry, cli.no_history, budgeted) {
+        if history_cfg.scrub_secrets {
+            history::scrub_entry(&mut entry, &redaction);
+        }
+        if let Err(err) = history::write_entry(entry, &history_cfg.retention) {
+            eprintln!("Warning: failed to write history: {:#}", err);
+        }
+    }
+
+    if let Err(err) = budget::check_daily_budget(budget_cfg.as_ref()) {
+        eprintln!("Warning: {:#}", err);
     }
 
     exit_code
@@ -162,7 +424,7 @@ where
 }
 
 pub fn run_with_reader<G, E, R>(
-    cli: Cli,
+    mut cli: Cli,
     generator: &G,
     executor: &E,
     reader: &mut R,
@@ -173,6 +435,7 @@ where
     R: BufRead,
 {
     let global_config_path = find_global_config_path();
+    deprecation::warn(&deprecation::cli_usages(&cli, "command line"));
 
     if cli.init {
         ops::init_global_config(&global_config_path)?;
@@ -196,217 +459,2676 @@ where
     }
 
     if cli.list_tools {
-        ops::list_tools(&global_config_path, cli.arg1.as_deref())?;
+        ops::list_tools(
+            &global_config_path,
+            cli.arg1.as_deref(),
+            cli.host.as_deref(),
+        )?;
         let mut summary = RunSummary::from_cli(&cli);
         summary.notes = Some("list_tools".to_string());
         return Ok(summary);
     }
 
-    let global_cfg = load_global_config(&global_config_path)?;
+    let cwd = env::current_dir().context("Failed to determine current directory")?;
+    let global_cfg = load_run_config(&global_config_path, &cwd)?;
+    deprecation::warn(&deprecation::config_file_usages(&global_config_path).unwrap_or_default());
+    let org_policy = OrgPolicy::load(&orgpolicy::org_policy_path())?.unwrap_or_default();
+    org_policy.check_unsafe(cli.unsafe_mode)?;
+
+    if cli.usage {
+        let entries = history::read_all_entries()?;
+        let budget_cfg = global_cfg.budget.clone().unwrap_or_default();
+        print!("{}", budget::render_usage_report(&entries, &budget_cfg));
+        let mut summary = RunSummary::from_cli(&cli);
+        summary.notes = Some("usage".to_string());
+        return Ok(summary);
+    }
+
+    budget::check_daily_budget(global_cfg.budget.as_ref())?;
+
+    if org_policy.providers.is_some() && cli.estimate.is_none() {
+        org_policy.check_provider(&resolve_ai_for_cli(global_cfg.ai.clone(), &cli)?)?;
+    }
+
+    if cli.analyze.is_some() || cli.analyze_last.is_some() {
+        return run_analyze(&global_cfg, &cli, generator);
+    }
+
+    if let Some(batch) = &cli.estimate {
+        return run_estimate(&global_cfg, &cli, batch, &cwd);
+    }
 
-    if cli.analyze {
-        return run_analyze(&global_cfg, generator);
+    // --rerun: the command comes from history and is checked against the
+    // prompt configs of the original run; nothing is sent to the model.
+    let rerun = cli.rerun.map(rerun::load).transpose()?;
+    if rerun.is_none() {
+        if let Some(path) = projectcfg::apply_dir_defaults(&cwd, &mut cli.scope, &mut cli.peek)? {
+            eprintln!("Using defaults from {}", path.display());
+        }
     }
 
-    let arg1 = cli.arg1.clone().ok_or_else(|| {
+    let (config_args, nl_prompt) = match &rerun {
+        Some(rerun) => {
+            cli.with = rerun.with.clone();
+            (rerun.config_args.clone(), Some(rerun.prompt.clone()))
+        }
+        // The prompt is typed in the TUI.
+        None if cli.tui => (cli.positional(), Some(String::new())),
+        None => cli.prompt_and_configs(),
+    };
+    let nl_prompt = nl_prompt.ok_or_else(|| {
         anyhow!("Expected a prompt or prompt config path when not running with --init")
     })?;
+    // --refine: the prompt is feedback on the latest command, which the
+    // request carries along with the prompts that led to it.
+    let refinement = cli.refine.then(refine::load).transpose()?;
+    let config_args = match &refinement {
+        Some(refinement) if config_args.is_empty() && cli.with.is_empty() => {
+            cli.with = refinement.with.clone();
+            refinement.config_args.clone()
+        }
+        _ => config_args,
+    };
+    let request_base = match &refinement {
+        Some(refinement) => {
+            eprintln!("Refining: {}", refinement.command);
+            refinement.request_prompt(&nl_prompt)
+        }
+        None => nl_prompt.clone(),
+    };
+    if let Some(rerun) = &rerun {
+        eprintln!(
+            "Running again the command of run #{} ({}, in {})",
+            rerun.index, rerun.ts, rerun.cwd
+        );
+    }
+    let (prompt_source, config_paths, mut prompt_cfg) =
+        load_run_prompt_config(&global_cfg, &cli, &config_args, &cwd)?;
+
+    let guard_matches = global_cfg
+        .prompt_guard
+        .as_ref()
+        .map(|cfg| guard::check_prompt(cfg, &nl_prompt))
+        .unwrap_or_default();
+    let guard_note = (!guard_matches.is_empty()).then(|| {
+        let categories: Vec<&str> = guard_matches.iter().map(|m| m.category.as_str()).collect();
+        format!("prompt_guard: {}", categories.join(", "))
+    });
+    if !guard_matches.is_empty() && !acknowledge_flagged_prompt(reader, &guard_matches)? {
+        eprintln!("Cancelled.");
+        let mut summary = RunSummary::from_cli(&cli);
+        summary.notes = guard_note.map(|note| format!("{} (declined)", note));
+        return Ok(summary);
+    }
+
+    add_temporary_tools(&mut prompt_cfg, &cli.allow_tool)?;
+    let read_only = read_only_mode(cli.read_only);
+    let dropped_tools = restrict_tools(
+        &mut prompt_cfg,
+        &org_policy,
+        &cli.allow_tool,
+        read_only,
+        cli.unsafe_mode,
+    )?;
+    if !dropped_tools.is_empty() {
+        eprintln!(
+            "Note: the organization policy does not allow {}",
+            dropped_tools.join(", ")
+        );
+    }
+    let shellcheck_cfg = global_cfg.shellcheck.clone().unwrap_or_default();
+    let review_enabled = cli.review
+        || global_cfg
+            .confirmation
+            .as_ref()
+            .is_some_and(|c| c.llm_review);
+    // Remote commands see the host's file system, which the jail cannot check.
+    let jail = match cli.host {
+        Some(_) => None,
+        None => jail::jail_for_run(global_cfg.path_jail.as_ref(), cli.jail, &cwd)?,
+    };
+    let network = NetworkGuard::for_run(global_cfg.network.as_ref(), cli.allow_network);
+
+    let (system_prompt, allowed_tools) = build_system_prompt(&prompt_cfg)?;
+    let system_prompt = if cli.with_docs.is_empty() {
+        system_prompt
+    } else {
+        let mut docs = Vec::new();
+        for name in &cli.with_docs {
+            if !allowed_tools.iter().any(|t| t == name) {
+                return Err(anyhow!(
+                    "--with-docs '{}' is not a tool in the active prompt config",
+                    name
+                ));
+            }
+            let raw = fetch_tool_docs(name)?;
+            docs.push((name.clone(), distill_tool_docs(&raw)));
+        }
+        append_tool_docs(&system_prompt, &docs)
+    };
+    let mut peek_paths = cli.peek.clone();
+    let auto_peek = global_cfg.auto_peek.clone().unwrap_or_default();
+    if rerun.is_none() && (cli.auto_peek || auto_peek.enabled) {
+        for path in files_named_in_prompt(&nl_prompt, &auto_peek, &cli.peek) {
+            eprintln!("Auto-peek: sending a sample of {}", path.display());
+            peek_paths.push(path);
+        }
+    }
+    let peek_context = with_diff_context(
+        build_peek_context(&peek_paths, &cli.fields)?,
+        &cli.peek_diff,
+    )?;
+    peek_paths.extend(cli.peek_diff.iter().cloned());
+    let redaction = global_cfg.redaction.clone().unwrap_or_default();
+    let race_ai = if cli.race {
+        let mut race_ai = resolve_race_for_cli(&global_cfg, &cli)?;
+        org_policy.check_provider(&race_ai)?;
+        race_ai.redaction = redaction.clone();
+        Some(race_ai)
+    } else {
+        None
+    };
+    let mut effective_ai = resolve_ai_for_cli(global_cfg.ai.clone(), &cli)?;
+    effective_ai.redaction = redaction;
+    let mut race_winner = None;
+    // With --host the command runs remotely, so `--scope .` lists the host.
+    let scope_hint = match (cli.host.as_deref(), cli.scope.as_deref()) {
+        (Some(host), Some(".")) => Some(remote_scope_hint(host)?),
+        _ => cli.scope.clone(),
+    };
 
-    let (prompt_cfg, prompt_source): (crate::config::PromptConfig, Option<PathBuf>) =
-        match cli.prompt.as_ref() {
-            Some(_nl_prompt) => {
-                let cfg_path = PathBuf::from(&arg1);
-                let prompt_cfg = load_prompt_config(&cfg_path)?;
-                (prompt_cfg, Some(cfg_path))
+    // Commands the user declined and chose to regenerate, with their reasons
+    // and corrections; the next request tells the model about them.
+    let mut rejected: Vec<Rejection> = Vec::new();
+    let validate = |cmd: &str| {
+        validate_command(
+            cmd,
+            &allowed_tools,
+            &prompt_cfg,
+            jail.as_ref(),
+            network.as_ref(),
+            cli.unsafe_mode,
+            read_only,
+        )
+    };
+    let mut declined_notes = None;
+    // A validated command to use instead of generating one: picked in the
+    // TUI, or typed at the confirmation prompt ([e]dit), which is confirmed
+    // again.
+    let mut preset: Option<(String, Vec<String>)> = None;
+    let mut user_edited = false;
+    // --tui: the prompt and command are picked in the TUI; the command then
+    // goes through the checks and confirmation policy below like any other.
+    let (nl_prompt, request_base) = if cli.tui {
+        let mut backend = TuiSession {
+            generator,
+            ai: &effective_ai,
+            system_prompt: &system_prompt,
+            scope_hint: scope_hint.as_deref(),
+            peek_context: peek_context.as_deref(),
+            guard: global_cfg.prompt_guard.as_ref(),
+            validate: &validate,
+        };
+        match tui::run(&mut backend, "", &history::read_all_entries()?)? {
+            Some(choice) => {
+                preset = Some((choice.command.clone(), validate(&choice.command)?));
+                (choice.prompt.clone(), choice.prompt)
             }
             None => {
-                let prompt_cfg = global_cfg.default_prompt.clone().ok_or_else(|| {
-                    anyhow!("No default_prompt found in global config for simple mode")
-                })?;
-                (prompt_cfg, None)
+                let mut summary = RunSummary::from_cli(&cli);
+                summary.notes = Some("tui closed".to_string());
+                return Ok(summary);
+            }
+        }
+    } else {
+        (nl_prompt, request_base)
+    };
+    // A project's pinned tools (node_modules/.bin, .venv/bin) win over PATH
+    // locally; remote hosts resolve tools themselves.
+    let project = if cli.host.is_some() {
+        ProjectPath::default()
+    } else {
+        ProjectPath::discover(&global_cfg.project_tools.clone().unwrap_or_default(), &cwd)?
+    };
+    let (cmd_line, tokens, mut summary, run_dir, project_tool) = 'generate: loop {
+        interrupt::check()?;
+        let request_prompt = with_rejections(&request_base, &rejected);
+        let (cmd_line, tokens) = if let Some(preset) = preset.take() {
+            eprintln!(">> {}", preset.0);
+            preset
+        } else if let Some(rerun) = &rerun {
+            eprintln!(">> {}", rerun.command);
+            let tokens = validate(&rerun.command)?;
+            (rerun.command.clone(), tokens)
+        } else if let Some(count) = cli.candidates {
+            let spinner = Spinner::start("Generating candidate commands");
+            let candidates = generator
+                .generate_candidates(
+                    &effective_ai,
+                    &system_prompt,
+                    &request_prompt,
+                    scope_hint.as_deref(),
+                    peek_context.as_deref(),
+                    count,
+                )
+                .context("Failed to obtain candidate commands from LLM");
+            spinner.stop();
+
+            match choose_candidate(reader, &candidates?, &validate)? {
+                Some(choice) => choice,
+                None => {
+                    eprintln!("Cancelled.");
+                    let mut summary = RunSummary::from_cli(&cli);
+                    summary.notes = Some("cancelled".to_string());
+                    return Ok(summary);
+                }
+            }
+        } else if let Some(race_ai) = &race_ai {
+            let ais = [effective_ai.clone(), race_ai.clone()];
+            let spinner = Spinner::start("Racing providers");
+            let winner = generator
+                .generate_race(
+                    &ais,
+                    &system_prompt,
+                    &request_prompt,
+                    scope_hint.as_deref(),
+                    peek_context.as_deref(),
+                    &|cmd| validate(cmd).map(drop),
+                )
+                .context("Failed to obtain command from LLM");
+            spinner.stop();
+            let (idx, cmd_line) = winner?;
+
+            let [primary, race] = ais;
+            effective_ai = if idx == 0 { primary } else { race };
+            if !cli.print && !cli.fill {
+                eprintln!(">> {}   (from {})", cmd_line, effective_ai.model_name());
+            }
+            race_winner = Some(effective_ai.model_name().to_string());
+
+            let tokens = validate(&cmd_line)?;
+            (cmd_line, tokens)
+        } else {
+            let cache_hits = generator.cache_hits();
+            let spinner = Spinner::start("Generating command");
+            let cmd_line = generator
+                .generate_streaming(
+                    &effective_ai,
+                    &system_prompt,
+                    &request_prompt,
+                    scope_hint.as_deref(),
+                    peek_context.as_deref(),
+                    &mut |token| spinner.push_partial(token),
+                )
+                .context("Failed to obtain command from LLM");
+            spinner.stop();
+            let cmd_line = cmd_line?;
+
+            if !cli.print && !cli.fill {
+                let cached = generator.cache_hits() > cache_hits;
+                let note = if cached {
+                    "   (cached; --no-cache asks again)"
+                } else {
+                    ""
+                };
+                eprintln!(">> {}{}", cmd_line, note);
             }
+
+            let tokens = validate(&cmd_line)?;
+            (cmd_line, tokens)
         };
 
-    let nl_prompt = cli.prompt.clone().unwrap_or_else(|| arg1.clone());
+        if cli.osc52 {
+            osc52::copy_to_clipboard(&cmd_line)?;
+            eprintln!("Copied to the clipboard via OSC 52");
+        }
 
-    let (system_prompt, allowed_tools) = build_system_prompt(&prompt_cfg)?;
-    let peek_context = build_peek_context(&cli.peek)?;
-    let effective_ai = resolve_ai_config(global_cfg.ai)?;
-
-    let cmd_line = generator
-        .generate(
-            &effective_ai,
-            &system_prompt,
-            &nl_prompt,
-            cli.scope.as_deref(),
-            peek_context.as_deref(),
-        )
-        .context("Failed to obtain command from LLM")?;
+        let mut copy_note = None;
+        if let Some(mode) = cli.copy {
+            let method = clipboard::copy(&cmd_line)?;
+            eprintln!("Copied to the clipboard via {}", method.name());
+            if mode == CopyMode::Only {
+                copy_note = Some(format!("copied via {}", method.name()));
+            }
+        }
+
+        // --print, --fill and --copy stop here, without running the command.
+        if cli.print || cli.fill || copy_note.is_some() {
+            let note = match &cli.export_task {
+                _ if cli.fill => {
+                    print!("{}", cmd_line);
+                    io::stdout().flush()?;
+                    Some("filled".to_string())
+                }
+                _ if !cli.print => None,
+                Some(task) => {
+                    let task_prompt = match &refinement {
+                        Some(refinement) => refinement.full_prompt(&nl_prompt),
+                        None => nl_prompt.clone(),
+                    };
+                    let name = task
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| tasks::task_name(&task_prompt));
+                    print!(
+                        "{}",
+                        tasks::render_task(task.format, &name, &task_prompt, &cmd_line)
+                    );
+                    Some(format!("exported as {} task {}", task.format.name(), name))
+                }
+                None => {
+                    println!("{}", cmd_line);
+                    Some("printed".to_string())
+                }
+            };
+            let mut summary = RunSummary::from_cli(&cli);
+            summary.generated_command = Some(cmd_line);
+            summary.peek_files = display_paths(&peek_paths);
+            summary.notes = join_notes(
+                join_notes(
+                    guard_note.clone(),
+                    refinement.as_ref().map(refine::Refinement::note),
+                ),
+                join_notes(copy_note, note),
+            );
+            summary.model = race_winner.take();
+            summary.tui_prompt = cli.tui.then(|| nl_prompt.clone());
+            return Ok(summary);
+        }
+
+        let shell = runs_in_shell(&cmd_line, cli.unsafe_mode);
+        let run_dir = command_dir(
+            &prompt_cfg.tools,
+            &cmd_line,
+            shell,
+            &cwd,
+            cli.host.is_some(),
+        )?;
+
+        // Check if the generated command uses a tool that requires forced explain mode
+        let tool_requires_explain =
+            crate::prompt::should_force_explain(&prompt_cfg.tools, &cmd_line);
+        let effective_explain = cli.explain || tool_requires_explain;
+        let destructive = risk::destructive_tool(global_cfg.confirmation.as_ref(), &cmd_line);
+        let risk = match destructive {
+            Some(_) => RiskLevel::High,
+            None => risk::classify(&cmd_line, &prompt_cfg.tools),
+        };
+        let risk_mode = global_cfg
+            .confirmation
+            .as_ref()
+            .map(|c| c.mode_for(risk))
+            .unwrap_or(ConfirmMode::Auto);
+        let review = review_enabled
+            .then(|| review_command(generator, &effective_ai, &cmd_line))
+            .flatten();
+        let network_tools = network
+            .map(|n| n.confirm_tools(&cmd_line, &prompt_cfg.tools))
+            .unwrap_or_default();
+        // Safe mode runs the project copy of the tool; a shell searches PATH.
+        let project_tool = if runs_in_shell(&cmd_line, cli.unsafe_mode) {
+            None
+        } else {
+            project.resolve(&tokens[0])
+        };
+        let effective_confirm = cli.confirm
+            || rerun.is_some()
+            || user_edited
+            || org_policy.force_confirm
+            || !network_tools.is_empty()
+            || cli.unsafe_mode
+            || effective_explain
+            || destructive.is_some()
+            || review
+                .as_ref()
+                .is_some_and(|r| r.effect == CommandEffect::Destructive)
+            || risk_mode != ConfirmMode::Auto;
 
-    eprintln!(">> {}", cmd_line);
+        let mut summary = RunSummary::from_cli(&cli);
+        summary.generated_command = Some(cmd_line.clone());
+        summary.peek_files = display_paths(&peek_paths);
+        summary.notes = join_notes(
+            join_notes(guard_note.clone(), declined_notes.clone()),
+            race_winner
+                .as_deref()
+                .map(|model| format!("race won by {}", model)),
+        );
+        summary.notes = join_notes(
+            summary.notes.take(),
+            cli.host.as_deref().map(|host| format!("ran on {}", host)),
+        );
+        summary.notes = join_notes(summary.notes.take(), rerun.as_ref().map(rerun::note));
+        summary.notes = join_notes(
+            summary.notes.take(),
+            refinement.as_ref().map(refine::Refinement::note),
+        );
+        summary.model = race_winner.take();
+        summary.tui_prompt = cli.tui.then(|| nl_prompt.clone());
+        summary.explain = effective_explain;
+        summary.confirm = effective_confirm;
+        summary.review = review.clone();
+        summary.risk = Some(risk);
+        let findings = shellcheck_findings(&shellcheck_cfg, &cmd_line)?;
+        if !effective_confirm {
+            for finding in &findings {
+                eprintln!("ShellCheck: {}", finding);
+            }
+            if let Some(path) = &project_tool {
+                eprintln!("Using project tool {}", path.display());
+            }
+        }
 
-    let tokens = validate_and_split_command(&cmd_line, &allowed_tools, cli.unsafe_mode)?;
+        if !network_tools.is_empty() {
+            eprintln!(
+                "Note: {} reaches the network; network.policy asks before running it \
+                 (--allow-network skips this)",
+                network_tools.join(", ")
+            );
+        }
 
-    // Check if the generated command uses a tool that requires forced explain mode
-    let tool_requires_explain = crate::prompt::should_force_explain(&prompt_cfg.tools, &cmd_line);
-    let effective_explain = cli.explain || tool_requires_explain;
-    let effective_confirm = cli.confirm || cli.unsafe_mode || effective_explain;
+        if tool_requires_explain && !cli.explain {
+            eprintln!("Note: This tool requires explanation mode (force_explain is enabled)");
+            eprintln!();
+        }
 
-    let mut summary = RunSummary::from_cli(&cli);
-    summary.generated_command = Some(cmd_line.clone());
-    summary.explain = effective_explain;
-    summary.confirm = effective_confirm;
+        if effective_explain {
+            summary.explanation = print_command_explanation(generator, &effective_ai, &cmd_line)?;
+        }
 
-    if tool_requires_explain && !cli.explain {
-        eprintln!("Note: This tool requires explanation mode (force_explain is enabled)");
-        eprintln!();
+        let details = ConfirmDetails {
+            global_cfg_path: &global_config_path,
+            prompt_cfg_path: prompt_source.as_deref(),
+            merged_cfg_paths: &config_paths,
+            nl_prompt: &nl_prompt,
+            scope_hint: cli.scope.as_deref(),
+            host: cli.host.as_deref(),
+            project_tool: project_tool.as_deref(),
+            extra_tools: &cli.allow_tool,
+            cmd_line: &cmd_line,
+            risk: global_cfg.confirmation.is_some().then_some(risk),
+            type_to_confirm: destructive.as_deref().or_else(|| {
+                (risk_mode == ConfirmMode::TypeCommandName).then(|| tokens[0].as_str())
+            }),
+            review: review.as_ref(),
+            shellcheck: &findings,
+            menu: match rerun {
+                Some(_) => &[ConfirmChoice::Edit, ConfirmChoice::Explain],
+                None => &[
+                    ConfirmChoice::Edit,
+                    ConfirmChoice::Regenerate,
+                    ConfirmChoice::Explain,
+                ],
+            },
+        };
+        if effective_confirm {
+            // No answer (end of input or Ctrl-C) skips the follow-up questions.
+            let mut answer = confirm(reader, &details)?;
+            loop {
+                match answer {
+                    Some(ConfirmChoice::Explain) => {
+                        summary.explanation =
+                            print_command_explanation(generator, &effective_ai, &cmd_line)?;
+                    }
+                    Some(ConfirmChoice::Edit) => {
+                        if let Some(edit) = edit_command(reader, &cmd_line, &validate)? {
+                            preset = Some(edit);
+                            user_edited = true;
+                            declined_notes = join_notes(declined_notes, Some("edited".to_string()));
+                            continue 'generate;
+                        }
+                    }
+                    _ => break,
+                }
+                answer = ask_confirm_choice(reader, &details)?;
+            }
+            if answer == Some(ConfirmChoice::Regenerate) {
+                declined_notes = join_notes(declined_notes, Some("regenerated".to_string()));
+                rejected.push(Rejection {
+                    command: cmd_line,
+                    reason: None,
+                    correction: None,
+                });
+                continue;
+            }
+            if answer != Some(ConfirmChoice::Yes) {
+                let reason = match answer {
+                    Some(_) => ask_decline_reason(reader)?,
+                    None => None,
+                };
+                let note = |outcome: &str| match reason {
+                    Some(reason) => format!("{} ({})", outcome, reason.label()),
+                    None => outcome.to_string(),
+                };
+                let next = match answer {
+                    Some(_) if rerun.is_none() => ask_after_decline(reader, reason.is_some())?,
+                    _ => AfterDecline::Stop,
+                };
+                if next != AfterDecline::Stop {
+                    let (outcome, correction) = match next {
+                        AfterDecline::Correct(text) => ("corrected", Some(text)),
+                        _ => ("regenerated", None),
+                    };
+                    declined_notes = join_notes(declined_notes, Some(note(outcome)));
+                    rejected.push(Rejection {
+                        command: cmd_line,
+                        reason,
+                        correction,
+                    });
+                    continue;
+                }
+                eprintln!("Cancelled.");
+                summary.exit_code = 0;
+                summary.notes = join_notes(declined_notes, Some(note("cancelled")));
+                return Ok(summary);
+            }
+        }
+        break (cmd_line, tokens, summary, run_dir, project_tool);
+    };
+    // Allowed operators need a shell just like --unsafe.
+    let shell = runs_in_shell(&cmd_line, cli.unsafe_mode);
+
+    // Safe mode expands globs itself; thousands of matches can exceed ARG_MAX.
+    // On a --host the remote shell expands them instead.
+    let size = if shell || cli.host.is_some() {
+        None
+    } else {
+        Some(argument_size(&tokens, run_dir.as_deref())?)
+    };
+    let batches = size.map_or(1, |s| s.batches);
+    if let Some(size) = size.filter(|s| s.batches > 1) {
+        if !confirm_batches(reader, &size)? {
+            eprintln!("Cancelled.");
+            summary.exit_code = 0;
+            summary.notes = join_notes(
+                summary.notes.take(),
+                Some("cancelled (argument list too long)".to_string()),
+            );
+            return Ok(summary);
+        }
+        summary.notes = join_notes(
+            summary.notes.take(),
+            Some(format!("ran in {} batches", size.batches)),
+        );
     }
 
-    if effective_explain {
-        print_command_explanation(generator, &effective_ai, &cmd_line)?;
+    summary.notes = join_notes(
+        summary.notes.take(),
+        project_tool.map(|path| format!("project tool {}", path.display())),
+    );
+
+    if cli.backup && is_modifying(summary.risk, summary.review.as_ref()) {
+        let note = back_up(&tokens, run_dir.as_deref().unwrap_or(&cwd))?;
+        summary.notes = join_notes(summary.notes.take(), note);
     }
+    // --transaction: the command runs in a staging workspace, and what it
+    // writes there is moved into place only once it succeeded.
+    let txn = cli
+        .transaction
+        .then(|| {
+            let dir = run_dir.as_deref().unwrap_or(&cwd);
+            begin_transaction(&cmd_line, &tokens, shell, dir, &cwd)
+        })
+        .transpose()?;
+    let run_dir = match &txn {
+        Some(txn) => Some(txn.dir_for(run_dir.as_deref().unwrap_or(&cwd))),
+        None => run_dir,
+    };
 
-    if effective_confirm
-        && !confirm(
-            reader,
-            &global_config_path,
-            prompt_source.as_deref(),
-            &nl_prompt,
-            cli.scope.as_deref(),
+    let execution = global_cfg.execution.clone().unwrap_or_default();
+    let max_output_lines = execution.output_limit(cli.full_output);
+    let pager = global_cfg
+        .pager
+        .as_ref()
+        .and_then(Pager::for_terminal)
+        .or_else(|| {
+            Pager::for_output_limit(global_cfg.pager.as_ref(), &execution, max_output_lines?)
+        });
+    let exec_opts = ExecOptions {
+        batch_args: batches > 1,
+        project,
+        pager,
+        max_output_lines,
+        dir: run_dir,
+        ..policy_exec_options(
+            &global_cfg,
+            &prompt_cfg.tools,
             &cmd_line,
-        )?
+            shell,
+            cli.exec_timeout,
+        )
+    };
+
+    interrupt::check()?;
+    let history_cfg = global_cfg.history.clone().unwrap_or_default();
+    // Nothing is kept when the run leaves no history entry.
+    let keep_output =
+        (cli.capture || history_cfg.capture_output) && history_cfg.enabled && !cli.no_history;
+    let transcript = transcript_file(&cli, history_cfg.enabled).is_some();
+    let mut captured = if cli.verify.is_some()
+        || keep_output
+        || transcript
+        || cli.summarize_output
+        || cli.analyze_output
+        || cli.auto_fix.is_some()
     {
-        eprintln!("Cancelled.");
-        summary.exit_code = 0;
-        summary.notes = Some("cancelled".to_string());
-        return Ok(summary);
+        Some(executor.execute_captured(&cmd_line, &tokens, shell, &exec_opts)?)
+    } else {
+        None
+    };
+    summary.output = captured
+        .as_ref()
+        .filter(|_| keep_output)
+        .map(|c| CommandOutput::keep_tail(&c.stdout, &c.stderr, history_cfg.capture_max_bytes));
+    summary.transcript_output = captured.as_ref().filter(|_| transcript).map(|c| {
+        CommandOutput::keep_tail(&c.stdout, &c.stderr, transcript::TRANSCRIPT_OUTPUT_BYTES)
+    });
+    summary.exit_code = match &captured {
+        Some(captured) => captured.exit_code,
+        None => executor.execute_with(&cmd_line, &tokens, shell, &exec_opts)?,
+    };
+    summary.executed = true;
+    summary.tools = allowlisted_tools(&cmd_line, shell, &allowed_tools, &prompt_cfg.tools);
+    if interrupt::is_interrupted() {
+        return Ok(stopped(summary));
+    }
+    if let (EXIT_TIMED_OUT, Some(timeout)) = (summary.exit_code, exec_opts.timeout) {
+        summary.notes = join_notes(
+            summary.notes.take(),
+            Some(format!("timed out after {}", humanize::duration(timeout))),
+        );
+    }
+    if let Some(txn) = txn {
+        let note = finish_transaction(txn, summary.exit_code)?;
+        summary.notes = join_notes(summary.notes.take(), Some(note));
+    }
+
+    // --auto-fix: a failed command goes back to the model with its error
+    // output; every fix is validated and confirmed before it runs.
+    let mut cmd_line = cmd_line;
+    let mut failures: Vec<(String, CapturedOutput)> = Vec::new();
+    let mut flag_docs: Vec<(String, String)> = Vec::new();
+    while let Some(failed) = captured
+        .as_ref()
+        .filter(|c| c.exit_code != 0 && c.exit_code != EXIT_TIMED_OUT)
+        .filter(|_| failures.len() < cli.auto_fix.unwrap_or(0) as usize)
+    {
+        eprintln!(
+            "\nCommand exited with code {}; asking for a fix (attempt {} of {}).",
+            failed.exit_code,
+            failures.len() + 1,
+            cli.auto_fix.unwrap_or(0)
+        );
+        // A wrong flag is best fixed with the tool's own --help at hand; it
+        // is read locally, so not for --host.
+        let stderr = String::from_utf8_lossy(&failed.stderr);
+        if cli.host.is_none() && is_flag_error(&stderr) {
+            if let Some(tool) = failing_tool(&cmd_line, &stderr)
+                .filter(|tool| !flag_docs.iter().any(|(name, _)| name == tool))
+            {
+                if let Some(help) = flag_help(&tool) {
+                    eprintln!("Including `{} --help` in the fix request.", tool);
+                    flag_docs.push((tool, help));
+                }
+            }
+        }
+        failures.push((cmd_line.clone(), failed.clone()));
+        let spinner = Spinner::start("Generating a fix");
+        let fix = generator
+            .generate(
+                &effective_ai,
+                &system_prompt,
+                &with_failures(&request_base, &failures, &flag_docs),
+                scope_hint.as_deref(),
+                peek_context.as_deref(),
+            )
+            .context("Failed to obtain a fixed command from LLM");
+        spinner.stop();
+        let fix = fix?;
+        eprintln!(">> {}", fix);
+
+        let fix_tokens = match validate_command(
+            &fix,
+            &allowed_tools,
+            &prompt_cfg,
+            jail.as_ref(),
+            network.as_ref(),
+            cli.unsafe_mode,
+            read_only,
+        ) {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                eprintln!("Fix rejected: {:#}", err);
+                summary.notes =
+                    join_notes(summary.notes.take(), Some("auto-fix rejected".to_string()));
+                break;
+            }
+        };
+        let fix_shell = runs_in_shell(&fix, cli.unsafe_mode);
+        let fix_dir =
+            match command_dir(&prompt_cfg.tools, &fix, fix_shell, &cwd, cli.host.is_some()) {
+                Ok(dir) => dir,
+                Err(err) => {
+                    eprintln!("Fix rejected: {:#}", err);
+                    summary.notes =
+                        join_notes(summary.notes.take(), Some("auto-fix rejected".to_string()));
+                    break;
+                }
+            };
+        let destructive = risk::destructive_tool(global_cfg.confirmation.as_ref(), &fix);
+        let fix_review = review_enabled
+            .then(|| review_command(generator, &effective_ai, &fix))
+            .flatten();
+        let fix_findings = match shellcheck_findings(&shellcheck_cfg, &fix) {
+            Ok(findings) => findings,
+            Err(err) => {
+                eprintln!("Fix rejected: {:#}", err);
+                summary.notes =
+                    join_notes(summary.notes.take(), Some("auto-fix rejected".to_string()));
+                break;
+            }
+        };
+        let risk = match destructive {
+            Some(_) => RiskLevel::High,
+            None => risk::classify(&fix, &prompt_cfg.tools),
+        };
+        let risk_mode = global_cfg
+            .confirmation
+            .as_ref()
+            .map(|c| c.mode_for(risk))
+            .unwrap_or(ConfirmMode::Auto);
+        let fix_tool = if fix_shell {
+            None
+        } else {
+            exec_opts.project.resolve(&fix_tokens[0])
+        };
+        let details = ConfirmDetails {
+            global_cfg_path: &global_config_path,
+            prompt_cfg_path: prompt_source.as_deref(),
+            merged_cfg_paths: &config_paths,
+            nl_prompt: &nl_prompt,
+            scope_hint: cli.scope.as_deref(),
+            host: cli.host.as_deref(),
+            project_tool: fix_tool.as_deref(),
+            extra_tools: &cli.allow_tool,
+            cmd_line: &fix,
+            risk: global_cfg.confirmation.is_some().then_some(risk),
+            type_to_confirm: destructive.as_deref().or_else(|| {
+                (risk_mode == ConfirmMode::TypeCommandName).then(|| fix_tokens[0].as_str())
+            }),
+            review: fix_review.as_ref(),
+            shellcheck: &fix_findings,
+            menu: &[],
+        };
+        if confirm(reader, &details)? != Some(ConfirmChoice::Yes) {
+            eprintln!("Fix not run.");
+            summary.notes = join_notes(summary.notes.take(), Some("auto-fix declined".to_string()));
+            break;
+        }
+
+        let fix_size = if fix_shell || cli.host.is_some() {
+            None
+        } else {
+            Some(argument_size(&fix_tokens, fix_dir.as_deref())?)
+        };
+        let fix_batches = fix_size.map_or(1, |s| s.batches);
+        if let Some(size) = fix_size.filter(|s| s.batches > 1) {
+            if !confirm_batches(reader, &size)? {
+                eprintln!("Fix not run.");
+                summary.notes = join_notes(
+                    summary.notes.take(),
+                    Some("auto-fix cancelled (argument list too long)".to_string()),
+                );
+                break;
+            }
+        }
+        if cli.backup && is_modifying(Some(risk), fix_review.as_ref()) {
+            let note = back_up(&fix_tokens, fix_dir.as_deref().unwrap_or(&cwd))?;
+            summary.notes = join_notes(summary.notes.take(), note);
+        }
+        let fix_txn = cli
+            .transaction
+            .then(|| {
+                let dir = fix_dir.as_deref().unwrap_or(&cwd);
+                begin_transaction(&fix, &fix_tokens, fix_shell, dir, &cwd)
+            })
+            .transpose()?;
+        let fix_dir = match &fix_txn {
+            Some(txn) => Some(txn.dir_for(fix_dir.as_deref().unwrap_or(&cwd))),
+            None => fix_dir,
+        };
+
+        interrupt::check()?;
+        let fix_opts = ExecOptions {
+            timeout: exec_timeout(cli.exec_timeout, &prompt_cfg.tools, &fix, fix_shell),
+            batch_args: fix_batches > 1,
+            tool_env: tool_env(&prompt_cfg.tools, &fix, fix_shell),
+            dir: fix_dir,
+            ..exec_opts.clone()
+        };
+        let result = executor.execute_captured(&fix, &fix_tokens, fix_shell, &fix_opts)?;
+        summary.notes = join_notes(
+            summary.notes.take(),
+            Some(format!(
+                "auto-fix {}: {} (exit {})",
+                failures.len(),
+                fix,
+                result.exit_code
+            )),
+        );
+        if let Some(txn) = fix_txn {
+            let note = finish_transaction(txn, result.exit_code)?;
+            summary.notes = join_notes(summary.notes.take(), Some(note));
+        }
+        summary.generated_command = Some(fix.clone());
+        summary.tools = allowlisted_tools(&fix, fix_shell, &allowed_tools, &prompt_cfg.tools);
+        summary.confirm = true;
+        summary.risk = Some(risk);
+        summary.exit_code = result.exit_code;
+        summary.output = keep_output.then(|| {
+            CommandOutput::keep_tail(
+                &result.stdout,
+                &result.stderr,
+                history_cfg.capture_max_bytes,
+            )
+        });
+        summary.transcript_output = transcript.then(|| {
+            CommandOutput::keep_tail(
+                &result.stdout,
+                &result.stderr,
+                transcript::TRANSCRIPT_OUTPUT_BYTES,
+            )
+        });
+        cmd_line = fix;
+        captured = Some(result);
+        if interrupt::is_interrupted() {
+            return Ok(stopped(summary));
+        }
+    }
+
+    if let Some(captured) = captured
+        .as_ref()
+        .filter(|c| cli.summarize_output && is_long(c))
+    {
+        let spinner = Spinner::start("Summarizing output");
+        let summary_text = summarize_output(generator, &effective_ai, &cmd_line, captured);
+        spinner.stop();
+        match summary_text {
+            Ok(text) => eprintln!("\nSummary:\n{}", text.trim()),
+            Err(err) if interrupt::is_interrupt_error(&err) => return Err(err),
+            Err(err) => eprintln!("Warning: failed to summarize output: {:#}", err),
+        }
+    }
+
+    if let Some(captured) = captured.as_ref().filter(|_| cli.analyze_output) {
+        let spinner = Spinner::start("Analyzing output");
+        let analysis = analyze_output(generator, &effective_ai, &nl_prompt, &cmd_line, captured);
+        spinner.stop();
+        match analysis {
+            Ok(text) => eprintln!("\nAnalysis:\n{}", text.trim()),
+            Err(err) if interrupt::is_interrupt_error(&err) => return Err(err),
+            Err(err) => eprintln!("Warning: failed to analyze output: {:#}", err),
+        }
     }
 
-    let status = executor.execute(&cmd_line, &tokens, cli.unsafe_mode)?;
-    summary.exit_code = status;
+    if let (Some(expectation), Some(captured)) = (cli.verify.as_deref(), &captured) {
+        let spinner = Spinner::start("Verifying output");
+        let verdict = verify_output(generator, &effective_ai, &cmd_line, expectation, captured);
+        spinner.stop();
+        let verdict = verdict.context("Failed to verify command output")?;
+
+        let outcome = if verdict.passed { "passed" } else { "failed" };
+        eprintln!("Verification {}: {}", outcome, verdict.reason);
+        let note = format!("verify {} (command exit {})", outcome, captured.exit_code);
+        summary.notes = join_notes(summary.notes.take(), Some(note));
+        summary.exit_code = if verdict.passed {
+            0
+        } else {
+            EXIT_VERIFY_FAILED
+        };
+    }
     Ok(summary)
 }
 
-fn confirm(
-    reader: &mut dyn BufRead,
-    global_cfg_path: &Path,
-    prompt_cfg_path: Option<&Path>,
-    nl_prompt: &str,
-    scope_hint: Option<&str>,
+/// The transcript this run is recorded in: `--transcript`, or the one
+/// started with `sai transcript start` unless the run has `--no-history` or
+/// `history.enabled` is false.
+fn transcript_file(cli: &Cli, history_enabled: bool) -> Option<PathBuf> {
+    cli.transcript.clone().or_else(|| {
+        (history_enabled && !cli.no_history)
+            .then(transcript::active_transcript)
+            .flatten()
+    })
+}
+
+/// Joins history notes with "; ".
+fn join_notes(first: Option<String>, second: Option<String>) -> Option<String> {
+    match (first, second) {
+        (Some(first), Some(second)) => Some(format!("{}; {}", first, second)),
+        (first, second) => first.or(second),
+    }
+}
+
+/// The global config at `path` with the project policy for `cwd` merged in;
+/// runs and [`crate::session::Session::configure`] load it the same way.
+pub(crate) fn load_run_config(path: &Path, cwd: &Path) -> Result<crate::config::GlobalConfig> {
+    let mut global_cfg = load_global_config(path)?;
+    projectcfg::apply_project_policy(&mut global_cfg, cwd)?;
+    Ok(global_cfg)
+}
+
+/// Execution settings every run takes from the config: the timeout, limits,
+/// environment policy and the tool's `env:` map.
+pub(crate) fn policy_exec_options(
+    global_cfg: &crate::config::GlobalConfig,
+    tools: &[crate::config::ToolConfig],
     cmd_line: &str,
-) -> Result<bool> {
-    eprintln!("Global config file: {}", global_cfg_path.display());
-    if let Some(p) = prompt_cfg_path {
-        eprintln!("Prompt config file: {}", p.display());
-    } else {
-        eprintln!("Prompt config: default_prompt from global config");
+    shell: bool,
+    cli_timeout: Option<u64>,
+) -> ExecOptions {
+    ExecOptions {
+        timeout: exec_timeout(cli_timeout, tools, cmd_line, shell),
+        limits: global_cfg.limits.clone().unwrap_or_default(),
+        env: global_cfg.env_policy.clone().unwrap_or_default(),
+        tool_env: tool_env(tools, cmd_line, shell),
+        ..ExecOptions::default()
     }
-    eprintln!();
-    eprintln!("Natural language prompt:");
-    eprintln!("  {}", nl_prompt);
-    eprintln!();
-    if let Some(scope) = scope_hint {
-        eprintln!("Scope hint:");
-        eprintln!("  {}", scope);
-        eprintln!();
+}
+
+/// The shortest of --exec-timeout and the `timeout` of every configured tool
+/// the command invokes.
+fn exec_timeout(
+    cli_timeout: Option<u64>,
+    tools: &[crate::config::ToolConfig],
+    cmd_line: &str,
+    unsafe_mode: bool,
+) -> Option<Duration> {
+    let invoked = envinfo::invoked_tools(cmd_line, unsafe_mode);
+    tools
+        .iter()
+        .filter(|t| invoked.iter().any(|word| t.matches(word)))
+        .filter_map(|t| t.timeout)
+        .chain(cli_timeout)
+        .min()
+        .map(Duration::from_secs)
+}
+
+/// The allowlist names of the tools the command runs. Words that do not
+/// resolve through the allowlist are left out, so nothing else is probed.
+fn allowlisted_tools(
+    cmd_line: &str,
+    unsafe_mode: bool,
+    allowed_tools: &[String],
+    tools: &[crate::config::ToolConfig],
+) -> Vec<String> {
+    let mut resolved: Vec<String> = Vec::new();
+    for word in envinfo::invoked_tools(cmd_line, unsafe_mode) {
+        if let Some(name) = resolve_tool(&word, allowed_tools, tools) {
+            if !resolved.iter().any(|t| t == name) {
+                resolved.push(name.to_string());
+            }
+        }
     }
-    eprintln!("LLM output (command):");
-    eprintln!("  {}", cmd_line);
-    eprintln!();
+    resolved
+}
 
-    eprint!("Execute this command? [y/N] ");
-    io::stdout().flush().ok();
-    let mut buf = String::new();
-    reader.read_line(&mut buf)?;
-    let ans = buf.trim().to_lowercase();
-    Ok(ans == "y" || ans == "yes")
+/// The `env:` map of the tool the command runs. With `--unsafe` only when the
+/// line invokes that tool alone, since the shell passes it to every command.
+fn tool_env(
+    tools: &[crate::config::ToolConfig],
+    cmd_line: &str,
+    unsafe_mode: bool,
+) -> BTreeMap<String, String> {
+    match envinfo::invoked_tools(cmd_line, unsafe_mode).as_slice() {
+        [invoked] => tools
+            .iter()
+            .find(|t| t.matches(invoked))
+            .map(|t| t.env.clone())
+            .unwrap_or_default(),
+        _ => BTreeMap::new(),
+    }
 }
 
-fn print_command_explanation<G>(
+/// Output needs at least this many lines before --summarize-output kicks in.
+const SUMMARIZE_MIN_LINES: usize = 40;
+
+fn is_long(captured: &CapturedOutput) -> bool {
+    let lines = |data: &[u8]| data.iter().filter(|&&b| b == b'\n').count();
+    lines(&captured.stdout) + lines(&captured.stderr) >= SUMMARIZE_MIN_LINES
+}
+
+fn summarize_output<G>(
     generator: &G,
     ai: &crate::config::EffectiveAiConfig,
     cmd_line: &str,
-) -> Result<()>
+    captured: &CapturedOutput,
+) -> Result<String>
 where
     G: ChatClient,
 {
-    let system_prompt = "You are a shell and tool usage explainer. \
-Given a shell command, explain in concise technical language what it will do, \
-describing each flag and argument, and the overall effect. \
-Do not invent behaviour not implied by the command.";
+    let system_prompt = "You summarize the output of a shell command for the person who ran it. \
+In at most six short lines, report the outcome, counts that matter (files, tests, errors, warnings), \
+the most important error lines quoted verbatim, and anything anomalous. Do not restate normal progress output.";
+    generator.respond(ai, system_prompt, &output_prompt(cmd_line, captured), 0.0)
+}
+
+fn analyze_output<G>(
+    generator: &G,
+    ai: &crate::config::EffectiveAiConfig,
+    nl_prompt: &str,
+    cmd_line: &str,
+    captured: &CapturedOutput,
+) -> Result<String>
+where
+    G: ChatClient,
+{
+    let system_prompt = "You interpret the output of a shell command for the person who ran it. \
+Answer what they wanted to know from it: explain what the output means for their request, point out \
+the values, errors or warnings that matter and what they imply, and suggest a next step when the \
+command failed or the output looks wrong. Be concise and do not restate the output line by line.";
     let user_prompt = format!(
-        "Explain this command in detail, but concisely:\n\n{}",
-        cmd_line
+        "Request: {}\n{}",
+        nl_prompt,
+        output_prompt(cmd_line, captured)
     );
+    generator.respond(ai, system_prompt, &user_prompt, 0.0)
+}
 
-    println!("Generated command:\n  {}\n", cmd_line);
-    match generator.respond(ai, system_prompt, &user_prompt, 0.0) {
-        Ok(explanation) => {
-            println!("Explanation:\n{}", explanation);
-        }
-        Err(err) => {
-            eprintln!("Failed to explain command: {:#}", err);
-        }
+/// Command, exit code and a sample of the output for the model. Long output
+/// is sampled like --peek, plus its end.
+fn output_prompt(cmd_line: &str, captured: &CapturedOutput) -> String {
+    let mut user_prompt = format!(
+        "Command: {}\nExit code: {}\n\nOutput:\n{}",
+        cmd_line,
+        captured.exit_code,
+        build_output_context(&captured.stdout, &captured.stderr)
+    );
+    // The sample above is cut from the end; the end is where totals and
+    // final errors usually are.
+    if captured.stdout.len().max(captured.stderr.len()) > PEEK_MAX_BYTES {
+        let tail = CommandOutput::keep_tail(
+            &captured.stdout,
+            &captured.stderr,
+            history::DEFAULT_CAPTURE_MAX_BYTES,
+        );
+        user_prompt.push_str(&format!(
+            "End of output:\n=== stdout ===\n{}\n=== stderr ===\n{}\n",
+            tail.stdout, tail.stderr
+        ));
     }
+    user_prompt
+}
 
-    Ok(())
+/// The model's judgement of whether command output meets an expectation.
+struct Verdict {
+    passed: bool,
+    reason: String,
 }
 
-fn run_analyze<G>(global_cfg: &crate::config::GlobalConfig, generator: &G) -> Result<RunSummary>
+fn verify_output<G>(
+    generator: &G,
+    ai: &crate::config::EffectiveAiConfig,
+    cmd_line: &str,
+    expectation: &str,
+    captured: &CapturedOutput,
+) -> Result<Verdict>
 where
     G: ChatClient,
 {
-    let mut summary = RunSummary::analyze_mode();
+    let system_prompt =
+        "You check whether the output of a shell command meets a stated expectation. \
+Answer PASS or FAIL on the first line, followed by one short sentence explaining why. \
+Judge only from the exit code and output shown; if they are not enough to decide, answer FAIL.";
+    let user_prompt = format!(
+        "Command: {}\nExit code: {}\nExpectation: {}\n\nOutput:\n{}",
+        cmd_line,
+        captured.exit_code,
+        expectation,
+        build_output_context(&captured.stdout, &captured.stderr)
+    );
+    let answer = generator.respond(ai, system_prompt, &user_prompt, 0.0)?;
+    Ok(parse_verdict(&answer))
+}
+
+/// Reads PASS/FAIL from the first line of the answer. Anything that is not
+/// clearly PASS counts as a failure.
+fn parse_verdict(answer: &str) -> Verdict {
+    let answer = answer.trim();
+    let word_end = answer
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '*')
+        .unwrap_or(answer.len());
+    let passed = answer[..word_end]
+        .trim_matches('*')
+        .eq_ignore_ascii_case("pass");
+    let reason = answer[word_end..]
+        .trim_start_matches(['*', ':', '.', '-'])
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let reason = if reason.is_empty() {
+        "(no reason given)".to_string()
+    } else {
+        reason
+    };
+    Verdict { passed, reason }
+}
+
+/// Everything shown on the confirmation screen.
+struct ConfirmDetails<'a> {
+    global_cfg_path: &'a Path,
+    prompt_cfg_path: Option<&'a Path>,
+    /// Prompt configs merged in for this run (`--with`, further positionals).
+    merged_cfg_paths: &'a [PathBuf],
+    nl_prompt: &'a str,
+    scope_hint: Option<&'a str>,
+    /// `--host`, where the command will run.
+    host: Option<&'a str>,
+    /// The project copy of the tool that will run (`project_tools:`).
+    project_tool: Option<&'a Path>,
+    extra_tools: &'a [String],
+    cmd_line: &'a str,
+    /// Risk level, shown when a `confirmation:` policy is configured.
+    risk: Option<RiskLevel>,
+    /// Token the user must type instead of "y" (high-risk and destructive
+    /// commands).
+    type_to_confirm: Option<&'a str>,
+    /// The model's review, with --review.
+    review: Option<&'a LlmReview>,
+    /// ShellCheck findings for the command.
+    shellcheck: &'a [Finding],
+    /// Choices offered besides yes and no.
+    menu: &'a [ConfirmChoice],
+}
+
+/// Asks to run the command. `None` when there was no answer (end of input or
+/// Ctrl-C), which declines without follow-up questions.
+fn confirm(reader: &mut dyn BufRead, details: &ConfirmDetails) -> Result<Option<ConfirmChoice>> {
+    let ConfirmDetails {
+        global_cfg_path,
+        prompt_cfg_path,
+        merged_cfg_paths,
+        nl_prompt,
+        scope_hint,
+        host,
+        project_tool,
+        extra_tools,
+        cmd_line,
+        risk,
+        type_to_confirm: _,
+        review,
+        shellcheck,
+        menu: _,
+    } = *details;
+
+    eprintln!("Global config file: {}", global_cfg_path.display());
+    if let Some(p) = prompt_cfg_path {
+        eprintln!("Prompt config file: {}", p.display());
+    } else {
+        eprintln!("Prompt config: default_prompt from global config");
+    }
+    for p in merged_cfg_paths {
+        eprintln!("Merged prompt config: {}", p.display());
+    }
+    eprintln!();
+    if !extra_tools.is_empty() {
+        eprintln!("!! Temporarily allowed for this run only (--allow-tool):");
+        eprintln!("!!   {}", extra_tools.join(", "));
+        eprintln!();
+    }
+    eprintln!("Natural language prompt:");
+    eprintln!("  {}", nl_prompt);
+    eprintln!();
+    if let Some(scope) = scope_hint {
+        eprintln!("Scope hint:");
+        eprintln!("  {}", scope);
+        eprintln!();
+    }
+    eprintln!("LLM output (command):");
+    eprintln!("  {}", cmd_line);
+    eprintln!();
+    if let Some(host) = host {
+        eprintln!("Runs on host:");
+        eprintln!("  {}", host);
+        eprintln!();
+    }
+    if let Some(path) = project_tool {
+        eprintln!("Runs project tool:");
+        eprintln!("  {}", path.display());
+        eprintln!();
+    }
+    if let Some(level) = risk {
+        eprintln!("Risk level: {}", level);
+        eprintln!();
+    }
+    if let Some(review) = review {
+        eprintln!("Model review: {} - {}", review.effect, review.reason);
+        eprintln!();
+    }
+    if !shellcheck.is_empty() {
+        eprintln!("ShellCheck:");
+        for finding in shellcheck {
+            eprintln!("  {}", finding);
+        }
+        eprintln!();
+    }
+
+    ask_confirm_choice(reader, details)
+}
+
+/// The question of the confirmation screen, asked again after an inline
+/// explanation or a rejected edit. Unknown answers decline.
+fn ask_confirm_choice(
+    reader: &mut dyn BufRead,
+    details: &ConfirmDetails,
+) -> Result<Option<ConfirmChoice>> {
+    let menu: Vec<&str> = details.menu.iter().map(|c| c.label()).collect();
+    if let Some(expected) = details.type_to_confirm {
+        if menu.is_empty() {
+            eprint!("High-risk command. Type '{}' to execute it: ", expected);
+        } else {
+            eprint!(
+                "High-risk command. Type '{}' to execute it, or [n]o / {}: ",
+                expected,
+                menu.join(" / ")
+            );
+        }
+        io::stdout().flush().ok();
+        let ans = read_answer(reader)?;
+        return Ok(
+            ans.map(|ans| match ConfirmChoice::parse(&ans, details.menu) {
+                _ if ans == expected => ConfirmChoice::Yes,
+                // Only the typed token runs a high-risk command.
+                Some(ConfirmChoice::Yes) | None => ConfirmChoice::No,
+                Some(choice) => choice,
+            }),
+        );
+    }
+
+    if menu.is_empty() {
+        eprint!("Execute this command? [y/N] ");
+    } else {
+        eprint!(
+            "Execute this command? [y]es / [N]o / {}: ",
+            menu.join(" / ")
+        );
+    }
+    io::stdout().flush().ok();
+    let ans = read_answer(reader)?;
+    Ok(ans.map(|ans| ConfirmChoice::parse(&ans, details.menu).unwrap_or(ConfirmChoice::No)))
+}
+
+/// An answer at the confirmation prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfirmChoice {
+    Yes,
+    No,
+    /// Replace the command with one typed by the user, validated again.
+    Edit,
+    /// Ask the model for a different command.
+    Regenerate,
+    /// Explain the command, then ask again.
+    Explain,
+}
+
+impl ConfirmChoice {
+    fn label(self) -> &'static str {
+        match self {
+            ConfirmChoice::Yes => "[y]es",
+            ConfirmChoice::No => "[n]o",
+            ConfirmChoice::Edit => "[e]dit",
+            ConfirmChoice::Regenerate => "[r]egenerate",
+            ConfirmChoice::Explain => "e[x]plain",
+        }
+    }
+
+    /// Accepts the key or the word ("e", "edit"); `menu` holds the choices
+    /// offered besides yes and no.
+    fn parse(answer: &str, menu: &[ConfirmChoice]) -> Option<Self> {
+        let choice = match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => ConfirmChoice::Yes,
+            "n" | "no" => ConfirmChoice::No,
+            "e" | "edit" => ConfirmChoice::Edit,
+            "r" | "regenerate" => ConfirmChoice::Regenerate,
+            "x" | "explain" => ConfirmChoice::Explain,
+            _ => return None,
+        };
+        (matches!(choice, ConfirmChoice::Yes | ConfirmChoice::No) || menu.contains(&choice))
+            .then_some(choice)
+    }
+}
+
+/// Asks for a replacement of `cmd_line`, validated like a generated command.
+/// `None` when Enter kept the command, the replacement was rejected or there
+/// was no answer.
+fn edit_command(
+    reader: &mut dyn BufRead,
+    cmd_line: &str,
+    validate: &dyn Fn(&str) -> Result<Vec<String>>,
+) -> Result<Option<(String, Vec<String>)>> {
+    eprintln!("Current command:\n  {}", cmd_line);
+    eprint!("New command (Enter keeps it): ");
+    io::stderr().flush().ok();
+    let Some(edited) = read_answer(reader)?.filter(|ans| !ans.is_empty() && ans != cmd_line) else {
+        return Ok(None);
+    };
+    match validate(&edited) {
+        Ok(tokens) => Ok(Some((edited, tokens))),
+        Err(err) => {
+            eprintln!("Edited command rejected: {:#}", err);
+            Ok(None)
+        }
+    }
+}
+
+/// Why the user declined a command: recorded in the history notes and told
+/// to the model when they regenerate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeclineReason {
+    WrongTool,
+    WrongPaths,
+    TooRisky,
+    Other,
+}
+
+impl DeclineReason {
+    const ALL: [DeclineReason; 4] = [
+        DeclineReason::WrongTool,
+        DeclineReason::WrongPaths,
+        DeclineReason::TooRisky,
+        DeclineReason::Other,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            DeclineReason::WrongTool => "wrong tool",
+            DeclineReason::WrongPaths => "wrong paths",
+            DeclineReason::TooRisky => "too risky",
+            DeclineReason::Other => "other",
+        }
+    }
+
+    /// Accepts the menu number or the label ("2", "wrong paths", "paths").
+    fn parse(answer: &str) -> Option<Self> {
+        let answer = answer.trim().to_lowercase();
+        if let Ok(n) = answer.parse::<usize>() {
+            return Self::ALL.get(n.checked_sub(1)?).copied();
+        }
+        Self::ALL
+            .into_iter()
+            .find(|r| r.label() == answer || r.label().split(' ').any(|w| w == answer))
+    }
+}
+
+/// Optional follow-up to a declined command. Enter, end of input or an
+/// unknown answer skips it.
+fn ask_decline_reason(reader: &mut dyn BufRead) -> Result<Option<DeclineReason>> {
+    let menu: Vec<String> = DeclineReason::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, r)| format!("{}) {}", i + 1, r.label()))
+        .collect();
+    eprint!("Why not? {} (Enter to skip): ", menu.join("  "));
+    io::stderr().flush().ok();
+    Ok(read_answer(reader)?.and_then(|ans| DeclineReason::parse(&ans)))
+}
+
+/// A declined command, with why and what to change, for the next request.
+struct Rejection {
+    command: String,
+    reason: Option<DeclineReason>,
+    /// What the user typed after declining, e.g. "too broad, only .rs files".
+    correction: Option<String>,
+}
+
+/// What to do after a declined command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AfterDecline {
+    Stop,
+    Regenerate,
+    /// Ask again with this correction.
+    Correct(String),
+}
+
+/// Offers to regenerate or to type a correction. Enter, end of input, "n" or
+/// "q" stops; any other answer is the correction.
+fn ask_after_decline(reader: &mut dyn BufRead, with_reason: bool) -> Result<AfterDecline> {
+    if with_reason {
+        eprint!("[r]egenerate with this feedback, type a correction, or Enter to stop: ");
+    } else {
+        eprint!("[r]egenerate, type a correction (e.g. \"only .rs files\"), or Enter to stop: ");
+    }
+    io::stderr().flush().ok();
+    let ans = read_answer(reader)?.unwrap_or_default();
+    Ok(match ans.to_lowercase().as_str() {
+        "" | "n" | "no" | "q" | "quit" => AfterDecline::Stop,
+        "r" | "regenerate" => AfterDecline::Regenerate,
+        _ => AfterDecline::Correct(ans),
+    })
+}
+
+/// Reports a Ctrl-C or SIGTERM that stopped the command in `summary`.
+fn stopped(mut summary: RunSummary) -> RunSummary {
+    let stop = interrupt::stop_signal();
+    eprintln!("{}", stop.message());
+    summary.exit_code = stop.exit_code();
+    summary.notes = join_notes(summary.notes.take(), Some(stop.note().to_string()));
+    summary
+}
+
+/// Bytes of a failed command's error output sent with an --auto-fix request.
+const AUTO_FIX_OUTPUT_BYTES: usize = 2048;
+
+/// The --auto-fix request: the user's request plus every command that failed
+/// so far, with its exit code and the end of its error output, and the
+/// `--help` text of tools that rejected a flag.
+fn with_failures(
+    nl_prompt: &str,
+    failures: &[(String, CapturedOutput)],
+    flag_docs: &[(String, String)],
+) -> String {
+    let mut prompt = format!(
+        "{}\n\nThese commands were run for this request and failed; suggest a corrected command:",
+        nl_prompt
+    );
+    for (cmd, output) in failures {
+        let tail = CommandOutput::keep_tail(&[], &output.stderr, AUTO_FIX_OUTPUT_BYTES);
+        let stderr = tail.stderr.trim_end();
+        prompt.push_str(&format!(
+            "\n- {} (exit code {})\n  Error output:\n{}",
+            cmd,
+            output.exit_code,
+            if stderr.is_empty() { "(none)" } else { stderr }
+        ));
+    }
+    for (tool, help) in flag_docs {
+        prompt.push_str(&format!(
+            "\n\n{} rejected a flag. Its --help output, for choosing valid options:\n{}",
+            tool, help
+        ));
+    }
+    prompt
+}
+
+/// The prompt for the next attempt: the user's request plus the commands
+/// they declined so far, why, and their corrections.
+fn with_rejections(nl_prompt: &str, rejected: &[Rejection]) -> String {
+    if rejected.is_empty() {
+        return nl_prompt.to_string();
+    }
+    let mut prompt = format!(
+        "{}\n\nThe user declined these commands; suggest a different one that avoids the problem:",
+        nl_prompt
+    );
+    for rejection in rejected {
+        match rejection.reason {
+            Some(reason) => prompt.push_str(&format!(
+                "\n- {} (reason: {})",
+                rejection.command,
+                reason.label()
+            )),
+            None => prompt.push_str(&format!("\n- {}", rejection.command)),
+        }
+        if let Some(correction) = &rejection.correction {
+            prompt.push_str(&format!("\n  The user's correction: {}", correction));
+        }
+    }
+    prompt
+}
+
+/// Warns that the expanded arguments are too long for one run and asks
+/// whether to run the command in batches instead.
+fn confirm_batches(reader: &mut dyn BufRead, size: &ArgumentSize) -> Result<bool> {
+    eprintln!(
+        "Warning: after glob expansion the arguments take {}, more than the system allows ({}).",
+        humanize::bytes(size.bytes as u64),
+        humanize::bytes(size.limit as u64)
+    );
+    eprint!(
+        "Run the command in {} batches instead (stopping at the first failure)? [y/N] ",
+        size.batches
+    );
+    io::stderr().flush().ok();
+    let ans = read_answer(reader)?.unwrap_or_default().to_lowercase();
+    Ok(ans == "y" || ans == "yes")
+}
+
+/// Explains why the prompt was flagged and asks the user to type the
+/// acknowledgment word. Anything else, end of input, or Ctrl-C declines.
+fn acknowledge_flagged_prompt(reader: &mut dyn BufRead, matches: &[GuardMatch]) -> Result<bool> {
+    eprintln!("!! This prompt matches patterns your organization flags for review:");
+    for m in matches {
+        eprintln!(
+            "!!   {} (matched '{}')",
+            m.category.replace('_', " "),
+            m.term
+        );
+    }
+    eprintln!("!! Use sai only for legitimate, authorized work. The run is logged.");
+    eprint!(
+        "Type '{}' to send this prompt to the LLM: ",
+        guard::ACKNOWLEDGMENT
+    );
+    io::stderr().flush().ok();
+    let answer = read_answer(reader)?.unwrap_or_default();
+    Ok(answer.eq_ignore_ascii_case(guard::ACKNOWLEDGMENT))
+}
+
+/// Reads one trimmed line of input. Returns `None` on end of input or when
+/// Ctrl-C interrupted the read, which callers treat as declining.
+fn read_answer(reader: &mut dyn BufRead) -> Result<Option<String>> {
+    let mut buf = String::new();
+    match reader.read_line(&mut buf) {
+        Ok(0) => Ok(None),
+        Ok(_) => Ok(Some(buf.trim().to_string())),
+        Err(err) => {
+            let err = anyhow::Error::from(err);
+            if interrupt::is_interrupt_error(&err) {
+                eprintln!();
+                return Ok(None);
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Validates every candidate, lists the ones that pass as a numbered menu and
+/// lets the user pick one. Returns `None` when the user cancels.
+fn choose_candidate(
+    reader: &mut dyn BufRead,
+    candidates: &[String],
+    validate: &dyn Fn(&str) -> Result<Vec<String>>,
+) -> Result<Option<(String, Vec<String>)>> {
+    let mut valid = Vec::new();
+    eprintln!("Candidate commands:");
+    for cmd in candidates {
+        match validate(cmd) {
+            Ok(tokens) => {
+                valid.push((cmd.clone(), tokens));
+                eprintln!("  [{}] {}", valid.len(), cmd);
+            }
+            Err(err) => eprintln!("  [-] {}  (rejected: {})", cmd, err),
+        }
+    }
+    eprintln!();
+
+    if valid.is_empty() {
+        return Err(anyhow!(
+            "None of the {} candidate commands passed validation",
+            candidates.len()
+        ));
+    }
+
+    loop {
+        eprint!(
+            "Select a command [1-{}], or press Enter to cancel: ",
+            valid.len()
+        );
+        let Some(answer) = read_answer(reader)? else {
+            return Ok(None);
+        };
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        match answer.parse::<usize>() {
+            Ok(n) if (1..=valid.len()).contains(&n) => {
+                let choice = valid.swap_remove(n - 1);
+                eprintln!(">> {}", choice.0);
+                return Ok(Some(choice));
+            }
+            _ => eprintln!("Please enter a number between 1 and {}.", valid.len()),
+        }
+    }
+}
+
+/// Resolves AI settings and applies the per-run --model/--temperature/--seed overrides.
+fn resolve_ai_for_cli(
+    global_ai: Option<crate::config::AiConfig>,
+    cli: &Cli,
+) -> Result<crate::config::EffectiveAiConfig> {
+    let mut ai = resolve_ai_config(global_ai)?;
+    if let Some(model) = cli.model.as_deref() {
+        ai.set_model(model);
+    }
+    if cli.temperature.is_some() {
+        ai.temperature = cli.temperature;
+    }
+    if cli.seed.is_some() {
+        ai.seed = cli.seed;
+    }
+    Ok(ai)
+}
+
+/// True when the risk classification or the model's review says the command
+/// changes something, which is when `--backup` copies its files.
+fn is_modifying(risk: Option<RiskLevel>, review: Option<&LlmReview>) -> bool {
+    risk.is_some_and(|risk| risk >= RiskLevel::Medium)
+        || review.is_some_and(|r| r.effect != CommandEffect::ReadOnly)
+}
+
+/// Checks the per-tool `cwd_must_match:` and `chdir:` of the command and
+/// returns the directory it runs in. With --host the command runs in the
+/// remote login directory instead.
+fn command_dir(
+    tools: &[crate::config::ToolConfig],
+    cmd_line: &str,
+    shell: bool,
+    cwd: &Path,
+    remote: bool,
+) -> Result<Option<PathBuf>> {
+    let invoked = envinfo::invoked_tools(cmd_line, shell);
+    if remote {
+        let ruled = tools.iter().any(|t| {
+            (t.cwd_must_match.is_some() || t.chdir.is_some())
+                && invoked.iter().any(|word| t.matches(word))
+        });
+        if ruled {
+            eprintln!(
+                "Warning: tool `cwd_must_match:` and `chdir:` are not applied to \
+                 commands run with --host."
+            );
+        }
+        return Ok(None);
+    }
+    let dir = workdir::run_dir(tools, &invoked, cwd)?;
+    if let Some(dir) = &dir {
+        eprintln!("Note: runs in {} (chdir)", dir.display());
+    }
+    Ok(dir)
+}
+
+/// --transaction: stages the files the command names in a workspace in
+/// `cwd` and checks that its paths stay inside it.
+fn begin_transaction(
+    cmd_line: &str,
+    tokens: &[String],
+    shell: bool,
+    dir: &Path,
+    cwd: &Path,
+) -> Result<Transaction> {
+    let txn = Transaction::begin(cwd, &backup::referenced_paths(tokens, dir))?;
+    txn.check(cmd_line, tokens, shell, dir)?;
+    eprintln!(
+        "Note: runs in the transaction workspace {}",
+        txn.workspace().display()
+    );
+    Ok(txn)
+}
+
+/// Commits the transaction when the command succeeded and rolls it back
+/// otherwise. Returns the history note, with the staged -> final mapping.
+fn finish_transaction(txn: Transaction, exit_code: i32) -> Result<String> {
+    if exit_code != 0 {
+        drop(txn);
+        eprintln!("--transaction: the command failed; nothing it wrote was kept.");
+        return Ok("transaction rolled back".to_string());
+    }
+    let moves: Vec<String> = txn
+        .commit()?
+        .iter()
+        .map(|(staged, target)| format!("{} -> {}", staged.display(), target.display()))
+        .collect();
+    if moves.is_empty() {
+        eprintln!("--transaction: the command wrote no files.");
+        return Ok("transaction: no files written".to_string());
+    }
+    eprintln!(
+        "--transaction: moved {} into place:",
+        humanize::plural(moves.len() as u64, "file", "files")
+    );
+    for line in &moves {
+        eprintln!("  {}", line);
+    }
+    Ok(format!("transaction: {}", moves.join(", ")))
+}
+
+/// --backup: copies the existing files the command names, relative to
+/// `dir`, and returns the history note naming the backup.
+fn back_up(tokens: &[String], dir: &Path) -> Result<Option<String>> {
+    let paths = backup::referenced_paths(tokens, dir);
+    match backup::create_backup(&paths, &backup::backups_dir())? {
+        Some(backup) => {
+            eprintln!(
+                "Backed up {} path(s) to {}. To restore:",
+                backup.entries.len(),
+                backup.dir.display()
+            );
+            for command in backup.restore_commands() {
+                eprintln!("  {}", command);
+            }
+            Ok(Some(format!("backup {}", backup.dir.display())))
+        }
+        None => {
+            eprintln!("--backup: the command names no existing files to back up.");
+            Ok(None)
+        }
+    }
+}
+
+/// Tool allowlist and operator checks, plus the template of the tool the
+/// command uses, if it has one.
+pub(crate) fn validate_command(
+    cmd_line: &str,
+    allowed_tools: &[String],
+    prompt_cfg: &PromptConfig,
+    jail: Option<&PathJail>,
+    network: Option<&NetworkGuard>,
+    unsafe_mode: bool,
+    read_only: bool,
+) -> Result<Vec<String>> {
+    let tokens = validate_and_split_command(cmd_line, allowed_tools, unsafe_mode, prompt_cfg)?;
+    if read_only {
+        check_read_only(cmd_line, &prompt_cfg.tools)?;
+    }
+    let shell = runs_in_shell(cmd_line, unsafe_mode);
+    template::check_command(&prompt_cfg.tools, cmd_line, &tokens, shell)?;
+    if let Some(jail) = jail {
+        jail.check(cmd_line, &tokens, shell)?;
+    }
+    if let Some(network) = network {
+        network.check(cmd_line, &prompt_cfg.tools)?;
+    }
+    Ok(tokens)
+}
+
+/// `--read-only`, or SAI_READ_ONLY set to 1/true/yes.
+pub(crate) fn read_only_mode(cli_read_only: bool) -> bool {
+    cli_read_only
+        || env::var("SAI_READ_ONLY")
+            .is_ok_and(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+}
+
+/// Narrows the prompt's tools to those the organization policy pins and, in
+/// read-only mode, to read-only ones. Returns the tools the policy dropped.
+pub(crate) fn restrict_tools(
+    prompt_cfg: &mut PromptConfig,
+    org_policy: &OrgPolicy,
+    extra_tools: &[String],
+    read_only: bool,
+    unsafe_mode: bool,
+) -> Result<Vec<String>> {
+    let dropped = org_policy.pin_tools(prompt_cfg, extra_tools)?;
+    if read_only {
+        if unsafe_mode {
+            return Err(anyhow!(
+                "--unsafe cannot be used in read-only mode (SAI_READ_ONLY)"
+            ));
+        }
+        restrict_to_read_only_tools(prompt_cfg)?;
+    }
+    Ok(dropped)
+}
+
+/// Drops the tools read-only mode does not allow, so the model is only
+/// offered read-only ones.
+fn restrict_to_read_only_tools(prompt_cfg: &mut PromptConfig) -> Result<()> {
+    let tools = prompt_cfg.tools.clone();
+    prompt_cfg
+        .tools
+        .retain(|tool| is_read_only_tool(&tools, &tool.name));
+    if prompt_cfg.tools.is_empty() {
+        return Err(anyhow!(
+            "Read-only mode: no tool of the prompt config is read-only \
+             (set `read_only: true` on the tools that are)"
+        ));
+    }
+    Ok(())
+}
+
+/// Resolves the `race` provider for --race; --temperature and --seed apply to
+/// it as well, --model only to the `ai` provider.
+fn resolve_race_for_cli(
+    global_cfg: &crate::config::GlobalConfig,
+    cli: &Cli,
+) -> Result<crate::config::EffectiveAiConfig> {
+    let race = global_cfg.race.clone().ok_or_else(|| {
+        anyhow!("--race needs a second provider in the `race:` section of the global config")
+    })?;
+    let mut ai = resolve_race_config(global_cfg.ai.clone(), race)
+        .context("Invalid `race:` provider configuration")?;
+    if cli.temperature.is_some() {
+        ai.temperature = cli.temperature;
+    }
+    if cli.seed.is_some() {
+        ai.seed = cli.seed;
+    }
+    Ok(ai)
+}
+
+/// Adds the --allow-tool tools to the prompt config for this run only.
+fn add_temporary_tools(
+    prompt_cfg: &mut crate::config::PromptConfig,
+    names: &[String],
+) -> Result<()> {
+    for name in names {
+        if name.is_empty() || name.chars().any(|c| c.is_whitespace() || c == '/') {
+            return Err(anyhow!(
+                "--allow-tool expects a plain command name, got '{}'",
+                name
+            ));
+        }
+        if prompt_cfg.tools.iter().any(|t| &t.name == name) {
+            continue;
+        }
+        prompt_cfg.tools.push(crate::config::ToolConfig {
+            name: name.clone(),
+            force_explain: None,
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
+            config: ops::default_tool_config(name),
+        });
+    }
+    Ok(())
+}
+
+pub(crate) const EXPLAIN_SYSTEM_PROMPT: &str = "You are a shell and tool usage explainer. \
+Given a shell command, explain in concise technical language what it will do, \
+describing each flag and argument, and the overall effect. \
+Do not invent behaviour not implied by the command.";
+
+pub(crate) fn explain_request(cmd_line: &str) -> String {
+    format!(
+        "Explain this command in detail, but concisely:\n\n{}",
+        cmd_line
+    )
+}
+
+/// Generation for --tui, with the prompt config, checks and model of the run.
+struct TuiSession<'a, G> {
+    generator: &'a G,
+    ai: &'a crate::config::EffectiveAiConfig,
+    system_prompt: &'a str,
+    scope_hint: Option<&'a str>,
+    peek_context: Option<&'a str>,
+    guard: Option<&'a crate::guard::PromptGuardConfig>,
+    validate: &'a dyn Fn(&str) -> Result<Vec<String>>,
+}
+
+impl<G> TuiBackend for TuiSession<'_, G>
+where
+    G: CommandGenerator + ChatClient,
+{
+    fn generate(&mut self, prompt: &str) -> Result<String> {
+        // Flagged prompts need the acknowledgement the TUI cannot ask for.
+        let flagged = self
+            .guard
+            .map(|cfg| guard::check_prompt(cfg, prompt))
+            .unwrap_or_default();
+        if !flagged.is_empty() {
+            let categories: Vec<&str> = flagged.iter().map(|m| m.category.as_str()).collect();
+            return Err(anyhow!(
+                "prompt_guard flagged this prompt ({}); run it outside --tui to confirm it",
+                categories.join(", ")
+            ));
+        }
+        let cmd_line = self
+            .generator
+            .generate(
+                self.ai,
+                self.system_prompt,
+                prompt,
+                self.scope_hint,
+                self.peek_context,
+            )
+            .context("Failed to obtain command from LLM")?;
+        (self.validate)(&cmd_line)
+            .with_context(|| format!("Generated command rejected: {}", cmd_line))?;
+        Ok(cmd_line)
+    }
+
+    fn validate(&mut self, command: &str) -> Result<()> {
+        (self.validate)(command).map(drop)
+    }
+
+    fn explain(&mut self, command: &str) -> Result<String> {
+        self.generator.respond(
+            self.ai,
+            EXPLAIN_SYSTEM_PROMPT,
+            &explain_request(command),
+            0.0,
+        )
+    }
+}
+
+/// Prints the model's explanation of the command and returns it.
+fn print_command_explanation<G>(
+    generator: &G,
+    ai: &crate::config::EffectiveAiConfig,
+    cmd_line: &str,
+) -> Result<Option<String>>
+where
+    G: ChatClient,
+{
+    println!("Generated command:\n  {}\n", cmd_line);
+    println!("Explanation:");
+    let user_prompt = explain_request(cmd_line);
+    match generator.respond_streaming(
+        ai,
+        EXPLAIN_SYSTEM_PROMPT,
+        &user_prompt,
+        0.0,
+        &mut print_token,
+    ) {
+        Ok(text) => {
+            println!();
+            Ok(Some(text))
+        }
+        Err(err) => {
+            eprintln!("Failed to explain command: {:#}", err);
+            Ok(None)
+        }
+    }
+}
+
+/// ShellCheck findings for a command; with `shellcheck.blocking` any finding
+/// rejects it. A shellcheck that fails to run only warns.
+fn shellcheck_findings(cfg: &ShellcheckConfig, cmd_line: &str) -> Result<Vec<Finding>> {
+    let findings = match shellcheck::check_command(cfg, cmd_line) {
+        Ok(findings) => findings.unwrap_or_default(),
+        Err(err) => {
+            eprintln!("Warning: {:#}", err);
+            Vec::new()
+        }
+    };
+    if cfg.blocking && !findings.is_empty() {
+        return Err(shellcheck::blocking_error(&findings));
+    }
+    Ok(findings)
+}
+
+/// Second model pass for --review. A failed or unreadable review only warns:
+/// the regular confirmation rules still apply.
+fn review_command<G>(
+    generator: &G,
+    ai: &crate::config::EffectiveAiConfig,
+    cmd_line: &str,
+) -> Option<LlmReview>
+where
+    G: ChatClient,
+{
+    let spinner = Spinner::start("Reviewing command");
+    let reply = generator.respond(
+        ai,
+        risk::REVIEW_SYSTEM_PROMPT,
+        &format!("Command:\n{}", cmd_line),
+        0.0,
+    );
+    spinner.stop();
+    match reply {
+        Ok(text) => {
+            let review = risk::parse_review(&text);
+            if review.is_none() {
+                eprintln!(
+                    "Warning: could not read the model's review: {}",
+                    text.trim()
+                );
+            }
+            review
+        }
+        Err(err) => {
+            eprintln!("Warning: command review failed: {:#}", err);
+            None
+        }
+    }
+}
+
+fn print_token(token: &str) {
+    print!("{}", token);
+    io::stdout().flush().ok();
+}
+
+/// The prompt config for a run: the first config on the command line, else
+/// the project's .sai/prompt.yaml, else the global default_prompt. Further
+/// configs and `--with` files are merged in for this run. Also returns where
+/// the config came from and the merged paths, for the confirmation details.
+fn load_run_prompt_config(
+    global_cfg: &crate::config::GlobalConfig,
+    cli: &Cli,
+    config_args: &[String],
+    cwd: &Path,
+) -> Result<(Option<PathBuf>, Vec<PathBuf>, PromptConfig)> {
+    let mut config_paths: Vec<PathBuf> = config_args.iter().map(PathBuf::from).collect();
+    let prompt_source = (!config_paths.is_empty())
+        .then(|| config_paths.remove(0))
+        .or_else(|| projectcfg::project_prompt(cwd));
+    config_paths.extend(cli.with.iter().map(PathBuf::from));
+
+    let prompt_cfg = match &prompt_source {
+        Some(cfg_path) => {
+            let cfg = load_prompt_config(cfg_path)?;
+            if config_args.is_empty() {
+                projectcfg::check_project_prompt(&cfg, cfg_path)?;
+                eprintln!("Using project prompt {}", cfg_path.display());
+            }
+            cfg
+        }
+        None => global_cfg
+            .default_prompt
+            .clone()
+            .ok_or_else(|| anyhow!("No default_prompt found in global config for simple mode"))?,
+    };
+    let prompt_cfg = if config_paths.is_empty() {
+        prompt_cfg
+    } else {
+        ops::merge_prompt_configs(prompt_cfg, &config_paths)?
+    };
+    Ok((prompt_source, config_paths, prompt_cfg))
+}
+
+/// --estimate: prompt and output tokens, and cost where the budget has a
+/// price for the model, of every prompt in a batch file. Nothing is sent.
+fn run_estimate(
+    global_cfg: &crate::config::GlobalConfig,
+    cli: &Cli,
+    batch: &Path,
+    cwd: &Path,
+) -> Result<RunSummary> {
+    let content = fs::read_to_string(batch)
+        .with_context(|| format!("Failed to read batch file {}", batch.display()))?;
+    let prompts = batch_prompts(&content);
+    if prompts.is_empty() {
+        return Err(anyhow!("Batch file {} has no prompts", batch.display()));
+    }
+
+    let (_, _, mut prompt_cfg) = load_run_prompt_config(global_cfg, cli, &cli.positional(), cwd)?;
+    add_temporary_tools(&mut prompt_cfg, &cli.allow_tool)?;
+    let (system_prompt, _) = build_system_prompt(&prompt_cfg)?;
+    let peek_context =
+        with_diff_context(build_peek_context(&cli.peek, &cli.fields)?, &cli.peek_diff)?;
+    let ai = resolve_ai_for_cli(global_cfg.ai.clone(), cli)?;
+
+    let mut rows = Vec::new();
+    for prompt in prompts {
+        rows.push(EstimateRow {
+            prompt_tokens: llm::estimate_generate_tokens(
+                &system_prompt,
+                prompt,
+                cli.scope.as_deref(),
+                peek_context.as_deref(),
+            )?,
+            completion_tokens: llm::ESTIMATED_COMMAND_TOKENS,
+            prompt: prompt.to_string(),
+        });
+    }
+    print!(
+        "{}",
+        budget::render_estimate(
+            &rows,
+            ai.model_name(),
+            &global_cfg.budget.clone().unwrap_or_default()
+        )
+    );
+
+    let mut summary = RunSummary::from_cli(cli);
+    summary.notes = Some(format!("estimate ({} prompts)", rows.len()));
+    Ok(summary)
+}
+
+/// Prompts of a batch file: one per line; blank lines and `#` comments are skipped.
+fn batch_prompts(content: &str) -> Vec<&str> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+/// The prompt config an analyzed run used, rebuilt from its argv and working
+/// directory. `None` when that is no longer possible, e.g. the file is gone.
+fn analyzed_prompt_config(
+    global_cfg: &crate::config::GlobalConfig,
+    entry: &HistoryEntry,
+) -> Option<PromptConfig> {
+    let mut cli = Cli::try_parse_from(&entry.argv).ok()?;
+    let cwd = Path::new(&entry.cwd);
+    let (config_args, _) = cli.prompt_and_configs();
+    let config_args: Vec<String> = config_args
+        .iter()
+        .map(|arg| cwd.join(arg).display().to_string())
+        .collect();
+    for path in &mut cli.with {
+        *path = cwd.join(&*path).display().to_string();
+    }
+    let (_, _, mut prompt_cfg) =
+        load_run_prompt_config(global_cfg, &cli, &config_args, cwd).ok()?;
+    add_temporary_tools(&mut prompt_cfg, &entry.extra_tools).ok()?;
+    if cli.read_only {
+        restrict_to_read_only_tools(&mut prompt_cfg).ok()?;
+    }
+    Some(prompt_cfg)
+}
+
+/// The parts of a prompt config that explain failures caused by the
+/// configuration: the meta prompt, the tools and the allowed operators.
+fn describe_prompt_config(prompt_cfg: PromptConfig) -> String {
+    let tools: Vec<&str> = prompt_cfg.tools.iter().map(|t| t.name.as_str()).collect();
+    let mut text = format!(
+        "The prompt config active for that invocation:\nAllowed tools: {}\n",
+        tools.join(", ")
+    );
+    if !prompt_cfg.allowed_operators.is_empty() {
+        text.push_str(&format!(
+            "Operators allowed without --unsafe: {}\n",
+            prompt_cfg.allowed_operators.join(" ")
+        ));
+    }
+    match prompt_cfg.meta_prompt.as_deref().map(str::trim) {
+        Some(meta) if !meta.is_empty() => text.push_str(&format!("Meta prompt:\n{}", meta)),
+        _ => text.push_str("Meta prompt: (none)"),
+    }
+    text
+}
+
+const ANALYZE_SYSTEM_PROMPT: &str = "You are a debugging assistant for the SAI CLI. You receive structured information about one SAI invocation (command line, generated shell command, exit code, etc.). Explain in concise technical terms what likely happened and why, and suggest what the user might try next. When an `output` field is present it holds the end of the command's stdout and stderr. When the prompt config of the invocation is given, say so if the failure comes from the configuration rather than the command itself: a tool the task needs is not in the allowed tools, or the meta prompt is too strict for the request. If information is missing, state the limitations.";
+
+const ANALYZE_WINDOW_SYSTEM_PROMPT: &str = "You are a debugging assistant for the SAI CLI. You receive structured information about the most recent SAI invocations, oldest first (command line, generated shell command, exit code, etc.). Look for patterns across them rather than explaining each one: repeated failures of the same tool or with the same error, a request rephrased until a command worked, commands that were declined or cancelled. Explain in concise technical terms what they likely have in common and why, and suggest what the user might change, in the prompt, the prompt config or the flags. When an `output` field is present it holds the end of the command's stdout and stderr. If information is missing, state the limitations.";
+
+/// The request for `--analyze-last`: the entries, oldest first, as a JSON array.
+fn analyze_window_prompt(entries: &[HistoryEntry]) -> Result<String> {
+    Ok(format!(
+        "Here are the last {} SAI invocations, oldest first, as a JSON array:\n\n{}\n\n\
+         Please point out patterns across them and explain what likely happened and why.",
+        entries.len(),
+        serde_json::to_string_pretty(entries)?
+    ))
+}
+
+fn run_analyze<G>(
+    global_cfg: &crate::config::GlobalConfig,
+    cli: &Cli,
+    generator: &G,
+) -> Result<RunSummary>
+where
+    G: ChatClient,
+{
+    let mut summary = RunSummary::analyze_mode();
     summary.notes = Some("analyze mode".to_string());
 
-    let latest = history::read_latest_entry()?;
-    let Some(entry) = latest else {
-        println!("No history available to analyze yet.");
-        summary.exit_code = 2;
-        return Ok(summary);
-    };
+    let selector = cli.analyze.clone().unwrap_or(EntrySelector::Index(1));
+    let entries: Vec<HistoryEntry> = match cli.analyze_last {
+        Some(count) => {
+            let mut entries = history::read_all_entries()?;
+            entries.drain(..entries.len().saturating_sub(count as usize));
+            summary.notes = Some(format!("analyze mode (last {})", count));
+            entries
+        }
+        None => history::read_selected_entry(&selector)?
+            .into_iter()
+            .collect(),
+    };
+    let Some(entry) = entries.last() else {
+        match &selector {
+            EntrySelector::Index(1) => println!("No history available to analyze yet."),
+            EntrySelector::Index(index) => println!("No run #{} in history to analyze.", index),
+            EntrySelector::Time(ts) => println!("No run from {} in history to analyze.", ts),
+        }
+        summary.exit_code = 2;
+        return Ok(summary);
+    };
+
+    let (system_prompt, user_prompt) = if cli.analyze_last.is_some() {
+        (
+            ANALYZE_WINDOW_SYSTEM_PROMPT,
+            analyze_window_prompt(&entries)?,
+        )
+    } else {
+        let entry_json = serde_json::to_string_pretty(entry)?;
+        let which = match selector {
+            EntrySelector::Index(1) => "the last",
+            _ => "an earlier",
+        };
+        let mut user_prompt = format!(
+            "Here is {} SAI invocation as a JSON object:\n\n{}\n\n",
+            which, entry_json
+        );
+        if let Some(context) = analyzed_prompt_config(global_cfg, entry).map(describe_prompt_config)
+        {
+            user_prompt.push_str(&context);
+            user_prompt.push_str("\n\n");
+        }
+        user_prompt.push_str("Please explain what likely happened and why.");
+        (ANALYZE_SYSTEM_PROMPT, user_prompt)
+    };
+
+    let effective_ai = resolve_ai_for_cli(global_cfg.ai.clone(), cli)?;
+    generator.respond_streaming(
+        &effective_ai,
+        system_prompt,
+        &user_prompt,
+        0.0,
+        &mut print_token,
+    )?;
+
+    println!();
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use crate::config::set_config_dir_override_for_tests;
+    use crate::llm::{ChatClient, CommandGenerator};
+    use std::cell::{Cell, RefCell};
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    struct StubGenerator {
+        command: String,
+        response: String,
+    }
+
+    impl StubGenerator {
+        fn new(command: &str, response: &str) -> Self {
+            Self {
+                command: command.to_string(),
+                response: response.to_string(),
+            }
+        }
+    }
+
+    impl CommandGenerator for StubGenerator {
+        fn generate(
+            &self,
+            _ai: &crate::config::EffectiveAiConfig,
+            _system_prompt: &str,
+            _nl_prompt: &str,
+            _scope_hint: Option<&str>,
+            _peek_text: Option<&str>,
+        ) -> Result<String> {
+            Ok(self.command.clone())
+        }
+    }
+
+    impl ChatClient for StubGenerator {
+        fn respond(
+            &self,
+            _ai: &crate::config::EffectiveAiConfig,
+            _system_prompt: &str,
+            _user_prompt: &str,
+            _temperature: f32,
+        ) -> Result<String> {
+            Ok(self.response.clone())
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingExecutor {
+        ran: Cell<bool>,
+    }
+
+    impl RecordingExecutor {
+        fn ran(&self) -> bool {
+            self.ran.get()
+        }
+    }
+
+    impl CommandExecutor for RecordingExecutor {
+        fn execute(&self, _cmd_line: &str, _tokens: &[String], _unsafe_mode: bool) -> Result<i32> {
+            self.ran.set(true);
+            Ok(0)
+        }
+
+        fn execute_captured(
+            &self,
+            _cmd_line: &str,
+            _tokens: &[String],
+            _unsafe_mode: bool,
+            _opts: &ExecOptions,
+        ) -> Result<CapturedOutput> {
+            self.ran.set(true);
+            Ok(CapturedOutput {
+                exit_code: 1,
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            })
+        }
+    }
+
+    fn write_minimal_config(dir: &Path) {
+        fs::create_dir_all(dir).unwrap();
+        let cfg = r#"
+ai:
+  provider: openai
+  openai_api_key: test-key
+  openai_model: test-model
+default_prompt:
+  tools:
+    - name: echo
+      config: "echo tool"
+"#;
+        fs::write(dir.join("config.yaml"), cfg).unwrap();
+    }
+
+    #[test]
+    fn analyze_without_history_returns_message() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+
+        let cli = Cli::parse_from(["sai", "--analyze"]);
+
+        let generator = StubGenerator::new("echo hi", "analysis");
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(Vec::<u8>::new());
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+
+        assert_eq!(summary.exit_code, 2);
+        assert!(!summary.confirm);
+        assert!(!executor.ran());
+    }
+
+    #[test]
+    fn analyze_describes_the_prompt_config_of_the_analyzed_run() {
+        let temp = TempDir::new().unwrap();
+        let work = temp.path().join("work");
+        fs::create_dir_all(&work).unwrap();
+        fs::write(
+            work.join("p.yaml"),
+            "meta_prompt: Only use ls.\ntools:\n  - name: ls\n    config: ls\n",
+        )
+        .unwrap();
+        let entry: HistoryEntry = serde_json::from_value(serde_json::json!({
+            "ts": "2026-01-01T00:00:00Z",
+            "cwd": work.display().to_string(),
+            "argv": ["sai", "p.yaml", "--allow-tool", "jq", "count json keys"],
+            "exit_code": 1,
+            "generated_command": null,
+            "unsafe_mode": false,
+            "confirm": false,
+            "explain": false,
+            "scope": null,
+            "peek_files": [],
+            "notes": null,
+            "extra_tools": ["jq"],
+        }))
+        .unwrap();
+        let global = crate::config::GlobalConfig::default();
+        let prompt_cfg = analyzed_prompt_config(&global, &entry).unwrap();
+        assert_eq!(
+            describe_prompt_config(prompt_cfg),
+            "The prompt config active for that invocation:\nAllowed tools: ls, jq\n\
+             Meta prompt:\nOnly use ls."
+        );
+
+        fs::remove_file(work.join("p.yaml")).unwrap();
+        assert!(analyzed_prompt_config(&global, &entry).is_none());
+    }
+
+    #[test]
+    fn analyze_last_sends_the_window_oldest_first() {
+        let entry = |ts: &str, exit_code: i32| HistoryEntry {
+            ts: ts.to_string(),
+            cwd: "/srv/app".to_string(),
+            argv: vec!["sai".to_string(), "run the tests".to_string()],
+            exit_code,
+            generated_command: Some("cargo test".to_string()),
+            ..Default::default()
+        };
+        let entries = [
+            entry("2026-01-01T00:00:00Z", 101),
+            entry("2026-01-01T00:01:00Z", 101),
+        ];
+        let prompt = analyze_window_prompt(&entries).unwrap();
+        assert!(prompt.starts_with("Here are the last 2 SAI invocations, oldest first"));
+        let json = &prompt[prompt.find('[').unwrap()..=prompt.rfind(']').unwrap()];
+        let sent: Vec<HistoryEntry> = serde_json::from_str(json).unwrap();
+        assert_eq!(sent, entries);
+
+        let cli = Cli::parse_from(["sai", "--analyze-last", "5"]);
+        assert_eq!(cli.analyze_last, Some(5));
+        assert!(Cli::try_parse_from(["sai", "--analyze-last", "5", "--confirm"]).is_err());
+        assert!(Cli::try_parse_from(["sai", "--analyze", "--analyze-last", "5"]).is_err());
+    }
+
+    #[test]
+    fn with_docs_rejects_unconfigured_tool() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+
+        let cli = Cli::parse_from(["sai", "--with-docs", "rm", "say hi"]);
+
+        let generator = StubGenerator::new("echo hello", "");
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(Vec::<u8>::new());
+        let err = run_with_reader(cli, &generator, &executor, &mut reader).unwrap_err();
+
+        assert!(err.to_string().contains("not a tool in the active prompt"));
+        assert!(!executor.ran());
+    }
+
+    #[test]
+    fn seed_is_recorded_in_history() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+
+        let cli = Cli::parse_from(["sai", "--seed", "7", "say hi"]);
+        let generator = StubGenerator::new("echo hello", "");
+        let executor = RecordingExecutor::default();
+        run_and_log(cli, &generator, &executor);
+
+        let entry = history::read_latest_entry().unwrap().unwrap();
+        assert_eq!(entry.generated_command.as_deref(), Some("echo hello"));
+        assert_eq!(entry.seed, Some(7));
+        assert!(history::format_entry(&entry).contains("Seed"));
+    }
+
+    #[test]
+    fn explain_forces_confirmation_and_allows_cancel() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+
+        let cli = Cli::parse_from(["sai", "--explain", "say hi"]);
+
+        let generator = StubGenerator::new("echo hello", "will echo hello");
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(b"n\n".to_vec());
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+
+        assert_eq!(summary.exit_code, 0);
+        assert_eq!(summary.notes.as_deref(), Some("cancelled"));
+        assert!(summary.confirm);
+        assert!(!executor.ran());
+    }
+
+    #[test]
+    fn confirmation_menu_edits_and_explains_the_command() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+
+        let cli = Cli::parse_from(["sai", "--confirm", "say hi"]);
+        let generator = StubGenerator::new("echo hello", "prints its arguments");
+        let executor = RecordingExecutor::default();
+        // A disallowed edit is rejected and the menu asked again.
+        let mut reader = Cursor::new(b"e\nls -la\ne\necho edited\nx\ny\n".to_vec());
+        let summary = run_with_reader(cli.clone(), &generator, &executor, &mut reader).unwrap();
+        assert_eq!(summary.generated_command.as_deref(), Some("echo edited"));
+        assert_eq!(summary.notes.as_deref(), Some("edited"));
+        assert_eq!(summary.explanation.as_deref(), Some("prints its arguments"));
+        assert!(executor.ran());
+
+        let menu = [ConfirmChoice::Edit, ConfirmChoice::Explain];
+        assert_eq!(
+            ConfirmChoice::parse("Edit", &menu),
+            Some(ConfirmChoice::Edit)
+        );
+        assert_eq!(ConfirmChoice::parse("r", &menu), None);
+        assert_eq!(ConfirmChoice::parse("r", &[]), None);
+        assert_eq!(ConfirmChoice::parse("yes", &[]), Some(ConfirmChoice::Yes));
+    }
+
+    #[test]
+    fn destructive_review_forces_confirmation_and_is_recorded() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+
+        let cli = Cli::parse_from(["sai", "--review", "say hi"]);
+        let generator = StubGenerator::new("echo hello", "destructive: pretends to wipe data");
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(b"n\n".to_vec());
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+
+        assert!(summary.confirm);
+        assert!(!executor.ran());
+        let review = summary.review.unwrap();
+        assert_eq!(review.effect, CommandEffect::Destructive);
+        assert_eq!(review.reason, "pretends to wipe data");
+    }
+
+    #[test]
+    fn allow_tool_permits_unconfigured_tool_for_one_run() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+
+        let generator = StubGenerator::new("wc -l notes.txt", "");
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(Vec::<u8>::new());
+        let cli = Cli::parse_from(["sai", "count lines"]);
+        assert!(run_with_reader(cli, &generator, &executor, &mut reader).is_err());
+        assert!(!executor.ran());
+
+        let cli = Cli::parse_from(["sai", "--allow-tool", "wc", "count lines"]);
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert_eq!(summary.exit_code, 0);
+        assert!(executor.ran());
+
+        let config = fs::read_to_string(config_root.join("config.yaml")).unwrap();
+        assert!(!config.contains("wc"));
+    }
+
+    #[test]
+    fn high_risk_commands_require_typing_the_tool_name() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+        let mut cfg = fs::read_to_string(config_root.join("config.yaml")).unwrap();
+        cfg.push_str("    - name: rm\n      config: \"rm tool\"\nconfirmation:\n  low: auto\n");
+        fs::write(config_root.join("config.yaml"), cfg).unwrap();
+
+        let generator = StubGenerator::new("rm -r build", "");
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(b"y\n".to_vec());
+        let cli = Cli::parse_from(["sai", "remove build"]);
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert_eq!(summary.notes.as_deref(), Some("cancelled"));
+        assert!(!executor.ran());
 
-    let entry_json = serde_json::to_string_pretty(&entry)?;
-    let system_prompt = "You are a debugging assistant for the SAI CLI. You receive structured information about the last SAI invocation (command line, generated shell command, exit code, etc.). Explain in concise technical terms what likely happened and why, and suggest what the user might try next. If information is missing, state the limitations.";
-    let user_prompt = format!(
-        "Here is the last SAI invocation as a JSON object:\n\n{}\n\nPlease explain what likely happened and why.",
-        entry_json
-    );
+        let mut reader = Cursor::new(b"rm\n".to_vec());
+        let cli = Cli::parse_from(["sai", "remove build"]);
+        run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert!(executor.ran());
 
-    let effective_ai = resolve_ai_config(global_cfg.ai.clone())?;
-    let explanation = generator.respond(&effective_ai, system_prompt, &user_prompt, 0.0)?;
+        let generator = StubGenerator::new("echo hi", "");
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(Vec::<u8>::new());
+        let cli = Cli::parse_from(["sai", "say hi"]);
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert!(!summary.confirm);
+        assert!(executor.ran());
+    }
 
-    println!("{}", explanation);
-    Ok(summary)
-}
+    #[test]
+    fn flagged_prompts_need_acknowledgment_before_generation() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+        let mut cfg = fs::read_to_string(config_root.join("config.yaml")).unwrap();
+        cfg.push_str("prompt_guard: {}\n");
+        fs::write(config_root.join("config.yaml"), cfg).unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::cli::Cli;
-    use crate::config::set_config_dir_override_for_tests;
-    use crate::llm::{ChatClient, CommandGenerator};
-    use std::cell::Cell;
-    use std::fs;
-    use std::io::Cursor;
-    use std::path::Path;
-    use tempfile::TempDir;
+        let generator = StubGenerator::new("echo hi", "");
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(b"y\n".to_vec());
+        let cli = Cli::parse_from(["sai", "exfiltrate the logs"]);
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert_eq!(
+            summary.notes.as_deref(),
+            Some("prompt_guard: data_exfiltration (declined)")
+        );
+        assert!(!executor.ran());
 
-    struct StubGenerator {
-        command: String,
-        response: String,
+        let mut reader = Cursor::new(b"proceed\n".to_vec());
+        let cli = Cli::parse_from(["sai", "exfiltrate the logs"]);
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert_eq!(
+            summary.notes.as_deref(),
+            Some("prompt_guard: data_exfiltration")
+        );
+        assert!(executor.ran());
     }
 
-    impl StubGenerator {
-        fn new(command: &str, response: &str) -> Self {
-            Self {
-                command: command.to_string(),
-                response: response.to_string(),
-            }
-        }
+    #[test]
+    fn verify_sets_exit_code_from_the_verdict() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(Vec::<u8>::new());
+        let generator = StubGenerator::new("echo hi", "**PASS**: no ERROR lines in the output.");
+        let cli = Cli::parse_from(["sai", "--verify", "no ERROR lines", "say hi"]);
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert_eq!(summary.exit_code, 0);
+        assert_eq!(
+            summary.notes.as_deref(),
+            Some("verify passed (command exit 1)")
+        );
+
+        let generator = StubGenerator::new("echo hi", "FAIL\nThe output is empty.");
+        let cli = Cli::parse_from(["sai", "--verify", "prints a greeting", "say hi"]);
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert_eq!(summary.exit_code, EXIT_VERIFY_FAILED);
+
+        let verdict = parse_verdict("Passing grade");
+        assert!(!verdict.passed);
     }
 
-    impl CommandGenerator for StubGenerator {
+    #[test]
+    fn analyze_output_runs_the_command_captured() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(Vec::<u8>::new());
+        let generator = StubGenerator::new("echo hi", "Nothing was printed.");
+        let cli = Cli::parse_from(["sai", "--analyze-output", "say hi"]);
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert!(executor.ran());
+        // The command's exit code from execute_captured, not the analysis.
+        assert_eq!(summary.exit_code, 1);
+        assert!(Cli::try_parse_from(["sai", "--analyze-output", "--print", "say hi"]).is_err());
+    }
+
+    /// Returns its commands in turn, then repeats the last one.
+    struct SequenceGenerator(RefCell<Vec<&'static str>>);
+
+    impl CommandGenerator for SequenceGenerator {
         fn generate(
             &self,
             _ai: &crate::config::EffectiveAiConfig,
@@ -415,11 +3137,17 @@ mod tests {
             _scope_hint: Option<&str>,
             _peek_text: Option<&str>,
         ) -> Result<String> {
-            Ok(self.command.clone())
+            let mut commands = self.0.borrow_mut();
+            let command = if commands.len() > 1 {
+                commands.remove(0)
+            } else {
+                commands[0]
+            };
+            Ok(command.to_string())
         }
     }
 
-    impl ChatClient for StubGenerator {
+    impl ChatClient for SequenceGenerator {
         fn respond(
             &self,
             _ai: &crate::config::EffectiveAiConfig,
@@ -427,30 +3155,42 @@ mod tests {
             _user_prompt: &str,
             _temperature: f32,
         ) -> Result<String> {
-            Ok(self.response.clone())
+            Ok(String::new())
         }
     }
 
+    /// Fails every command, recording the directory it was run in.
     #[derive(Default)]
-    struct RecordingExecutor {
-        ran: Cell<bool>,
+    struct DirRecordingExecutor {
+        dirs: RefCell<Vec<Option<PathBuf>>>,
     }
 
-    impl RecordingExecutor {
-        fn ran(&self) -> bool {
-            self.ran.get()
+    impl CommandExecutor for DirRecordingExecutor {
+        fn execute(&self, _cmd_line: &str, _tokens: &[String], _unsafe_mode: bool) -> Result<i32> {
+            Ok(1)
         }
-    }
 
-    impl CommandExecutor for RecordingExecutor {
-        fn execute(&self, _cmd_line: &str, _tokens: &[String], _unsafe_mode: bool) -> Result<i32> {
-            self.ran.set(true);
-            Ok(0)
+        fn execute_captured(
+            &self,
+            _cmd_line: &str,
+            _tokens: &[String],
+            _unsafe_mode: bool,
+            opts: &ExecOptions,
+        ) -> Result<CapturedOutput> {
+            self.dirs.borrow_mut().push(opts.dir.clone());
+            Ok(CapturedOutput {
+                exit_code: 1,
+                ..CapturedOutput::default()
+            })
         }
     }
 
-    fn write_minimal_config(dir: &Path) {
-        fs::create_dir_all(dir).unwrap();
+    #[test]
+    fn auto_fix_applies_the_directory_rules_of_the_fix() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        fs::create_dir_all(&config_root).unwrap();
         let cfg = r#"
 ai:
   provider: openai
@@ -460,72 +3200,439 @@ default_prompt:
   tools:
     - name: echo
       config: "echo tool"
+    - name: ls
+      config: "ls tool"
+      chdir: src
+    - name: wc
+      config: "wc tool"
+      cwd_must_match: /nowhere/**
 "#;
-        fs::write(dir.join("config.yaml"), cfg).unwrap();
+        fs::write(config_root.join("config.yaml"), cfg).unwrap();
+        let src = env::current_dir().unwrap().join("src");
+
+        let generator = SequenceGenerator(RefCell::new(vec!["echo hi", "ls"]));
+        let executor = DirRecordingExecutor::default();
+        let mut reader = Cursor::new(b"y\n".to_vec());
+        let cli = Cli::parse_from(["sai", "--auto-fix", "1", "say hi"]);
+        run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert_eq!(*executor.dirs.borrow(), vec![None, Some(src)]);
+
+        let generator = SequenceGenerator(RefCell::new(vec!["echo hi", "wc -l notes.txt"]));
+        let executor = DirRecordingExecutor::default();
+        let mut reader = Cursor::new(b"y\n".to_vec());
+        let cli = Cli::parse_from(["sai", "--auto-fix", "1", "say hi"]);
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert_eq!(summary.notes.as_deref(), Some("auto-fix rejected"));
+        assert_eq!(executor.dirs.borrow().len(), 1);
     }
 
     #[test]
-    fn analyze_without_history_returns_message() {
+    fn transactions_keep_outputs_only_when_the_command_succeeds() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        fs::write(root.join("in.txt"), "b\na\n").unwrap();
+        let line = "sort in.txt > out.txt";
+        let tokens = shell_words::split(line).unwrap();
+
+        let txn = begin_transaction(line, &tokens, true, root, root).unwrap();
+        fs::write(txn.dir_for(root).join("out.txt"), "a\nb\n").unwrap();
+        assert_eq!(
+            finish_transaction(txn, 2).unwrap(),
+            "transaction rolled back"
+        );
+        assert!(!root.join("out.txt").exists());
+
+        let txn = begin_transaction(line, &tokens, true, root, root).unwrap();
+        fs::write(txn.dir_for(root).join("out.txt"), "a\nb\n").unwrap();
+        let note = finish_transaction(txn, 0).unwrap();
+        assert!(note.starts_with("transaction: .sai-txn-"));
+        assert!(note.ends_with("/out.txt -> out.txt"));
+        assert_eq!(fs::read_to_string(root.join("out.txt")).unwrap(), "a\nb\n");
+    }
+
+    #[test]
+    fn auto_fix_confirms_and_runs_fixes_up_to_the_limit() {
         let temp = TempDir::new().unwrap();
         let config_root = temp.path().join("config");
         let _guard = set_config_dir_override_for_tests(&config_root);
         write_minimal_config(&config_root);
 
-        let cli = Cli {
-            init: false,
-            create_prompt: None,
-            add_prompt: None,
-            list_tools: false,
-            analyze: true,
-            confirm: false,
-            explain: false,
-            unsafe_mode: false,
-            peek: Vec::new(),
-            scope: None,
-            arg1: None,
-            prompt: None,
+        // RecordingExecutor fails every command with exit code 1.
+        let executor = RecordingExecutor::default();
+        let generator = StubGenerator::new("echo hi", "");
+        let mut reader = Cursor::new(b"y\ny\n".to_vec());
+        let cli = Cli::parse_from(["sai", "--auto-fix", "2", "say hi"]);
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert_eq!(summary.exit_code, 1);
+        assert_eq!(
+            summary.notes.as_deref(),
+            Some("auto-fix 1: echo hi (exit 1); auto-fix 2: echo hi (exit 1)")
+        );
+
+        let mut reader = Cursor::new(b"n\n".to_vec());
+        let cli = Cli::parse_from(["sai", "--auto-fix", "2", "say hi"]);
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert_eq!(summary.notes.as_deref(), Some("auto-fix declined"));
+
+        let failed = CapturedOutput {
+            exit_code: 2,
+            stdout: Vec::new(),
+            stderr: b"ls: cannot access 'x': No such file\n".to_vec(),
         };
+        let failures = [("ls x".to_string(), failed)];
+        let prompt = with_failures("list x", &failures, &[]);
+        assert!(prompt.ends_with(
+            "- ls x (exit code 2)\n  Error output:\nls: cannot access 'x': No such file"
+        ));
+        let docs = [(
+            "ls".to_string(),
+            "Usage: ls [OPTION]... [FILE]...".to_string(),
+        )];
+        let prompt = with_failures("list x", &failures, &docs);
+        assert!(prompt.ends_with(
+            "ls rejected a flag. Its --help output, for choosing valid options:\n\
+             Usage: ls [OPTION]... [FILE]..."
+        ));
+    }
 
-        let generator = StubGenerator::new("echo hi", "analysis");
+    #[test]
+    fn only_long_outputs_are_summarized() {
+        let output = |lines: usize| CapturedOutput {
+            exit_code: 0,
+            stdout: "sent file\n".repeat(lines).into_bytes(),
+            stderr: b"warning: skipped\n".to_vec(),
+        };
+        assert!(!is_long(&output(SUMMARIZE_MIN_LINES - 2)));
+        assert!(is_long(&output(SUMMARIZE_MIN_LINES - 1)));
+    }
+
+    #[test]
+    fn exec_timeout_uses_the_shortest_applicable_limit() {
+        let tools: Vec<crate::config::ToolConfig> =
+            serde_yaml::from_str("- name: find\n  timeout: 30\n  config: find\n- name: sort\n  timeout: 5\n  config: sort\n").unwrap();
+        let secs = |t: Option<Duration>| t.map(|d| d.as_secs());
+
+        assert_eq!(
+            secs(exec_timeout(None, &tools, "find . -name x", false)),
+            Some(30)
+        );
+        assert_eq!(
+            secs(exec_timeout(Some(10), &tools, "find . -name x", false)),
+            Some(10)
+        );
+        assert_eq!(
+            secs(exec_timeout(None, &tools, "find . | sort", true)),
+            Some(5)
+        );
+        assert_eq!(secs(exec_timeout(None, &tools, "echo hi", false)), None);
+    }
+
+    struct TimingOutExecutor;
+
+    impl CommandExecutor for TimingOutExecutor {
+        fn execute(&self, _cmd_line: &str, _tokens: &[String], _unsafe_mode: bool) -> Result<i32> {
+            Ok(EXIT_TIMED_OUT)
+        }
+
+        fn execute_with(
+            &self,
+            _cmd_line: &str,
+            _tokens: &[String],
+            _unsafe_mode: bool,
+            _opts: &ExecOptions,
+        ) -> Result<i32> {
+            Ok(EXIT_TIMED_OUT)
+        }
+    }
+
+    #[test]
+    fn timeout_note_keeps_the_earlier_notes() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+
+        let cli = Cli::parse_from(["sai", "--host", "box", "--exec-timeout", "2", "say hi"]);
+        let generator = StubGenerator::new("echo hello", "");
+        let mut reader = Cursor::new(b"y\n".to_vec());
+        let summary = run_with_reader(cli, &generator, &TimingOutExecutor, &mut reader).unwrap();
+
+        assert_eq!(summary.exit_code, EXIT_TIMED_OUT);
+        assert_eq!(
+            summary.notes.as_deref(),
+            Some("ran on box; timed out after 2.0s")
+        );
+    }
+
+    #[test]
+    fn tool_env_applies_only_to_the_tool_that_runs() {
+        let tools: Vec<crate::config::ToolConfig> = serde_yaml::from_str(
+            "- name: jq\n  env:\n    JQ_COLORS: \"0;31\"\n  config: jq\n- name: sort\n  config: sort\n",
+        )
+        .unwrap();
+        let jq_colors = BTreeMap::from([("JQ_COLORS".to_string(), "0;31".to_string())]);
+        assert_eq!(tool_env(&tools, "jq . data.json", false), jq_colors);
+        assert_eq!(tool_env(&tools, "jq . data.json", true), jq_colors);
+        assert!(tool_env(&tools, "jq . data.json | sort", true).is_empty());
+        assert!(tool_env(&tools, "sort data.txt", false).is_empty());
+    }
+
+    #[test]
+    fn only_allowlisted_tools_are_recorded_for_version_probes() {
+        let allowed = vec!["ls".to_string(), "wc".to_string()];
+        assert_eq!(
+            allowlisted_tools("ls x; /tmp/x --y | wc -l", true, &allowed, &[]),
+            vec!["ls", "wc"]
+        );
+        assert!(allowlisted_tools("/tmp/x", false, &allowed, &[]).is_empty());
+    }
+
+    #[test]
+    fn candidate_menu_skips_rejected_commands_and_reprompts() {
+        let candidates = vec![
+            "rm -rf build".to_string(),
+            "echo one".to_string(),
+            "echo two".to_string(),
+        ];
+        let allowed = vec!["echo".to_string()];
+        let validate =
+            |cmd: &str| validate_and_split_command(cmd, &allowed, false, &PromptConfig::default());
+        let mut reader = Cursor::new(b"3\n2\n".to_vec());
+        let (cmd, tokens) = choose_candidate(&mut reader, &candidates, &validate)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cmd, "echo two");
+        assert_eq!(tokens, vec!["echo", "two"]);
+
+        let mut reader = Cursor::new(b"\n".to_vec());
+        assert!(choose_candidate(&mut reader, &candidates, &validate)
+            .unwrap()
+            .is_none());
+
+        let err = choose_candidate(&mut reader, &candidates[..1], &validate).unwrap_err();
+        assert!(err.to_string().contains("passed validation"));
+    }
+
+    #[test]
+    fn candidates_run_the_selected_command() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+
+        let cli = Cli::parse_from(["sai", "--candidates", "3", "say hi"]);
+        let generator = StubGenerator::new("echo hello", "");
         let executor = RecordingExecutor::default();
-        let mut reader = Cursor::new(Vec::<u8>::new());
+        let mut reader = Cursor::new(b"1\n".to_vec());
         let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
 
-        assert_eq!(summary.exit_code, 2);
-        assert!(!summary.confirm);
+        assert_eq!(summary.generated_command.as_deref(), Some("echo hello"));
+        assert!(executor.ran());
+    }
+
+    #[test]
+    fn print_mode_does_not_run_the_command() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+
+        let cli = Cli::parse_from(["sai", "--print", "say hi"]);
+        let generator = StubGenerator::new("echo hello", "");
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(Vec::new());
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+
+        assert_eq!(summary.generated_command.as_deref(), Some("echo hello"));
+        assert_eq!(summary.notes.as_deref(), Some("printed"));
         assert!(!executor.ran());
+
+        let cli = Cli::parse_from(["sai", "--fill", "--", "say hi"]);
+        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        assert_eq!(summary.notes.as_deref(), Some("filled"));
+        assert!(!executor.ran());
+
+        let cli = Cli::parse_from(["sai", "--print", "say hi"]);
+        let generator = StubGenerator::new("rm -rf build", "");
+        assert!(run_with_reader(cli, &generator, &executor, &mut reader).is_err());
+    }
+
+    /// Answers with a disallowed command unless asked through the race model.
+    struct RaceGenerator;
+
+    impl CommandGenerator for RaceGenerator {
+        fn generate(
+            &self,
+            ai: &crate::config::EffectiveAiConfig,
+            _system_prompt: &str,
+            _nl_prompt: &str,
+            _scope_hint: Option<&str>,
+            _peek_text: Option<&str>,
+        ) -> Result<String> {
+            Ok(match ai.model_name() {
+                "race-model" => "echo from race".to_string(),
+                _ => "rm -rf build".to_string(),
+            })
+        }
+    }
+
+    impl ChatClient for RaceGenerator {
+        fn respond(
+            &self,
+            _ai: &crate::config::EffectiveAiConfig,
+            _system_prompt: &str,
+            _user_prompt: &str,
+            _temperature: f32,
+        ) -> Result<String> {
+            Ok(String::new())
+        }
     }
 
     #[test]
-    fn explain_forces_confirmation_and_allows_cancel() {
+    fn race_uses_the_first_command_that_passes_validation() {
         let temp = TempDir::new().unwrap();
         let config_root = temp.path().join("config");
         let _guard = set_config_dir_override_for_tests(&config_root);
         write_minimal_config(&config_root);
 
-        let cli = Cli {
-            init: false,
-            create_prompt: None,
-            add_prompt: None,
-            list_tools: false,
-            analyze: false,
-            confirm: false,
-            explain: true,
-            unsafe_mode: false,
-            peek: Vec::new(),
-            scope: None,
-            arg1: Some("say hi".to_string()),
-            prompt: None,
-        };
+        let cli = Cli::parse_from(["sai", "--race", "say hi"]);
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(Vec::new());
+        let err = run_with_reader(cli.clone(), &RaceGenerator, &executor, &mut reader).unwrap_err();
+        assert!(err.to_string().contains("`race:` section"));
 
-        let generator = StubGenerator::new("echo hello", "will echo hello");
+        let path = config_root.join("config.yaml");
+        let mut cfg = fs::read_to_string(&path).unwrap();
+        cfg.push_str("race:\n  openai_model: race-model\n");
+        fs::write(&path, cfg).unwrap();
+        let summary = run_with_reader(cli, &RaceGenerator, &executor, &mut reader).unwrap();
+
+        assert_eq!(summary.generated_command.as_deref(), Some("echo from race"));
+        assert_eq!(summary.model.as_deref(), Some("race-model"));
+        assert_eq!(summary.notes.as_deref(), Some("race won by race-model"));
+        assert!(executor.ran());
+    }
+
+    /// Suggests a different command once the prompt lists declined ones.
+    struct RetryGenerator;
+
+    impl CommandGenerator for RetryGenerator {
+        fn generate(
+            &self,
+            _ai: &crate::config::EffectiveAiConfig,
+            _system_prompt: &str,
+            nl_prompt: &str,
+            _scope_hint: Option<&str>,
+            _peek_text: Option<&str>,
+        ) -> Result<String> {
+            Ok(
+                if nl_prompt.contains("- echo first\n  The user's correction: only .rs files") {
+                    "echo corrected".to_string()
+                } else if nl_prompt.contains("- echo first (reason: wrong paths)") {
+                    "echo second".to_string()
+                } else {
+                    "echo first".to_string()
+                },
+            )
+        }
+    }
+
+    impl ChatClient for RetryGenerator {
+        fn respond(
+            &self,
+            _ai: &crate::config::EffectiveAiConfig,
+            _system_prompt: &str,
+            _user_prompt: &str,
+            _temperature: f32,
+        ) -> Result<String> {
+            Ok(String::new())
+        }
+    }
+
+    #[test]
+    fn declined_commands_can_be_regenerated_with_a_reason_or_correction() {
+        let temp = TempDir::new().unwrap();
+        let config_root = temp.path().join("config");
+        let _guard = set_config_dir_override_for_tests(&config_root);
+        write_minimal_config(&config_root);
+
+        let cli = Cli::parse_from(["sai", "--confirm", "say hi"]);
         let executor = RecordingExecutor::default();
-        let mut reader = Cursor::new(b"n\n".to_vec());
-        let summary = run_with_reader(cli, &generator, &executor, &mut reader).unwrap();
+        let mut reader = Cursor::new(b"n\n2\nr\ny\n".to_vec());
+        let summary =
+            run_with_reader(cli.clone(), &RetryGenerator, &executor, &mut reader).unwrap();
+        assert_eq!(summary.generated_command.as_deref(), Some("echo second"));
+        assert_eq!(summary.notes.as_deref(), Some("regenerated (wrong paths)"));
+        assert!(executor.ran());
 
-        assert_eq!(summary.exit_code, 0);
-        assert_eq!(summary.notes.as_deref(), Some("cancelled"));
-        assert!(summary.confirm);
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(b"n\n\nonly .rs files\ny\n".to_vec());
+        let summary =
+            run_with_reader(cli.clone(), &RetryGenerator, &executor, &mut reader).unwrap();
+        assert_eq!(summary.generated_command.as_deref(), Some("echo corrected"));
+        assert_eq!(summary.notes.as_deref(), Some("corrected"));
+
+        let executor = RecordingExecutor::default();
+        let mut reader = Cursor::new(b"n\ntoo risky\n\n".to_vec());
+        let summary = run_with_reader(cli, &RetryGenerator, &executor, &mut reader).unwrap();
+        assert_eq!(summary.notes.as_deref(), Some("cancelled (too risky)"));
         assert!(!executor.ran());
+        assert_eq!(
+            DeclineReason::parse("paths"),
+            Some(DeclineReason::WrongPaths)
+        );
+        assert_eq!(DeclineReason::parse("9"), None);
+    }
+
+    #[test]
+    fn correction_prompt_reads_the_answer_into_the_next_request() {
+        let answer = |input: &str| ask_after_decline(&mut Cursor::new(input.as_bytes()), false);
+        assert_eq!(answer("\n").unwrap(), AfterDecline::Stop);
+        assert_eq!(answer("").unwrap(), AfterDecline::Stop);
+        assert_eq!(answer("Q\n").unwrap(), AfterDecline::Stop);
+        assert_eq!(answer("r\n").unwrap(), AfterDecline::Regenerate);
+        assert_eq!(
+            answer("Too broad, only .rs files\n").unwrap(),
+            AfterDecline::Correct("Too broad, only .rs files".to_string())
+        );
+
+        let rejected = [
+            Rejection {
+                command: "find . -type f".to_string(),
+                reason: None,
+                correction: Some("only .rs files".to_string()),
+            },
+            Rejection {
+                command: "find . -name '*.rs'".to_string(),
+                reason: Some(DeclineReason::WrongPaths),
+                correction: None,
+            },
+        ];
+        assert_eq!(
+            with_rejections("list source files", &rejected),
+            "list source files\n\nThe user declined these commands; suggest a different one \
+             that avoids the problem:\n- find . -type f\n  The user's correction: only .rs files\n\
+             - find . -name '*.rs' (reason: wrong paths)"
+        );
+        assert_eq!(
+            with_rejections("list source files", &[]),
+            "list source files"
+        );
+    }
+
+    #[test]
+    fn no_history_runs_stay_out_of_the_active_transcript() {
+        let temp = TempDir::new().unwrap();
+        let _guard = set_config_dir_override_for_tests(temp.path().join("config"));
+        let notes = temp.path().join("notes.md");
+        transcript::start(&notes).unwrap();
+
+        let cli = Cli::parse_from(["sai", "list files"]);
+        assert_eq!(transcript_file(&cli, true), Some(notes));
+        assert_eq!(transcript_file(&cli, false), None);
+        let cli = Cli::parse_from(["sai", "--no-history", "list files"]);
+        assert_eq!(transcript_file(&cli, true), None);
+        let cli = Cli::parse_from(["sai", "--no-history", "--transcript", "x.md", "list files"]);
+        assert_eq!(transcript_file(&cli, false), Some(PathBuf::from("x.md")));
     }
 }
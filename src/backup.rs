@@ -0,0 +1,231 @@
+//! `--backup`: before a modifying command runs, the files and directories
+//! named in its arguments are copied into a timestamped directory under the
+//! sai config directory, with the commands that put them back.
+
+use crate::config::config_root_dir;
+use crate::executor::expand_glob_in;
+use crate::humanize;
+use crate::safety::{ALLOWABLE_OPERATORS, COMMAND_SEPARATORS};
+use anyhow::{anyhow, Context, Result};
+use chrono::Local;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Largest total size copied; a bigger backup is refused rather than
+/// filling the disk.
+pub const MAX_BACKUP_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Name of the file in each backup directory listing the restore commands.
+pub const RESTORE_FILE: &str = "RESTORE.txt";
+
+/// Where backups are kept.
+pub fn backups_dir() -> PathBuf {
+    config_root_dir().join("backups")
+}
+
+/// Existing paths named in the arguments of `tokens`, resolved against `cwd`.
+/// Tool names and flags are skipped, the value of `--flag=VALUE` counts, and
+/// globs are expanded.
+pub fn referenced_paths(tokens: &[String], cwd: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    let mut command_start = true;
+    for token in tokens {
+        if COMMAND_SEPARATORS.contains(&token.as_str()) {
+            command_start = true;
+            continue;
+        }
+        if std::mem::take(&mut command_start) || ALLOWABLE_OPERATORS.contains(&token.as_str()) {
+            continue;
+        }
+        let arg = match token.strip_prefix('-') {
+            Some(flag) => match flag.split_once('=') {
+                Some((_, value)) => value,
+                None => continue,
+            },
+            None => token.as_str(),
+        };
+        if arg.is_empty() {
+            continue;
+        }
+        for arg in expand_glob_in(arg, Some(cwd)) {
+            let path = cwd.join(arg);
+            if fs::symlink_metadata(&path).is_ok() && !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    paths
+}
+
+pub(crate) fn size_of(path: &Path) -> Result<u64> {
+    let meta = fs::symlink_metadata(path)?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        total += size_of(&entry?.path())?;
+    }
+    Ok(total)
+}
+
+/// Copies `src` to `dest`, directories recursively and symlinks as links.
+/// `skip` is left out, for a `dest` inside `src`.
+pub(crate) fn copy_tree(src: &Path, dest: &Path, skip: Option<&Path>) -> Result<()> {
+    let meta = fs::symlink_metadata(src)?;
+    if meta.is_dir() {
+        fs::create_dir_all(dest)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            if skip.is_some_and(|skip| entry.path() == skip) {
+                continue;
+            }
+            copy_tree(&entry.path(), &dest.join(entry.file_name()), skip)?;
+        }
+        fs::set_permissions(dest, meta.permissions())?;
+    } else if meta.file_type().is_symlink() {
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(fs::read_link(src)?, dest)?;
+        #[cfg(not(unix))]
+        fs::copy(src, dest)?;
+    } else {
+        fs::copy(src, dest)?;
+    }
+    Ok(())
+}
+
+/// A finished backup.
+#[derive(Debug)]
+pub struct Backup {
+    pub dir: PathBuf,
+    /// Copy in the backup and the path it came from.
+    pub entries: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Backup {
+    /// Shell commands that put every backed-up path back.
+    pub fn restore_commands(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|(copy, original)| {
+                let quote = |p: &Path| shell_words::quote(&p.display().to_string()).into_owned();
+                let original = quote(original);
+                format!("rm -rf {} && cp -a {} {}", original, quote(copy), original)
+            })
+            .collect()
+    }
+}
+
+/// Copies `paths` into a new timestamped directory under `root` and writes
+/// [`RESTORE_FILE`] there. Returns `None` when there is nothing to copy.
+pub fn create_backup(paths: &[PathBuf], root: &Path) -> Result<Option<Backup>> {
+    if paths.is_empty() {
+        return Ok(None);
+    }
+    let mut total = 0;
+    for path in paths {
+        total += size_of(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    }
+    if total > MAX_BACKUP_BYTES {
+        return Err(anyhow!(
+            "--backup would copy {}, more than the {} limit; back the files up yourself",
+            humanize::bytes(total),
+            humanize::bytes(MAX_BACKUP_BYTES)
+        ));
+    }
+
+    let stamp = Local::now().format("%Y%m%d-%H%M%S");
+    let dir = root.join(format!("{}-{}", stamp, process::id()));
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create backup directory {}", dir.display()))?;
+    let mut entries = Vec::new();
+    for (idx, path) in paths.iter().enumerate() {
+        let original = absolute(path);
+        let name = original
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "root".to_string());
+        let copy = dir.join(format!("{}-{}", idx + 1, name));
+        copy_tree(path, &copy, None)
+            .with_context(|| format!("Failed to back up {}", path.display()))?;
+        entries.push((copy, original));
+    }
+    let backup = Backup { dir, entries };
+    let mut restore = backup.restore_commands().join("\n");
+    restore.push('\n');
+    fs::write(backup.dir.join(RESTORE_FILE), restore)
+        .with_context(|| format!("Failed to write {}", RESTORE_FILE))?;
+    Ok(Some(backup))
+}
+
+/// `path` with its parent directory canonicalized but its own name kept, so
+/// a symlink is restored as the link and not over its target.
+fn absolute(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            fs::canonicalize(parent)
+                .map(|parent| parent.join(name))
+                .unwrap_or_else(|_| path.to_path_buf())
+        }
+        _ => fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn referenced_files_are_copied_with_restore_commands() {
+        let temp = TempDir::new().unwrap();
+        let work = temp.path().join("work");
+        fs::create_dir_all(work.join("build")).unwrap();
+        fs::write(work.join("notes.txt"), "keep me").unwrap();
+        fs::write(work.join("build/out.o"), "obj").unwrap();
+
+        let tokens: Vec<String> = ["rm", "-rf", "build", "--log=notes.txt", "missing.txt"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let paths = referenced_paths(&tokens, &work);
+        assert_eq!(paths, [work.join("build"), work.join("notes.txt")]);
+
+        let root = temp.path().join("backups");
+        let backup = create_backup(&paths, &root).unwrap().unwrap();
+        assert_eq!(
+            fs::read_to_string(backup.entries[0].0.join("out.o")).unwrap(),
+            "obj"
+        );
+        assert_eq!(fs::read_to_string(&backup.entries[1].0).unwrap(), "keep me");
+        let restore = fs::read_to_string(backup.dir.join(RESTORE_FILE)).unwrap();
+        assert_eq!(restore.lines().count(), 2);
+        assert!(restore.contains("cp -a"));
+        assert!(create_backup(&[], &root).unwrap().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_restored_as_links() {
+        let temp = TempDir::new().unwrap();
+        fs::write(temp.path().join("target.txt"), "data").unwrap();
+        let link = temp.path().join("link.txt");
+        std::os::unix::fs::symlink("target.txt", &link).unwrap();
+
+        let backup = create_backup(&[link], &temp.path().join("backups"))
+            .unwrap()
+            .unwrap();
+        let original = &backup.entries[0].1;
+        assert_eq!(original.file_name().unwrap(), "link.txt");
+        assert!(fs::symlink_metadata(&backup.entries[0].0)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+    }
+}
@@ -0,0 +1,349 @@
+use crate::history::{self, HistoryEntry};
+use crate::humanize;
+use anyhow::{anyhow, Result};
+use chrono::{Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Number of days shown by `sai --usage`.
+pub const USAGE_REPORT_DAYS: i64 = 14;
+
+/// Daily spending limits, checked against history before and after LLM calls.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BudgetConfig {
+    /// Maximum prompt + completion tokens per UTC day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_tokens: Option<u64>,
+
+    /// Maximum spend per UTC day in USD, computed from `prices`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_cost: Option<f64>,
+
+    /// Price table keyed by model (OpenAI) or deployment (Azure) name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prices: BTreeMap<String, ModelPrice>,
+}
+
+/// USD per one million tokens.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct ModelPrice {
+    #[serde(default)]
+    pub prompt: f64,
+    #[serde(default)]
+    pub completion: f64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Spend {
+    pub runs: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost: f64,
+    /// Models with token usage but no entry in the price table.
+    pub unpriced_models: Vec<String>,
+}
+
+impl Spend {
+    pub fn tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+pub fn today() -> NaiveDate {
+    Utc::now().date_naive()
+}
+
+/// Sums token usage and cost of all history entries from the given UTC day.
+pub fn spend_for_day(entries: &[HistoryEntry], day: NaiveDate, budget: &BudgetConfig) -> Spend {
+    let prefix = day.format("%Y-%m-%d").to_string();
+    let mut spend = Spend::default();
+
+    for entry in entries.iter().filter(|e| e.ts.starts_with(&prefix)) {
+        let Some(usage) = entry.usage else {
+            continue;
+        };
+        spend.runs += 1;
+        spend.prompt_tokens += usage.prompt_tokens;
+        spend.completion_tokens += usage.completion_tokens;
+
+        let model = entry.model.clone().unwrap_or_default();
+        match budget.prices.get(&model) {
+            Some(price) => {
+                spend.cost += usage.prompt_tokens as f64 * price.prompt / 1_000_000.0
+                    + usage.completion_tokens as f64 * price.completion / 1_000_000.0;
+            }
+            None => {
+                if !spend.unpriced_models.contains(&model) {
+                    spend.unpriced_models.push(model);
+                }
+            }
+        }
+    }
+
+    spend
+}
+
+/// Fails when today's spend already reached a configured cap.
+pub fn ensure_within_budget(budget: &BudgetConfig, spend: &Spend) -> Result<()> {
+    if let Some(limit) = budget.daily_tokens {
+        if spend.tokens() >= limit {
+            return Err(anyhow!(
+                "Daily token budget exhausted: {} of {} tokens used today. \
+                 Raise budget.daily_tokens in the global config or wait until tomorrow (UTC).",
+                humanize::count(spend.tokens()),
+                humanize::count(limit)
+            ));
+        }
+    }
+
+    if let Some(limit) = budget.daily_cost {
+        if spend.cost >= limit {
+            return Err(anyhow!(
+                "Daily cost budget exhausted: ${:.4} of ${:.2} spent today. \
+                 Raise budget.daily_cost in the global config or wait until tomorrow (UTC).",
+                spend.cost,
+                limit
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks today's recorded spend against the configured caps, if any.
+pub fn check_daily_budget(budget: Option<&BudgetConfig>) -> Result<()> {
+    let Some(budget) = budget else {
+        return Ok(());
+    };
+    let entries = history::read_all_entries()?;
+    ensure_within_budget(budget, &spend_for_day(&entries, today(), budget))
+}
+
+/// Renders the `sai --usage` report: per-day totals plus today's budget status.
+pub fn render_usage_report(entries: &[HistoryEntry], budget: &BudgetConfig) -> String {
+    let today = today();
+    let mut out = String::new();
+    out.push_str(&format!(
+        "{:<10}  {:>5}  {:>10}  {:>10}  {:>10}\n",
+        "Date", "Runs", "Prompt", "Completion", "Cost (USD)"
+    ));
+
+    let mut unpriced: Vec<String> = Vec::new();
+    for offset in (0..USAGE_REPORT_DAYS).rev() {
+        let day = today - Duration::days(offset);
+        let spend = spend_for_day(entries, day, budget);
+        if spend.runs == 0 {
+            continue;
+        }
+        out.push_str(&format!(
+            "{:<10}  {:>5}  {:>10}  {:>10}  {:>10.4}\n",
+            day.format("%Y-%m-%d"),
+            humanize::count(spend.runs as u64),
+            humanize::count(spend.prompt_tokens),
+            humanize::count(spend.completion_tokens),
+            spend.cost
+        ));
+        for model in spend.unpriced_models {
+            if !unpriced.contains(&model) {
+                unpriced.push(model);
+            }
+        }
+    }
+
+    let spend = spend_for_day(entries, today, budget);
+    out.push('\n');
+    match budget.daily_tokens {
+        Some(limit) => out.push_str(&format!(
+            "Today: {} of {} tokens\n",
+            humanize::count(spend.tokens()),
+            humanize::count(limit)
+        )),
+        None => out.push_str(&format!(
+            "Today: {} tokens (no token cap)\n",
+            humanize::count(spend.tokens())
+        )),
+    }
+    if let Some(limit) = budget.daily_cost {
+        out.push_str(&format!("Today: ${:.4} of ${:.2}\n", spend.cost, limit));
+    }
+    if !unpriced.is_empty() {
+        let names: Vec<String> = unpriced
+            .iter()
+            .map(|m| {
+                if m.is_empty() {
+                    "(unknown)".to_string()
+                } else {
+                    m.clone()
+                }
+            })
+            .collect();
+        out.push_str(&format!(
+            "No price configured for: {} (cost counted as 0)\n",
+            names.join(", ")
+        ));
+    }
+
+    out
+}
+
+/// Estimated input (prompt) and output (completion) tokens of one batch prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EstimateRow {
+    pub prompt: String,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+/// Renders the `--estimate` table: tokens and, when `model` has a price,
+/// cost per prompt, followed by the total.
+pub fn render_estimate(rows: &[EstimateRow], model: &str, budget: &BudgetConfig) -> String {
+    const PROMPT_WIDTH: usize = 40;
+    let price = budget.prices.get(model);
+    let cost = |prompt: u64, completion: u64| {
+        price.map(|p| {
+            prompt as f64 * p.prompt / 1_000_000.0 + completion as f64 * p.completion / 1_000_000.0
+        })
+    };
+
+    let mut out = format!(
+        "Estimate for {} ({} prompt{}, no API calls made)\n",
+        model,
+        rows.len(),
+        if rows.len() == 1 { "" } else { "s" }
+    );
+    out.push_str(&format!(
+        "{:>3}  {:<PROMPT_WIDTH$}  {:>7}  {:>6}{}\n",
+        "#",
+        "Prompt",
+        "Input",
+        "Output",
+        if price.is_some() { "  Cost" } else { "" }
+    ));
+    let (mut prompt_total, mut completion_total) = (0u64, 0u64);
+    for (idx, row) in rows.iter().enumerate() {
+        let mut text: String = row.prompt.chars().take(PROMPT_WIDTH).collect();
+        if row.prompt.chars().count() > PROMPT_WIDTH {
+            text.pop();
+            text.push('…');
+        }
+        prompt_total += u64::from(row.prompt_tokens);
+        completion_total += u64::from(row.completion_tokens);
+        let row_cost = cost(row.prompt_tokens.into(), row.completion_tokens.into())
+            .map(|c| format!("  ${:.4}", c))
+            .unwrap_or_default();
+        out.push_str(&format!(
+            "{:>3}  {:<PROMPT_WIDTH$}  {:>7}  {:>6}{}\n",
+            idx + 1,
+            text,
+            humanize::count(row.prompt_tokens.into()),
+            humanize::count(row.completion_tokens.into()),
+            row_cost
+        ));
+    }
+    let total_cost = match cost(prompt_total, completion_total) {
+        Some(c) => format!(", about ${:.4}", c),
+        None => format!("; no price configured for {}", model),
+    };
+    out.push_str(&format!(
+        "Total: {} tokens ({} prompt + {} output){}\n",
+        humanize::count(prompt_total + completion_total),
+        humanize::count(prompt_total),
+        humanize::count(completion_total),
+        total_cost
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::TokenUsage;
+
+    fn entry(ts: &str, model: &str, prompt: u64, completion: u64) -> HistoryEntry {
+        HistoryEntry {
+            ts: ts.to_string(),
+            cwd: "/tmp".to_string(),
+            argv: vec!["sai".to_string()],
+            usage: Some(TokenUsage {
+                prompt_tokens: prompt,
+                completion_tokens: completion,
+            }),
+            model: Some(model.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn budget() -> BudgetConfig {
+        serde_yaml::from_str(
+            "daily_tokens: 1000\ndaily_cost: 0.01\nprices:\n  m:\n    prompt: 2.0\n    completion: 8.0\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn spend_sums_only_the_requested_day() {
+        let day = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        let entries = vec![
+            entry("2024-05-01T10:00:00Z", "m", 1000, 500),
+            entry("2024-05-01T11:00:00Z", "other", 10, 0),
+            entry("2024-04-30T23:59:59Z", "m", 9999, 9999),
+        ];
+        let spend = spend_for_day(&entries, day, &budget());
+        assert_eq!(spend.runs, 2);
+        assert_eq!(spend.tokens(), 1510);
+        assert!((spend.cost - 0.006).abs() < 1e-9);
+        assert_eq!(spend.unpriced_models, vec!["other".to_string()]);
+    }
+
+    #[test]
+    fn estimate_totals_tokens_and_prices_known_models() {
+        let rows = vec![
+            EstimateRow {
+                prompt: "list files".to_string(),
+                prompt_tokens: 1000,
+                completion_tokens: 60,
+            },
+            EstimateRow {
+                prompt: "x".repeat(50),
+                prompt_tokens: 500,
+                completion_tokens: 60,
+            },
+        ];
+        let out = render_estimate(&rows, "m", &budget());
+        assert!(out.contains("  1  list files"));
+        assert!(out.contains(&format!("{}…", "x".repeat(39))));
+        assert!(out.contains("$0.0025"));
+        assert!(out.ends_with("Total: 1,620 tokens (1,500 prompt + 120 output), about $0.0040\n"));
+
+        let out = render_estimate(&rows, "unknown", &budget());
+        assert!(!out.contains('$'));
+        assert!(out.ends_with("; no price configured for unknown\n"));
+    }
+
+    #[test]
+    fn exhausted_budget_is_reported() {
+        let spend = Spend {
+            runs: 1,
+            prompt_tokens: 900,
+            completion_tokens: 100,
+            cost: 0.0,
+            unpriced_models: Vec::new(),
+        };
+        let err = ensure_within_budget(&budget(), &spend).unwrap_err();
+        assert!(err.to_string().contains("Daily token budget exhausted"));
+
+        let ok = Spend {
+            prompt_tokens: 10,
+            ..spend
+        };
+        assert!(ensure_within_budget(&budget(), &ok).is_ok());
+    }
+
+    #[test]
+    fn usage_report_lists_today() {
+        let ts = format!("{}T08:00:00Z", today().format("%Y-%m-%d"));
+        let report = render_usage_report(&[entry(&ts, "m", 100, 50)], &budget());
+        assert!(report.contains(&today().format("%Y-%m-%d").to_string()));
+        assert!(report.contains("Today: 150 of 1,000 tokens"));
+    }
+}
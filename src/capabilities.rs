@@ -0,0 +1,183 @@
+//! What each provider/model supports (streaming, JSON mode, function calling,
+//! seeds, context size). Requests leave out features the model lacks instead
+//! of failing with an opaque HTTP error; `ai.capabilities` in the global config
+//! corrects the built-in table for models it does not know.
+
+use crate::config::ProviderConfig;
+use crate::pattern::glob_match;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Server-sent event streaming of responses.
+    pub streaming: bool,
+    /// `response_format: json_object`.
+    pub json_mode: bool,
+    /// Function calling (`tools` / `tool_choice`).
+    pub tools: bool,
+    /// The `seed` request parameter.
+    pub seed: bool,
+    /// Prompt plus response tokens the model accepts; `None` when unknown.
+    pub max_context_tokens: Option<u32>,
+}
+
+/// Assumed for models missing from the table: everything is tried, and
+/// rejected function-calling requests still fall back to plain text.
+const UNKNOWN_MODEL: Capabilities = Capabilities {
+    streaming: true,
+    json_mode: true,
+    tools: true,
+    seed: true,
+    max_context_tokens: None,
+};
+
+const MOCK: Capabilities = Capabilities {
+    streaming: false,
+    json_mode: false,
+    tools: false,
+    seed: false,
+    max_context_tokens: None,
+};
+
+const fn full(max_context_tokens: u32) -> Capabilities {
+    Capabilities {
+        streaming: true,
+        json_mode: true,
+        tools: true,
+        seed: true,
+        max_context_tokens: Some(max_context_tokens),
+    }
+}
+
+/// Known model name patterns (case-insensitive globs); the first match wins.
+/// Azure deployments are looked up by deployment name, which usually mirrors
+/// the model name.
+const KNOWN_MODELS: &[(&str, Capabilities)] = &[
+    (
+        "o1-mini*",
+        Capabilities {
+            streaming: false,
+            json_mode: false,
+            tools: false,
+            seed: true,
+            max_context_tokens: Some(128_000),
+        },
+    ),
+    (
+        "o1-preview*",
+        Capabilities {
+            streaming: false,
+            json_mode: false,
+            tools: false,
+            seed: true,
+            max_context_tokens: Some(128_000),
+        },
+    ),
+    ("o1*", full(200_000)),
+    ("o3*", full(200_000)),
+    ("o4*", full(200_000)),
+    ("gpt-4.1*", full(1_047_576)),
+    ("gpt-4o*", full(128_000)),
+    ("gpt-4-turbo*", full(128_000)),
+    ("gpt-4-*-preview", full(128_000)),
+    (
+        "gpt-4-32k*",
+        Capabilities {
+            json_mode: false,
+            ..full(32_768)
+        },
+    ),
+    (
+        "gpt-4",
+        Capabilities {
+            json_mode: false,
+            ..full(8_192)
+        },
+    ),
+    (
+        "gpt-4-0*",
+        Capabilities {
+            json_mode: false,
+            ..full(8_192)
+        },
+    ),
+    ("gpt-3.5-turbo*", full(16_385)),
+];
+
+/// `ai.capabilities` section: per-field corrections to the built-in table.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapabilityOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub streaming: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_mode: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_context_tokens: Option<u32>,
+}
+
+/// Built-in capabilities for a model name.
+pub fn for_model(model: &str) -> Capabilities {
+    KNOWN_MODELS
+        .iter()
+        .find(|(pattern, _)| glob_match(pattern, model))
+        .map(|(_, caps)| *caps)
+        .unwrap_or(UNKNOWN_MODEL)
+}
+
+/// Capabilities of the configured provider, with overrides applied.
+pub fn lookup(provider: &ProviderConfig, overrides: &CapabilityOverrides) -> Capabilities {
+    let caps = match provider {
+        ProviderConfig::OpenAI { model, .. } => for_model(model),
+        ProviderConfig::Azure { deployment, .. } => for_model(deployment),
+        ProviderConfig::Mock { .. } => MOCK,
+    };
+    Capabilities {
+        streaming: overrides.streaming.unwrap_or(caps.streaming),
+        json_mode: overrides.json_mode.unwrap_or(caps.json_mode),
+        tools: overrides.tools.unwrap_or(caps.tools),
+        seed: overrides.seed.unwrap_or(caps.seed),
+        max_context_tokens: overrides.max_context_tokens.or(caps.max_context_tokens),
+    }
+}
+
+/// Rough token count for context checks (about four characters per token).
+pub fn estimate_tokens(text_len: usize) -> u32 {
+    u32::try_from(text_len.div_ceil(4)).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn known_models_match_by_prefix_and_overrides_win() {
+        assert!(!for_model("o1-mini-2024-09-12").streaming);
+        assert!(for_model("GPT-4o-mini").tools);
+        assert!(!for_model("gpt-4").json_mode);
+        assert_eq!(for_model("gpt-4-0613").max_context_tokens, Some(8_192));
+        assert_eq!(for_model("my-local-llama"), UNKNOWN_MODEL);
+
+        let provider = ProviderConfig::Azure {
+            api_key: "k".to_string(),
+            endpoint: "https://example".to_string(),
+            deployment: "gpt-4o".to_string(),
+            api_version: "2024-06-01".to_string(),
+        };
+        let overrides: CapabilityOverrides =
+            serde_yaml::from_str("tools: false\nmax_context_tokens: 4096\n").unwrap();
+        let caps = lookup(&provider, &overrides);
+        assert!(!caps.tools);
+        assert!(caps.streaming);
+        assert_eq!(caps.max_context_tokens, Some(4096));
+
+        let mock = ProviderConfig::Mock {
+            responses: PathBuf::from("mock.yaml"),
+        };
+        assert_eq!(lookup(&mock, &CapabilityOverrides::default()), MOCK);
+    }
+}
@@ -0,0 +1,162 @@
+//! Record/replay of LLM traffic. With `SAI_RECORD=path` every chat request and
+//! its response body are appended to a cassette file (NDJSON); with
+//! `SAI_REPLAY=path` responses come from that file instead of the provider, so
+//! integration tests and demos run deterministically and offline.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// One recorded exchange. Credentials travel in headers, so neither field
+/// contains API keys.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    /// JSON body sent to the chat completions endpoint.
+    pub request: Value,
+    /// Whether the response was a server-sent event stream.
+    #[serde(default)]
+    pub event_stream: bool,
+    /// Raw response body.
+    pub body: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum Cassette {
+    Record(PathBuf),
+    Replay {
+        path: PathBuf,
+        /// Interactions not yet replayed, loaded on first use.
+        remaining: RefCell<Option<Vec<Interaction>>>,
+    },
+}
+
+impl Cassette {
+    /// Reads SAI_RECORD / SAI_REPLAY. Setting both is an error.
+    pub fn from_env() -> Result<Option<Self>> {
+        let record = env::var_os("SAI_RECORD").filter(|v| !v.is_empty());
+        let replay = env::var_os("SAI_REPLAY").filter(|v| !v.is_empty());
+        match (record, replay) {
+            (Some(_), Some(_)) => Err(anyhow!(
+                "SAI_RECORD and SAI_REPLAY are both set; use only one"
+            )),
+            (Some(path), None) => Ok(Some(Cassette::Record(path.into()))),
+            (None, Some(path)) => Ok(Some(Cassette::replay(path))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    pub fn replay(path: impl Into<PathBuf>) -> Self {
+        Cassette::Replay {
+            path: path.into(),
+            remaining: RefCell::new(None),
+        }
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self, Cassette::Replay { .. })
+    }
+
+    /// Appends an interaction to a recording cassette.
+    pub fn record(&self, interaction: &Interaction) -> Result<()> {
+        let Cassette::Record(path) = self else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open cassette {}", path.display()))?;
+        let line = serde_json::to_string(interaction).context("Failed to serialize interaction")?;
+        writeln!(file, "{}", line)
+            .with_context(|| format!("Failed to write cassette {}", path.display()))
+    }
+
+    /// Takes the first unused recorded interaction whose request matches.
+    pub fn take(&self, request: &Value) -> Result<Interaction> {
+        let Cassette::Replay { path, remaining } = self else {
+            return Err(anyhow!("Cassette is not in replay mode"));
+        };
+        let mut remaining = remaining.borrow_mut();
+        if remaining.is_none() {
+            *remaining = Some(load_interactions(path)?);
+        }
+        let interactions = remaining.as_mut().expect("cassette loaded above");
+        let pos = interactions
+            .iter()
+            .position(|i| &i.request == request)
+            .ok_or_else(|| {
+                anyhow!(
+                    "No recorded response in cassette {} matches this request \
+                     (re-record with SAI_RECORD={})",
+                    path.display(),
+                    path.display()
+                )
+            })?;
+        Ok(interactions.remove(pos))
+    }
+}
+
+fn load_interactions(path: &Path) -> Result<Vec<Interaction>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read cassette {}", path.display()))?;
+    content
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .enumerate()
+        .map(|(idx, line)| {
+            serde_json::from_str(line).with_context(|| {
+                format!(
+                    "Malformed interaction on line {} of {}",
+                    idx + 1,
+                    path.display()
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn interaction(prompt: &str, body: &str) -> Interaction {
+        Interaction {
+            request: json!({ "messages": [{ "role": "user", "content": prompt }] }),
+            event_stream: false,
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn recorded_interactions_replay_in_order_by_request() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("session.cassette");
+
+        let recorder = Cassette::Record(path.clone());
+        recorder.record(&interaction("a", "first a")).unwrap();
+        recorder.record(&interaction("b", "only b")).unwrap();
+        recorder.record(&interaction("a", "second a")).unwrap();
+
+        let player = Cassette::replay(&path);
+        let a = interaction("a", "").request;
+        assert_eq!(player.take(&a).unwrap().body, "first a");
+        assert_eq!(player.take(&a).unwrap().body, "second a");
+        let err = player.take(&a).unwrap_err();
+        assert!(err.to_string().contains("No recorded response"));
+        assert_eq!(
+            player.take(&interaction("b", "").request).unwrap().body,
+            "only b"
+        );
+    }
+}
@@ -1,5 +1,12 @@
+use crate::clipboard::CopyMode;
+use crate::favorites::AliasShell;
 use crate::help;
-use clap::Parser;
+use crate::histexport::ExportFormat;
+use crate::history::{self, EntrySelector};
+use crate::promptdocs::DocsFormat;
+use crate::tasks::{TaskExport, TaskFormat};
+use clap::{ArgGroup, Parser, Subcommand};
+use std::path::PathBuf;
 
 /// Command-line interface definition for sai.
 #[derive(Parser, Debug, Clone)]
@@ -9,7 +16,26 @@ use clap::Parser;
     about = help::CLI_ABOUT,
     long_about = help::CLI_LONG_ABOUT,
     override_usage = help::CLI_USAGE,
-    after_help = help::CLI_AFTER_HELP
+    after_help = help::CLI_AFTER_HELP,
+    group(
+        ArgGroup::new("analysis").conflicts_with_all([
+            "init",
+            "create_prompt",
+            "add_prompt",
+            "list_tools",
+            "usage",
+            "confirm",
+            "unsafe_mode",
+            "peek",
+            "peek_diff",
+            "scope",
+            "arg1",
+            "prompt",
+            "more",
+            "with",
+            "explain"
+        ])
+    )
 )]
 pub struct Cli {
     /// Initialize the default config file with placeholder values
@@ -28,31 +54,97 @@ pub struct Cli {
     #[arg(long = "list-tools")]
     pub list_tools: bool,
 
-    /// Analyze the latest SAI invocation and explain what happened
+    /// Show daily token usage and cost from history, plus today's budget status, and exit
+    #[arg(long)]
+    pub usage: bool,
+
+    /// Estimate tokens and cost of generating a command for every prompt in
+    /// FILE (one per line), without calling the API; positional arguments are
+    /// prompt config files
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["analysis", "print", "candidates", "race", "auto_fix"]
+    )]
+    pub estimate: Option<PathBuf>,
+
+    /// Analyze a SAI invocation and explain what happened: the latest, or
+    /// the one INDEX runs back or from TIMESTAMP
     #[arg(
         long,
+        value_name = "INDEX|TIMESTAMP",
+        num_args = 0..=1,
+        default_missing_value = "1",
+        value_parser = history::parse_entry_selector,
+        group = "analysis"
+    )]
+    pub analyze: Option<EntrySelector>,
+
+    /// Analyze the last N invocations together, e.g. to spot repeated
+    /// failures of the same tool
+    #[arg(
+        long = "analyze-last",
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..=50),
+        group = "analysis"
+    )]
+    pub analyze_last: Option<u32>,
+
+    /// Run the command of a past run again (1 = the latest, the default)
+    /// without asking the model; it is validated and confirmed first
+    #[arg(
+        long,
+        value_name = "INDEX",
+        num_args = 0..=1,
+        default_missing_value = "1",
         conflicts_with_all = [
-            "init",
-            "create_prompt",
-            "add_prompt",
-            "list_tools",
-            "confirm",
-            "unsafe_mode",
+            "analysis",
+            "estimate",
+            "print",
+            "candidates",
+            "race",
             "peek",
+            "peek_diff",
+            "auto_peek",
             "scope",
+            "with_docs",
             "arg1",
             "prompt",
-            "explain"
+            "more",
+            "with"
+        ]
+    )]
+    pub rerun: Option<usize>,
+
+    /// Treat the prompt as feedback on the previous command and adjust that
+    /// command instead of starting over
+    #[arg(long, conflicts_with_all = ["analysis", "estimate", "rerun"])]
+    pub refine: bool,
+
+    /// Open a full-screen interface with panes for the prompt, the command,
+    /// its explanation and the history; positional arguments are prompt
+    /// config files
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "analysis",
+            "estimate",
+            "rerun",
+            "refine",
+            "print",
+            "candidates",
+            "race",
+            "auto_peek"
         ]
     )]
-    pub analyze: bool,
+    pub tui: bool,
 
     /// Ask for confirmation before executing the generated command
     #[arg(short, long)]
     pub confirm: bool,
 
     /// Explain the generated command and always ask for confirmation
-    #[arg(short = 'e', long, conflicts_with = "analyze")]
+    #[arg(short = 'e', long, conflicts_with = "analysis")]
     pub explain: bool,
 
     /// Disable operator-level safety checks (pipes, redirects, etc.).
@@ -63,16 +155,488 @@ pub struct Cli {
     /// Sample data files to send to the LLM (truncated, for schema inference).
     /// Each file is read up to PEEK_MAX_BYTES and clearly marked as sample data.
     #[arg(short = 'p', long = "peek")]
-    pub peek: Vec<String>,
+    pub peek: Vec<PathBuf>,
+
+    /// Also send samples of existing files named in the prompt (see `auto_peek` config)
+    #[arg(long = "auto-peek")]
+    pub auto_peek: bool,
+
+    /// Send a comparison of two versions of a file (columns added/removed, row
+    /// count, changed rows), for prompts like "turn A into B"
+    #[arg(long = "peek-diff", num_args = 2, value_names = ["BEFORE", "AFTER"])]
+    pub peek_diff: Vec<PathBuf>,
+
+    /// Restrict --peek samples to these CSV/TSV columns or dot-separated JSON paths
+    #[arg(long, value_name = "FIELDS", value_delimiter = ',', requires = "peek")]
+    pub fields: Vec<String>,
 
     /// Provide a path or glob hint to narrow the LLM response
     #[arg(short = 's', long = "scope", value_name = "PATTERN")]
     pub scope: Option<String>,
 
+    /// Use this model (OpenAI) or deployment (Azure) instead of the configured one
+    #[arg(long, value_name = "NAME")]
+    pub model: Option<String>,
+
+    /// Sampling temperature for command generation (0.0-2.0)
+    #[arg(long, value_name = "T", value_parser = parse_temperature)]
+    pub temperature: Option<f32>,
+
+    /// Sampling seed for providers that support reproducible output
+    #[arg(long, value_name = "N")]
+    pub seed: Option<u64>,
+
+    /// Ask for N alternative commands and pick one from a numbered menu
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(2..=9),
+        conflicts_with = "analysis"
+    )]
+    pub candidates: Option<u32>,
+
+    /// Ask the `ai` and `race` providers at once and use the first valid command
+    #[arg(long, conflicts_with_all = ["analysis", "candidates"])]
+    pub race: bool,
+
+    /// Stop the generated command after SECS seconds (exit code 124)
+    #[arg(
+        long = "exec-timeout",
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub exec_timeout: Option<u64>,
+
+    /// Reject path arguments outside the working directory (or `path_jail.root`)
+    #[arg(long)]
+    pub jail: bool,
+
+    /// Only allow read-only tools, without write-like flags or output
+    /// redirection (also enabled by SAI_READ_ONLY=1)
+    #[arg(long = "read-only", conflicts_with = "unsafe_mode")]
+    pub read_only: bool,
+
+    /// Before running a modifying command, copy the files its arguments name
+    /// into a backup directory and print how to restore them
+    #[arg(long, conflicts_with_all = ["analysis", "print", "host"])]
+    pub backup: bool,
+
+    /// Run the command in a staging workspace and move the files it writes
+    /// into place only if every step succeeds
+    #[arg(long, conflicts_with_all = ["analysis", "print", "host"])]
+    pub transaction: bool,
+
+    /// Let commands use network tools (curl, ssh, ...) that the `network:`
+    /// policy would otherwise confirm or refuse
+    #[arg(long = "allow-network")]
+    pub allow_network: bool,
+
+    /// Ask the model whether the command is read-only, modifying or destructive
+    /// before confirming it (see `confirmation.llm_review`)
+    #[arg(long, conflicts_with = "analysis")]
+    pub review: bool,
+
+    /// Run the command on USER@HOST over ssh; `--scope .` lists that host's
+    /// login directory and `--list-tools` checks its tools
+    #[arg(long, value_name = "USER@HOST", conflicts_with = "analysis")]
+    pub host: Option<String>,
+
+    /// Print the validated command to stdout and exit without running it
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "analysis",
+            "explain",
+            "confirm",
+            "capture",
+            "verify",
+            "analyze_output",
+            "auto_fix"
+        ]
+    )]
+    pub print: bool,
+
+    /// Print only the validated command, without a trailing newline, for a
+    /// shell key binding to put on the command line (see `sai shell-init`)
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "analysis",
+            "explain",
+            "confirm",
+            "capture",
+            "verify",
+            "analyze_output",
+            "auto_fix",
+            "print",
+            "copy",
+            "tui"
+        ]
+    )]
+    pub fill: bool,
+
+    /// Set by `sai export-task`: print the command as a task runner stanza.
+    #[arg(skip)]
+    pub export_task: Option<TaskExport>,
+
+    /// Copy the validated command to the local clipboard with an OSC 52
+    /// escape sequence, for terminals reached over SSH
+    #[arg(long, conflicts_with = "analysis")]
+    pub osc52: bool,
+
+    /// Copy the validated command to the clipboard instead of running it;
+    /// --copy=run copies it and runs it as usual
+    #[arg(
+        long,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "only",
+        conflicts_with_all = ["analysis", "estimate", "osc52"]
+    )]
+    pub copy: Option<CopyMode>,
+
+    /// When the command fails, send its error output to the model and offer a
+    /// corrected command, up to N times
+    #[arg(
+        long = "auto-fix",
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..=9),
+        conflicts_with = "analysis"
+    )]
+    pub auto_fix: Option<u32>,
+
+    /// Keep the end of the command's output in history for --analyze
+    #[arg(long, conflicts_with = "analysis")]
+    pub capture: bool,
+
+    /// Print all of the command's output despite `execution.max_output_lines`
+    #[arg(long, conflicts_with = "analysis")]
+    pub full_output: bool,
+
+    /// Append this run to a Markdown transcript (see `sai transcript start`)
+    #[arg(long, value_name = "FILE", conflicts_with = "analysis")]
+    pub transcript: Option<PathBuf>,
+
+    /// Do not write a history entry for this run (see `history.skipped`)
+    #[arg(long = "no-history", conflicts_with_all = ["analysis", "capture"])]
+    pub no_history: bool,
+
+    /// After a long output, print a short model-written summary of it
+    #[arg(long = "summarize-output", conflicts_with = "analysis")]
+    pub summarize_output: bool,
+
+    /// After running, print the model's interpretation of the output in the
+    /// light of the prompt
+    #[arg(long = "analyze-output", conflicts_with = "analysis")]
+    pub analyze_output: bool,
+
+    /// After running, ask the model whether the output meets EXPECTATION; exits
+    /// with 3 when it does not
+    #[arg(long, value_name = "EXPECTATION", conflicts_with = "analysis")]
+    pub verify: Option<String>,
+
+    /// Allow an extra tool for this invocation only, without changing any config
+    /// (repeatable)
+    #[arg(long = "allow-tool", value_name = "NAME")]
+    pub allow_tool: Vec<String>,
+
+    /// Ask the provider even when the same request was answered before,
+    /// instead of reusing the cached command
+    #[arg(long = "no-cache")]
+    pub no_cache: bool,
+
+    /// Add a distilled summary of a configured tool's man page or --help output
+    /// to the system prompt (repeatable)
+    #[arg(long = "with-docs", value_name = "NAME")]
+    pub with_docs: Vec<String>,
+
+    /// Merge the tools of another prompt config file into this run (repeatable)
+    #[arg(long = "with", value_name = "PATH")]
+    pub with: Vec<String>,
+
     /// Either a per-call prompt config YAML file, or the natural language prompt (simple mode)
-    #[arg(required_unless_present_any = ["init", "create_prompt", "add_prompt", "list_tools", "analyze"])]
+    #[arg(required_unless_present_any = ["init", "create_prompt", "add_prompt", "list_tools", "usage", "analysis", "estimate", "rerun", "tui"])]
     pub arg1: Option<String>,
 
     /// Natural language prompt (advanced mode, when arg1 is a config file)
     pub prompt: Option<String>,
+
+    /// More prompt config files followed by the prompt (`sai a.yaml b.yaml "..."`)
+    #[arg(hide = true)]
+    pub more: Vec<String>,
+}
+
+impl Cli {
+    /// Prompt config paths and the natural language prompt from the positional
+    /// arguments: the last one is the prompt, any before it are config files.
+    pub fn prompt_and_configs(&self) -> (Vec<String>, Option<String>) {
+        let mut positional = self.positional();
+        let prompt = positional.pop();
+        (positional, prompt)
+    }
+
+    /// All positional arguments in order.
+    pub fn positional(&self) -> Vec<String> {
+        self.arg1
+            .iter()
+            .chain(&self.prompt)
+            .chain(&self.more)
+            .cloned()
+            .collect()
+    }
+}
+
+fn parse_temperature(value: &str) -> Result<f32, String> {
+    let t: f32 = value
+        .parse()
+        .map_err(|_| format!("'{}' is not a number", value))?;
+    if !(0.0..=2.0).contains(&t) {
+        return Err("temperature must be between 0.0 and 2.0".to_string());
+    }
+    Ok(t)
+}
+
+/// Words that select a subcommand instead of a natural language prompt.
+pub const SUBCOMMANDS: &[&str] = &[
+    "init",
+    "tools",
+    "prompts",
+    "fav",
+    "history",
+    "transcript",
+    "export-task",
+    "selftest",
+    "doctor",
+    "tour",
+    "shell-init",
+];
+
+/// Command-line interface for `sai <subcommand> ...` invocations.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "sai", version, about = help::CLI_ABOUT)]
+pub struct SubcommandCli {
+    #[command(subcommand)]
+    pub command: SaiCommand,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum SaiCommand {
+    /// Write the global config (like --init), or with --project a `.sai/`
+    /// directory for the current repository
+    Init {
+        /// Scaffold `.sai/` with a project prompt config, policy and history location
+        #[arg(long)]
+        project: bool,
+    },
+    /// Inspect configured tools
+    Tools {
+        #[command(subcommand)]
+        action: ToolsAction,
+    },
+    /// Maintain prompt configs
+    Prompts {
+        #[command(subcommand)]
+        action: PromptsAction,
+    },
+    /// Save invocations under a name and run them with `sai fav NAME`
+    Fav {
+        #[command(subcommand)]
+        action: FavAction,
+    },
+    /// Browse, search and export past runs
+    History {
+        #[command(subcommand)]
+        action: HistoryAction,
+    },
+    /// Generate and validate a command, then print it as a Makefile target,
+    /// justfile recipe or package.json script instead of running it
+    ExportTask {
+        /// Task runner to write for
+        #[arg(long, value_enum)]
+        format: TaskFormat,
+
+        /// Task name; defaults to the first words of the prompt
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Arguments for sai (flags and prompt)
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Record the following runs in a Markdown transcript
+    Transcript {
+        #[command(subcommand)]
+        action: TranscriptAction,
+    },
+    /// Check config loading, prompt building, validation, execution and history
+    /// end to end with a temporary config and the mock provider
+    Selftest,
+    /// List deprecated flags and config values in the global config and favorites
+    Doctor,
+    /// Walk through simple mode, scope, peek, explain, confirm and analyze with
+    /// harmless examples
+    Tour,
+    /// Print shell functions that bind Ctrl-X Ctrl-A to replace the request
+    /// typed on the command line with the command sai generates for it
+    ShellInit {
+        /// Shell syntax to emit (defaults to the one in $SHELL)
+        #[arg(value_enum)]
+        shell: Option<AliasShell>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TranscriptAction {
+    /// Append every following run to FILE (created when missing)
+    Start { file: PathBuf },
+    /// Stop recording and print the transcript's path
+    Stop,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum HistoryAction {
+    /// The latest runs, oldest first, numbered from 1 for the latest
+    List {
+        /// Number of runs to show
+        #[arg(short = 'n', default_value_t = 20)]
+        n: usize,
+    },
+    /// Every recorded detail of one run; 1 is the latest
+    Show { index: usize },
+    /// Write all runs, including rotated ones, as a JSON array or CSV table
+    /// to stdout
+    Export {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+
+        /// Only runs from this date (YYYY-MM-DD, UTC) or RFC 3339 time on
+        #[arg(long, value_name = "DATE")]
+        since: Option<String>,
+    },
+    /// Past runs whose prompt or command is similar to QUERY, best match first
+    Find {
+        query: String,
+
+        /// Number of runs to show
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Past runs whose prompt, generated command or working directory
+    /// contains QUERY, newest first
+    Search {
+        query: String,
+
+        /// Number of runs to show
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum FavAction {
+    /// Save sai arguments under NAME, e.g. `sai fav add todos -- -s src "count TODOs"`
+    Add {
+        name: String,
+
+        /// Arguments for sai (flags and prompt)
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// List saved favorites
+    List,
+    /// Delete a favorite
+    Remove { name: String },
+    /// Print a shell function that runs `sai fav NAME`, or append it to your shell rc file
+    Alias {
+        name: String,
+
+        /// Shell syntax to emit (defaults to the one in $SHELL)
+        #[arg(long, value_enum)]
+        shell: Option<AliasShell>,
+
+        /// Append to ~/.bashrc, ~/.zshrc or fish config.fish after confirmation
+        #[arg(long)]
+        append: bool,
+    },
+    /// Run a favorite: `sai fav NAME [EXTRA FLAGS...]`
+    #[command(external_subcommand)]
+    Run(Vec<String>),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ToolsAction {
+    /// Show the local man page (or --help output) of a configured tool
+    Man {
+        /// Tool name as configured in the prompt
+        name: String,
+
+        /// Optional prompt config file to look the tool up in
+        #[arg(value_name = "PROMPT_CONFIG")]
+        prompt: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum PromptsAction {
+    /// Suggest tool additions/removals for the default prompt based on history,
+    /// printed as a unified diff against the global config
+    Suggest {
+        /// Minimum number of validation rejections before suggesting a tool
+        #[arg(long, value_name = "N", default_value_t = 2)]
+        min_rejections: usize,
+    },
+    /// Render a prompt config (meta prompt, tools, rules and examples) as a
+    /// human-readable document
+    Docs {
+        /// Prompt config file; defaults to the global default_prompt
+        #[arg(value_name = "PROMPT_CONFIG")]
+        prompt: Option<String>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = DocsFormat::Md)]
+        format: DocsFormat,
+    },
+}
+
+/// Returns true when the raw arguments start with a known subcommand.
+pub fn is_subcommand(args: &[String]) -> bool {
+    args.first()
+        .map(|a| SUBCOMMANDS.contains(&a.as_str()))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temperature_must_be_in_range() {
+        let cli = Cli::parse_from(["sai", "--temperature", "0.7", "list files"]);
+        assert_eq!(cli.temperature, Some(0.7));
+        assert!(Cli::try_parse_from(["sai", "--temperature", "3", "list files"]).is_err());
+        assert!(Cli::try_parse_from(["sai", "--temperature", "hot", "list files"]).is_err());
+    }
+
+    #[test]
+    fn last_positional_is_the_prompt() {
+        let cli = Cli::parse_from(["sai", "a.yaml", "b.yaml", "c.yaml", "do it"]);
+        assert_eq!(
+            cli.prompt_and_configs(),
+            (
+                vec![
+                    "a.yaml".to_string(),
+                    "b.yaml".to_string(),
+                    "c.yaml".to_string()
+                ],
+                Some("do it".to_string())
+            )
+        );
+        let cli = Cli::parse_from(["sai", "list files"]);
+        assert_eq!(
+            cli.prompt_and_configs(),
+            (vec![], Some("list files".to_string()))
+        );
+    }
 }
@@ -0,0 +1,118 @@
+//! `--copy`: puts the validated command on the system clipboard. Local
+//! sessions use the platform's clipboard tool (pbcopy, wl-copy, xclip, xsel,
+//! clip); over SSH, or when none is installed, the command is sent to the
+//! terminal as an OSC 52 sequence (see [`osc52`]), which reaches the
+//! clipboard of the machine the terminal runs on.
+
+use crate::envinfo::find_on_path;
+use crate::osc52;
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CopyMode {
+    /// Copy the command instead of running it
+    Only,
+    /// Copy the command, then run it as usual
+    Run,
+}
+
+/// How the command reaches the clipboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// A clipboard tool reading the text on stdin.
+    Tool(&'static str, &'static [&'static str]),
+    Osc52,
+}
+
+impl Method {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Method::Tool(tool, _) => tool,
+            Method::Osc52 => "OSC 52",
+        }
+    }
+}
+
+/// Clipboard tools in order of preference, with the session variable each
+/// needs (none for the platform's own).
+const TOOLS: &[(Option<&str>, &str, &[&str])] = &[
+    (None, "pbcopy", &[]),
+    (Some("WAYLAND_DISPLAY"), "wl-copy", &[]),
+    (Some("DISPLAY"), "xclip", &["-selection", "clipboard"]),
+    (Some("DISPLAY"), "xsel", &["--clipboard", "--input"]),
+    (None, "clip.exe", &[]),
+];
+
+/// Picks the method for a session described by `var` (environment lookup)
+/// and `installed` (whether a tool is on PATH).
+pub fn choose(var: impl Fn(&str) -> bool, installed: impl Fn(&str) -> bool) -> Method {
+    // The clipboard tools would fill the remote machine's clipboard.
+    if var("SSH_CONNECTION") || var("SSH_TTY") {
+        return Method::Osc52;
+    }
+    TOOLS
+        .iter()
+        .find(|(needs, tool, _)| needs.is_none_or(&var) && installed(tool))
+        .map_or(Method::Osc52, |(_, tool, args)| Method::Tool(tool, args))
+}
+
+/// Copies `text` to the clipboard and returns the method used.
+pub fn copy(text: &str) -> Result<Method> {
+    let method = choose(
+        |name| env::var_os(name).is_some_and(|v| !v.is_empty()),
+        |tool| find_on_path(tool).is_some(),
+    );
+    match method {
+        Method::Osc52 => osc52::copy_to_clipboard(text)?,
+        Method::Tool(tool, args) => {
+            let mut child = Command::new(tool)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .with_context(|| format!("Failed to start {}", tool))?;
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin
+                    .write_all(text.as_bytes())
+                    .with_context(|| format!("Failed to write the command to {}", tool))?;
+            }
+            let status = child.wait()?;
+            if !status.success() {
+                return Err(anyhow!("{} could not copy the command ({})", tool, status));
+            }
+        }
+    }
+    Ok(method)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_pick_a_clipboard_tool_or_osc52() {
+        let session = |vars: &'static [&'static str], tools: &'static [&'static str]| {
+            choose(|v| vars.contains(&v), |t| tools.contains(&t))
+        };
+        assert_eq!(session(&[], &["pbcopy"]), Method::Tool("pbcopy", &[]));
+        assert_eq!(
+            session(&["DISPLAY"], &["xsel", "wl-copy"]),
+            Method::Tool("xsel", &["--clipboard", "--input"])
+        );
+        assert_eq!(
+            session(&["WAYLAND_DISPLAY", "DISPLAY"], &["xclip", "wl-copy"]).name(),
+            "wl-copy"
+        );
+        // No display for xclip, or a remote session: the terminal copies.
+        assert_eq!(session(&[], &["xclip"]), Method::Osc52);
+        assert_eq!(
+            session(&["SSH_CONNECTION", "DISPLAY"], &["xclip"]),
+            Method::Osc52
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use crate::app;
+use crate::cli::{
+    FavAction, HistoryAction, PromptsAction, SaiCommand, SubcommandCli, ToolsAction,
+    TranscriptAction,
+};
+use crate::config::find_global_config_path;
+use crate::deprecation;
+use crate::favorites::{self, AliasShell};
+use crate::histexport;
+use crate::history;
+use crate::ops;
+use crate::promptdocs;
+use crate::recall;
+use crate::selftest;
+use crate::shellinit;
+use crate::suggest;
+use crate::tasks;
+use crate::tour;
+use crate::transcript;
+use anyhow::{anyhow, Context, Result};
+use std::env;
+use std::io;
+
+/// Runs a `sai <subcommand>` invocation and returns the process exit code.
+pub fn run(cli: SubcommandCli) -> Result<i32> {
+    let global_config_path = find_global_config_path();
+
+    match cli.command {
+        SaiCommand::Init { project: false } => ops::init_global_config(&global_config_path)?,
+        SaiCommand::Init { project: true } => {
+            let cwd = env::current_dir().context("Failed to determine current directory")?;
+            ops::init_project_config(&cwd)?;
+        }
+        SaiCommand::Tools { action } => match action {
+            ToolsAction::Man { name, prompt } => {
+                ops::show_tool_docs(&global_config_path, &name, prompt.as_deref())?;
+            }
+        },
+        SaiCommand::Prompts { action } => match action {
+            PromptsAction::Suggest { min_rejections } => {
+                suggest::print_prompt_suggestions(&global_config_path, min_rejections)?;
+            }
+            PromptsAction::Docs { prompt, format } => {
+                promptdocs::print_prompt_docs(&global_config_path, prompt.as_deref(), format)?;
+            }
+        },
+        SaiCommand::Fav { action } => {
+            let path = favorites::favorites_path();
+            match action {
+                FavAction::Add { name, args } => favorites::add_favorite(&path, &name, &args)?,
+                FavAction::List => favorites::list_favorites(&path)?,
+                FavAction::Remove { name } => favorites::remove_favorite(&path, &name)?,
+                FavAction::Alias {
+                    name,
+                    shell,
+                    append,
+                } => {
+                    let mut stdin = io::stdin().lock();
+                    favorites::alias_favorite(&path, &name, shell, append, &mut stdin)?;
+                }
+                FavAction::Run(words) => {
+                    let (name, extra) = words
+                        .split_first()
+                        .ok_or_else(|| anyhow!("Expected a favorite name"))?;
+                    let cli = favorites::favorite_cli(&path, name, extra)?;
+                    return app::run_cli(cli);
+                }
+            }
+        }
+        SaiCommand::History { action } => match action {
+            HistoryAction::List { n } => history::print_list(n)?,
+            HistoryAction::Show { index } => history::print_entry(index)?,
+            HistoryAction::Export { format, since } => {
+                histexport::run_export(format, since.as_deref())?
+            }
+            HistoryAction::Find { query, limit } => recall::print_matches(&query, limit)?,
+            HistoryAction::Search { query, limit } => recall::print_search(&query, limit)?,
+        },
+        SaiCommand::ExportTask { format, name, args } => {
+            return app::run_cli(tasks::export_cli(format, name, &args)?);
+        }
+        SaiCommand::Transcript { action } => match action {
+            TranscriptAction::Start { file } => {
+                let file = transcript::start(&file)?;
+                println!(
+                    "Recording runs in {}; `sai transcript stop` ends it.",
+                    file.display()
+                );
+            }
+            TranscriptAction::Stop => {
+                println!("Transcript saved to {}", transcript::stop()?.display());
+            }
+        },
+        SaiCommand::Selftest => return selftest::run_selftest(),
+        SaiCommand::Doctor => return deprecation::run_doctor(),
+        SaiCommand::Tour => return tour::run_tour_interactive(),
+        SaiCommand::ShellInit { shell } => {
+            print!(
+                "{}",
+                shellinit::init_script(shell.unwrap_or_else(AliasShell::detect))
+            );
+        }
+    }
+
+    Ok(0)
+}
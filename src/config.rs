@@ -1,7 +1,24 @@
+use crate::budget::BudgetConfig;
+use crate::capabilities::{self, Capabilities, CapabilityOverrides};
+use crate::executor::{validate_env_names, EnvPolicy, ResourceLimits};
+use crate::guard::PromptGuardConfig;
+use crate::history::HistoryConfig;
+use crate::jail::PathJailConfig;
+use crate::network::NetworkConfig;
+use crate::pager::{ExecutionConfig, PagerConfig};
+use crate::peek::AutoPeekConfig;
+use crate::project::ProjectToolsConfig;
+use crate::redact::RedactionConfig;
+use crate::risk::ConfirmationConfig;
+use crate::safety::{validate_allowed_operators, validate_flag_lists};
+use crate::shellcheck::ShellcheckConfig;
+use crate::targets::TargetConfig;
+use crate::webhook::HooksConfig;
 use anyhow::{anyhow, Context, Result};
 use dirs::config_dir;
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -14,6 +31,73 @@ pub struct GlobalConfig {
 
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub default_prompt: Option<PromptConfig>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget: Option<BudgetConfig>,
+
+    /// Confirmation behavior per risk level of the generated command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<ConfirmationConfig>,
+
+    /// Wordlists that flag suspicious prompts and require an acknowledgment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_guard: Option<PromptGuardConfig>,
+
+    /// Attaches samples of files the prompt names, as if given with --peek.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_peek: Option<AutoPeekConfig>,
+
+    /// What history entries record beyond the defaults.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<HistoryConfig>,
+
+    /// Niceness and resource limits for generated commands.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<ResourceLimits>,
+
+    /// Which environment variables generated commands inherit or get set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_policy: Option<EnvPolicy>,
+
+    /// Sends long command output through `$PAGER` on a terminal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pager: Option<PagerConfig>,
+
+    /// Caps the stdout lines printed for chatty commands.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execution: Option<ExecutionConfig>,
+
+    /// Second provider for `--race`; keys left out are taken from `ai`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub race: Option<AiConfig>,
+
+    /// Keeps path arguments inside the working directory or a configured root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_jail: Option<PathJailConfig>,
+
+    /// Confirms, refuses in CI or requires --allow-network for network tools.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<NetworkConfig>,
+
+    /// ShellCheck findings for generated commands, shown or blocking.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shellcheck: Option<ShellcheckConfig>,
+
+    /// Audit webhook notified after each executed command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hooks: Option<HooksConfig>,
+
+    /// Secrets replaced with placeholders before a request is sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redaction: Option<RedactionConfig>,
+
+    /// Project-local tool directories searched before PATH.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_tools: Option<ProjectToolsConfig>,
+
+    /// SSH hosts and containers checked by `--list-tools`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<TargetConfig>,
 }
 
 /// AI configuration that may come from file and/or environment.
@@ -39,6 +123,49 @@ pub struct AiConfig {
     pub azure_deployment: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub azure_api_version: Option<String>,
+
+    /// Stream tokens for explanations and analysis as they arrive.
+    /// Defaults to true; providers that don't stream fall back transparently.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+
+    /// Total attempts for an LLM request when transient failures occur (429, 5xx,
+    /// connection errors). Defaults to 3.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+
+    /// Base delay in milliseconds for exponential backoff between attempts. Defaults to 500.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_base_delay_ms: Option<u64>,
+
+    /// Proxy URL for LLM requests (e.g. http://proxy.corp:3128).
+    /// When unset, the standard HTTP_PROXY/HTTPS_PROXY/NO_PROXY variables apply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+
+    /// Ask for the command through function calling (structured JSON) instead of
+    /// free text. Defaults to true; requests the provider rejects are retried as text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_calling: Option<bool>,
+
+    /// Sampling seed sent with each request, for providers that support
+    /// deterministic sampling. Unset by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+
+    /// Upper bound on response tokens, mainly to keep --explain/--analyze output
+    /// short. Command generation always allows at least 256 tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+
+    /// YAML file of canned prompt-pattern → command answers for `provider: mock`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mock_responses: Option<String>,
+
+    /// Corrections to the built-in capability table for the configured model
+    /// (streaming, json_mode, tools, seed, max_context_tokens).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<CapabilityOverrides>,
 }
 
 /// Prompt configuration (also used as per-call config).
@@ -48,10 +175,15 @@ pub struct PromptConfig {
     pub meta_prompt: Option<String>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub tools: Vec<ToolConfig>,
+    /// Shell operators commands may use without --unsafe, e.g. `["|", ">"]`.
+    /// Such commands run through the shell, and every command in them must
+    /// use an allowed tool.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_operators: Vec<String>,
 }
 
 /// Single tool description for the LLM.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ToolConfig {
     pub name: String,
 
@@ -62,6 +194,60 @@ pub struct ToolConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub force_explain: Option<bool>,
 
+    /// Maximum run time in seconds for commands using this tool; the command
+    /// is killed when it is exceeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+
+    /// Fixed command line with typed placeholders, e.g.
+    /// `rsync -av {src:dir} {dst:path}`. Commands using this tool must match
+    /// it exactly; only the placeholders vary (see `template`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+
+    /// Environment variables set only when this tool is the executed binary,
+    /// e.g. `JQ_COLORS` or `AWS_PROFILE`. Applied after `env_policy`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+
+    /// When set, the only flags commands using this tool may pass, e.g.
+    /// `["-s", "-L", "--fail"]`. Short flags also count inside clusters
+    /// such as `-sL`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_flags: Vec<String>,
+
+    /// Flags commands using this tool may not pass, e.g. `["-r", "-R",
+    /// "--recursive"]` for rm; `-r` also matches `-rf`, `--output` also
+    /// `--output=FILE`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied_flags: Vec<String>,
+
+    /// Whether the tool never modifies anything, for `--read-only`. Unset
+    /// uses the built-in list of read-only tools.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+
+    /// Other names commands may invoke the tool by, e.g. `python3` for a
+    /// `python` tool.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+
+    /// Whether the tool reaches the network, for the `network:` policy;
+    /// unset uses the built-in list (curl, wget, ssh, kubectl, ...).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<bool>,
+
+    /// Glob the working directory must match for commands using this tool,
+    /// e.g. `**/infra/modules/*`; without a leading `/` it may match the end
+    /// of the path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd_must_match: Option<String>,
+
+    /// Subdirectory of the working directory that commands using this tool
+    /// run in, e.g. `frontend/`; their relative paths start there.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chdir: Option<String>,
+
     /// The tool configuration or description.
     /// Example:
     ///    Tool: jq
@@ -77,9 +263,94 @@ pub struct ToolConfig {
     pub config: String,
 }
 
-/// Provider resolved after merging env + file.
+impl ToolConfig {
+    /// Whether the command word `word` invokes this tool: its name or an
+    /// alias, possibly spelled as a path (see [`command_name`]).
+    pub fn matches(&self, word: &str) -> bool {
+        command_name(word)
+            .is_some_and(|name| self.name == name || self.aliases.iter().any(|a| a == name))
+    }
+}
+
+/// The program name a command word runs: the word itself, or the file name
+/// of a path into one of the PATH directories (`/usr/bin/jq` gives `jq`).
+/// Other paths give `None`, so `./jq` or `/tmp/x/jq` never pass for a tool.
+pub fn command_name(word: &str) -> Option<&str> {
+    let path = Path::new(word);
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Some(word);
+    };
+    if dir.as_os_str().is_empty() {
+        return Some(word);
+    }
+    let on_path = env::var_os("PATH")
+        .is_some_and(|paths| env::split_paths(&paths).any(|p| p.is_absolute() && p == dir));
+    on_path.then(|| name.to_str()).flatten()
+}
+
+/// AI settings resolved after merging env + file.
+#[derive(Debug, Clone)]
+pub struct EffectiveAiConfig {
+    pub provider: ProviderConfig,
+    pub stream: bool,
+    pub retry: RetryPolicy,
+    pub proxy: Option<String>,
+    pub function_calling: bool,
+    /// Sampling temperature for command generation; `None` uses the built-in default.
+    pub temperature: Option<f32>,
+    /// Sampling seed passed to the provider; recorded in history for reproduction.
+    pub seed: Option<u64>,
+    pub max_tokens: Option<u32>,
+    pub capability_overrides: CapabilityOverrides,
+    /// Applied to the prompt, scope listing and peek sample of command
+    /// generation requests; set from the global `redaction:` section.
+    pub redaction: RedactionConfig,
+}
+
+impl EffectiveAiConfig {
+    /// What the configured provider/model supports, after `ai.capabilities`.
+    pub fn capabilities(&self) -> Capabilities {
+        capabilities::lookup(&self.provider, &self.capability_overrides)
+    }
+
+    /// Replaces the OpenAI model or the Azure deployment for this run.
+    pub fn set_model(&mut self, name: &str) {
+        match &mut self.provider {
+            ProviderConfig::OpenAI { model, .. } => *model = name.to_string(),
+            ProviderConfig::Azure { deployment, .. } => *deployment = name.to_string(),
+            ProviderConfig::Mock { .. } => {}
+        }
+    }
+
+    /// Model (OpenAI) or deployment (Azure) name, used to price token usage.
+    pub fn model_name(&self) -> &str {
+        match &self.provider {
+            ProviderConfig::OpenAI { model, .. } => model,
+            ProviderConfig::Azure { deployment, .. } => deployment,
+            ProviderConfig::Mock { .. } => "mock",
+        }
+    }
+}
+
+/// Retry behaviour for transient LLM request failures.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay_ms: 500,
+        }
+    }
+}
+
+/// Provider endpoint and credentials.
 #[derive(Debug, Clone)]
-pub enum EffectiveAiConfig {
+pub enum ProviderConfig {
     OpenAI {
         api_key: String,
         base_url: String,
@@ -91,6 +362,8 @@ pub enum EffectiveAiConfig {
         deployment: String,
         api_version: String,
     },
+    /// Canned answers from a local file; no network access or API key.
+    Mock { responses: PathBuf },
 }
 
 thread_local! {
@@ -121,7 +394,7 @@ pub fn set_config_dir_override_for_tests<P: Into<PathBuf>>(dir: P) -> ConfigDirO
     let dir = dir.into();
     let prev = CONFIG_ROOT_OVERRIDE.with(|cell| {
         let mut guard = cell.borrow_mut();
-        std::mem::replace(&mut *guard, Some(dir))
+        guard.replace(dir)
     });
     ConfigDirOverrideGuard { prev }
 }
@@ -144,6 +417,26 @@ pub fn load_global_config(path: &Path) -> Result<GlobalConfig> {
         .with_context(|| format!("Failed to read global config file {}", path.display()))?;
     let cfg: GlobalConfig = serde_yaml::from_str(&content)
         .with_context(|| format!("Failed to parse global config YAML {}", path.display()))?;
+    if let Some(limits) = &cfg.limits {
+        limits
+            .validate()
+            .with_context(|| format!("Invalid global config {}", path.display()))?;
+    }
+    if let Some(policy) = &cfg.env_policy {
+        policy
+            .validate()
+            .with_context(|| format!("Invalid global config {}", path.display()))?;
+    }
+    if let Some(prompt) = &cfg.default_prompt {
+        validate_allowed_operators(&prompt.allowed_operators)
+            .and_then(|()| prompt.tools.iter().try_for_each(validate_flag_lists))
+            .with_context(|| format!("Invalid global config {}", path.display()))?;
+    }
+    for target in &cfg.targets {
+        target
+            .validate()
+            .with_context(|| format!("Invalid global config {}", path.display()))?;
+    }
     Ok(cfg)
 }
 
@@ -152,22 +445,102 @@ pub fn load_prompt_config(path: &Path) -> Result<PromptConfig> {
         .with_context(|| format!("Failed to read prompt config file {}", path.display()))?;
     let cfg: PromptConfig = serde_yaml::from_str(&content)
         .with_context(|| format!("Failed to parse prompt config YAML {}", path.display()))?;
+    for tool in &cfg.tools {
+        validate_env_names(&tool.env, &format!("Tool '{}' env", tool.name))
+            .and_then(|()| validate_flag_lists(tool))
+            .with_context(|| format!("Invalid prompt config {}", path.display()))?;
+    }
+    validate_allowed_operators(&cfg.allowed_operators)
+        .with_context(|| format!("Invalid prompt config {}", path.display()))?;
     Ok(cfg)
 }
 
 pub fn resolve_ai_config(global_ai: Option<AiConfig>) -> Result<EffectiveAiConfig> {
-    let file_ai = global_ai.unwrap_or_default();
+    resolve_layered(global_ai.unwrap_or_default(), AiConfig::default())
+}
 
-    let provider = env_or(file_ai.provider, "SAI_PROVIDER");
+/// The second provider for `--race`: keys set in the `race:` section win, the
+/// rest resolve like `ai` (environment first, then file).
+pub fn resolve_race_config(
+    global_ai: Option<AiConfig>,
+    race: AiConfig,
+) -> Result<EffectiveAiConfig> {
+    resolve_layered(global_ai.unwrap_or_default(), race)
+}
 
-    let openai_api_key = env_or(file_ai.openai_api_key, "SAI_OPENAI_API_KEY");
-    let openai_base_url = env_or(file_ai.openai_base_url, "SAI_OPENAI_BASE_URL");
-    let openai_model = env_or(file_ai.openai_model, "SAI_OPENAI_MODEL");
+/// Resolves `file_ai` with environment overrides, except for the keys set in
+/// `pinned`, which beat both.
+fn resolve_layered(file_ai: AiConfig, pinned: AiConfig) -> Result<EffectiveAiConfig> {
+    let text = |pinned: Option<String>, file: Option<String>, env_key: &str| {
+        pinned.or_else(|| env_or(file, env_key))
+    };
+    let flag = |pinned: Option<bool>, file: Option<bool>, env_key: &str| {
+        pinned.or_else(|| env_bool_or(file, env_key))
+    };
 
-    let azure_api_key = env_or(file_ai.azure_api_key, "SAI_AZURE_API_KEY");
-    let azure_endpoint = env_or(file_ai.azure_endpoint, "SAI_AZURE_ENDPOINT");
-    let azure_deployment = env_or(file_ai.azure_deployment, "SAI_AZURE_DEPLOYMENT");
-    let azure_api_version = env_or(file_ai.azure_api_version, "SAI_AZURE_API_VERSION");
+    let provider = text(pinned.provider, file_ai.provider, "SAI_PROVIDER");
+
+    let openai_api_key = text(
+        pinned.openai_api_key,
+        file_ai.openai_api_key,
+        "SAI_OPENAI_API_KEY",
+    );
+    let openai_base_url = text(
+        pinned.openai_base_url,
+        file_ai.openai_base_url,
+        "SAI_OPENAI_BASE_URL",
+    );
+    let openai_model = text(
+        pinned.openai_model,
+        file_ai.openai_model,
+        "SAI_OPENAI_MODEL",
+    );
+
+    let azure_api_key = text(
+        pinned.azure_api_key,
+        file_ai.azure_api_key,
+        "SAI_AZURE_API_KEY",
+    );
+    let azure_endpoint = text(
+        pinned.azure_endpoint,
+        file_ai.azure_endpoint,
+        "SAI_AZURE_ENDPOINT",
+    );
+    let azure_deployment = text(
+        pinned.azure_deployment,
+        file_ai.azure_deployment,
+        "SAI_AZURE_DEPLOYMENT",
+    );
+    let azure_api_version = text(
+        pinned.azure_api_version,
+        file_ai.azure_api_version,
+        "SAI_AZURE_API_VERSION",
+    );
+    let mock_responses = text(
+        pinned.mock_responses,
+        file_ai.mock_responses,
+        "SAI_MOCK_RESPONSES",
+    );
+
+    let stream = flag(pinned.stream, file_ai.stream, "SAI_STREAM").unwrap_or(true);
+    let proxy = text(pinned.proxy, file_ai.proxy, "SAI_PROXY");
+    let function_calling = flag(
+        pinned.function_calling,
+        file_ai.function_calling,
+        "SAI_FUNCTION_CALLING",
+    )
+    .unwrap_or(true);
+    let defaults = RetryPolicy::default();
+    let retry = RetryPolicy {
+        max_attempts: pinned
+            .max_attempts
+            .or(file_ai.max_attempts)
+            .unwrap_or(defaults.max_attempts),
+        base_delay_ms: pinned
+            .retry_base_delay_ms
+            .or(file_ai.retry_base_delay_ms)
+            .unwrap_or(defaults.base_delay_ms),
+    };
 
     let provider = if let Some(p) = provider {
         p.to_lowercase()
@@ -181,7 +554,7 @@ pub fn resolve_ai_config(global_ai: Option<AiConfig>) -> Result<EffectiveAiConfi
         ));
     };
 
-    match provider.as_str() {
+    let provider = match provider.as_str() {
         "openai" => {
             let api_key = openai_api_key.ok_or_else(|| {
                 anyhow!("OpenAI selected but no OPENAI API key configured (SAI_OPENAI_API_KEY)")
@@ -191,11 +564,14 @@ pub fn resolve_ai_config(global_ai: Option<AiConfig>) -> Result<EffectiveAiConfi
             let model = openai_model.ok_or_else(|| {
                 anyhow!("OpenAI selected but no model configured (SAI_OPENAI_MODEL)")
             })?;
-            Ok(EffectiveAiConfig::OpenAI {
+            ensure_not_placeholder(&api_key, "openai_api_key", "SAI_OPENAI_API_KEY")?;
+            ensure_not_placeholder(&base_url, "openai_base_url", "SAI_OPENAI_BASE_URL")?;
+            ensure_not_placeholder(&model, "openai_model", "SAI_OPENAI_MODEL")?;
+            ProviderConfig::OpenAI {
                 api_key,
                 base_url,
                 model,
-            })
+            }
         }
         "azure" => {
             let api_key = azure_api_key.ok_or_else(|| {
@@ -210,18 +586,95 @@ pub fn resolve_ai_config(global_ai: Option<AiConfig>) -> Result<EffectiveAiConfi
             let api_version = azure_api_version.ok_or_else(|| {
                 anyhow!("Azure selected but no API version configured (SAI_AZURE_API_VERSION)")
             })?;
-            Ok(EffectiveAiConfig::Azure {
+            ensure_not_placeholder(&api_key, "azure_api_key", "SAI_AZURE_API_KEY")?;
+            ensure_not_placeholder(&endpoint, "azure_endpoint", "SAI_AZURE_ENDPOINT")?;
+            ensure_not_placeholder(&deployment, "azure_deployment", "SAI_AZURE_DEPLOYMENT")?;
+            ensure_not_placeholder(&api_version, "azure_api_version", "SAI_AZURE_API_VERSION")?;
+            ProviderConfig::Azure {
                 api_key,
                 endpoint,
                 deployment,
                 api_version,
-            })
+            }
+        }
+        "mock" => {
+            let responses = mock_responses.ok_or_else(|| {
+                anyhow!("Mock provider selected but no mock_responses file configured (SAI_MOCK_RESPONSES)")
+            })?;
+            ProviderConfig::Mock {
+                responses: PathBuf::from(responses),
+            }
+        }
+        other => {
+            return Err(anyhow!(
+                "Unsupported provider '{}'. Use 'openai', 'azure' or 'mock'.",
+                other
+            ))
         }
-        other => Err(anyhow!(
-            "Unsupported provider '{}'. Use 'openai' or 'azure'.",
-            other
-        )),
+    };
+
+    Ok(EffectiveAiConfig {
+        provider,
+        stream,
+        retry,
+        proxy,
+        function_calling,
+        temperature: None,
+        seed: pinned.seed.or(file_ai.seed),
+        max_tokens: pinned.max_tokens.or(file_ai.max_tokens),
+        capability_overrides: pinned
+            .capabilities
+            .or(file_ai.capabilities)
+            .unwrap_or_default(),
+        redaction: RedactionConfig::default(),
+    })
+}
+
+/// Values written by `sai --init` (or copied from docs) that must be replaced.
+const PLACEHOLDER_VALUES: &[&str] = &[
+    "changeme",
+    "change-me",
+    "change_me",
+    "replaceme",
+    "replace-me",
+    "your-api-key",
+    "your_api_key",
+    "todo",
+    "xxx",
+];
+
+fn is_placeholder(value: &str) -> bool {
+    let value = value.trim().to_ascii_lowercase();
+    PLACEHOLDER_VALUES.contains(&value.as_str())
+        || value.contains("your-azure-openai-resource")
+        || (value.starts_with('<') && value.ends_with('>'))
+}
+
+/// Rejects template placeholders up front, naming the key and where it came
+/// from, instead of letting the provider fail later with a generic auth error.
+fn ensure_not_placeholder(value: &str, file_key: &str, env_key: &str) -> Result<()> {
+    if !is_placeholder(value) {
+        return Ok(());
+    }
+
+    let from_env = env::var(env_key).map(|v| !v.is_empty()).unwrap_or(false);
+    if from_env {
+        return Err(anyhow!(
+            "Environment variable {} still has the placeholder value '{}'. \
+             Set it to a real value or unset it to use the config file.",
+            env_key,
+            value
+        ));
     }
+
+    Err(anyhow!(
+        "ai.{} in {} still has the placeholder value '{}' written by `sai --init`. \
+         Edit the file and set a real value, or export {} to override it.",
+        file_key,
+        find_global_config_path().display(),
+        value,
+        env_key
+    ))
 }
 
 fn env_or(file_value: Option<String>, env_key: &str) -> Option<String> {
@@ -233,6 +686,17 @@ fn env_or(file_value: Option<String>, env_key: &str) -> Option<String> {
     file_value
 }
 
+fn env_bool_or(file_value: Option<bool>, env_key: &str) -> Option<bool> {
+    if let Ok(v) = env::var(env_key) {
+        match v.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => return Some(true),
+            "0" | "false" | "no" | "off" => return Some(false),
+            _ => {}
+        }
+    }
+    file_value
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -257,6 +721,105 @@ mod tests {
         }
     }
 
+    #[test]
+    fn proxy_and_seed_are_read_from_config_file() {
+        let _guard = ENV_MUTEX.lock().unwrap();
+        let ai = AiConfig {
+            provider: Some("openai".to_string()),
+            openai_api_key: Some("key".to_string()),
+            openai_model: Some("model".to_string()),
+            proxy: Some("http://proxy.example:3128".to_string()),
+            seed: Some(42),
+            ..AiConfig::default()
+        };
+        let cfg = resolve_ai_config(Some(ai)).unwrap();
+        assert_eq!(cfg.proxy.as_deref(), Some("http://proxy.example:3128"));
+        assert_eq!(cfg.seed, Some(42));
+    }
+
+    #[test]
+    fn mock_provider_needs_only_a_responses_file() {
+        let _guard = ENV_MUTEX.lock().unwrap();
+        let ai = AiConfig {
+            provider: Some("mock".to_string()),
+            ..AiConfig::default()
+        };
+        let err = resolve_ai_config(Some(ai.clone())).unwrap_err();
+        assert!(err.to_string().contains("mock_responses"));
+
+        let ai = AiConfig {
+            mock_responses: Some("ci/mock.yaml".to_string()),
+            ..ai
+        };
+        let cfg = resolve_ai_config(Some(ai)).unwrap();
+        assert!(matches!(cfg.provider, ProviderConfig::Mock { .. }));
+        assert_eq!(cfg.model_name(), "mock");
+    }
+
+    #[test]
+    fn race_section_overrides_environment_and_inherits_the_rest() {
+        let _guard = ENV_MUTEX.lock().unwrap();
+        unsafe {
+            env::set_var("SAI_OPENAI_MODEL", "env-model");
+        }
+        let ai = AiConfig {
+            provider: Some("openai".to_string()),
+            openai_api_key: Some("key".to_string()),
+            seed: Some(7),
+            ..AiConfig::default()
+        };
+        let race = AiConfig {
+            openai_model: Some("race-model".to_string()),
+            ..AiConfig::default()
+        };
+        let primary = resolve_ai_config(Some(ai.clone())).unwrap();
+        let second = resolve_race_config(Some(ai), race).unwrap();
+        unsafe {
+            env::remove_var("SAI_OPENAI_MODEL");
+        }
+        assert_eq!(primary.model_name(), "env-model");
+        assert_eq!(second.model_name(), "race-model");
+        assert_eq!(second.seed, Some(7));
+    }
+
+    #[test]
+    fn model_override_replaces_model_or_deployment() {
+        let _guard = ENV_MUTEX.lock().unwrap();
+        let ai = AiConfig {
+            provider: Some("azure".to_string()),
+            azure_api_key: Some("key".to_string()),
+            azure_endpoint: Some("https://example.openai.azure.com".to_string()),
+            azure_deployment: Some("small".to_string()),
+            azure_api_version: Some("2024-02-15-preview".to_string()),
+            ..AiConfig::default()
+        };
+        let mut cfg = resolve_ai_config(Some(ai)).unwrap();
+        assert_eq!(cfg.model_name(), "small");
+        cfg.set_model("large");
+        assert_eq!(cfg.model_name(), "large");
+    }
+
+    #[test]
+    fn init_placeholders_are_reported_with_key_and_file() {
+        let _guard = ENV_MUTEX.lock().unwrap();
+        let ai = AiConfig {
+            provider: Some("openai".to_string()),
+            openai_api_key: Some("changeme".to_string()),
+            openai_model: Some("gpt-4.1-mini".to_string()),
+            ..AiConfig::default()
+        };
+        let err = resolve_ai_config(Some(ai)).unwrap_err().to_string();
+        assert!(err.contains("ai.openai_api_key"));
+        assert!(err.contains("config.yaml"));
+        assert!(err.contains("SAI_OPENAI_API_KEY"));
+
+        assert!(is_placeholder("<your-key>"));
+        assert!(is_placeholder(
+            "https://your-azure-openai-resource.openai.azure.com"
+        ));
+        assert!(!is_placeholder("sk-live-123"));
+    }
+
     #[test]
     fn tool_config_deserializes_force_explain() {
         let yaml = r#"
@@ -284,6 +847,16 @@ config: "safe"
             name: "echo".to_string(),
             config: "test".to_string(),
             force_explain: None,
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         };
         let yaml = serde_yaml::to_string(&tool).unwrap();
         assert!(!yaml.contains("force_explain"));
@@ -295,6 +868,16 @@ config: "safe"
             name: "rm".to_string(),
             config: "dangerous".to_string(),
             force_explain: Some(true),
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         };
         let yaml = serde_yaml::to_string(&tool).unwrap();
         assert!(yaml.contains("force_explain: true"));
@@ -0,0 +1,230 @@
+//! Deprecated flags and config values. They keep working, but each use prints
+//! a warning naming the replacement and the release that removes it, and
+//! `sai doctor` lists every deprecated usage in the user's config files.
+
+use crate::cli::Cli;
+use crate::config::find_global_config_path;
+use crate::favorites::{favorites_path, load_favorites};
+use crate::humanize;
+use anyhow::{Context, Result};
+use clap::Parser;
+use serde_yaml::Value;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// What a deprecation applies to.
+#[derive(Debug, Clone, Copy)]
+pub enum Target {
+    /// A command-line flag; `used` tells whether a parsed command line sets it.
+    Flag {
+        name: &'static str,
+        used: fn(&Cli) -> bool,
+    },
+    /// A config key holding a given value. Key segments are separated by
+    /// dots; `*` matches any key at that level.
+    ConfigValue {
+        key: &'static str,
+        value: &'static str,
+    },
+}
+
+/// One deprecated flag or config value.
+#[derive(Debug, Clone, Copy)]
+pub struct Deprecation {
+    pub target: Target,
+    pub replacement: &'static str,
+    /// Release that removes the old spelling.
+    pub removed_in: &'static str,
+}
+
+pub const DEPRECATIONS: &[Deprecation] = &[
+    Deprecation {
+        target: Target::Flag {
+            name: "--init",
+            used: |cli| cli.init,
+        },
+        replacement: "sai init",
+        removed_in: "2.0.0",
+    },
+    Deprecation {
+        target: Target::ConfigValue {
+            key: "confirmation.*",
+            value: "type-command-name",
+        },
+        replacement: "confirm+type-command-name",
+        removed_in: "2.0.0",
+    },
+];
+
+/// A deprecated flag or value found in a command line or config file.
+#[derive(Debug, Clone)]
+pub struct Usage {
+    pub deprecation: &'static Deprecation,
+    /// Where it was found: "command line", a config key or a favorite.
+    pub location: String,
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let d = self.deprecation;
+        match d.target {
+            Target::Flag { name, .. } => write!(f, "{} ({})", name, self.location)?,
+            Target::ConfigValue { value, .. } => write!(f, "{}: {}", self.location, value)?,
+        }
+        write!(
+            f,
+            " is deprecated and will be removed in sai {}; use `{}` instead",
+            d.removed_in, d.replacement
+        )
+    }
+}
+
+/// Deprecated flags set on `cli`, reported at `location`.
+pub fn cli_usages(cli: &Cli, location: &str) -> Vec<Usage> {
+    DEPRECATIONS
+        .iter()
+        .filter(|d| matches!(d.target, Target::Flag { used, .. } if used(cli)))
+        .map(|deprecation| Usage {
+            deprecation,
+            location: location.to_string(),
+        })
+        .collect()
+}
+
+/// Deprecated values in a parsed config document.
+pub fn config_usages(doc: &Value) -> Vec<Usage> {
+    let mut usages = Vec::new();
+    for deprecation in DEPRECATIONS {
+        if let Target::ConfigValue { key, value } = deprecation.target {
+            let segments: Vec<&str> = key.split('.').collect();
+            let mut found = Vec::new();
+            find_values(doc, &segments, String::new(), value, &mut found);
+            usages.extend(found.into_iter().map(|location| Usage {
+                deprecation,
+                location,
+            }));
+        }
+    }
+    usages
+}
+
+fn find_values(
+    node: &Value,
+    segments: &[&str],
+    path: String,
+    value: &str,
+    found: &mut Vec<String>,
+) {
+    let Some((segment, rest)) = segments.split_first() else {
+        if node.as_str() == Some(value) {
+            found.push(path);
+        }
+        return;
+    };
+    let Some(map) = node.as_mapping() else {
+        return;
+    };
+    for (key, child) in map {
+        let Some(key) = key.as_str() else {
+            continue;
+        };
+        if *segment == "*" || *segment == key {
+            let child_path = if path.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", path, key)
+            };
+            find_values(child, rest, child_path, value, found);
+        }
+    }
+}
+
+/// Deprecated values in the config file at `path` (none when it is missing).
+pub fn config_file_usages(path: &Path) -> Result<Vec<Usage>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let doc: Value = serde_yaml::from_str(&text)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(config_usages(&doc))
+}
+
+/// Prints one warning per usage to stderr.
+pub fn warn(usages: &[Usage]) {
+    for usage in usages {
+        eprintln!("Warning: {}", usage);
+    }
+}
+
+/// `sai doctor`: lists deprecated usages in the global config and saved
+/// favorites. The exit code is 1 when any were found.
+pub fn run_doctor() -> Result<i32> {
+    let config_path = find_global_config_path();
+    let favorites_file = favorites_path();
+    let mut sections = vec![(config_path.clone(), config_file_usages(&config_path)?)];
+    let mut favorite_usages = Vec::new();
+    for (name, favorite) in load_favorites(&favorites_file)?.favorites {
+        let argv = std::iter::once("sai".to_string()).chain(favorite.args);
+        if let Ok(cli) = Cli::try_parse_from(argv) {
+            favorite_usages.extend(cli_usages(&cli, &format!("favorite '{}'", name)));
+        }
+    }
+    sections.push((favorites_file, favorite_usages));
+
+    let mut total = 0;
+    for (path, usages) in &sections {
+        if usages.is_empty() {
+            println!("{}: no deprecated settings", path.display());
+            continue;
+        }
+        println!("{}:", path.display());
+        for usage in usages {
+            println!("  {}", usage);
+        }
+        total += usages.len();
+    }
+    if total == 0 {
+        Ok(0)
+    } else {
+        println!(
+            "{} found.",
+            humanize::plural(total as u64, "deprecated usage", "deprecated usages")
+        );
+        Ok(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deprecated_flags_and_config_values_are_reported() {
+        let cli = Cli::try_parse_from(["sai", "--init"]).unwrap();
+        let usages = cli_usages(&cli, "command line");
+        assert_eq!(usages.len(), 1);
+        assert_eq!(
+            usages[0].to_string(),
+            "--init (command line) is deprecated and will be removed in sai 2.0.0; \
+             use `sai init` instead"
+        );
+        let cli = Cli::try_parse_from(["sai", "list files"]).unwrap();
+        assert!(cli_usages(&cli, "command line").is_empty());
+
+        let doc: Value = serde_yaml::from_str(
+            "confirmation:\n  low: auto\n  high: type-command-name\n\
+             notes: type-command-name\n",
+        )
+        .unwrap();
+        let usages = config_usages(&doc);
+        assert_eq!(usages.len(), 1);
+        assert_eq!(
+            usages[0].to_string(),
+            "confirmation.high: type-command-name is deprecated and will be removed \
+             in sai 2.0.0; use `confirm+type-command-name` instead"
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Best-effort capture of the execution environment for history entries, so
+//! `--analyze` can explain "works on my machine" differences.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a single `--version` probe may take before it is abandoned.
+const VERSION_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Maximum length of a recorded version string.
+const VERSION_MAX_CHARS: usize = 120;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvironmentInfo {
+    /// FNV-1a hash of PATH; equal hashes mean the same tool lookup order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    pub os: String,
+    /// First line of `<tool> --version` for the tools the command invoked.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tool_versions: BTreeMap<String, String>,
+}
+
+/// Captures PATH hash, shell, OS version and the versions of `tools`.
+pub fn capture(tools: &[String]) -> EnvironmentInfo {
+    let tool_versions = tools
+        .iter()
+        .filter_map(|tool| tool_version(tool).map(|v| (tool.clone(), v)))
+        .collect();
+
+    EnvironmentInfo {
+        path_hash: env::var_os("PATH").map(|p| fnv1a_hex(p.to_string_lossy().as_bytes())),
+        shell: powershell_program()
+            .map(str::to_string)
+            .or_else(|| env::var("SHELL").or_else(|_| env::var("COMSPEC")).ok()),
+        os: os_version(),
+        tool_versions,
+    }
+}
+
+/// Tool names a command line invokes: the first word, plus the first word of
+/// every pipeline or list segment when shell operators are allowed.
+pub fn invoked_tools(cmd_line: &str, unsafe_mode: bool) -> Vec<String> {
+    let segments: Vec<&str> = if unsafe_mode {
+        cmd_line.split(['|', ';', '&']).collect()
+    } else {
+        vec![cmd_line]
+    };
+
+    let mut tools = Vec::new();
+    for segment in segments {
+        let Some(word) = segment.split_whitespace().next() else {
+            continue;
+        };
+        if !tools.iter().any(|t| t == word) {
+            tools.push(word.to_string());
+        }
+    }
+    tools
+}
+
+/// `powershell` or `pwsh` when sai was started from PowerShell on Windows,
+/// so `--unsafe` commands run there and the model is told to use its syntax.
+pub fn powershell_program() -> Option<&'static str> {
+    if !cfg!(windows) {
+        return None;
+    }
+    detect_powershell(
+        &env::var("PSModulePath").ok()?,
+        &env::var("USERPROFILE").ok()?,
+    )
+}
+
+/// PowerShell puts the user's module directory first in PSModulePath; a
+/// plain cmd.exe only has the system-wide entries.
+fn detect_powershell(module_path: &str, user_profile: &str) -> Option<&'static str> {
+    let profile = user_profile.trim_end_matches('\\').to_lowercase();
+    if profile.is_empty() {
+        return None;
+    }
+    let profile = format!("{}\\", profile);
+    module_path
+        .split(';')
+        .map(str::to_lowercase)
+        .filter(|entry| entry.starts_with(&profile))
+        .find_map(|entry| {
+            if entry.contains("\\windowspowershell\\") {
+                Some("powershell")
+            } else if entry.contains("\\powershell\\") {
+                Some("pwsh")
+            } else {
+                None
+            }
+        })
+}
+
+/// Resolves a tool name against PATH (absolute paths are checked directly).
+pub fn find_on_path(tool: &str) -> Option<PathBuf> {
+    let path = Path::new(tool);
+    if path.is_absolute() {
+        return path.exists().then(|| path.to_path_buf());
+    }
+
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .map(|dir| dir.join(tool))
+        .find(|candidate| candidate.is_file())
+}
+
+fn tool_version(tool: &str) -> Option<String> {
+    let path = find_on_path(tool)?;
+    let output = run_with_timeout(Command::new(path).arg("--version"))?;
+    let line = output.lines().map(str::trim).find(|l| !l.is_empty())?;
+    Some(line.chars().take(VERSION_MAX_CHARS).collect())
+}
+
+fn os_version() -> String {
+    let release = if cfg!(target_os = "macos") {
+        run_with_timeout(Command::new("sw_vers").arg("-productVersion"))
+            .map(|v| format!("macOS {}", v.trim()))
+    } else if cfg!(windows) {
+        run_with_timeout(Command::new("cmd").args(["/C", "ver"])).map(|v| v.trim().to_string())
+    } else {
+        let distro = std::fs::read_to_string("/etc/os-release")
+            .ok()
+            .and_then(|text| pretty_name(&text));
+        let kernel =
+            run_with_timeout(Command::new("uname").arg("-sr")).map(|v| v.trim().to_string());
+        match (distro, kernel) {
+            (Some(d), Some(k)) => Some(format!("{} ({})", d, k)),
+            (d, k) => d.or(k),
+        }
+    };
+
+    release
+        .filter(|r| !r.is_empty())
+        .unwrap_or_else(|| env::consts::OS.to_string())
+}
+
+fn pretty_name(os_release: &str) -> Option<String> {
+    os_release
+        .lines()
+        .find_map(|l| l.strip_prefix("PRETTY_NAME="))
+        .map(|v| v.trim_matches('"').to_string())
+}
+
+/// Runs a probe command with no stdin and returns stdout (or stderr, which some
+/// tools use for `--version`), killing it after VERSION_PROBE_TIMEOUT.
+fn run_with_timeout(cmd: &mut Command) -> Option<String> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ok()?;
+
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if started.elapsed() < VERSION_PROBE_TIMEOUT => {
+                thread::sleep(Duration::from_millis(20));
+            }
+            _ => {
+                child.kill().ok();
+                child.wait().ok();
+                return None;
+            }
+        }
+    }
+
+    let mut out = String::new();
+    child.stdout.take()?.read_to_string(&mut out).ok()?;
+    if out.trim().is_empty() {
+        child.stderr.take()?.read_to_string(&mut out).ok()?;
+    }
+    Some(out)
+}
+
+fn fnv1a_hex(data: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in data {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn powershell_is_detected_from_the_user_module_path() {
+        let profile = r"C:\Users\ana";
+        let windows_ps = r"C:\Users\ana\Documents\WindowsPowerShell\Modules;C:\Program Files\WindowsPowerShell\Modules";
+        let pwsh = r"C:\Users\ana\OneDrive\Documents\PowerShell\Modules;C:\Program Files\PowerShell\Modules;C:\Program Files\WindowsPowerShell\Modules";
+        let cmd = r"C:\Program Files\WindowsPowerShell\Modules;C:\WINDOWS\system32\WindowsPowerShell\v1.0\Modules";
+        assert_eq!(detect_powershell(windows_ps, profile), Some("powershell"));
+        assert_eq!(detect_powershell(pwsh, profile), Some("pwsh"));
+        assert_eq!(detect_powershell(cmd, profile), None);
+    }
+
+    #[test]
+    fn invoked_tools_follow_pipelines_only_in_unsafe_mode() {
+        assert_eq!(invoked_tools("grep -c foo a.txt", false), vec!["grep"]);
+        assert_eq!(
+            invoked_tools("grep foo a.txt | sort | uniq -c && grep x b", true),
+            vec!["grep", "sort", "uniq"]
+        );
+    }
+
+    #[test]
+    fn path_hash_is_stable_and_distinguishes_values() {
+        assert_eq!(fnv1a_hex(b"/usr/bin:/bin"), fnv1a_hex(b"/usr/bin:/bin"));
+        assert_ne!(fnv1a_hex(b"/usr/bin:/bin"), fnv1a_hex(b"/bin:/usr/bin"));
+    }
+
+    #[test]
+    fn os_release_pretty_name_is_unquoted() {
+        let text = "NAME=Debian\nPRETTY_NAME=\"Debian GNU/Linux 12 (bookworm)\"\n";
+        assert_eq!(
+            pretty_name(text).as_deref(),
+            Some("Debian GNU/Linux 12 (bookworm)")
+        );
+    }
+}
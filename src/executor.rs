@@ -1,79 +1,1124 @@
-use anyhow::{Context, Result};
-use glob::glob;
-use std::process::Command;
+use crate::humanize;
+use crate::interrupt;
+use crate::pager::Pager;
+use crate::pattern::glob_match;
+use crate::project::ProjectPath;
+use crate::scope::glob_safe_name;
+use anyhow::{anyhow, Context, Result};
+use glob::{glob, Pattern};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Exit code for commands stopped by a timeout (the same as GNU `timeout`).
+pub const EXIT_TIMED_OUT: i32 = 124;
+
+/// How long a timed-out command gets to exit after SIGTERM before SIGKILL.
+const KILL_GRACE: Duration = Duration::from_secs(2);
+
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Most bytes of each stream [`CommandExecutor::execute_captured`] keeps.
+/// Only the end is kept: history, --auto-fix and the output prompts read
+/// the end of long output, and a chatty command should not fill memory.
+pub const CAPTURE_MAX_BYTES: usize = 1024 * 1024;
 
 /// Expands glob patterns in a command argument.
 /// If the argument contains glob metacharacters (*, ?, [) and matches files,
 /// returns the expanded paths. Otherwise returns the original argument.
-fn expand_glob_if_needed(arg: &str) -> Vec<String> {
+/// Matches are passed on as raw OS strings, so file names that are not valid
+/// UTF-8 reach the child unchanged.
+pub(crate) fn expand_glob_if_needed(arg: &str) -> Vec<OsString> {
     // Check if this looks like a glob pattern
     if !arg.contains('*') && !arg.contains('?') && !arg.contains('[') {
-        return vec![arg.to_string()];
+        return vec![arg.into()];
     }
 
     // Try to expand the glob
     match glob(arg) {
         Ok(paths) => {
-            let expanded: Vec<String> = paths
+            let mut expanded: Vec<OsString> = paths
                 .filter_map(|entry| entry.ok())
-                .map(|path| path.to_string_lossy().to_string())
+                .map(|path| path.into_os_string())
+                .chain(non_utf8_matches(arg))
                 .collect();
+            expanded.sort();
 
             // If we got matches, use them; otherwise fall back to literal
             if expanded.is_empty() {
-                vec![arg.to_string()]
+                vec![arg.into()]
             } else {
                 expanded
             }
         }
         Err(_) => {
             // If glob parsing fails, use the literal string
-            vec![arg.to_string()]
+            vec![arg.into()]
+        }
+    }
+}
+
+/// Like [`expand_glob_if_needed`] for a command running in `dir`: relative
+/// patterns match there and expand to paths relative to it.
+pub(crate) fn expand_glob_in(arg: &str, dir: Option<&Path>) -> Vec<OsString> {
+    let Some(dir) = dir.filter(|_| Path::new(arg).is_relative()) else {
+        return expand_glob_if_needed(arg);
+    };
+    if !arg.contains('*') && !arg.contains('?') && !arg.contains('[') {
+        return vec![arg.into()];
+    }
+    let pattern = format!("{}/{}", Pattern::escape(&dir.to_string_lossy()), arg);
+    let expanded = expand_glob_if_needed(&pattern);
+    if expanded == [OsString::from(&pattern)] {
+        return vec![arg.into()];
+    }
+    expanded
+        .into_iter()
+        .map(|path| match Path::new(&path).strip_prefix(dir) {
+            Ok(relative) => relative.as_os_str().to_owned(),
+            Err(_) => path,
+        })
+        .collect()
+}
+
+/// Per-run execution settings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecOptions {
+    /// Stop the command when it runs longer than this; it then reports
+    /// [`EXIT_TIMED_OUT`].
+    pub timeout: Option<Duration>,
+    /// Priority and resource limits set in the child before it starts.
+    pub limits: ResourceLimits,
+    /// Split the largest expanded glob over several runs when the arguments
+    /// exceed the system limit (see [`argument_size`]). Runs stop at the
+    /// first non-zero exit code; `timeout` covers all of them.
+    pub batch_args: bool,
+    /// Project tool directories searched before PATH (`project_tools:`).
+    pub project: ProjectPath,
+    /// Which inherited environment variables the command sees (`env_policy:`).
+    pub env: EnvPolicy,
+    /// The `env:` map of the tool being run, set after `env`.
+    pub tool_env: BTreeMap<String, String>,
+    /// Send stdout through a pager once it fills the screen (`pager:`). The
+    /// output is captured either way.
+    pub pager: Option<Pager>,
+    /// Stop printing stdout after this many lines when no pager takes over
+    /// (`execution.max_output_lines`); the rest is still captured, up to
+    /// [`CAPTURE_MAX_BYTES`].
+    pub max_output_lines: Option<usize>,
+    /// Directory the command runs in (a tool's `chdir:`); sai's own when
+    /// unset. Safe-mode globs expand there.
+    pub dir: Option<PathBuf>,
+}
+
+/// Room left for the child's own environment changes, as in xargs.
+const ARG_HEADROOM: usize = 2048;
+
+/// How much of the system argument limit the expanded command uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArgumentSize {
+    /// Bytes the arguments take, counted like the kernel does.
+    pub bytes: usize,
+    /// Bytes available for arguments after the environment.
+    pub limit: usize,
+    /// Runs needed with [`ExecOptions::batch_args`]; 1 when it fits.
+    pub batches: usize,
+}
+
+/// Measures the safe-mode command after glob expansion. Fails when it is too
+/// long and cannot be split, i.e. when no glob argument carries the bulk.
+pub fn argument_size(tokens: &[String], dir: Option<&Path>) -> Result<ArgumentSize> {
+    let args = expand_all(tokens, dir);
+    let limit = argument_limit();
+    let bytes = args.iter().flatten().map(arg_cost).sum();
+    let batches = plan_batches(args, limit)?.len();
+    Ok(ArgumentSize {
+        bytes,
+        limit,
+        batches,
+    })
+}
+
+/// The program followed by every argument's expansion.
+fn expand_all(tokens: &[String], dir: Option<&Path>) -> Vec<Vec<OsString>> {
+    let mut args = vec![vec![OsString::from(&tokens[0])]];
+    args.extend(tokens[1..].iter().map(|arg| expand_glob_in(arg, dir)));
+    args
+}
+
+/// Space an argument takes in the new process: the string, its NUL and
+/// the pointer to it.
+fn arg_cost(arg: &OsString) -> usize {
+    arg.len() + 1 + std::mem::size_of::<usize>()
+}
+
+#[cfg(unix)]
+fn argument_limit() -> usize {
+    // SAFETY: sysconf has no memory-safety preconditions.
+    let arg_max = match unsafe { libc::sysconf(libc::_SC_ARG_MAX) } {
+        n if n > 0 => n as usize,
+        _ => 128 * 1024,
+    };
+    let env: usize = std::env::vars_os()
+        .map(|(k, v)| k.len() + v.len() + 2 + std::mem::size_of::<usize>())
+        .sum();
+    arg_max.saturating_sub(env + ARG_HEADROOM)
+}
+
+#[cfg(not(unix))]
+fn argument_limit() -> usize {
+    // CreateProcess limits the whole command line to 32767 characters.
+    32_767 - ARG_HEADROOM
+}
+
+/// Splits the expansion with the most entries into runs that each fit in
+/// `limit`; all other arguments are repeated in every run.
+fn plan_batches(args: Vec<Vec<OsString>>, limit: usize) -> Result<Vec<Vec<OsString>>> {
+    let total: usize = args.iter().flatten().map(arg_cost).sum();
+    if total <= limit {
+        return Ok(vec![args.into_iter().flatten().collect()]);
+    }
+
+    let program = args[0][0].to_string_lossy().into_owned();
+    let too_long = || {
+        anyhow!(
+            "The expanded arguments of '{}' take {}, more than the system allows ({}), and cannot be split into batches",
+            program,
+            humanize::bytes(total as u64),
+            humanize::bytes(limit as u64)
+        )
+    };
+    let split = (1..args.len())
+        .max_by_key(|&i| args[i].len())
+        .filter(|&i| args[i].len() > 1)
+        .ok_or_else(too_long)?;
+    let fixed: usize = args
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != split)
+        .flat_map(|(_, a)| a)
+        .map(arg_cost)
+        .sum();
+    let room = limit.saturating_sub(fixed);
+
+    let mut chunks: Vec<&[OsString]> = Vec::new();
+    let mut start = 0;
+    let mut used = 0;
+    for (i, arg) in args[split].iter().enumerate() {
+        let cost = arg_cost(arg);
+        if cost > room {
+            return Err(too_long());
+        }
+        if used + cost > room {
+            chunks.push(&args[split][start..i]);
+            start = i;
+            used = 0;
+        }
+        used += cost;
+    }
+    chunks.push(&args[split][start..]);
+
+    Ok(chunks
+        .into_iter()
+        .map(|chunk| {
+            let mut batch = Vec::new();
+            for (i, expansion) in args.iter().enumerate() {
+                if i == split {
+                    batch.extend_from_slice(chunk);
+                } else {
+                    batch.extend(expansion.iter().cloned());
+                }
+            }
+            batch
+        })
+        .collect())
+}
+
+/// `limits:` section of the global config. Applied with nice(2) and
+/// setrlimit(2) on Unix; other platforms ignore it with a warning.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// Niceness the command runs at (0-19; higher means lower priority).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nice: Option<u8>,
+    /// Address space limit in MiB (RLIMIT_AS); allocations beyond it fail.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_memory_mb: Option<u64>,
+    /// Largest file the command may write, in MiB (RLIMIT_FSIZE); writing past
+    /// it kills the command with SIGXFSZ.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size_mb: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(nice) = self.nice.filter(|n| *n > 19) {
+            return Err(anyhow!(
+                "limits.nice must be between 0 and 19, got {}",
+                nice
+            ));
+        }
+        for (key, value) in [
+            ("max_memory_mb", self.max_memory_mb),
+            ("max_file_size_mb", self.max_file_size_mb),
+        ] {
+            if value == Some(0) {
+                return Err(anyhow!("limits.{} must be at least 1", key));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == ResourceLimits::default()
+    }
+}
+
+/// `env_policy:` section of the global config: which variables of sai's own
+/// environment generated commands inherit, and which ones they get in
+/// addition. Without it commands inherit everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvPolicy {
+    /// Only variables matching one of these globs are passed on (all when
+    /// empty), e.g. `[PATH, HOME, LANG, LC_*, TERM]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// Variables matching one of these globs are withheld even when allowed,
+    /// e.g. `["*_TOKEN", "*_SECRET*", AWS_*]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+    /// Variables set for every command after filtering.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<String, String>,
+}
+
+impl EnvPolicy {
+    pub fn validate(&self) -> Result<()> {
+        validate_env_names(&self.set, "env_policy.set")
+    }
+
+    /// Whether an inherited variable called `name` reaches the command.
+    /// Names match case-insensitively.
+    pub fn passes(&self, name: &str) -> bool {
+        let matches = |patterns: &[String]| patterns.iter().any(|p| glob_match(p, name));
+        (self.allow.is_empty() || matches(&self.allow)) && !matches(&self.deny)
+    }
+
+    fn apply(&self, cmd: &mut Command) {
+        if !self.allow.is_empty() || !self.deny.is_empty() {
+            cmd.env_clear();
+            for (name, value) in std::env::vars_os() {
+                if self.passes(&name.to_string_lossy()) {
+                    cmd.env(name, value);
+                }
+            }
         }
+        cmd.envs(&self.set);
+    }
+}
+
+/// Rejects variable names the OS cannot set; `what` names the config key.
+pub fn validate_env_names(vars: &BTreeMap<String, String>, what: &str) -> Result<()> {
+    match vars
+        .keys()
+        .find(|name| name.is_empty() || name.contains(['=', '\0']))
+    {
+        Some(name) => Err(anyhow!("{} has an invalid name '{}'", what, name)),
+        None => Ok(()),
+    }
+}
+
+/// Output of a command run through [`CommandExecutor::execute_captured`].
+/// Each stream keeps its last [`CAPTURE_MAX_BYTES`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapturedOutput {
+    pub exit_code: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// The glob crate skips names that are not valid UTF-8. Matches them in the
+/// last path component against their glob-safe form, as shown by --scope .
+fn non_utf8_matches(arg: &str) -> Vec<OsString> {
+    let path = Path::new(arg);
+    let (Some(dir), Some(file_pattern)) = (path.parent(), path.file_name()) else {
+        return Vec::new();
+    };
+    let dir_text = dir.to_string_lossy();
+    if dir_text.contains(['*', '?', '[']) {
+        return Vec::new();
     }
+    let Some(pattern) = file_pattern.to_str().and_then(|p| Pattern::new(p).ok()) else {
+        return Vec::new();
+    };
+    let read_from = if dir_text.is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let Ok(entries) = fs::read_dir(read_from) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name())
+        .filter(|name| name.to_str().is_none() && pattern.matches(&glob_safe_name(name)))
+        .map(|name| dir.join(name).into_os_string())
+        .collect()
 }
 
 pub trait CommandExecutor {
     fn execute(&self, cmd_line: &str, tokens: &[String], unsafe_mode: bool) -> Result<i32>;
+
+    /// Like `execute`, honoring `opts`. The default only accepts empty
+    /// options, so limits are never silently ignored.
+    fn execute_with(
+        &self,
+        cmd_line: &str,
+        tokens: &[String],
+        unsafe_mode: bool,
+        opts: &ExecOptions,
+    ) -> Result<i32> {
+        if *opts != ExecOptions::default() {
+            return Err(anyhow!("This executor does not support execution limits"));
+        }
+        self.execute(cmd_line, tokens, unsafe_mode)
+    }
+
+    /// Runs the command like `execute_with`, still showing its output, and
+    /// also returns what it wrote (used by --verify). Executors that cannot
+    /// capture output keep the default, which fails.
+    fn execute_captured(
+        &self,
+        cmd_line: &str,
+        tokens: &[String],
+        unsafe_mode: bool,
+        opts: &ExecOptions,
+    ) -> Result<CapturedOutput> {
+        let _ = (cmd_line, tokens, unsafe_mode, opts);
+        Err(anyhow!("This executor cannot capture command output"))
+    }
 }
 
 pub struct ShellCommandExecutor;
 
 impl CommandExecutor for ShellCommandExecutor {
     fn execute(&self, cmd_line: &str, tokens: &[String], unsafe_mode: bool) -> Result<i32> {
-        let status = if unsafe_mode {
-            #[cfg(windows)]
-            let mut cmd = {
+        self.execute_with(cmd_line, tokens, unsafe_mode, &ExecOptions::default())
+    }
+
+    fn execute_with(
+        &self,
+        cmd_line: &str,
+        tokens: &[String],
+        unsafe_mode: bool,
+        opts: &ExecOptions,
+    ) -> Result<i32> {
+        if opts.pager.is_some() || opts.max_output_lines.is_some() {
+            return self
+                .execute_captured(cmd_line, tokens, unsafe_mode, opts)
+                .map(|captured| captured.exit_code);
+        }
+        let started = Instant::now();
+        let mut code = 0;
+        for cmd in build_commands(cmd_line, tokens, unsafe_mode, opts)? {
+            let timeout = remaining(opts.timeout, started);
+            code = run_child(cmd, &opts.limits, true, |child| wait_for(child, timeout))
+                .with_context(|| failure_message(cmd_line, tokens, unsafe_mode))?;
+            if code != 0 || interrupt::is_interrupted() {
+                break;
+            }
+        }
+        Ok(code)
+    }
+
+    fn execute_captured(
+        &self,
+        cmd_line: &str,
+        tokens: &[String],
+        unsafe_mode: bool,
+        opts: &ExecOptions,
+    ) -> Result<CapturedOutput> {
+        let started = Instant::now();
+        let mut all = CapturedOutput::default();
+        for mut cmd in build_commands(cmd_line, tokens, unsafe_mode, opts)? {
+            cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+            let timeout = remaining(opts.timeout, started);
+            let captured = run_child(cmd, &opts.limits, false, |child| {
+                wait_teeing_output(child, timeout, opts.pager.as_ref(), opts.max_output_lines)
+            })
+            .with_context(|| failure_message(cmd_line, tokens, unsafe_mode))?;
+            all.exit_code = captured.exit_code;
+            all.stdout.extend(captured.stdout);
+            all.stderr.extend(captured.stderr);
+            if all.exit_code != 0 || interrupt::is_interrupted() {
+                break;
+            }
+        }
+        Ok(all)
+    }
+}
+
+/// Runs commands on another machine over ssh (`--host`). Globs are expanded
+/// by the remote shell; operators stay quoted unless `--unsafe` is set.
+pub struct SshExecutor {
+    host: String,
+}
+
+impl SshExecutor {
+    pub fn new(host: impl Into<String>) -> Self {
+        Self { host: host.into() }
+    }
+
+    fn command(&self, cmd_line: &str, tokens: &[String], unsafe_mode: bool, tty: bool) -> Command {
+        let line = if unsafe_mode {
+            cmd_line.to_string()
+        } else {
+            remote_command_line(tokens)
+        };
+        let mut cmd = Command::new("ssh");
+        if tty {
+            cmd.arg("-t");
+        }
+        // The login shell may not be POSIX; run the line with sh on the host.
+        cmd.arg("--")
+            .arg(&self.host)
+            .arg(format!("sh -c {}", shell_words::quote(&line)));
+        cmd
+    }
+
+    fn failure_message(&self) -> String {
+        format!("Failed to run ssh to '{}'", self.host)
+    }
+}
+
+impl CommandExecutor for SshExecutor {
+    fn execute(&self, cmd_line: &str, tokens: &[String], unsafe_mode: bool) -> Result<i32> {
+        self.execute_with(cmd_line, tokens, unsafe_mode, &ExecOptions::default())
+    }
+
+    fn execute_with(
+        &self,
+        cmd_line: &str,
+        tokens: &[String],
+        unsafe_mode: bool,
+        opts: &ExecOptions,
+    ) -> Result<i32> {
+        if opts.max_output_lines.is_some() {
+            return self
+                .execute_captured(cmd_line, tokens, unsafe_mode, opts)
+                .map(|captured| captured.exit_code);
+        }
+        warn_remote_limits(opts);
+        let tty = io::stdin().is_terminal() && io::stdout().is_terminal();
+        let cmd = self.command(cmd_line, tokens, unsafe_mode, tty);
+        run_child(cmd, &ResourceLimits::default(), true, |child| {
+            wait_for(child, opts.timeout)
+        })
+        .with_context(|| self.failure_message())
+    }
+
+    fn execute_captured(
+        &self,
+        cmd_line: &str,
+        tokens: &[String],
+        unsafe_mode: bool,
+        opts: &ExecOptions,
+    ) -> Result<CapturedOutput> {
+        warn_remote_limits(opts);
+        let mut cmd = self.command(cmd_line, tokens, unsafe_mode, false);
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        run_child(cmd, &ResourceLimits::default(), false, |child| {
+            wait_teeing_output(child, opts.timeout, None, opts.max_output_lines)
+        })
+        .with_context(|| self.failure_message())
+    }
+}
+
+fn warn_remote_limits(opts: &ExecOptions) {
+    if !opts.limits.is_empty() {
+        eprintln!("Warning: `limits:` are not applied to commands run with --host.");
+    }
+    if !opts.env.set.is_empty() || !opts.tool_env.is_empty() {
+        eprintln!(
+            "Warning: `env_policy.set` and tool `env:` maps are not applied to commands run with --host."
+        );
+    }
+}
+
+/// Quotes every token for the remote shell, leaving glob characters
+/// unquoted so the host expands them against its own files.
+fn remote_command_line(tokens: &[String]) -> String {
+    tokens
+        .iter()
+        .map(|token| {
+            if !token.contains(['*', '?', '[']) {
+                return shell_words::quote(token).into_owned();
+            }
+            let mut word = String::new();
+            let mut literal = String::new();
+            for c in token.chars() {
+                if matches!(c, '*' | '?' | '[' | ']') {
+                    if !literal.is_empty() {
+                        word.push_str(&shell_words::quote(&literal));
+                        literal.clear();
+                    }
+                    word.push(c);
+                } else {
+                    literal.push(c);
+                }
+            }
+            if !literal.is_empty() {
+                word.push_str(&shell_words::quote(&literal));
+            }
+            word
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// What is left of `timeout` since `started`, for the next batch.
+fn remaining(timeout: Option<Duration>, started: Instant) -> Option<Duration> {
+    timeout.map(|t| t.saturating_sub(started.elapsed()))
+}
+
+/// One command, or one per batch when `batch_args` is set and the expanded
+/// arguments are too long for a single run.
+fn build_commands(
+    cmd_line: &str,
+    tokens: &[String],
+    unsafe_mode: bool,
+    opts: &ExecOptions,
+) -> Result<Vec<Command>> {
+    if unsafe_mode || !opts.batch_args {
+        return Ok(vec![build_command(cmd_line, tokens, unsafe_mode, opts)]);
+    }
+    let program = program(&tokens[0], &opts.project);
+    let batches = plan_batches(expand_all(tokens, opts.dir.as_deref()), argument_limit())?;
+    Ok(batches
+        .into_iter()
+        .map(|args| {
+            let mut cmd = Command::new(&program);
+            cmd.args(&args[1..]);
+            opts.env.apply(&mut cmd);
+            cmd.envs(&opts.tool_env);
+            if let Some(dir) = &opts.dir {
+                cmd.current_dir(dir);
+            }
+            cmd
+        })
+        .collect())
+}
+
+/// The project copy of the tool when there is one, else the name for a PATH
+/// lookup.
+fn program(tool: &str, project: &ProjectPath) -> OsString {
+    project
+        .resolve(tool)
+        .map(|path| path.into_os_string())
+        .unwrap_or_else(|| tool.into())
+}
+
+fn build_command(
+    cmd_line: &str,
+    tokens: &[String],
+    unsafe_mode: bool,
+    opts: &ExecOptions,
+) -> Command {
+    if unsafe_mode {
+        #[cfg(windows)]
+        let mut cmd = match crate::envinfo::powershell_program() {
+            Some(program) => {
+                let mut command = Command::new(program);
+                command.args(["-NoProfile", "-Command"]).arg(cmd_line);
+                command
+            }
+            None => {
                 let mut command = Command::new("cmd");
                 command.arg("/C").arg(cmd_line);
                 command
-            };
+            }
+        };
 
-            #[cfg(not(windows))]
-            let mut cmd = {
-                let mut command = Command::new("sh");
-                command.arg("-c").arg(cmd_line);
-                command
+        #[cfg(not(windows))]
+        let mut cmd = {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(cmd_line);
+            command
+        };
+
+        opts.env.apply(&mut cmd);
+        cmd.envs(&opts.tool_env);
+        if let Some(dir) = &opts.dir {
+            cmd.current_dir(dir);
+        }
+        cmd
+    } else {
+        // Safe mode: expand globs in arguments before executing
+        let mut cmd = Command::new(program(&tokens[0], &opts.project));
+        if tokens.len() > 1 {
+            let mut expanded_args = Vec::new();
+            for arg in &tokens[1..] {
+                expanded_args.extend(expand_glob_in(arg, opts.dir.as_deref()));
+            }
+            cmd.args(&expanded_args);
+        }
+        opts.env.apply(&mut cmd);
+        cmd.envs(&opts.tool_env);
+        if let Some(dir) = &opts.dir {
+            cmd.current_dir(dir);
+        }
+        cmd
+    }
+}
+
+fn failure_message(cmd_line: &str, tokens: &[String], unsafe_mode: bool) -> String {
+    if unsafe_mode {
+        format!("Failed to execute command '{}'", cmd_line)
+    } else {
+        format!("Failed to execute command '{}'", tokens[0])
+    }
+}
+
+/// Spawns the command with `limits` applied and hands it to `wait`,
+/// forwarding Ctrl-C and SIGTERM to it.
+///
+/// The command always runs in a process group of its own, so a timeout or a
+/// forwarded signal reaches every member of a pipeline. With `foreground`,
+/// when sai owns the terminal, the group also becomes the terminal's
+/// foreground group for as long as it runs: it gets Ctrl-C from the terminal
+/// and may read from it. Otherwise sai keeps the terminal (captured output
+/// may go through a pager) and the command gets no terminal input.
+fn run_child<T>(
+    mut cmd: Command,
+    limits: &ResourceLimits,
+    foreground: bool,
+    wait: impl FnOnce(&mut Child) -> io::Result<T>,
+) -> io::Result<T> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        cmd.process_group(0);
+        let foreground = foreground && owns_terminal();
+        if !foreground && io::stdin().is_terminal() {
+            cmd.stdin(Stdio::null());
+        }
+        let limits = limits.clone();
+        // SAFETY: the closure only calls getpid, signal, tcsetpgrp, nice and
+        // setrlimit, which are async-signal-safe, and allocates nothing.
+        unsafe {
+            cmd.pre_exec(move || {
+                if foreground {
+                    set_foreground(libc::getpid());
+                }
+                if limits.is_empty() {
+                    Ok(())
+                } else {
+                    apply_limits(&limits)
+                }
+            });
+        }
+        let mut child = cmd.spawn()?;
+        let pid = child.id() as i32;
+        if foreground {
+            // Also from here, in case the parent gets to read first.
+            set_foreground(pid);
+        }
+        let _guard = interrupt::ChildGuard::register(pid);
+        let result = wait(&mut child);
+        if foreground {
+            // SAFETY: getpgrp has no preconditions.
+            set_foreground(unsafe { libc::getpgrp() });
+        }
+        result
+    }
+
+    #[cfg(not(unix))]
+    {
+        // No process groups or terminal handover here.
+        let _ = foreground;
+        if !limits.is_empty() {
+            eprintln!(
+                "Warning: resource limits are not supported on this platform; ignoring them."
+            );
+        }
+        let mut child = cmd.spawn()?;
+        wait(&mut child)
+    }
+}
+
+/// True when stdin is a terminal whose foreground process group is sai's.
+#[cfg(unix)]
+fn owns_terminal() -> bool {
+    // SAFETY: tcgetpgrp and getpgrp have no memory-safety preconditions.
+    io::stdin().is_terminal() && unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp() }
+}
+
+/// Makes `pgid` the terminal's foreground process group. SIGTTOU is ignored
+/// for the call, since a background group may not otherwise change it.
+/// Async-signal-safe, so the child can call it before exec.
+#[cfg(unix)]
+fn set_foreground(pgid: libc::pid_t) {
+    // SAFETY: signal and tcsetpgrp have no memory-safety preconditions; the
+    // previous SIGTTOU disposition is restored right away.
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::signal(libc::SIGTTOU, previous);
+    }
+}
+
+/// Runs in the forked child just before exec.
+#[cfg(unix)]
+fn apply_limits(limits: &ResourceLimits) -> io::Result<()> {
+    const MIB: u64 = 1024 * 1024;
+
+    if let Some(nice) = limits.nice {
+        // SAFETY: setpriority has no memory-safety preconditions.
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, libc::c_int::from(nice)) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    for (resource, mib) in [
+        (libc::RLIMIT_AS, limits.max_memory_mb),
+        (libc::RLIMIT_FSIZE, limits.max_file_size_mb),
+    ] {
+        let Some(mib) = mib else { continue };
+        let bytes = mib.saturating_mul(MIB) as libc::rlim_t;
+        let limit = libc::rlimit {
+            rlim_cur: bytes,
+            rlim_max: bytes,
+        };
+        // SAFETY: `limit` is a valid rlimit for the duration of the call.
+        if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Waits for the child and returns its exit code, stopping it with
+/// [`EXIT_TIMED_OUT`] once `timeout` has passed.
+fn wait_for(child: &mut Child, timeout: Option<Duration>) -> io::Result<i32> {
+    let Some(timeout) = timeout else {
+        return child.wait().map(exit_code);
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(exit_code(status));
+        }
+        if Instant::now() >= deadline {
+            eprintln!(
+                "Command timed out after {}; stopping it.",
+                humanize::duration(timeout)
+            );
+            stop_child(child)?;
+            return Ok(EXIT_TIMED_OUT);
+        }
+        thread::sleep(WAIT_POLL_INTERVAL);
+    }
+}
+
+/// Sends SIGTERM to the child's process group (see [`run_child`]), so every
+/// member of a pipeline stops, then SIGKILL if it is still running after a
+/// grace period.
+fn stop_child(child: &mut Child) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let target = -(child.id() as i32);
+        // SAFETY: kill has no memory-safety preconditions.
+        unsafe {
+            libc::kill(target, libc::SIGTERM);
+        }
+        let deadline = Instant::now() + KILL_GRACE;
+        while Instant::now() < deadline {
+            if child.try_wait()?.is_some() {
+                return Ok(());
+            }
+            thread::sleep(WAIT_POLL_INTERVAL);
+        }
+        // SAFETY: as above.
+        unsafe {
+            libc::kill(target, libc::SIGKILL);
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        child.kill().ok();
+    }
+
+    child.wait().map(|_| ())
+}
+
+/// Waits for a child with piped stdout/stderr, copying both to the terminal
+/// as they arrive and keeping a copy. After a timeout the readers get
+/// [`KILL_GRACE`] to finish: a process that left the killed group may still
+/// hold the pipes open, and what it writes is then not waited for.
+fn wait_teeing_output(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    pager: Option<&Pager>,
+    max_lines: Option<usize>,
+) -> io::Result<CapturedOutput> {
+    let stdout = child.stdout.take().map(|out| match (pager, max_lines) {
+        (None, None) => tee(out, io::stdout),
+        _ => tee_paged(out, pager.cloned(), max_lines),
+    });
+    let stderr = child.stderr.take().map(|err| tee(err, io::stderr));
+    let code = wait_for(child, timeout)?;
+    let deadline = (code == EXIT_TIMED_OUT).then(|| Instant::now() + KILL_GRACE);
+    let collect = |handle: Option<thread::JoinHandle<io::Result<Vec<u8>>>>| match handle {
+        Some(handle) => {
+            while deadline.is_some_and(|d| Instant::now() < d) && !handle.is_finished() {
+                thread::sleep(WAIT_POLL_INTERVAL);
+            }
+            if deadline.is_some() && !handle.is_finished() {
+                return Ok(Vec::new());
+            }
+            handle
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("output reader panicked")))
+        }
+        None => Ok(Vec::new()),
+    };
+    Ok(CapturedOutput {
+        exit_code: code,
+        stdout: collect(stdout)?,
+        stderr: collect(stderr)?,
+    })
+}
+
+fn tee<R, W>(mut source: R, sink: fn() -> W) -> thread::JoinHandle<io::Result<Vec<u8>>>
+where
+    R: Read + Send + 'static,
+    W: Write + 'static,
+{
+    thread::spawn(move || {
+        let mut captured = Capture::default();
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = match source.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
             };
+            let mut out = sink();
+            out.write_all(&buf[..n]).ok();
+            out.flush().ok();
+            captured.push(&buf[..n]);
+        }
+        Ok(captured.into_bytes())
+    })
+}
 
-            cmd.status()
-                .with_context(|| format!("Failed to execute command '{}'", cmd_line))?
-        } else {
-            // Safe mode: expand globs in arguments before executing
-            let mut cmd = Command::new(&tokens[0]);
-            if tokens.len() > 1 {
-                let mut expanded_args = Vec::new();
-                for arg in &tokens[1..] {
-                    expanded_args.extend(expand_glob_if_needed(arg));
+/// The last [`CAPTURE_MAX_BYTES`] of a stream.
+#[derive(Default)]
+struct Capture(Vec<u8>);
+
+impl Capture {
+    fn push(&mut self, data: &[u8]) {
+        self.0.extend_from_slice(data);
+        // Trimmed only once twice the cap is reached, so the buffer is not
+        // shifted on every read.
+        if self.0.len() > 2 * CAPTURE_MAX_BYTES {
+            self.0.drain(..self.0.len() - CAPTURE_MAX_BYTES);
+        }
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        let excess = self.0.len().saturating_sub(CAPTURE_MAX_BYTES);
+        self.0.drain(..excess);
+        self.0
+    }
+}
+
+enum PagedOutput {
+    /// Less than a screenful so far, held back here; printed when the
+    /// command ends.
+    Buffering(Pager, Vec<u8>),
+    /// Written to the pager; `None` once the pager has quit.
+    Paging(Child, Option<ChildStdin>),
+    /// No pager, or it could not be started; written straight to stdout.
+    Direct,
+}
+
+/// Stdout printed directly, cut off after `max` lines; what follows is only
+/// counted.
+struct LimitedStdout {
+    max: Option<usize>,
+    lines: usize,
+    /// Complete lines past `max`.
+    hidden: usize,
+    /// True when the output past `max` ends in the middle of a line.
+    hidden_partial: bool,
+}
+
+impl LimitedStdout {
+    fn new(max: Option<usize>) -> Self {
+        Self {
+            max,
+            lines: 0,
+            hidden: 0,
+            hidden_partial: false,
+        }
+    }
+
+    /// Prints `data` up to the limit and counts the rest.
+    fn write(&mut self, data: &[u8]) {
+        let mut end = data.len();
+        if let Some(max) = self.max {
+            if self.lines >= max {
+                end = 0;
+            }
+            for (idx, _) in data[..end].iter().enumerate().filter(|(_, b)| **b == b'\n') {
+                self.lines += 1;
+                if self.lines >= max {
+                    end = idx + 1;
+                    break;
                 }
-                cmd.args(&expanded_args);
             }
-            cmd.status()
-                .with_context(|| format!("Failed to execute command '{}'", tokens[0]))?
+        }
+        write_stdout(&data[..end]);
+        let rest = &data[end..];
+        if !rest.is_empty() {
+            self.hidden += rest.iter().filter(|&&b| b == b'\n').count();
+            self.hidden_partial = !rest.ends_with(b"\n");
+        }
+    }
+
+    /// Tells how much of the output was not shown, if any.
+    fn report_truncation(&self) {
+        let lines = self.hidden + usize::from(self.hidden_partial);
+        if lines == 0 {
+            return;
+        }
+        eprintln!(
+            "[output truncated after {}; {} more not shown, run with --full-output to see all of it]",
+            humanize::plural(self.max.unwrap_or_default() as u64, "line", "lines"),
+            humanize::count(lines as u64)
+        );
+    }
+}
+
+/// Like [`tee`] for stdout. With a pager, output is held back until it
+/// reaches `pager.rows` lines; from then on it goes through the pager,
+/// starting with what was held back, and short output is printed when the
+/// command ends. Output printed directly stops after `max_lines`, with a
+/// notice on stderr once the command ends.
+fn tee_paged<R>(
+    mut source: R,
+    pager: Option<Pager>,
+    max_lines: Option<usize>,
+) -> thread::JoinHandle<io::Result<Vec<u8>>>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut captured = Capture::default();
+        let mut direct = LimitedStdout::new(max_lines);
+        let mut state = match pager {
+            Some(pager) => PagedOutput::Buffering(pager, Vec::new()),
+            None => PagedOutput::Direct,
         };
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = match source.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            captured.push(&buf[..n]);
+            match &mut state {
+                PagedOutput::Buffering(pager, held) => {
+                    held.extend_from_slice(&buf[..n]);
+                    // A screenful can also be a few very long lines.
+                    if held.iter().filter(|&&b| b == b'\n').count() < pager.rows
+                        && held.len() < CAPTURE_MAX_BYTES
+                    {
+                        continue;
+                    }
+                    state = match pager.spawn() {
+                        Ok(mut child) => {
+                            let stdin = child
+                                .stdin
+                                .take()
+                                .and_then(|mut stdin| stdin.write_all(held).ok().map(|_| stdin));
+                            PagedOutput::Paging(child, stdin)
+                        }
+                        Err(err) => {
+                            eprintln!(
+                                "Warning: could not start pager '{}': {}",
+                                pager.command[0], err
+                            );
+                            direct.write(held);
+                            PagedOutput::Direct
+                        }
+                    };
+                }
+                PagedOutput::Paging(_, stdin) => {
+                    // Once the pager quits the rest is only captured.
+                    if stdin
+                        .as_mut()
+                        .is_some_and(|s| s.write_all(&buf[..n]).is_err())
+                    {
+                        *stdin = None;
+                    }
+                }
+                PagedOutput::Direct => direct.write(&buf[..n]),
+            }
+        }
+        match state {
+            PagedOutput::Buffering(_, held) => {
+                direct.write(&held);
+                direct.report_truncation();
+            }
+            PagedOutput::Paging(mut child, stdin) => {
+                drop(stdin);
+                child.wait()?;
+            }
+            PagedOutput::Direct => direct.report_truncation(),
+        }
+        Ok(captured.into_bytes())
+    })
+}
+
+fn write_stdout(data: &[u8]) {
+    let mut out = io::stdout();
+    out.write_all(data).ok();
+    out.flush().ok();
+}
+
+/// Exit code of the child; processes killed by a signal report 128 + signal.
+fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
 
-        Ok(status.code().unwrap_or(1))
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            if signal == libc::SIGINT {
+                // With the terminal, the command's group got the Ctrl-C.
+                interrupt::child_interrupted();
+            }
+            return 128 + signal;
+        }
     }
+
+    1
 }
 
 #[cfg(test)]
@@ -96,6 +1141,189 @@ mod tests {
         assert_eq!(exec.execute("", &[], false).unwrap(), 0);
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn captured_output_keeps_both_streams_and_exit_code() {
+        let tokens: Vec<String> = ["sh", "-c", "echo out; echo err >&2; exit 3"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        let captured = ShellCommandExecutor
+            .execute_captured("", &tokens, false, &ExecOptions::default())
+            .unwrap();
+        assert_eq!(captured.exit_code, 3);
+        assert_eq!(captured.stdout, b"out\n");
+        assert_eq!(captured.stderr, b"err\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeout_stops_the_command() {
+        let tokens = vec!["sleep".to_string(), "5".to_string()];
+        let opts = ExecOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..ExecOptions::default()
+        };
+        let started = Instant::now();
+        let code = ShellCommandExecutor
+            .execute_with("sleep 5", &tokens, false, &opts)
+            .unwrap();
+        assert_eq!(code, EXIT_TIMED_OUT);
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeout_stops_every_member_of_a_captured_pipeline() {
+        let opts = ExecOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..ExecOptions::default()
+        };
+        let started = Instant::now();
+        let captured = ShellCommandExecutor
+            .execute_captured("sleep 20 | cat", &[], true, &opts)
+            .unwrap();
+        assert_eq!(captured.exit_code, EXIT_TIMED_OUT);
+        assert!(started.elapsed() < Duration::from_secs(6));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn limits_apply_to_the_child() {
+        let temp = TempDir::new().unwrap();
+        let target = temp.path().join("big.bin");
+        let script = format!(
+            "test \"$(nice)\" -eq 5 || exit 1; head -c 2097152 /dev/zero > '{}'",
+            target.display()
+        );
+        let tokens: Vec<String> = vec!["sh".into(), "-c".into(), script];
+        let opts = ExecOptions {
+            limits: ResourceLimits {
+                nice: Some(5),
+                max_file_size_mb: Some(1),
+                ..ResourceLimits::default()
+            },
+            ..ExecOptions::default()
+        };
+        let code = ShellCommandExecutor
+            .execute_with("", &tokens, false, &opts)
+            .unwrap();
+        assert_eq!(code, 128 + libc::SIGXFSZ);
+        assert_eq!(fs::metadata(&target).unwrap().len(), 1024 * 1024);
+
+        let err = ResourceLimits {
+            nice: Some(20),
+            ..ResourceLimits::default()
+        }
+        .validate()
+        .unwrap_err();
+        assert!(err.to_string().contains("between 0 and 19"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn env_policy_filters_and_sets_variables() {
+        let policy: EnvPolicy = serde_yaml::from_str(
+            "allow: [PATH, HOME, LC_*]\ndeny: [home]\nset:\n  SAI_GREETING: hi\n",
+        )
+        .unwrap();
+        assert!(policy.passes("LC_ALL"));
+        assert!(!policy.passes("HOME"));
+        assert!(!policy.passes("GITHUB_TOKEN"));
+
+        let script = "echo \"${HOME:-none}|$SAI_GREETING|${PATH:+path}\"";
+        let tokens: Vec<String> = vec!["sh".into(), "-c".into(), script.into()];
+        let mut opts = ExecOptions {
+            env: policy,
+            ..ExecOptions::default()
+        };
+        let captured = ShellCommandExecutor
+            .execute_captured("", &tokens, false, &opts)
+            .unwrap();
+        assert_eq!(captured.stdout, b"none|hi|path\n");
+
+        opts.tool_env = BTreeMap::from([("SAI_GREETING".to_string(), "tool".to_string())]);
+        let captured = ShellCommandExecutor
+            .execute_captured("", &tokens, false, &opts)
+            .unwrap();
+        assert_eq!(captured.stdout, b"none|tool|path\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_longer_than_the_screen_goes_through_the_pager() {
+        let temp = TempDir::new().unwrap();
+        let paged = temp.path().join("paged.txt");
+        let opts = ExecOptions {
+            pager: Some(Pager {
+                command: vec![
+                    "sh".into(),
+                    "-c".into(),
+                    format!("cat > '{}'", paged.display()),
+                ],
+                rows: 3,
+            }),
+            ..ExecOptions::default()
+        };
+        let run = |lines: &str| {
+            let tokens: Vec<String> = vec!["seq".into(), lines.into()];
+            ShellCommandExecutor
+                .execute_captured("", &tokens, false, &opts)
+                .unwrap()
+        };
+
+        assert_eq!(run("2").stdout, b"1\n2\n");
+        assert!(!paged.exists());
+
+        let captured = run("5");
+        assert_eq!(captured.stdout, b"1\n2\n3\n4\n5\n");
+        assert_eq!(fs::read(&paged).unwrap(), captured.stdout);
+
+        // A few very long lines fill the screen too.
+        let captured = ShellCommandExecutor
+            .execute_captured("head -c 3000000 /dev/zero", &[], true, &opts)
+            .unwrap();
+        assert_eq!(captured.stdout.len(), CAPTURE_MAX_BYTES);
+        assert_eq!(fs::metadata(&paged).unwrap().len(), 3_000_000);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_past_max_output_lines_is_cut_off_but_captured() {
+        let mut direct = LimitedStdout::new(Some(3));
+        direct.write(b"1\n2\n");
+        direct.write(b"3\n4\n");
+        direct.write(b"5\n6");
+        assert_eq!(
+            (direct.lines, direct.hidden, direct.hidden_partial),
+            (3, 2, true)
+        );
+
+        let opts = ExecOptions {
+            max_output_lines: Some(3),
+            ..ExecOptions::default()
+        };
+        let tokens: Vec<String> = vec!["seq".into(), "10".into()];
+        let captured = ShellCommandExecutor
+            .execute_captured("", &tokens, false, &opts)
+            .unwrap();
+        assert_eq!(captured.stdout.iter().filter(|&&b| b == b'\n').count(), 10);
+    }
+
+    #[test]
+    fn capture_keeps_the_end_of_long_output() {
+        let mut captured = Capture::default();
+        let chunk = vec![b'a'; CAPTURE_MAX_BYTES];
+        for _ in 0..3 {
+            captured.push(&chunk);
+        }
+        captured.push(b"end\n");
+        assert!(captured.0.len() <= 2 * CAPTURE_MAX_BYTES);
+        let bytes = captured.into_bytes();
+        assert_eq!(bytes.len(), CAPTURE_MAX_BYTES);
+        assert!(bytes.ends_with(b"aaend\n"));
+    }
+
     #[test]
     fn expand_glob_no_metacharacters() {
         let result = expand_glob_if_needed("simple.txt");
@@ -115,8 +1343,56 @@ mod tests {
         let result = expand_glob_if_needed(&pattern);
 
         assert_eq!(result.len(), 2);
-        assert!(result.iter().any(|s| s.ends_with("test1.txt")));
-        assert!(result.iter().any(|s| s.ends_with("test2.txt")));
+        assert!(result
+            .iter()
+            .any(|s| s.to_string_lossy().ends_with("test1.txt")));
+        assert!(result
+            .iter()
+            .any(|s| s.to_string_lossy().ends_with("test2.txt")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn expand_glob_keeps_non_utf8_names_intact() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let name = std::ffi::OsStr::from_bytes(b"caf\xe9.txt");
+        if File::create(temp_dir.path().join(name)).is_err() {
+            return; // filesystem insists on UTF-8 names
+        }
+
+        let pattern = format!("{}/caf?.txt", temp_dir.path().display());
+        let result = expand_glob_if_needed(&pattern);
+        assert_eq!(result, vec![temp_dir.path().join(name).into_os_string()]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commands_with_a_dir_run_and_expand_globs_there() {
+        let temp = TempDir::new().unwrap();
+        fs::create_dir(temp.path().join("web")).unwrap();
+        fs::write(temp.path().join("web/a.js"), "").unwrap();
+        fs::write(temp.path().join("web/b.js"), "").unwrap();
+        let dir = temp.path().join("web");
+        assert_eq!(
+            expand_glob_in("*.js", Some(&dir)),
+            vec![OsString::from("a.js"), OsString::from("b.js")]
+        );
+        assert_eq!(
+            expand_glob_in("*.css", Some(&dir)),
+            vec![OsString::from("*.css")]
+        );
+
+        let opts = ExecOptions {
+            dir: Some(dir.clone()),
+            ..ExecOptions::default()
+        };
+        let tokens: Vec<String> = vec!["ls".into(), "*.js".into()];
+        let captured = ShellCommandExecutor
+            .execute_captured("", &tokens, false, &opts)
+            .unwrap();
+        assert_eq!(captured.stdout, b"a.js\nb.js\n");
     }
 
     #[test]
@@ -133,4 +1409,44 @@ mod tests {
         // Should fall back to literal on parse error
         assert_eq!(result, vec!["file[.txt"]);
     }
+
+    #[test]
+    fn remote_command_lines_keep_globs_and_quote_the_rest() {
+        let tokens: Vec<String> = ["grep", "-n", "it's", "logs dir/*.log", "$HOME"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            remote_command_line(&tokens),
+            r#"grep -n 'it'\''s' 'logs dir/'*.log '$HOME'"#
+        );
+    }
+
+    #[test]
+    fn long_expansions_are_split_into_batches() {
+        let os = |items: &[&str]| items.iter().map(OsString::from).collect::<Vec<_>>();
+        let args = vec![
+            os(&["rm"]),
+            os(&["-f"]),
+            os(&["a.log", "b.log", "c.log", "d.log", "e.log"]),
+            os(&["--"]),
+        ];
+        // rm, -f and -- take 33 bytes; each file 14.
+        let batches = plan_batches(args.clone(), 33 + 2 * 14).unwrap();
+        assert_eq!(
+            batches,
+            vec![
+                os(&["rm", "-f", "a.log", "b.log", "--"]),
+                os(&["rm", "-f", "c.log", "d.log", "--"]),
+                os(&["rm", "-f", "e.log", "--"]),
+            ]
+        );
+        assert_eq!(plan_batches(args.clone(), 1000).unwrap().len(), 1);
+        let err = plan_batches(args, 33 + 10).unwrap_err();
+        assert!(err.to_string().contains("cannot be split"));
+
+        let size = argument_size(&["echo".to_string(), "hi".to_string()], None).unwrap();
+        assert_eq!(size.batches, 1);
+        assert!(size.bytes < size.limit);
+    }
 }
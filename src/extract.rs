@@ -0,0 +1,245 @@
+//! Extraction of the command line from model output.
+//!
+//! Models asked for "exactly one command" still occasionally wrap it in prose
+//! ("Here is the command:") or append an explanation on the same line
+//! ("ls -la  This command lists…"). These heuristics pick the command line and
+//! cut such text off, reporting what was removed so it can be shown to the user.
+
+use anyhow::{anyhow, Result};
+
+/// Line prefixes that mark a line as an introduction rather than a command.
+const INTRO_PREFIXES: &[&str] = &[
+    "here is",
+    "here's",
+    "sure",
+    "certainly",
+    "the command",
+    "the following",
+    "to do this",
+    "you can",
+    "use the following",
+];
+
+/// Phrases that start natural-language text trailing a command on the same
+/// line, when followed by whitespace or the end of the line.
+const TRAILING_PROSE_MARKERS: &[&str] = &[
+    "this command",
+    "this will",
+    "this lists",
+    "this finds",
+    "this searches",
+    "this shows",
+    "this prints",
+    "this counts",
+    "this displays",
+    "this returns",
+    "which will",
+    "which lists",
+    "that will",
+    "— ",
+    "– ",
+    "# this",
+    "(this",
+];
+
+/// Labels that start trailing prose only when capitalized, since lowercase
+/// forms are plausible arguments (`grep note: todo.txt`).
+const TRAILING_PROSE_LABELS: &[&str] = &["Note:", "Explanation:"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractedCommand {
+    pub command: String,
+    /// Text that was removed around the command, if any.
+    pub stripped: Option<String>,
+}
+
+/// Picks the command line out of model output and strips surrounding prose.
+pub fn extract_command(text: &str) -> Result<ExtractedCommand> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with("```"))
+        .collect();
+
+    let mut stripped = Vec::new();
+    let mut command_line = None;
+    let mut following = Vec::new();
+    for line in &lines {
+        if command_line.is_some() {
+            following.push(line.to_string());
+        } else if is_intro_line(line) {
+            stripped.push(line.to_string());
+        } else {
+            command_line = Some(*line);
+        }
+    }
+
+    let line = command_line.ok_or_else(|| {
+        if lines.is_empty() {
+            anyhow!("LLM returned an empty command line")
+        } else {
+            anyhow!(
+                "LLM output contained no command line, only prose: {}",
+                text.trim()
+            )
+        }
+    })?;
+
+    let line = line.strip_prefix("$ ").unwrap_or(line);
+    let (command, trailing) = split_inline_code(line).unwrap_or_else(|| split_trailing_prose(line));
+    stripped.extend(trailing);
+    stripped.extend(following);
+
+    if command.is_empty() {
+        return Err(anyhow!("LLM returned an empty command line"));
+    }
+
+    Ok(ExtractedCommand {
+        command,
+        stripped: (!stripped.is_empty()).then(|| stripped.join(" ")),
+    })
+}
+
+fn is_intro_line(line: &str) -> bool {
+    let lower = line.to_lowercase();
+    // Commands rarely start with a capital letter; "scp f host:" must survive.
+    (line.ends_with(':') && line.starts_with(char::is_uppercase))
+        || INTRO_PREFIXES.iter().any(|p| lower.starts_with(p))
+}
+
+/// Handles "`ls -la` lists all files": the command is the inline code span.
+fn split_inline_code(line: &str) -> Option<(String, Option<String>)> {
+    let rest = line.strip_prefix('`')?;
+    let end = rest.find('`')?;
+    let command = rest[..end].trim().to_string();
+    let trailing = rest[end + 1..].trim();
+    Some((
+        command,
+        (!trailing.is_empty()).then(|| trailing.to_string()),
+    ))
+}
+
+/// Splits the line at the first unquoted whitespace followed by a prose marker.
+fn split_trailing_prose(line: &str) -> (String, Option<String>) {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+
+    for (idx, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (_, '\\') if quote != Some('\'') => escaped = true,
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, c) if c.is_whitespace() => {
+                let rest = line[idx..].trim_start();
+                let lower = rest.to_lowercase();
+                if TRAILING_PROSE_MARKERS
+                    .iter()
+                    .any(|m| starts_with_word(&lower, m))
+                    || TRAILING_PROSE_LABELS
+                        .iter()
+                        .any(|m| starts_with_word(rest, m))
+                {
+                    let command = trim_sentence_period(line[..idx].trim_end());
+                    return (command, Some(rest.to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    (line.to_string(), None)
+}
+
+/// Whether `text` starts with `marker` as whole words: `this will` matches
+/// "this will list" but not "this_file.txt" or "this willow".
+fn starts_with_word(text: &str, marker: &str) -> bool {
+    text.strip_prefix(marker).is_some_and(|rest| {
+        marker.ends_with(char::is_whitespace)
+            || rest.is_empty()
+            || rest.starts_with(char::is_whitespace)
+    })
+}
+
+/// Drops a sentence-ending period glued to the last argument ("notes.txt."),
+/// but keeps lone "." and path-like endings ("./", "../.").
+fn trim_sentence_period(command: &str) -> String {
+    if let Some(body) = command.strip_suffix('.') {
+        let prev = body.chars().last();
+        if prev.is_some_and(|p| !p.is_whitespace() && p != '.' && p != '/') {
+            return body.to_string();
+        }
+    }
+    command.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(text: &str) -> String {
+        extract_command(text).unwrap().command
+    }
+
+    #[test]
+    fn clean_output_is_unchanged() {
+        let extracted = extract_command("grep -rn 'This command' src").unwrap();
+        assert_eq!(extracted.command, "grep -rn 'This command' src");
+        assert_eq!(extracted.stripped, None);
+    }
+
+    #[test]
+    fn intro_lines_and_trailing_prose_are_stripped() {
+        let extracted =
+            extract_command("Here is the command:\nls -la  This command lists all files.").unwrap();
+        assert_eq!(extracted.command, "ls -la");
+        assert_eq!(
+            extracted.stripped.as_deref(),
+            Some("Here is the command: This command lists all files.")
+        );
+    }
+
+    #[test]
+    fn sentence_period_is_dropped_but_dot_paths_are_kept() {
+        assert_eq!(
+            command("cat notes.txt. This will print it"),
+            "cat notes.txt"
+        );
+        assert_eq!(command("find . — searches everything"), "find .");
+        assert_eq!(command("ls ../. this will list"), "ls ../.");
+    }
+
+    #[test]
+    fn inline_code_and_prompt_markers_are_unwrapped() {
+        assert_eq!(command("`du -sh .` shows the size"), "du -sh .");
+        assert_eq!(command("$ wc -l notes.txt"), "wc -l notes.txt");
+    }
+
+    #[test]
+    fn command_like_colons_are_not_prose() {
+        assert_eq!(command("scp notes.txt backup:"), "scp notes.txt backup:");
+        assert_eq!(command("grep note: todo.txt"), "grep note: todo.txt");
+        assert_eq!(command("ls  Note: hidden files excluded"), "ls");
+    }
+
+    #[test]
+    fn markers_must_be_whole_words() {
+        for line in [
+            "git checkout -- this_file.txt",
+            "rm (this).txt",
+            "grep -c '#' this_willow.txt",
+        ] {
+            assert_eq!(command(line), line);
+        }
+        assert_eq!(command("du -sh . (this shows the size)"), "du -sh .");
+    }
+
+    #[test]
+    fn prose_only_output_is_an_error() {
+        let err = extract_command("Sure, here you go:").unwrap_err();
+        assert!(err.to_string().contains("only prose"));
+    }
+}
@@ -0,0 +1,329 @@
+//! Saved invocations (`sai fav`): a name mapped to the sai arguments to run,
+//! stored next to the global config, plus shell alias generation for them.
+
+use crate::cli::Cli;
+use crate::config;
+use crate::envinfo::find_on_path;
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Names taken by `sai fav` actions.
+const RESERVED_NAMES: &[&str] = &["add", "list", "remove", "alias", "help"];
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Favorites {
+    #[serde(default)]
+    pub favorites: BTreeMap<String, Favorite>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Favorite {
+    /// Arguments passed to sai, e.g. `["--scope", "src", "count TODO comments"]`.
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AliasShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl AliasShell {
+    /// Shell named by $SHELL, defaulting to bash.
+    pub fn detect() -> Self {
+        let shell = env::var("SHELL").unwrap_or_default();
+        match shell.rsplit('/').next() {
+            Some("zsh") => AliasShell::Zsh,
+            Some("fish") => AliasShell::Fish,
+            _ => AliasShell::Bash,
+        }
+    }
+
+    /// Startup file the alias is appended to.
+    pub fn rc_file(self) -> Option<PathBuf> {
+        let home = dirs::home_dir()?;
+        Some(match self {
+            AliasShell::Bash => home.join(".bashrc"),
+            AliasShell::Zsh => home.join(".zshrc"),
+            AliasShell::Fish => home.join(".config").join("fish").join("config.fish"),
+        })
+    }
+}
+
+pub fn favorites_path() -> PathBuf {
+    config::config_root_dir().join("favorites.yaml")
+}
+
+pub fn load_favorites(path: &Path) -> Result<Favorites> {
+    if !path.exists() {
+        return Ok(Favorites::default());
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read favorites file {}", path.display()))?;
+    serde_yaml::from_str(&content)
+        .with_context(|| format!("Failed to parse favorites YAML {}", path.display()))
+}
+
+fn save_favorites(path: &Path, favorites: &Favorites) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    let yaml = serde_yaml::to_string(favorites).context("Failed to serialize favorites")?;
+    fs::write(path, yaml)
+        .with_context(|| format!("Failed to write favorites file {}", path.display()))
+}
+
+/// Saves `args` under `name` after checking that they form a valid sai invocation.
+pub fn add_favorite(path: &Path, name: &str, args: &[String]) -> Result<()> {
+    validate_name(name)?;
+    Cli::try_parse_from(std::iter::once("sai".to_string()).chain(args.iter().cloned())).map_err(
+        |err| {
+            anyhow!(
+                "Favorite arguments are not a valid sai invocation:\n{}",
+                err
+            )
+        },
+    )?;
+
+    let mut favorites = load_favorites(path)?;
+    let replaced = favorites
+        .favorites
+        .insert(
+            name.to_string(),
+            Favorite {
+                args: args.to_vec(),
+            },
+        )
+        .is_some();
+    save_favorites(path, &favorites)?;
+    let verb = if replaced { "Updated" } else { "Saved" };
+    println!(
+        "{} favorite '{}': sai {}",
+        verb,
+        name,
+        shell_words::join(args)
+    );
+    Ok(())
+}
+
+pub fn remove_favorite(path: &Path, name: &str) -> Result<()> {
+    let mut favorites = load_favorites(path)?;
+    if favorites.favorites.remove(name).is_none() {
+        return Err(anyhow!("No favorite named '{}'", name));
+    }
+    save_favorites(path, &favorites)?;
+    println!("Removed favorite '{}'", name);
+    Ok(())
+}
+
+pub fn list_favorites(path: &Path) -> Result<()> {
+    let favorites = load_favorites(path)?;
+    if favorites.favorites.is_empty() {
+        println!("No favorites yet. Add one with: sai fav add NAME -- <sai arguments>");
+        return Ok(());
+    }
+    let width = favorites
+        .favorites
+        .keys()
+        .map(String::len)
+        .max()
+        .unwrap_or(0);
+    for (name, fav) in &favorites.favorites {
+        println!("{:width$}  sai {}", name, shell_words::join(&fav.args));
+    }
+    Ok(())
+}
+
+/// Builds the CLI for `sai fav NAME [EXTRA...]`; extra arguments (usually
+/// flags such as `--confirm`) are placed before the saved ones.
+pub fn favorite_cli(path: &Path, name: &str, extra: &[String]) -> Result<Cli> {
+    let favorites = load_favorites(path)?;
+    let fav = favorites.favorites.get(name).ok_or_else(|| {
+        anyhow!(
+            "No favorite named '{}'. List favorites with: sai fav list",
+            name
+        )
+    })?;
+
+    let argv = std::iter::once("sai".to_string())
+        .chain(extra.iter().cloned())
+        .chain(fav.args.iter().cloned());
+    Cli::try_parse_from(argv)
+        .map_err(|err| anyhow!("Favorite '{}' no longer parses:\n{}", name, err))
+}
+
+/// Shell snippet defining `name` as a function that runs `sai fav name`.
+pub fn render_alias(name: &str, shell: AliasShell) -> String {
+    match shell {
+        AliasShell::Bash | AliasShell::Zsh => {
+            format!("{}() {{ sai fav {} \"$@\"; }}\n", name, name)
+        }
+        AliasShell::Fish => format!("function {}\n    sai fav {} $argv\nend\n", name, name),
+    }
+}
+
+/// Prints the alias for a favorite, or appends it to the shell startup file
+/// after confirmation when `append` is set.
+pub fn alias_favorite(
+    path: &Path,
+    name: &str,
+    shell: Option<AliasShell>,
+    append: bool,
+    reader: &mut dyn BufRead,
+) -> Result<()> {
+    let favorites = load_favorites(path)?;
+    if !favorites.favorites.contains_key(name) {
+        return Err(anyhow!("No favorite named '{}'", name));
+    }
+    let shell = shell.unwrap_or_else(AliasShell::detect);
+    let snippet = render_alias(name, shell);
+
+    if !append {
+        print!("{}", snippet);
+        return Ok(());
+    }
+
+    if let Some(existing) = find_on_path(name) {
+        eprintln!(
+            "Warning: '{}' shadows {} in your shell",
+            name,
+            existing.display()
+        );
+    }
+    let rc = shell
+        .rc_file()
+        .ok_or_else(|| anyhow!("Cannot determine your home directory"))?;
+    append_alias(&rc, name, &snippet, reader)
+}
+
+fn append_alias(rc: &Path, name: &str, snippet: &str, reader: &mut dyn BufRead) -> Result<()> {
+    let marker = format!("# sai favorite: {}", name);
+    let current = fs::read_to_string(rc).unwrap_or_default();
+    if current.lines().any(|l| l.trim() == marker) {
+        println!(
+            "{} already defines '{}'; nothing to do.",
+            rc.display(),
+            name
+        );
+        return Ok(());
+    }
+
+    eprintln!("Append to {}:\n", rc.display());
+    eprintln!("{}", marker);
+    eprint!("{}", snippet);
+    eprint!("\nProceed? [y/N] ");
+    io::stderr().flush().ok();
+    let mut answer = String::new();
+    reader.read_line(&mut answer)?;
+    if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    if let Some(parent) = rc.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(rc)
+        .with_context(|| format!("Failed to open {}", rc.display()))?;
+    let separator = if current.is_empty() || current.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    write!(file, "{}\n{}\n{}", separator, marker, snippet)
+        .with_context(|| format!("Failed to write {}", rc.display()))?;
+    println!(
+        "Added '{}' to {}. Open a new shell or source the file to use it.",
+        name,
+        rc.display()
+    );
+    Ok(())
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    let valid_chars = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if name.is_empty() || !valid_chars || name.starts_with('-') {
+        return Err(anyhow!(
+            "Favorite names may only contain letters, digits, '-' and '_', got '{}'",
+            name
+        ));
+    }
+    if RESERVED_NAMES.contains(&name) {
+        return Err(anyhow!("'{}' is reserved by sai fav", name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn favorites_round_trip_and_run_with_extra_flags() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("favorites.yaml");
+
+        add_favorite(&path, "todos", &args(&["--scope", "src", "count TODOs"])).unwrap();
+        assert!(add_favorite(&path, "list", &args(&["x"])).is_err());
+        assert!(add_favorite(&path, "bad", &args(&["--no-such-flag", "x"])).is_err());
+
+        let cli = favorite_cli(&path, "todos", &args(&["--confirm"])).unwrap();
+        assert!(cli.confirm);
+        assert_eq!(cli.scope.as_deref(), Some("src"));
+        assert_eq!(cli.arg1.as_deref(), Some("count TODOs"));
+
+        remove_favorite(&path, "todos").unwrap();
+        assert!(favorite_cli(&path, "todos", &[]).is_err());
+    }
+
+    #[test]
+    fn aliases_forward_arguments() {
+        assert_eq!(
+            render_alias("todos", AliasShell::Bash),
+            "todos() { sai fav todos \"$@\"; }\n"
+        );
+        assert_eq!(
+            render_alias("todos", AliasShell::Fish),
+            "function todos\n    sai fav todos $argv\nend\n"
+        );
+    }
+
+    #[test]
+    fn alias_is_appended_once_after_confirmation() {
+        let temp = TempDir::new().unwrap();
+        let rc = temp.path().join(".bashrc");
+        fs::write(&rc, "export EDITOR=vi").unwrap();
+        let snippet = render_alias("todos", AliasShell::Bash);
+
+        append_alias(&rc, "todos", &snippet, &mut Cursor::new(b"n\n".to_vec())).unwrap();
+        assert_eq!(fs::read_to_string(&rc).unwrap(), "export EDITOR=vi");
+
+        append_alias(&rc, "todos", &snippet, &mut Cursor::new(b"y\n".to_vec())).unwrap();
+        append_alias(&rc, "todos", &snippet, &mut Cursor::new(b"y\n".to_vec())).unwrap();
+        let content = fs::read_to_string(&rc).unwrap();
+        assert_eq!(
+            content,
+            "export EDITOR=vi\n\n# sai favorite: todos\ntodos() { sai fav todos \"$@\"; }\n"
+        );
+    }
+}
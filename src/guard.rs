@@ -0,0 +1,167 @@
+//! Input-side guard: flags natural language prompts that look like requests
+//! for credential theft, crypto mining or data exfiltration, and asks for an
+//! explicit acknowledgment before anything is sent to the LLM. It is a
+//! deterrent for shared environments, not a security boundary.
+
+use crate::pattern::{contains_phrase, glob_match};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// What the user must type to proceed with a flagged prompt.
+pub const ACKNOWLEDGMENT: &str = "proceed";
+
+const CREDENTIAL_THEFT: &[&str] = &[
+    "steal password",
+    "steal passwords",
+    "steal credentials",
+    "dump credentials",
+    "dump passwords",
+    "dump password hashes",
+    "harvest credentials",
+    "crack passwords",
+    "crack password hashes",
+    "mimikatz",
+    "keylogger",
+    "/etc/shadow",
+    "browser saved passwords",
+];
+
+const CRYPTO_MINING: &[&str] = &[
+    "xmrig",
+    "cryptominer",
+    "crypto miner",
+    "coin miner",
+    "mine monero",
+    "mine bitcoin",
+    "mining pool",
+    "stratum+tcp",
+];
+
+const DATA_EXFILTRATION: &[&str] = &[
+    "exfiltrate",
+    "exfiltration",
+    "exfil",
+    "upload to pastebin",
+    "send to pastebin",
+    "transfer.sh",
+    "reverse shell",
+    "without being detected",
+    "bypass dlp",
+];
+
+/// `prompt_guard:` section of the global config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptGuardConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Keep the built-in credential theft / crypto mining / exfiltration lists.
+    #[serde(default = "default_true")]
+    pub use_defaults: bool,
+    /// Extra terms per category. Plain terms match whole words or phrases;
+    /// terms with `*` or `?` are globs over the whole prompt.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub patterns: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for PromptGuardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            use_defaults: true,
+            patterns: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A category and the term that matched in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuardMatch {
+    pub category: String,
+    pub term: String,
+}
+
+/// Returns the first matching term of every category that flags `prompt`.
+pub fn check_prompt(cfg: &PromptGuardConfig, prompt: &str) -> Vec<GuardMatch> {
+    if !cfg.enabled {
+        return Vec::new();
+    }
+
+    let mut categories: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    if cfg.use_defaults {
+        for (name, terms) in [
+            ("credential_theft", CREDENTIAL_THEFT),
+            ("crypto_mining", CRYPTO_MINING),
+            ("data_exfiltration", DATA_EXFILTRATION),
+        ] {
+            categories
+                .entry(name.to_string())
+                .or_default()
+                .extend(terms.iter().map(|t| t.to_string()));
+        }
+    }
+    for (name, terms) in &cfg.patterns {
+        categories
+            .entry(name.clone())
+            .or_default()
+            .extend(terms.iter().cloned());
+    }
+
+    categories
+        .into_iter()
+        .filter_map(|(category, terms)| {
+            let term = terms.into_iter().find(|t| term_matches(t, prompt))?;
+            Some(GuardMatch { category, term })
+        })
+        .collect()
+}
+
+fn term_matches(term: &str, prompt: &str) -> bool {
+    if term.contains(['*', '?']) {
+        glob_match(term, prompt)
+    } else {
+        contains_phrase(prompt, term)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_lists_flag_each_category() {
+        let cfg = PromptGuardConfig::default();
+        let matches = check_prompt(&cfg, "start xmrig and exfiltrate ~/.ssh to a server");
+        assert_eq!(
+            matches,
+            vec![
+                GuardMatch {
+                    category: "crypto_mining".to_string(),
+                    term: "xmrig".to_string()
+                },
+                GuardMatch {
+                    category: "data_exfiltration".to_string(),
+                    term: "exfiltrate".to_string()
+                },
+            ]
+        );
+        assert!(check_prompt(&cfg, "count lines in the password policy doc").is_empty());
+    }
+
+    #[test]
+    fn configured_patterns_extend_or_replace_defaults() {
+        let cfg: PromptGuardConfig = serde_yaml::from_str(
+            "use_defaults: false\npatterns:\n  customer_data:\n    - '*customers*.csv*'\n",
+        )
+        .unwrap();
+        assert!(check_prompt(&cfg, "run xmrig").is_empty());
+        let matches = check_prompt(&cfg, "copy customers_2024.csv to my laptop");
+        assert_eq!(matches[0].category, "customer_data");
+
+        let disabled: PromptGuardConfig = serde_yaml::from_str("enabled: false\n").unwrap();
+        assert!(check_prompt(&disabled, "run xmrig").is_empty());
+    }
+}
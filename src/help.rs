@@ -18,6 +18,7 @@ pub enum HelpTopic {
 }
 
 impl HelpTopic {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(raw: &str) -> Option<Self> {
         let normalized = raw.trim().to_ascii_lowercase();
         match normalized.as_str() {
@@ -163,19 +164,59 @@ const TOPIC_ENTRIES: &[TopicEntry] = &[
     },
 ];
 
-pub const CLI_USAGE: &str = "sai [FLAGS] [PROMPT_CONFIG] \"<natural language prompt>\"";
+pub const CLI_USAGE: &str = "sai [FLAGS] [PROMPT_CONFIG...] \"<natural language prompt>\"";
 pub const CLI_ABOUT: &str = "Sai-cli ('sai') - Tell the shell what you want, not how to do it";
 pub const CLI_LONG_ABOUT: &str = "Natural language to safe shell commands using whitelisted tools and an AI backend. Run 'sai help topics' for detailed guidance.";
 pub const CLI_AFTER_HELP: &str = r#"Common flags:
   -s, --scope <SCOPE>     Provide a path or hint to restrict context
   -p, --peek <FILE>...    Send sample file(s) for schema inference
+      --auto-peek         Also peek at existing files named in the prompt
+      --peek-diff A B     Send how file B differs from A (columns, rows, changed values)
   -c, --confirm           Ask before executing the generated command
   -u, --unsafe            Allow pipes and redirects (always implies confirm)
   -e, --explain           Explain the generated command, then ask to confirm
-      --analyze           Explain the last sai invocation, do not run anything
-      --init              Create a starter config.yaml
+      --analyze [RUN]     Explain the last (or RUN: index or timestamp) sai invocation
+      --analyze-last N    Look for patterns across the last N invocations
+      --rerun [INDEX]     Run a past command again (1 = latest) after validating it
+      --refine            Treat the prompt as feedback on the previous command
+      --candidates N      Generate N alternatives and pick one from a menu
+      --tui               Full-screen prompt, command, explanation and history panes
+      --race              Ask two providers at once; the first valid answer wins
+      --print             Print the validated command only; do not run it
+      --fill              Print just the command, no newline (see sai shell-init)
+      --osc52             Copy the command to the local clipboard over SSH (OSC 52)
+      --copy[=run]        Copy the command to the clipboard instead of (or before) running it
+      --host USER@HOST    Run the command on another machine over ssh
+      --capture           Keep the end of the command's output in history
+      --full-output       Print all output despite execution.max_output_lines
+      --no-history        Do not record this run in history
+      --transcript FILE   Append this run to a Markdown transcript
+      --verify TEXT       Check the output against TEXT; exit 3 if it fails
+      --summarize-output  Summarize long command output after it is shown
+      --analyze-output    Explain what the command's output means for the prompt
+      --auto-fix N        On failure, ask the model for a fix and retry up to N times
+      --exec-timeout SECS Stop the command after SECS seconds (exit 124)
+      --jail              Reject path arguments outside the working directory
+      --read-only         Only read-only tools, no write flags or redirects
+                          (also SAI_READ_ONLY=1)
+      --backup            Copy the files a modifying command names before it runs
+      --transaction       Stage the files the command writes; keep them only if
+                          every step succeeds
+      --allow-network     Let network tools run without the network: policy's checks
+      --review            Have the model rate the command read-only/modifying/destructive
+      --model NAME        Use another model/deployment for this run
+      --temperature T     Sampling temperature for command generation
+      --seed N            Sampling seed for reproducible output
+      --init              Create a starter config.yaml (deprecated: use `sai init`;
+                          `sai init --project` sets up .sai/ for a repository)
       --add-prompt PATH   Merge tools from a prompt file into the global config
+      --with PATH         Merge tools from another prompt file for this run only
       --list-tools [PATH] List tools from global config and optional prompt file
+      --allow-tool NAME   Allow an extra tool for this run only
+      --no-cache          Ask the model again instead of reusing a cached command
+      --usage             Show daily token usage, cost and budget status
+      --estimate FILE     Estimate tokens and cost of a prompt file (no API calls)
+      --with-docs NAME    Add a tool's man page summary to the system prompt
 
 Run:
   sai help topics    to list help topics
@@ -0,0 +1,206 @@
+//! `sai history export`: the entries of the history log and its rotated
+//! generations as one JSON array or CSV table, written entry by entry as the
+//! log is read, for reports and spreadsheets.
+
+use crate::history::{self, HistoryEntry};
+use crate::humanize;
+use crate::recall::entry_prompt;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::ValueEnum;
+use std::borrow::Cow;
+use std::io::{self, BufWriter, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// A JSON array of the entries as recorded, one per line
+    Json,
+    /// A CSV table with a header row and the prompt recovered from argv
+    Csv,
+}
+
+/// Columns of the CSV export, in order.
+pub const CSV_COLUMNS: &[&str] = &[
+    "ts",
+    "cwd",
+    "prompt",
+    "generated_command",
+    "exit_code",
+    "unsafe_mode",
+    "confirm",
+    "explain",
+    "scope",
+    "peek_files",
+    "model",
+    "prompt_tokens",
+    "completion_tokens",
+    "notes",
+];
+
+/// Parses `--since`: an RFC 3339 timestamp, or a date meaning the start of
+/// that day in UTC.
+pub fn parse_since(text: &str) -> Result<DateTime<Utc>> {
+    if let Ok(ts) = DateTime::parse_from_rfc3339(text) {
+        return Ok(ts.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .ok()
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .map(|start| start.and_utc())
+        .ok_or_else(|| {
+            anyhow!(
+                "Invalid --since '{}'; use a date (2024-05-01) or an RFC 3339 timestamp",
+                text
+            )
+        })
+}
+
+/// Quotes a CSV field when it holds a comma, quote or line break.
+fn csv_field(text: &str) -> Cow<'_, str> {
+    if text.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", text.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+fn csv_row(entry: &HistoryEntry) -> String {
+    let usage = entry.usage.unwrap_or_default();
+    let fields = [
+        entry.ts.clone(),
+        entry.cwd.clone(),
+        entry_prompt(entry).unwrap_or_default(),
+        entry.generated_command.clone().unwrap_or_default(),
+        entry.exit_code.to_string(),
+        entry.unsafe_mode.to_string(),
+        entry.confirm.to_string(),
+        entry.explain.to_string(),
+        entry.scope.clone().unwrap_or_default(),
+        entry.peek_files.join(" "),
+        entry.model.clone().unwrap_or_default(),
+        usage.prompt_tokens.to_string(),
+        usage.completion_tokens.to_string(),
+        entry.notes.clone().unwrap_or_default(),
+    ];
+    let fields: Vec<Cow<str>> = fields.iter().map(|f| csv_field(f)).collect();
+    fields.join(",")
+}
+
+/// Writes a document one entry at a time; [`Exporter::finish`] closes it.
+pub struct Exporter<W: Write> {
+    out: W,
+    format: ExportFormat,
+    since: Option<DateTime<Utc>>,
+    pub written: usize,
+}
+
+impl<W: Write> Exporter<W> {
+    /// Starts the document: the opening bracket or the CSV header.
+    pub fn new(mut out: W, format: ExportFormat, since: Option<DateTime<Utc>>) -> Result<Self> {
+        match format {
+            ExportFormat::Json => write!(out, "[")?,
+            ExportFormat::Csv => writeln!(out, "{}", CSV_COLUMNS.join(","))?,
+        }
+        Ok(Self {
+            out,
+            format,
+            since,
+            written: 0,
+        })
+    }
+
+    /// Adds `entry` unless it is older than `since`. Entries whose time does
+    /// not parse are left out when `since` is set.
+    pub fn add(&mut self, entry: &HistoryEntry) -> Result<()> {
+        if let Some(since) = self.since {
+            let recent = DateTime::parse_from_rfc3339(&entry.ts).is_ok_and(|ts| ts >= since);
+            if !recent {
+                return Ok(());
+            }
+        }
+        match self.format {
+            ExportFormat::Json => {
+                let sep = if self.written == 0 { "\n  " } else { ",\n  " };
+                write!(self.out, "{}{}", sep, serde_json::to_string(entry)?)?;
+            }
+            ExportFormat::Csv => writeln!(self.out, "{}", csv_row(entry))?,
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Closes the document and returns the writer.
+    pub fn finish(mut self) -> Result<W> {
+        if self.format == ExportFormat::Json {
+            let end = if self.written == 0 { "]" } else { "\n]" };
+            writeln!(self.out, "{}", end)?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// `sai history export`: writes the document to stdout and the entry count
+/// to stderr.
+pub fn run_export(format: ExportFormat, since: Option<&str>) -> Result<()> {
+    let since = since.map(parse_since).transpose()?;
+    let stdout = io::stdout();
+    let mut exporter = Exporter::new(BufWriter::new(stdout.lock()), format, since)?;
+    for path in history::log_files() {
+        history::visit_entries_in_file(&path, |entry| exporter.add(&entry))?;
+    }
+    let written = exporter.written;
+    exporter.finish()?;
+    eprintln!(
+        "Exported {}.",
+        humanize::plural(written as u64, "history entry", "history entries")
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(ts: &str, prompt: &str, command: &str) -> HistoryEntry {
+        HistoryEntry {
+            ts: ts.to_string(),
+            cwd: "/srv/app".to_string(),
+            argv: vec!["sai".to_string(), prompt.to_string()],
+            generated_command: Some(command.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exports_are_well_formed_and_honor_since() {
+        let entries = [
+            entry("2024-04-30T23:59:00Z", "old run", "ls"),
+            entry("2024-05-01T08:00:00Z", "count, \"quoted\"", "wc -l a.txt"),
+            entry("2024-05-02T08:00:00Z", "list", "ls -la"),
+        ];
+        let since = Some(parse_since("2024-05-01").unwrap());
+
+        let mut json = Exporter::new(Vec::new(), ExportFormat::Json, since).unwrap();
+        entries.iter().for_each(|e| json.add(e).unwrap());
+        let json = json.finish().unwrap();
+        let parsed: Vec<HistoryEntry> = serde_json::from_slice(&json).unwrap();
+        assert_eq!(parsed, entries[1..]);
+
+        let empty = Exporter::new(Vec::new(), ExportFormat::Json, None).unwrap();
+        assert_eq!(empty.finish().unwrap(), b"[]\n");
+
+        let mut csv = Exporter::new(Vec::new(), ExportFormat::Csv, since).unwrap();
+        entries.iter().for_each(|e| csv.add(e).unwrap());
+        let csv = String::from_utf8(csv.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("ts,cwd,prompt,generated_command,exit_code"));
+        assert!(lines[1].starts_with(
+            "2024-05-01T08:00:00Z,/srv/app,\"count, \"\"quoted\"\"\",wc -l a.txt,0,false"
+        ));
+
+        assert!(parse_since("2024-05-01T10:00:00+02:00").is_ok());
+        assert!(parse_since("yesterday").is_err());
+    }
+}
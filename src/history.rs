@@ -1,12 +1,20 @@
 use crate::config;
-use anyhow::{Context, Result};
-use chrono::{SecondsFormat, Utc};
+use crate::envinfo::EnvironmentInfo;
+use crate::humanize;
+use crate::llm::TokenUsage;
+use crate::projectcfg;
+use crate::recall;
+use crate::redact::{self, RedactionConfig};
+use crate::risk::LlmReview;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
 use serde::{Deserialize, Serialize};
+use std::env;
 use std::fs::{self, File, OpenOptions};
 use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct HistoryEntry {
     pub ts: String,
     pub cwd: String,
@@ -19,16 +27,234 @@ pub struct HistoryEntry {
     pub scope: Option<String>,
     pub peek_files: Vec<String>,
     pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+    /// Model (OpenAI) or deployment (Azure) that produced `usage`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Sampling seed sent with the generation request, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Tools allowed for this run only via --allow-tool.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_tools: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<EnvironmentInfo>,
+    /// End of what the command wrote, when output capture is on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<CommandOutput>,
+    /// The model's review of the command (--review / `confirmation.llm_review`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review: Option<LlmReview>,
 }
 
 pub const HISTORY_MAX_BYTES: u64 = 1_000_000;
 
+/// Bytes of each output stream kept by default when capturing.
+pub const DEFAULT_CAPTURE_MAX_BYTES: usize = 4096;
+
+/// `history:` section of the global config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryConfig {
+    /// Write history entries at all; `false` acts like `--no-history` on
+    /// every run.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// What runs without history leave in the log.
+    #[serde(default)]
+    pub skipped: SkippedRuns,
+    /// Store the end of each command's stdout/stderr in its entry (also `--capture`).
+    #[serde(default)]
+    pub capture_output: bool,
+    /// Bytes kept per stream; earlier output is dropped.
+    #[serde(default = "default_capture_max_bytes")]
+    pub capture_max_bytes: usize,
+    /// Redact likely secrets from the entry before it is written.
+    #[serde(default = "default_true")]
+    pub scrub_secrets: bool,
+    /// When the log rotates and how much history is kept.
+    #[serde(flatten)]
+    pub retention: Retention,
+}
+
+/// Retention settings in the `history:` section. The log rotates to
+/// `history.log.1` once it exceeds `max_bytes`, earlier generations moving up
+/// to `history.log.2` and so on; entries past `max_entries` (counting from the
+/// latest, across all files) or older than `max_age_days` are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Retention {
+    pub max_bytes: u64,
+    /// Rotated logs kept; 0 discards the log when it rotates.
+    pub max_generations: usize,
+    pub max_entries: Option<usize>,
+    pub max_age_days: Option<u32>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_bytes: HISTORY_MAX_BYTES,
+            max_generations: 1,
+            max_entries: None,
+            max_age_days: None,
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            skipped: SkippedRuns::default(),
+            capture_output: false,
+            capture_max_bytes: DEFAULT_CAPTURE_MAX_BYTES,
+            scrub_secrets: true,
+            retention: Retention::default(),
+        }
+    }
+}
+
+/// `history.skipped`: how a run with `--no-history` (or `enabled: false`)
+/// is logged. With a `budget:` section such runs always leave a stub, so
+/// skipping history does not skip the budget.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SkippedRuns {
+    /// Nothing is written.
+    #[default]
+    Omit,
+    /// A redacted entry: time, exit code, model and token usage only, so
+    /// `--usage` and budgets still count the run.
+    Stub,
+}
+
+impl HistoryConfig {
+    /// The entry to write for a finished run, if any. `budgeted` (a `budget:`
+    /// section is set) turns `skipped: omit` into a stub.
+    pub fn entry_to_write(
+        &self,
+        entry: HistoryEntry,
+        no_history: bool,
+        budgeted: bool,
+    ) -> Option<HistoryEntry> {
+        if self.enabled && !no_history {
+            return Some(entry);
+        }
+        match self.skipped {
+            SkippedRuns::Omit if !budgeted => None,
+            SkippedRuns::Omit | SkippedRuns::Stub => Some(HistoryEntry {
+                ts: entry.ts,
+                cwd: String::new(),
+                argv: Vec::new(),
+                exit_code: entry.exit_code,
+                generated_command: None,
+                unsafe_mode: false,
+                confirm: false,
+                explain: false,
+                scope: None,
+                peek_files: Vec::new(),
+                notes: Some(REDACTED_NOTE.to_string()),
+                usage: entry.usage,
+                model: entry.model,
+                seed: None,
+                extra_tools: Vec::new(),
+                environment: None,
+                output: None,
+                review: None,
+            }),
+        }
+    }
+}
+
+/// Replaces likely secrets (see [`crate::redact`]) in the invocation,
+/// command, notes and captured output of `entry`, so a key pasted into a
+/// prompt is not written to disk. Returns how many were replaced.
+pub fn scrub_entry(entry: &mut HistoryEntry, redaction: &RedactionConfig) -> usize {
+    let mut count = 0;
+    let mut scrub = |text: &mut String| {
+        let (clean, n) = redact::redact(text, redaction);
+        if n > 0 {
+            *text = clean;
+            count += n;
+        }
+    };
+    entry.argv.iter_mut().for_each(&mut scrub);
+    entry.generated_command.iter_mut().for_each(&mut scrub);
+    entry.notes.iter_mut().for_each(&mut scrub);
+    if let Some(output) = &mut entry.output {
+        scrub(&mut output.stdout);
+        scrub(&mut output.stderr);
+    }
+    count
+}
+
+/// Notes of a redacted stub entry.
+pub const REDACTED_NOTE: &str = "history skipped (redacted)";
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_capture_max_bytes() -> usize {
+    DEFAULT_CAPTURE_MAX_BYTES
+}
+
+/// Captured output of the executed command, cut to its last bytes.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// Bytes dropped from the start of the two streams together.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub omitted_bytes: usize,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+impl CommandOutput {
+    /// Keeps the last `max_bytes` of each stream (the end of the output is
+    /// where errors and summaries usually are).
+    pub fn keep_tail(stdout: &[u8], stderr: &[u8], max_bytes: usize) -> Self {
+        let (stdout, dropped_out) = tail(stdout, max_bytes);
+        let (stderr, dropped_err) = tail(stderr, max_bytes);
+        Self {
+            stdout,
+            stderr,
+            omitted_bytes: dropped_out + dropped_err,
+        }
+    }
+}
+
+/// Last `max_bytes` of `data` as text, starting on a character boundary, and
+/// how many bytes were dropped.
+fn tail(data: &[u8], max_bytes: usize) -> (String, usize) {
+    let mut start = data.len().saturating_sub(max_bytes);
+    while start < data.len() && start > 0 && (data[start] & 0b1100_0000) == 0b1000_0000 {
+        start += 1;
+    }
+    (String::from_utf8_lossy(&data[start..]).into_owned(), start)
+}
+
+/// The history log: in the project's `.sai/history/` when there is one,
+/// otherwise in the sai config directory.
 pub fn history_log_path() -> PathBuf {
-    config::config_root_dir().join("history.log")
+    env::current_dir()
+        .ok()
+        .and_then(|cwd| projectcfg::project_history_dir(&cwd))
+        .unwrap_or_else(config::config_root_dir)
+        .join("history.log")
 }
 
-pub fn write_entry(entry: HistoryEntry) -> Result<()> {
-    let path = history_log_path();
+pub fn write_entry(entry: HistoryEntry, retention: &Retention) -> Result<()> {
+    write_entry_to(&history_log_path(), entry, retention)
+}
+
+/// Appends `entry` to the log at `path`, then rotates and prunes it as
+/// `retention` says.
+pub fn write_entry_to(path: &Path, entry: HistoryEntry, retention: &Retention) -> Result<()> {
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent)
             .with_context(|| format!("Failed to create history directory {}", parent.display()))?;
@@ -37,38 +263,301 @@ pub fn write_entry(entry: HistoryEntry) -> Result<()> {
     let mut file = OpenOptions::new()
         .create(true)
         .append(true)
-        .open(&path)
+        .open(path)
         .with_context(|| format!("Failed to open history log {}", path.display()))?;
 
     let line = serde_json::to_string(&entry)?;
     writeln!(file, "{}", line)?;
     file.flush()?;
 
-    rotate_history_if_needed(&path)?;
+    rotate_history_if_needed(path, retention, Utc::now())?;
     Ok(())
 }
 
 pub fn read_latest_entry() -> Result<Option<HistoryEntry>> {
-    let path = history_log_path();
+    read_selected_entry(&EntrySelector::Index(1))
+}
+
+/// A run picked by position or time, e.g. for `--analyze 3`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntrySelector {
+    /// Runs back: 1 is the latest, as in `sai history list`.
+    Index(usize),
+    /// The start of a timestamp (`2024-05-01T10:00`); the latest run from
+    /// then is picked.
+    Time(String),
+}
+
+/// Parses an [`EntrySelector`]: a number is an index, a date or the start of
+/// one is a timestamp (a space may stand for the `T`).
+pub fn parse_entry_selector(value: &str) -> Result<EntrySelector, String> {
+    let value = value.trim();
+    if let Ok(index) = value.parse::<usize>() {
+        return match index {
+            0 => Err("runs are counted from 1, the latest".to_string()),
+            _ => Ok(EntrySelector::Index(index)),
+        };
+    }
+    let timestamp = value.contains('-')
+        && value.starts_with(|c: char| c.is_ascii_digit())
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || "-:T Z".contains(c));
+    if !timestamp {
+        return Err(format!(
+            "'{}' is neither a run index (1 is the latest) nor a timestamp like 2024-05-01T10:00",
+            value
+        ));
+    }
+    Ok(EntrySelector::Time(value.replacen(' ', "T", 1)))
+}
+
+/// Reads the entry `selector` picks from the current log and its rotated
+/// generations, newest first, stopping at the file that holds it.
+pub fn read_selected_entry(selector: &EntrySelector) -> Result<Option<HistoryEntry>> {
+    select_entry_in(&history_log_path(), selector)
+}
+
+fn select_entry_in(path: &Path, selector: &EntrySelector) -> Result<Option<HistoryEntry>> {
+    let mut back = match selector {
+        EntrySelector::Index(0) => return Ok(None),
+        EntrySelector::Index(index) => *index,
+        EntrySelector::Time(_) => 0,
+    };
+    for file in std::iter::once(path.to_path_buf()).chain(generations(path)) {
+        let mut entries = read_entries_from_file(&file)?;
+        match selector {
+            EntrySelector::Index(_) if back <= entries.len() => {
+                let idx = entries.len() - back;
+                return Ok(Some(entries.swap_remove(idx)));
+            }
+            EntrySelector::Index(_) => back -= entries.len(),
+            EntrySelector::Time(prefix) => {
+                if let Some(idx) = entries
+                    .iter()
+                    .rposition(|e| e.ts.starts_with(prefix.as_str()))
+                {
+                    return Ok(Some(entries.swap_remove(idx)));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Reads every readable entry, oldest first, across the rotated logs and the current one.
+pub fn read_all_entries() -> Result<Vec<HistoryEntry>> {
+    let mut entries = Vec::new();
+    for path in log_files() {
+        entries.extend(read_entries_from_file(&path)?);
+    }
+    Ok(entries)
+}
+
+/// The entry `index` runs back, across rotations: 1 is the latest run.
+pub fn entry_at(entries: &[HistoryEntry], index: usize) -> Option<&HistoryEntry> {
+    entries
+        .len()
+        .checked_sub(index)
+        .and_then(|idx| entries.get(idx))
+}
+
+/// The entry before `entry` in `entries` whose time follows `prefix` in the
+/// notes of `entry`, e.g. the original of a run noted "rerun of
+/// 2024-05-01T10:00:00Z". The nearest one wins when runs share a second.
+pub fn noted_entry<'a>(
+    entries: &'a [HistoryEntry],
+    entry: &HistoryEntry,
+    prefix: &str,
+) -> Option<&'a HistoryEntry> {
+    let ts = entry
+        .notes
+        .as_deref()?
+        .split("; ")
+        .find_map(|note| note.strip_prefix(prefix))?;
+    let end = entries
+        .iter()
+        .position(|e| std::ptr::eq(e, entry))
+        .unwrap_or(entries.len());
+    entries[..end].iter().rev().find(|e| e.ts == ts)
+}
+
+/// The `sai history list` table: the last `count` entries, oldest first,
+/// numbered as [`entry_at`] counts them.
+pub fn format_list(entries: &[HistoryEntry], count: usize) -> String {
+    const PROMPT_WIDTH: usize = 40;
+    let mut out = format!(
+        "{:>4}  {:<16}  {:>4}  {:<PROMPT_WIDTH$}  {}\n",
+        "#", "Time", "Exit", "Prompt", "Command"
+    );
+    let start = entries.len().saturating_sub(count);
+    for (idx, entry) in entries.iter().enumerate().skip(start) {
+        let ts = entry.ts.get(..16).unwrap_or(&entry.ts).replace('T', " ");
+        let prompt = recall::entry_prompt(entry);
+        let command = entry
+            .generated_command
+            .as_deref()
+            .or(entry.notes.as_deref())
+            .unwrap_or("-");
+        out.push_str(&format!(
+            "{:>4}  {:<16}  {:>4}  {:<PROMPT_WIDTH$}  {}\n",
+            entries.len() - idx,
+            ts,
+            entry.exit_code,
+            recall::fit(prompt.as_deref().unwrap_or("-"), PROMPT_WIDTH),
+            command
+        ));
+    }
+    out
+}
+
+/// Every recorded field of one entry, one per line, for `sai history show`.
+pub fn format_entry(entry: &HistoryEntry) -> String {
+    let mut lines = vec![
+        ("Time", entry.ts.clone()),
+        ("Directory", entry.cwd.clone()),
+        ("Invocation", shell_words::join(&entry.argv)),
+    ];
+    if let Some(prompt) = recall::entry_prompt(entry) {
+        lines.push(("Prompt", prompt));
+    }
+    if let Some(command) = &entry.generated_command {
+        lines.push(("Command", command.clone()));
+    }
+    lines.push(("Exit code", entry.exit_code.to_string()));
+    let flags: Vec<&str> = [
+        (entry.unsafe_mode, "unsafe"),
+        (entry.confirm, "confirm"),
+        (entry.explain, "explain"),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, name)| *name)
+    .collect();
+    if !flags.is_empty() {
+        lines.push(("Flags", flags.join(", ")));
+    }
+    if let Some(scope) = &entry.scope {
+        lines.push(("Scope", scope.clone()));
+    }
+    if !entry.peek_files.is_empty() {
+        lines.push(("Peek", entry.peek_files.join(", ")));
+    }
+    if !entry.extra_tools.is_empty() {
+        lines.push(("Extra tools", entry.extra_tools.join(", ")));
+    }
+    if let Some(model) = &entry.model {
+        lines.push(("Model", model.clone()));
+    }
+    if let Some(usage) = &entry.usage {
+        lines.push((
+            "Tokens",
+            format!(
+                "{} prompt + {} completion",
+                usage.prompt_tokens, usage.completion_tokens
+            ),
+        ));
+    }
+    if let Some(seed) = entry.seed {
+        lines.push(("Seed", seed.to_string()));
+    }
+    if let Some(review) = &entry.review {
+        lines.push(("Review", format!("{}: {}", review.effect, review.reason)));
+    }
+    if let Some(notes) = &entry.notes {
+        lines.push(("Notes", notes.clone()));
+    }
+    if let Some(env) = &entry.environment {
+        lines.push(("OS", env.os.clone()));
+        if let Some(shell) = &env.shell {
+            lines.push(("Shell", shell.clone()));
+        }
+        for (tool, version) in &env.tool_versions {
+            lines.push(("Tool", format!("{}: {}", tool, version)));
+        }
+    }
+    let mut out: String = lines
+        .iter()
+        .map(|(label, value)| format!("{:<12}{}\n", format!("{}:", label), value))
+        .collect();
+    if let Some(output) = &entry.output {
+        if output.omitted_bytes > 0 {
+            out.push_str(&format!(
+                "\nOutput ({} earlier not kept):\n",
+                humanize::bytes(output.omitted_bytes as u64)
+            ));
+        } else {
+            out.push_str("\nOutput:\n");
+        }
+        for (name, text) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            if !text.is_empty() {
+                out.push_str(&format!("--- {} ---\n{}", name, text));
+                if !text.ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+        }
+    }
+    out
+}
 
-    if let Some(entry) = read_latest_from_file(&path)? {
-        return Ok(Some(entry));
+/// Prints the table for `sai history list`.
+pub fn print_list(count: usize) -> Result<()> {
+    let entries = read_all_entries()?;
+    if entries.is_empty() {
+        return Err(anyhow!("No history entries found"));
     }
+    print!("{}", format_list(&entries, count));
+    Ok(())
+}
+
+/// Prints one entry for `sai history show`.
+pub fn print_entry(index: usize) -> Result<()> {
+    let entries = read_all_entries()?;
+    let entry = entry_at(&entries, index).ok_or_else(|| {
+        anyhow!(
+            "No history entry {}; the log holds {} (1 is the latest)",
+            index,
+            entries.len()
+        )
+    })?;
+    print!("{}", format_entry(entry));
+    Ok(())
+}
+
+/// Reads the readable entries of one log file, oldest first.
+pub fn read_entries_from_file(path: &Path) -> Result<Vec<HistoryEntry>> {
+    let mut entries = Vec::new();
+    visit_entries_in_file(path, |entry| {
+        entries.push(entry);
+        Ok(())
+    })?;
+    Ok(entries)
+}
 
-    let backup = backup_path(&path);
-    read_latest_from_file(&backup)
+/// The log files, oldest first: the rotated generations, then the current log.
+pub fn log_files() -> Vec<PathBuf> {
+    let path = history_log_path();
+    let mut files = generations(&path);
+    files.reverse();
+    files.push(path);
+    files
 }
 
-fn read_latest_from_file(path: &Path) -> Result<Option<HistoryEntry>> {
+/// Calls `visit` with each readable entry of one log file as it is read,
+/// oldest first, without holding the file in memory.
+pub fn visit_entries_in_file<F>(path: &Path, mut visit: F) -> Result<()>
+where
+    F: FnMut(HistoryEntry) -> Result<()>,
+{
     if !path.exists() {
-        return Ok(None);
+        return Ok(());
     }
 
     let file = File::open(path)
         .with_context(|| format!("Failed to open history log {}", path.display()))?;
     let reader = BufReader::new(file);
 
-    let mut last_good: Option<HistoryEntry> = None;
     for line in reader.lines() {
         let line = match line {
             Ok(l) => l,
@@ -83,7 +572,7 @@ fn read_latest_from_file(path: &Path) -> Result<Option<HistoryEntry>> {
         }
 
         match serde_json::from_str::<HistoryEntry>(&line) {
-            Ok(entry) => last_good = Some(entry),
+            Ok(entry) => visit(entry)?,
             Err(err) => {
                 eprintln!(
                     "Skipping malformed history entry in {}: {}",
@@ -94,29 +583,62 @@ fn read_latest_from_file(path: &Path) -> Result<Option<HistoryEntry>> {
         }
     }
 
-    Ok(last_good)
+    Ok(())
 }
 
-fn rotate_history_if_needed(path: &Path) -> Result<()> {
-    let meta = match fs::metadata(path) {
-        Ok(m) => m,
-        Err(_) => return Ok(()),
-    };
+fn rotate_history_if_needed(path: &Path, retention: &Retention, now: DateTime<Utc>) -> Result<()> {
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if size > retention.max_bytes {
+        rotate(path, retention.max_generations)?;
+    }
 
-    if meta.len() <= HISTORY_MAX_BYTES {
-        return Ok(());
+    let mut extra = retention.max_generations + 1;
+    while generation_path(path, extra).exists() {
+        let file = generation_path(path, extra);
+        fs::remove_file(&file)
+            .with_context(|| format!("Failed to remove history log {}", file.display()))?;
+        extra += 1;
+    }
+
+    if retention.max_entries.is_some() || retention.max_age_days.is_some() {
+        prune_entries(path, retention, now)?;
+    }
+    Ok(())
+}
+
+/// Moves each rotated log one generation up, dropping the oldest, and the
+/// current log to generation 1.
+fn rotate(path: &Path, generations: usize) -> Result<()> {
+    if generations == 0 {
+        return fs::remove_file(path)
+            .with_context(|| format!("Failed to remove history log {}", path.display()));
     }
 
-    let backup = backup_path(path);
-    if backup.exists() {
-        fs::remove_file(&backup).with_context(|| {
+    let oldest = generation_path(path, generations);
+    if oldest.exists() {
+        fs::remove_file(&oldest).with_context(|| {
             format!(
                 "Failed to remove existing history backup {}",
-                backup.display()
+                oldest.display()
             )
         })?;
     }
 
+    for n in (1..generations).rev() {
+        let from = generation_path(path, n);
+        if from.exists() {
+            let to = generation_path(path, n + 1);
+            fs::rename(&from, &to).with_context(|| {
+                format!(
+                    "Failed to rotate history log {} -> {}",
+                    from.display(),
+                    to.display()
+                )
+            })?;
+        }
+    }
+
+    let backup = generation_path(path, 1);
     fs::rename(path, &backup).with_context(|| {
         format!(
             "Failed to rotate history log {} -> {}",
@@ -128,10 +650,72 @@ fn rotate_history_if_needed(path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn backup_path(path: &Path) -> PathBuf {
-    let mut backup = path.to_path_buf();
-    backup.set_extension("log.1");
-    backup
+/// The time of an entry, read without parsing the rest of it.
+#[derive(Deserialize)]
+struct Stamp {
+    ts: String,
+}
+
+/// Drops the entries past `max_entries` or older than `max_age_days` from the
+/// log and its generations, rewriting the files that lose entries and
+/// removing those left empty. Lines that do not parse are kept.
+fn prune_entries(path: &Path, retention: &Retention, now: DateTime<Utc>) -> Result<()> {
+    let cutoff = retention
+        .max_age_days
+        .map(|days| now - Duration::days(i64::from(days)));
+    let mut remaining = retention.max_entries.unwrap_or(usize::MAX);
+
+    let files = std::iter::once(path.to_path_buf()).chain(generations(path));
+    for file in files {
+        let text = match fs::read_to_string(&file) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+        let mut kept = Vec::with_capacity(lines.len());
+        for line in lines.iter().rev() {
+            let Ok(stamp) = serde_json::from_str::<Stamp>(line) else {
+                kept.push(*line);
+                continue;
+            };
+            let expired = cutoff.is_some_and(|cutoff| {
+                DateTime::parse_from_rfc3339(&stamp.ts).is_ok_and(|ts| ts < cutoff)
+            });
+            if expired || remaining == 0 {
+                continue;
+            }
+            remaining -= 1;
+            kept.push(*line);
+        }
+        if kept.len() == lines.len() {
+            continue;
+        }
+
+        if kept.is_empty() {
+            fs::remove_file(&file)
+                .with_context(|| format!("Failed to remove history log {}", file.display()))?;
+        } else {
+            kept.reverse();
+            fs::write(&file, format!("{}\n", kept.join("\n")))
+                .with_context(|| format!("Failed to prune history log {}", file.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Rotated log `n` of the log at `path`: `history.log.1` is the newest.
+fn generation_path(path: &Path, n: usize) -> PathBuf {
+    let mut file = path.to_path_buf();
+    file.set_extension(format!("log.{}", n));
+    file
+}
+
+/// The rotated logs present next to `path`, newest first.
+fn generations(path: &Path) -> Vec<PathBuf> {
+    (1..)
+        .map(|n| generation_path(path, n))
+        .take_while(|file| file.exists())
+        .collect()
 }
 
 pub fn now_iso_ts() -> String {
@@ -153,21 +737,143 @@ mod tests {
             ts: "2024-01-01T00:00:00Z".to_string(),
             cwd: "/tmp".to_string(),
             argv: vec!["sai".to_string()],
-            exit_code: 0,
             generated_command: Some("echo hi".to_string()),
-            unsafe_mode: false,
             confirm: true,
-            explain: false,
             scope: Some(".".to_string()),
             peek_files: vec!["a.txt".to_string()],
             notes: Some("note".to_string()),
+            usage: Some(TokenUsage {
+                prompt_tokens: 10,
+                completion_tokens: 2,
+            }),
+            model: Some("test-model".to_string()),
+            extra_tools: vec!["jq".to_string()],
+            ..Default::default()
         };
 
-        write_entry(entry.clone()).unwrap();
+        write_entry(entry.clone(), &Retention::default()).unwrap();
         let latest = read_latest_entry().unwrap().unwrap();
         assert_eq!(latest.generated_command, entry.generated_command);
         assert_eq!(latest.peek_files, entry.peek_files);
         assert!(latest.confirm);
+        assert_eq!(latest.usage, entry.usage);
+    }
+
+    #[test]
+    fn skipped_runs_are_omitted_or_redacted() {
+        let entry = HistoryEntry {
+            ts: "2024-01-01T00:00:00Z".to_string(),
+            cwd: "/home/me/secret-project".to_string(),
+            argv: vec!["sai".to_string(), "--no-history".to_string()],
+            exit_code: 1,
+            generated_command: Some("grep -r password .".to_string()),
+            confirm: true,
+            usage: Some(TokenUsage {
+                prompt_tokens: 10,
+                completion_tokens: 2,
+            }),
+            model: Some("test-model".to_string()),
+            ..Default::default()
+        };
+        let cfg = HistoryConfig::default();
+        assert_eq!(
+            cfg.entry_to_write(entry.clone(), false, false),
+            Some(entry.clone())
+        );
+        assert_eq!(cfg.entry_to_write(entry.clone(), true, false), None);
+        let budget_stub = cfg.entry_to_write(entry.clone(), true, true).unwrap();
+        assert_eq!(budget_stub.usage, entry.usage);
+        assert_eq!(budget_stub.generated_command, None);
+
+        let cfg: HistoryConfig = serde_yaml::from_str("enabled: false\nskipped: stub\n").unwrap();
+        let stub = cfg.entry_to_write(entry.clone(), false, false).unwrap();
+        assert_eq!(stub.exit_code, 1);
+        assert_eq!(stub.usage, entry.usage);
+        assert!(stub.argv.is_empty() && stub.cwd.is_empty());
+        assert_eq!(stub.generated_command, None);
+        assert_eq!(stub.notes.as_deref(), Some(REDACTED_NOTE));
+    }
+
+    #[test]
+    fn secrets_are_scrubbed_from_the_entry() {
+        let key = "sk-proj-4f9a8b7c6d5e4f3a2b1c";
+        let mut entry = HistoryEntry {
+            ts: "2024-01-01T00:00:00Z".to_string(),
+            cwd: "/srv".to_string(),
+            argv: vec!["sai".to_string(), format!("call the api with {}", key)],
+            generated_command: Some(format!("curl -H 'Authorization: Bearer {}' api", key)),
+            notes: Some("password=hunter2".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(scrub_entry(&mut entry, &RedactionConfig::default()), 3);
+        assert_eq!(entry.argv[1], "call the api with [REDACTED]");
+        assert_eq!(
+            entry.generated_command.as_deref(),
+            Some("curl -H 'Authorization: Bearer [REDACTED]' api")
+        );
+        assert_eq!(entry.notes.as_deref(), Some("password=[REDACTED]"));
+        assert!(HistoryConfig::default().scrub_secrets);
+    }
+
+    #[test]
+    fn entries_without_usage_still_parse() {
+        let line = r#"{"ts":"t","cwd":"/","argv":[],"exit_code":0,"generated_command":null,"unsafe_mode":false,"confirm":false,"explain":false,"scope":null,"peek_files":[],"notes":null}"#;
+        let entry: HistoryEntry = serde_json::from_str(line).unwrap();
+        assert_eq!(entry.usage, None);
+    }
+
+    #[test]
+    fn captured_output_keeps_the_end_of_each_stream() {
+        let output = CommandOutput::keep_tail(b"line 1\nline 2\n", "caf\u{e9}!".as_bytes(), 7);
+        assert_eq!(output.stdout, "line 2\n");
+        assert_eq!(output.stderr, "caf\u{e9}!");
+        assert_eq!(output.omitted_bytes, 7);
+
+        // A cut inside a multi-byte character moves to the next boundary.
+        let output = CommandOutput::keep_tail(b"", "\u{e9}\u{e9}".as_bytes(), 3);
+        assert_eq!(output.stderr, "\u{e9}");
+        assert_eq!(output.omitted_bytes, 2);
+    }
+
+    #[test]
+    fn list_numbers_from_the_latest_and_show_prints_the_details() {
+        let entry = |prompt: &str, command: &str, exit_code: i32| HistoryEntry {
+            ts: "2024-05-01T10:00:00Z".to_string(),
+            cwd: "/srv/app".to_string(),
+            argv: vec!["sai".to_string(), "-c".to_string(), prompt.to_string()],
+            exit_code,
+            generated_command: Some(command.to_string()),
+            confirm: true,
+            model: Some("gpt-test".to_string()),
+            output: Some(CommandOutput {
+                stdout: String::new(),
+                stderr: "ls: missing: No such file".to_string(),
+                omitted_bytes: 0,
+            }),
+            ..Default::default()
+        };
+        let entries = vec![
+            entry("list files", "ls", 0),
+            entry("show disk usage", "du -sh .", 0),
+            entry("list missing", "ls missing", 2),
+        ];
+        assert_eq!(entry_at(&entries, 1), entries.last());
+        assert_eq!(entry_at(&entries, 3), entries.first());
+        assert!(entry_at(&entries, 0).is_none());
+        assert!(entry_at(&entries, 4).is_none());
+
+        let list = format_list(&entries, 2);
+        let rows: Vec<&str> = list.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[1].starts_with("   2  2024-05-01 10:00     0  show disk usage"));
+        assert!(rows[2].ends_with("ls missing"));
+
+        let shown = format_entry(&entries[2]);
+        assert!(shown.contains("Invocation: sai -c 'list missing'\n"));
+        assert!(shown.contains("Prompt:     list missing\n"));
+        assert!(shown.contains("Exit code:  2\n"));
+        assert!(shown.contains("Flags:      confirm\n"));
+        assert!(shown.ends_with("--- stderr ---\nls: missing: No such file\n"));
     }
 
     #[test]
@@ -179,28 +885,139 @@ mod tests {
             ts: "2024-01-01T00:00:00Z".to_string(),
             cwd: "/tmp".to_string(),
             argv: vec!["sai".to_string()],
-            exit_code: 0,
             generated_command: Some("echo hi".to_string()),
-            unsafe_mode: false,
             confirm: true,
-            explain: false,
-            scope: None,
-            peek_files: Vec::new(),
             notes: Some("small".to_string()),
+            ..Default::default()
         };
 
-        write_entry(base_entry.clone()).unwrap();
+        write_entry(base_entry.clone(), &Retention::default()).unwrap();
 
         let mut large_entry = base_entry.clone();
         large_entry.notes = Some("x".repeat((HISTORY_MAX_BYTES as usize) + 100));
-        write_entry(large_entry).unwrap();
+        write_entry(large_entry, &Retention::default()).unwrap();
 
         let log_path = history_log_path();
-        let backup = backup_path(&log_path);
+        let backup = generation_path(&log_path, 1);
         assert!(backup.exists());
 
-        write_entry(base_entry.clone()).unwrap();
+        write_entry(base_entry.clone(), &Retention::default()).unwrap();
         let latest = read_latest_entry().unwrap().unwrap();
         assert_eq!(latest.notes, base_entry.notes);
     }
+
+    #[test]
+    fn retention_keeps_generations_and_drops_old_or_excess_entries() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("history.log");
+        let entry = |ts: &str| HistoryEntry {
+            ts: ts.to_string(),
+            cwd: "/tmp".to_string(),
+            argv: vec!["sai".to_string()],
+            generated_command: Some("echo hi".to_string()),
+            ..Default::default()
+        };
+
+        // Every entry overflows max_bytes, so each write rotates.
+        let rotating = Retention {
+            max_bytes: 10,
+            max_generations: 2,
+            ..Retention::default()
+        };
+        for day in 1..=4 {
+            write_entry_to(
+                &path,
+                entry(&format!("2024-05-0{}T00:00:00Z", day)),
+                &rotating,
+            )
+            .unwrap();
+        }
+        assert!(!path.exists());
+        assert_eq!(generations(&path).len(), 2);
+        let newest = read_entries_from_file(&generation_path(&path, 1)).unwrap();
+        assert_eq!(newest[0].ts, "2024-05-04T00:00:00Z");
+
+        let _ = fs::remove_file(generation_path(&path, 1));
+        let _ = fs::remove_file(generation_path(&path, 2));
+        fs::write(
+            generation_path(&path, 1),
+            "{\"ts\":\"2024-04-01T00:00:00Z\"}\n",
+        )
+        .unwrap();
+        let pruning = Retention {
+            max_entries: Some(2),
+            max_age_days: Some(30),
+            ..Retention::default()
+        };
+        let now = DateTime::parse_from_rfc3339("2024-05-20T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        for ts in [
+            "2024-05-10T00:00:00Z",
+            "2024-05-11T00:00:00Z",
+            "2024-05-12T00:00:00Z",
+        ] {
+            write_entry_to(&path, entry(ts), &Retention::default()).unwrap();
+        }
+        fs::write(&path, fs::read_to_string(&path).unwrap() + "not json\n").unwrap();
+        rotate_history_if_needed(&path, &pruning, now).unwrap();
+
+        assert!(generations(&path).is_empty());
+        let kept: Vec<String> = read_entries_from_file(&path)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.ts)
+            .collect();
+        assert_eq!(kept, ["2024-05-11T00:00:00Z", "2024-05-12T00:00:00Z"]);
+        assert!(fs::read_to_string(&path).unwrap().ends_with("not json\n"));
+    }
+
+    #[test]
+    fn entries_are_selected_by_index_or_time_across_rotations() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("history.log");
+        let entry = |ts: &str| HistoryEntry {
+            ts: ts.to_string(),
+            cwd: "/tmp".to_string(),
+            argv: vec!["sai".to_string()],
+            ..Default::default()
+        };
+        let rotating = Retention {
+            max_bytes: 10,
+            max_generations: 3,
+            ..Retention::default()
+        };
+        for ts in [
+            "2024-05-01T09:00:00Z",
+            "2024-05-01T10:00:00Z",
+            "2024-05-02T10:00:00Z",
+        ] {
+            write_entry_to(&path, entry(ts), &rotating).unwrap();
+        }
+        let keeping = Retention {
+            max_generations: 3,
+            ..Retention::default()
+        };
+        write_entry_to(&path, entry("2024-05-03T10:00:00Z"), &keeping).unwrap();
+
+        let select = |value: &str| {
+            let selector = parse_entry_selector(value).unwrap();
+            select_entry_in(&path, &selector).unwrap().map(|e| e.ts)
+        };
+        assert_eq!(select("1").as_deref(), Some("2024-05-03T10:00:00Z"));
+        assert_eq!(select("4").as_deref(), Some("2024-05-01T09:00:00Z"));
+        assert_eq!(select("5"), None);
+        assert_eq!(
+            select("2024-05-01").as_deref(),
+            Some("2024-05-01T10:00:00Z")
+        );
+        assert_eq!(
+            select("2024-05-01 09").as_deref(),
+            Some("2024-05-01T09:00:00Z")
+        );
+        assert_eq!(select("2024-06"), None);
+
+        assert!(parse_entry_selector("0").is_err());
+        assert!(parse_entry_selector("yesterday").is_err());
+    }
 }
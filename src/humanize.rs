@@ -0,0 +1,75 @@
+//! Human-readable sizes, counts and durations for messages and reports
+//! ("16.0 KiB", "12,345", "2m 13s"). The output does not depend on the
+//! locale, so it reads the same everywhere and in tests.
+
+use std::time::Duration;
+
+const BYTE_UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+/// A byte size in binary units: `512 B`, `16.0 KiB`, `1.5 MiB`.
+pub fn bytes(n: u64) -> String {
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+    let mut value = n as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < BYTE_UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, BYTE_UNITS[unit])
+}
+
+/// A count with `,` between groups of thousands: `1,234,567`.
+pub fn count(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (idx, digit) in digits.chars().enumerate() {
+        if idx > 0 && (digits.len() - idx).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// A count with the singular or plural noun: `1 entry`, `3 entries`.
+pub fn plural(n: u64, one: &str, many: &str) -> String {
+    format!("{} {}", count(n), if n == 1 { one } else { many })
+}
+
+/// A duration: `4.2s` below ten seconds, then `42s`, `2m 13s`, `1h 05m`.
+pub fn duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..=9 => format!("{:.1}s", d.as_secs_f64()),
+        10..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_counts_and_durations_read_naturally() {
+        assert_eq!(bytes(512), "512 B");
+        assert_eq!(bytes(16 * 1024), "16.0 KiB");
+        assert_eq!(bytes(1536 * 1024), "1.5 MiB");
+        assert_eq!(bytes(u64::MAX), "16777216.0 TiB");
+
+        assert_eq!(count(0), "0");
+        assert_eq!(count(999), "999");
+        assert_eq!(count(1000), "1,000");
+        assert_eq!(count(1_234_567), "1,234,567");
+        assert_eq!(plural(1, "entry", "entries"), "1 entry");
+        assert_eq!(plural(2048, "entry", "entries"), "2,048 entries");
+
+        assert_eq!(duration(Duration::from_millis(4200)), "4.2s");
+        assert_eq!(duration(Duration::from_secs(42)), "42s");
+        assert_eq!(duration(Duration::from_secs(133)), "2m 13s");
+        assert_eq!(duration(Duration::from_secs(3900)), "1h 05m");
+    }
+}
@@ -0,0 +1,369 @@
+//! Ctrl-C (SIGINT) and SIGTERM handling.
+//!
+//! [`install`] replaces the default behaviour (immediate termination) with a
+//! flag the pipeline checks at safe points, so an interrupted run still writes
+//! a complete history entry. An in-flight LLM request is cancelled and a wait
+//! on the confirmation prompt is abandoned with [`Interrupted`]; while a
+//! command runs, the signal is forwarded to it so it is not left orphaned. A
+//! second signal is forwarded as well and then exits immediately.
+
+use anyhow::Result;
+use std::fmt;
+use std::future::Future;
+use std::io::{self, BufRead, Read};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::task::Poll;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Exit code reported for interrupted runs (128 + SIGINT, as shells do).
+pub const EXIT_INTERRUPTED: i32 = 130;
+/// Exit code reported for runs stopped by SIGTERM (128 + SIGTERM).
+pub const EXIT_TERMINATED: i32 = 143;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Set together with INTERRUPTED when the signal was SIGTERM.
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+/// Pid of the running child, which leads its own process group, else 0.
+static CHILD_PID: AtomicI32 = AtomicI32::new(0);
+
+/// The signal that stopped the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopSignal {
+    /// SIGINT, usually Ctrl-C.
+    Interrupt,
+    /// SIGTERM, e.g. from `kill` or a service manager.
+    Terminate,
+}
+
+impl StopSignal {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            StopSignal::Interrupt => EXIT_INTERRUPTED,
+            StopSignal::Terminate => EXIT_TERMINATED,
+        }
+    }
+
+    /// History note for a run stopped by this signal.
+    pub fn note(self) -> &'static str {
+        match self {
+            StopSignal::Interrupt => "interrupted",
+            StopSignal::Terminate => "terminated",
+        }
+    }
+
+    /// Message printed when the run stops.
+    pub fn message(self) -> &'static str {
+        match self {
+            StopSignal::Interrupt => "Interrupted.",
+            StopSignal::Terminate => "Terminated.",
+        }
+    }
+}
+
+/// Error returned when Ctrl-C aborted a wait.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+#[cfg(unix)]
+pub fn install() -> Result<()> {
+    use anyhow::Context;
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    let mut signals =
+        Signals::new([SIGINT, SIGTERM]).context("Failed to install Ctrl-C handler")?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGTERM {
+                TERMINATED.store(true, Ordering::SeqCst);
+            }
+            forward_to_child(signal);
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                eprintln!();
+                std::process::exit(stop_signal().exit_code());
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Sends `signal` to the running child's whole process group, so no member
+/// of a pipeline is left orphaned.
+#[cfg(unix)]
+fn forward_to_child(signal: i32) {
+    let pid = CHILD_PID.load(Ordering::SeqCst);
+    if pid <= 0 {
+        return;
+    }
+    // SAFETY: kill has no memory-safety preconditions.
+    unsafe {
+        libc::kill(-pid, signal);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() -> Result<()> {
+    Ok(())
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Which signal stopped the run; meaningful once [`is_interrupted`] is true.
+pub fn stop_signal() -> StopSignal {
+    if TERMINATED.load(Ordering::SeqCst) {
+        StopSignal::Terminate
+    } else {
+        StopSignal::Interrupt
+    }
+}
+
+/// Records a Ctrl-C that stopped the child: while it holds the terminal the
+/// terminal sends SIGINT to its process group only, not to sai.
+pub fn child_interrupted() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Fails with [`Interrupted`] once Ctrl-C was pressed.
+pub fn check() -> Result<()> {
+    if is_interrupted() {
+        return Err(Interrupted.into());
+    }
+    Ok(())
+}
+
+/// True when `err` (or anything in its chain) is an [`Interrupted`] error.
+pub fn is_interrupt_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        e.is::<Interrupted>()
+            || e.downcast_ref::<io::Error>()
+                .and_then(|io_err| io_err.get_ref())
+                .is_some_and(|inner| inner.is::<Interrupted>())
+    })
+}
+
+/// Runs `f` on a worker thread and waits for it, giving up when Ctrl-C is
+/// pressed. The abandoned worker is left to finish (or die with the process).
+pub fn run_interruptible<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        tx.send(f()).ok();
+    });
+
+    loop {
+        check()?;
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(value) => return Ok(value),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(anyhow::anyhow!("Worker thread terminated unexpectedly"))
+            }
+        }
+    }
+}
+
+/// Awaits `fut`, giving up with [`Interrupted`] when Ctrl-C is pressed. The
+/// future is dropped at that point, which aborts an in-flight HTTP request
+/// instead of leaving it running. Needs a tokio runtime with the time driver.
+pub async fn cancellable<F: Future>(fut: F) -> Result<F::Output> {
+    let mut fut = pin!(fut);
+    let mut ticker = tokio::time::interval(POLL_INTERVAL);
+    std::future::poll_fn(|cx| {
+        if is_interrupted() {
+            return Poll::Ready(Err(Interrupted.into()));
+        }
+        if let Poll::Ready(value) = fut.as_mut().poll(cx) {
+            return Poll::Ready(Ok(value));
+        }
+        // Drain ready ticks so the ticker registers a wakeup for the next one.
+        while ticker.poll_tick(cx).is_ready() {}
+        Poll::Pending
+    })
+    .await
+}
+
+/// Sleeps for `duration`, returning early with [`Interrupted`] on Ctrl-C.
+pub fn sleep(duration: Duration) -> Result<()> {
+    let deadline = Instant::now() + duration;
+    loop {
+        check()?;
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(());
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+/// Registers a running child, the leader of its own process group, so
+/// signals are forwarded to the group. The registration is removed when the
+/// guard is dropped.
+pub struct ChildGuard;
+
+impl ChildGuard {
+    pub fn register(pid: i32) -> Self {
+        CHILD_PID.store(pid, Ordering::SeqCst);
+        Self
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        CHILD_PID.store(0, Ordering::SeqCst);
+    }
+}
+
+/// Line-oriented stdin reader whose reads fail with [`Interrupted`] on Ctrl-C
+/// instead of blocking. Lines are read by a helper thread, one per request, so
+/// no input is consumed ahead of the child process.
+pub struct InterruptibleStdin {
+    requests: Sender<()>,
+    lines: Receiver<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+    pos: usize,
+    pending: bool,
+}
+
+impl InterruptibleStdin {
+    pub fn new() -> Self {
+        let (requests, request_rx) = mpsc::channel::<()>();
+        let (line_tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for () in request_rx {
+                let mut line = Vec::new();
+                let result = io::stdin().lock().read_until(b'\n', &mut line);
+                if line_tx.send(result.map(|_| line)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            requests,
+            lines,
+            buf: Vec::new(),
+            pos: 0,
+            pending: false,
+        }
+    }
+}
+
+impl Default for InterruptibleStdin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Read for InterruptibleStdin {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for InterruptibleStdin {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos < self.buf.len() {
+            return Ok(&self.buf[self.pos..]);
+        }
+
+        if !self.pending {
+            self.requests.send(()).ok();
+            self.pending = true;
+        }
+        loop {
+            if is_interrupted() {
+                // Not ErrorKind::Interrupted: std retries those transparently.
+                return Err(io::Error::other(Interrupted));
+            }
+            match self.lines.recv_timeout(POLL_INTERVAL) {
+                Ok(line) => {
+                    self.pending = false;
+                    self.buf = line?;
+                    self.pos = 0;
+                    break;
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.pending = false;
+                    self.buf.clear();
+                    self.pos = 0;
+                    break;
+                }
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn interrupt_errors_are_recognised_through_context_and_io() {
+        let err = anyhow::Error::from(Interrupted).context("Failed to obtain command");
+        assert!(is_interrupt_error(&err));
+
+        let err = anyhow::Error::from(io::Error::other(Interrupted));
+        assert!(is_interrupt_error(&err));
+
+        let err: Result<()> = Err(io::Error::other("boom")).context("x");
+        assert!(!is_interrupt_error(&err.unwrap_err()));
+    }
+
+    #[test]
+    fn stop_signals_map_to_shell_exit_codes() {
+        assert_eq!(StopSignal::Interrupt.exit_code(), 130);
+        assert_eq!(StopSignal::Terminate.exit_code(), 143);
+        assert_eq!(StopSignal::Terminate.note(), "terminated");
+        assert_eq!(stop_signal(), StopSignal::Interrupt);
+    }
+
+    #[test]
+    fn cancellable_returns_future_output() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let value = runtime
+            .block_on(cancellable(async {
+                tokio::time::sleep(Duration::from_millis(120)).await;
+                21 * 2
+            }))
+            .unwrap();
+        assert_eq!(value, 42);
+    }
+
+    #[test]
+    fn run_interruptible_returns_worker_result() {
+        let value = run_interruptible(|| 21 * 2).unwrap();
+        assert_eq!(value, 42);
+    }
+}
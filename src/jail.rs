@@ -0,0 +1,296 @@
+//! Path jail (`path_jail:` in the global config, or `--jail`): path arguments
+//! of a validated command must resolve inside the working directory or a
+//! configured root, so a generated command cannot touch `/etc` or `~/.ssh`.
+
+use crate::safety::{redirect_targets, ALLOWABLE_OPERATORS, COMMAND_SEPARATORS};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// `path_jail:` section of the global config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PathJailConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Directory paths must stay in; the working directory when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+    /// Paths outside the root that arguments may still name, with everything
+    /// below them (`/dev/null` always may).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+}
+
+/// Resolved jail for one run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathJail {
+    cwd: PathBuf,
+    root: PathBuf,
+    allow: Vec<PathBuf>,
+}
+
+impl PathJail {
+    pub fn new(cfg: &PathJailConfig, cwd: &Path) -> Result<Self> {
+        let cwd = resolve(cwd, cwd);
+        let root = match &cfg.root {
+            Some(root) => resolve(&cwd, &expand_home(root)),
+            None => cwd.clone(),
+        };
+        if !cwd.starts_with(&root) {
+            return Err(anyhow!(
+                "The working directory {} is outside the path_jail root {}",
+                cwd.display(),
+                root.display()
+            ));
+        }
+        let allow = std::iter::once("/dev/null")
+            .chain(cfg.allow.iter().map(String::as_str))
+            .map(|path| resolve(&cwd, &expand_home(path)))
+            .collect();
+        Ok(Self { cwd, root, allow })
+    }
+
+    /// Rejects the first argument that resolves outside the root. Tool names
+    /// and flags are skipped; the value of `--flag=VALUE` is checked, and so
+    /// is a path attached to a short option (`-C/etc`, `-f../x`). In a
+    /// shell, `$` arguments cannot be resolved before they run and are
+    /// rejected as well, and the targets of redirections in `cmd_line` are
+    /// checked however they are written (`f>/etc/x`, `2>/etc/x`).
+    pub fn check(&self, cmd_line: &str, tokens: &[String], shell: bool) -> Result<()> {
+        if shell {
+            for target in redirect_targets(cmd_line) {
+                self.check_path(&target, &target, shell)?;
+            }
+        }
+        let mut command_start = true;
+        for token in tokens {
+            if COMMAND_SEPARATORS.contains(&token.as_str()) {
+                command_start = true;
+                continue;
+            }
+            if std::mem::take(&mut command_start) || ALLOWABLE_OPERATORS.contains(&token.as_str()) {
+                continue;
+            }
+            let arg = if shell {
+                token.trim_start_matches(['<', '>', '&', '|', ';'])
+            } else {
+                token.as_str()
+            };
+            let arg = match arg.strip_prefix('-') {
+                Some(flag) => match flag.split_once('=') {
+                    Some((_, value)) => value,
+                    // Clusters like `-rn` name no path; from the first path
+                    // character on, the rest is the option's value.
+                    None if !flag.starts_with('-') => match flag.find(['/', '~', '.']) {
+                        Some(start) => &flag[start..],
+                        None => continue,
+                    },
+                    None => continue,
+                },
+                None => arg,
+            };
+            if !arg.is_empty() {
+                self.check_path(arg, token, shell)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects `arg` (from the command word `token`) when it resolves
+    /// outside the root and every allowed path.
+    fn check_path(&self, arg: &str, token: &str, shell: bool) -> Result<()> {
+        if shell && arg.starts_with('$') {
+            return Err(anyhow!(
+                "Argument '{}' cannot be checked against path_jail before the shell expands it",
+                token
+            ));
+        }
+        let resolved = resolve(&self.cwd, &expand_home(arg));
+        if !resolved.starts_with(&self.root) && !self.allow.iter().any(|a| resolved.starts_with(a))
+        {
+            return Err(anyhow!(
+                "Path '{}' resolves to {}, outside the path_jail root {}",
+                arg,
+                resolved.display(),
+                self.root.display()
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => dirs::home_dir()
+            .map(|home| home.join(rest.trim_start_matches('/')))
+            .unwrap_or_else(|| PathBuf::from(path)),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// `path` made absolute against `cwd` and normalized; the longest existing
+/// prefix is canonicalized so symlinks count where they point.
+fn resolve(cwd: &Path, path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in cwd.join(path).components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            other => normalized.push(other),
+        }
+    }
+    let mut existing = normalized.as_path();
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(real) = fs::canonicalize(existing) {
+            return rest.iter().rev().fold(real, |acc, part| acc.join(part));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return normalized,
+        }
+    }
+}
+
+/// Jail for this run: `--jail` or `path_jail.enabled` turns it on.
+pub fn jail_for_run(
+    cfg: Option<&PathJailConfig>,
+    cli_jail: bool,
+    cwd: &Path,
+) -> Result<Option<PathJail>> {
+    let cfg = cfg.cloned().unwrap_or_default();
+    if !(cli_jail || cfg.enabled) {
+        return Ok(None);
+    }
+    PathJail::new(&cfg, cwd)
+        .context("Invalid path_jail configuration")
+        .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn words(line: &str) -> Vec<String> {
+        shell_words::split(line).unwrap()
+    }
+
+    #[test]
+    fn arguments_outside_the_root_are_rejected() {
+        let temp = TempDir::new().unwrap();
+        let cwd = temp.path().join("work");
+        fs::create_dir_all(cwd.join("src")).unwrap();
+        let jail = PathJail::new(&PathJailConfig::default(), &cwd).unwrap();
+
+        jail.check(
+            "cat src/main.rs ./notes.txt",
+            &words("cat src/main.rs ./notes.txt"),
+            false,
+        )
+        .unwrap();
+        jail.check(
+            "grep -rn TODO src > /dev/null",
+            &words("grep -rn TODO src > /dev/null"),
+            true,
+        )
+        .unwrap();
+        assert!(jail
+            .check("cat /etc/passwd", &words("cat /etc/passwd"), false)
+            .is_err());
+        assert!(jail
+            .check("cat ~/.ssh/id_rsa", &words("cat ~/.ssh/id_rsa"), false)
+            .is_err());
+        assert!(jail
+            .check(
+                "cat src/../../secret",
+                &words("cat src/../../secret"),
+                false
+            )
+            .is_err());
+        assert!(jail
+            .check(
+                "sort --output=/tmp/x f",
+                &words("sort --output=/tmp/x f"),
+                false
+            )
+            .is_err());
+        for line in [
+            "tar -C/etc -xf a.tar",
+            "grep -f/etc/shadow x",
+            "sort -o/etc/x f",
+            "cp -t/etc f",
+            "grep -rf../../x y",
+        ] {
+            assert!(jail.check(line, &words(line), false).is_err(), "{}", line);
+        }
+        jail.check(
+            "grep -rn -C2 TODO -f./patterns src",
+            &words("grep -rn -C2 TODO -f./patterns src"),
+            false,
+        )
+        .unwrap();
+        assert!(jail
+            .check(
+                "cat f | wc -l >/etc/x",
+                &words("cat f | wc -l >/etc/x"),
+                true
+            )
+            .is_err());
+        assert!(jail
+            .check("cat $HOME/x", &words("cat $HOME/x"), true)
+            .is_err());
+        for line in [
+            "cat f>/etc/sai-x",
+            "grep hi f 2>/etc/sai-x",
+            "wc -l </etc/passwd",
+        ] {
+            assert!(jail.check(line, &words(line), true).is_err(), "{}", line);
+        }
+        let line = "grep hi f 2>&1 >out.txt";
+        jail.check(line, &words(line), true).unwrap();
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("/etc", cwd.join("etc")).unwrap();
+            assert!(jail
+                .check("cat etc/passwd", &words("cat etc/passwd"), false)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn configured_root_and_allow_list_widen_the_jail() {
+        let temp = TempDir::new().unwrap();
+        let cwd = temp.path().join("repo/sub");
+        fs::create_dir_all(&cwd).unwrap();
+        let cfg = PathJailConfig {
+            enabled: true,
+            root: Some(temp.path().join("repo").display().to_string()),
+            allow: vec!["/usr/share".to_string()],
+        };
+        let jail = PathJail::new(&cfg, &cwd).unwrap();
+        jail.check(
+            "diff ../README.md /usr/share/dict/words",
+            &words("diff ../README.md /usr/share/dict/words"),
+            false,
+        )
+        .unwrap();
+        assert!(jail
+            .check("cat ../../other", &words("cat ../../other"), false)
+            .is_err());
+
+        let outside = PathJailConfig {
+            root: Some(temp.path().join("elsewhere").display().to_string()),
+            ..cfg
+        };
+        assert!(PathJail::new(&outside, &cwd).is_err());
+        assert!(jail_for_run(None, false, &cwd).unwrap().is_none());
+    }
+}
@@ -0,0 +1,65 @@
+//! Core of sai-cli: natural language to validated shell commands.
+//!
+//! The `sai` binary is a thin wrapper around [`app::run`]. Programs that want
+//! to embed the pipeline (configure → generate → validate → execute) with
+//! their own confirmation UI should use [`session::Session`]; see
+//! `examples/custom_confirm.rs` for a complete example.
+
+pub mod app;
+pub mod backup;
+pub mod budget;
+pub mod capabilities;
+pub mod cassette;
+pub mod cli;
+pub mod clipboard;
+pub mod commands;
+pub mod config;
+pub mod deprecation;
+pub mod envinfo;
+pub mod executor;
+pub mod extract;
+pub mod favorites;
+pub mod guard;
+pub mod help;
+pub mod histexport;
+pub mod history;
+pub mod humanize;
+pub mod interrupt;
+pub mod jail;
+pub mod llm;
+pub mod mock;
+pub mod network;
+pub mod ops;
+pub mod orgpolicy;
+pub mod osc52;
+pub mod pager;
+pub mod pattern;
+pub mod peek;
+pub mod progress;
+pub mod project;
+pub mod projectcfg;
+pub mod prompt;
+pub mod promptdocs;
+pub mod recall;
+pub mod redact;
+pub mod refine;
+pub mod rerun;
+pub mod respcache;
+pub mod risk;
+pub mod safety;
+pub mod scope;
+pub mod selftest;
+pub mod session;
+pub mod shellcheck;
+pub mod shellinit;
+pub mod suggest;
+pub mod targets;
+pub mod tasks;
+pub mod template;
+pub mod tooldocs;
+pub mod tour;
+pub mod transaction;
+pub mod transcript;
+pub mod tui;
+pub mod webhook;
+pub mod workdir;
@@ -1,8 +1,27 @@
-use crate::config::EffectiveAiConfig;
-use crate::scope::build_scope_dot_listing;
+use crate::capabilities;
+use crate::cassette::{Cassette, Interaction};
+use crate::config::{EffectiveAiConfig, ProviderConfig};
+use crate::extract::extract_command;
+use crate::interrupt;
+use crate::mock::MockResponses;
+use crate::progress;
+use crate::redact::{self, RedactionConfig};
+use crate::respcache::ResponseCache;
+use crate::scope::scope_message;
 use anyhow::{anyhow, Context, Result};
-use reqwest::blocking::Client;
+use reqwest::header::{CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{Client, NoProxy, Proxy, RequestBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::fmt;
+use std::future::Future;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::runtime::{Builder, Runtime};
 
 pub trait CommandGenerator {
     fn generate(
@@ -13,6 +32,99 @@ pub trait CommandGenerator {
         scope_hint: Option<&str>,
         peek_text: Option<&str>,
     ) -> Result<String>;
+
+    /// Like `generate`, but hands provisional text to `on_token` as it streams in.
+    /// The default implementation does not stream.
+    fn generate_streaming(
+        &self,
+        ai: &EffectiveAiConfig,
+        system_prompt: &str,
+        nl_prompt: &str,
+        scope_hint: Option<&str>,
+        peek_text: Option<&str>,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        let _ = on_token;
+        self.generate(ai, system_prompt, nl_prompt, scope_hint, peek_text)
+    }
+
+    /// Returns up to `count` distinct alternative commands for the request.
+    /// The default implementation calls `generate` repeatedly.
+    fn generate_candidates(
+        &self,
+        ai: &EffectiveAiConfig,
+        system_prompt: &str,
+        nl_prompt: &str,
+        scope_hint: Option<&str>,
+        peek_text: Option<&str>,
+        count: u32,
+    ) -> Result<Vec<String>> {
+        let mut candidates = Vec::new();
+        for _ in 0..count {
+            let cmd = self.generate(ai, system_prompt, nl_prompt, scope_hint, peek_text)?;
+            push_unique(&mut candidates, cmd);
+        }
+        Ok(candidates)
+    }
+
+    /// Sends the request to every provider in `ais` at once and returns the
+    /// first command `accept` approves, with the index of the provider that
+    /// produced it. The default implementation asks the providers in turn.
+    fn generate_race(
+        &self,
+        ais: &[EffectiveAiConfig],
+        system_prompt: &str,
+        nl_prompt: &str,
+        scope_hint: Option<&str>,
+        peek_text: Option<&str>,
+        accept: &dyn Fn(&str) -> Result<()>,
+    ) -> Result<(usize, String)> {
+        let mut failures = Vec::new();
+        for (idx, ai) in ais.iter().enumerate() {
+            let cmd = self
+                .generate(ai, system_prompt, nl_prompt, scope_hint, peek_text)
+                .and_then(|cmd| accept(&cmd).map(|()| cmd));
+            match cmd {
+                Ok(cmd) => return Ok((idx, cmd)),
+                Err(err) => failures.push((idx, err)),
+            }
+        }
+        Err(race_failure(ais, failures))
+    }
+}
+
+/// Error for a race in which no provider produced an acceptable command.
+fn race_failure(
+    ais: &[EffectiveAiConfig],
+    mut failures: Vec<(usize, anyhow::Error)>,
+) -> anyhow::Error {
+    if let Err(err) = interrupt::check() {
+        return err;
+    }
+    if failures.len() == 1 {
+        return failures.remove(0).1;
+    }
+    failures.sort_by_key(|(idx, _)| *idx);
+    let details: Vec<String> = failures
+        .iter()
+        .map(|(idx, err)| format!("{}: {:#}", ais[*idx].model_name(), err))
+        .collect();
+    anyhow!(
+        "No provider produced a usable command ({})",
+        details.join("; ")
+    )
+}
+
+/// Lower bound for max_tokens on command generation requests.
+const MIN_COMMAND_MAX_TOKENS: u32 = 256;
+
+/// Sampling temperature for `--candidates`, high enough to get real alternatives.
+pub const CANDIDATE_TEMPERATURE: f32 = 0.8;
+
+fn push_unique(candidates: &mut Vec<String>, cmd: String) {
+    if !candidates.contains(&cmd) {
+        candidates.push(cmd);
+    }
 }
 
 pub trait ChatClient {
@@ -23,23 +135,171 @@ pub trait ChatClient {
         user_prompt: &str,
         temperature: f32,
     ) -> Result<String>;
+
+    /// Like `respond`, but hands each chunk of text to `on_token` as it arrives.
+    /// The default implementation does not stream and emits the full answer once.
+    fn respond_streaming(
+        &self,
+        ai: &EffectiveAiConfig,
+        system_prompt: &str,
+        user_prompt: &str,
+        temperature: f32,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        let text = self.respond(ai, system_prompt, user_prompt, temperature)?;
+        on_token(&text);
+        Ok(text)
+    }
+
+    /// Tokens consumed by all calls made through this client so far.
+    fn token_usage(&self) -> TokenUsage {
+        TokenUsage::default()
+    }
+
+    /// Command requests answered from the response cache so far.
+    fn cache_hits(&self) -> u32 {
+        0
+    }
 }
 
+/// Talks to the provider with the async reqwest client on a private
+/// single-threaded tokio runtime. Every network wait runs through
+/// [`interrupt::cancellable`], so Ctrl-C drops the request mid-flight.
 pub struct HttpCommandGenerator {
+    runtime: Runtime,
     client: Client,
+    usage: Cell<TokenUsage>,
+    cassette: Option<Cassette>,
+    response_cache: Option<ResponseCache>,
+    cache_hits: Cell<u32>,
 }
 
 impl HttpCommandGenerator {
-    pub fn new() -> Self {
-        Self {
+    /// Fails when the async runtime cannot be started.
+    pub fn try_new() -> Result<Self> {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("Failed to start the async runtime")?;
+        Ok(Self {
+            runtime,
             client: Client::new(),
+            usage: Cell::new(TokenUsage::default()),
+            cassette: None,
+            response_cache: None,
+            cache_hits: Cell::new(0),
+        })
+    }
+
+    /// Records requests to, or replays responses from, a cassette file.
+    pub fn with_cassette(mut self, cassette: Option<Cassette>) -> Self {
+        self.cassette = cassette;
+        self
+    }
+
+    /// Answers repeated command requests from `cache` (see [`ResponseCache`]).
+    pub fn with_response_cache(mut self, cache: Option<ResponseCache>) -> Self {
+        self.response_cache = cache;
+        self
+    }
+
+    /// The command for `messages` from the response cache, or else from
+    /// `ask`, whose answer is then cached.
+    fn cached_command(
+        &self,
+        ai: &EffectiveAiConfig,
+        messages: &[Message],
+        ask: impl FnOnce() -> Result<String>,
+    ) -> Result<String> {
+        let Some(cache) = &self.response_cache else {
+            return ask();
+        };
+        let endpoint = match &ai.provider {
+            ProviderConfig::OpenAI { base_url, .. } => base_url,
+            ProviderConfig::Azure { endpoint, .. } => endpoint,
+            // Canned answers cost nothing and should follow edits to the file.
+            ProviderConfig::Mock { .. } => return ask(),
+        };
+        let sampling = format!("temperature={:?} seed={:?}", ai.temperature, ai.seed);
+        let mut parts = vec![ai.model_name(), endpoint.as_str(), sampling.as_str()];
+        parts.extend(messages.iter().map(|m| m.content.as_str()));
+        let key = ResponseCache::key(&parts);
+        if let Some(cmd) = cache.get(&key) {
+            self.cache_hits.set(self.cache_hits.get() + 1);
+            return Ok(cmd);
+        }
+        let cmd = ask()?;
+        // A redacted copy would be useless to run, so a command holding a
+        // likely secret is not written to disk at all.
+        if redact::redact(&cmd, &ai.redaction).1 == 0 {
+            cache.put(&key, &cmd);
         }
+        Ok(cmd)
     }
 }
 
-impl Default for HttpCommandGenerator {
-    fn default() -> Self {
-        Self::new()
+/// A provider response, either still on the wire or already read in full.
+enum Reply {
+    Live(Response),
+    Recorded(Interaction),
+}
+
+/// Response body to parse, independent of where it came from.
+enum ReplyBody {
+    Live(Response),
+    Recorded(String),
+}
+
+impl ReplyBody {
+    fn json<T: serde::de::DeserializeOwned>(self, runtime: &Runtime) -> Result<T> {
+        match self {
+            ReplyBody::Live(resp) => Ok(runtime.block_on(interrupt::cancellable(resp.json()))??),
+            ReplyBody::Recorded(body) => Ok(serde_json::from_str(&body)?),
+        }
+    }
+
+    fn into_reader(self, runtime: &Runtime) -> Box<dyn Read + '_> {
+        match self {
+            ReplyBody::Live(resp) => Box::new(ChunkReader {
+                runtime,
+                resp,
+                chunk: Vec::new(),
+                pos: 0,
+            }),
+            ReplyBody::Recorded(body) => Box::new(Cursor::new(body.into_bytes())),
+        }
+    }
+}
+
+/// Blocking `Read` over a live response body, pulling one chunk at a time
+/// so streamed tokens are shown as soon as they arrive.
+struct ChunkReader<'a> {
+    runtime: &'a Runtime,
+    resp: Response,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChunkReader<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.chunk.len() {
+            let next = self
+                .runtime
+                .block_on(interrupt::cancellable(self.resp.chunk()))
+                .map_err(io::Error::other)?
+                .map_err(io::Error::other)?;
+            match next {
+                Some(bytes) => {
+                    self.chunk = bytes.to_vec();
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = out.len().min(self.chunk.len() - self.pos);
+        out[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
     }
 }
 
@@ -52,52 +312,216 @@ impl CommandGenerator for HttpCommandGenerator {
         scope_hint: Option<&str>,
         peek_text: Option<&str>,
     ) -> Result<String> {
-        let mut messages = vec![
-            Message {
-                role: "system".to_string(),
-                content: system_prompt.to_string(),
-            },
-            Message {
-                role: "user".to_string(),
-                content: nl_prompt.to_string(),
-            },
-        ];
+        let messages = build_generate_messages(
+            &ai.redaction,
+            system_prompt,
+            nl_prompt,
+            scope_hint,
+            peek_text,
+        )?;
+        self.generate_command(ai, &messages, false, &mut |_| {})
+    }
 
-        if let Some(scope) = scope_hint {
-            let scope_content = if scope == "." {
-                let listing = build_scope_dot_listing()?;
-                format!(
-                    "Scope: current directory.\nHere is a non-recursive listing of the working directory:\n{}",
-                    listing
-                )
-            } else {
-                format!(
-                    "Focus your command on files or paths matching this scope:\n{}",
-                    scope
-                )
+    fn generate_streaming(
+        &self,
+        ai: &EffectiveAiConfig,
+        system_prompt: &str,
+        nl_prompt: &str,
+        scope_hint: Option<&str>,
+        peek_text: Option<&str>,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        let messages = build_generate_messages(
+            &ai.redaction,
+            system_prompt,
+            nl_prompt,
+            scope_hint,
+            peek_text,
+        )?;
+        self.generate_command(ai, &messages, ai.stream, on_token)
+    }
+
+    fn generate_candidates(
+        &self,
+        ai: &EffectiveAiConfig,
+        system_prompt: &str,
+        nl_prompt: &str,
+        scope_hint: Option<&str>,
+        peek_text: Option<&str>,
+        count: u32,
+    ) -> Result<Vec<String>> {
+        let messages = build_generate_messages(
+            &ai.redaction,
+            system_prompt,
+            nl_prompt,
+            scope_hint,
+            peek_text,
+        )?;
+        self.with_text_fallback(ai, |format| {
+            let opts = RequestOptions {
+                format,
+                n: count,
+                max_tokens: command_max_tokens(ai),
+                expects_command: true,
+                ..RequestOptions::text(ai.temperature.unwrap_or(CANDIDATE_TEMPERATURE), false)
             };
+            let mut candidates = Vec::new();
+            for completion in self.complete_choices(ai, &messages, &opts)? {
+                // A single malformed choice should not sink the others.
+                if let Ok(cmd) = command_from_completion(completion.in_format(format)) {
+                    push_unique(&mut candidates, cmd);
+                }
+            }
+            if candidates.is_empty() {
+                return Err(anyhow!("LLM returned no usable candidate commands"));
+            }
+            Ok(candidates)
+        })
+    }
 
-            messages.push(Message {
-                role: "user".to_string(),
-                content: scope_content,
+    /// Each provider is asked from its own thread with its own runtime, so a
+    /// slow one does not hold up the other. Each request is redacted with its
+    /// provider's own settings. Losing requests are abandoned; their threads
+    /// end with the process, and only the usage of those that already
+    /// answered is recorded.
+    fn generate_race(
+        &self,
+        ais: &[EffectiveAiConfig],
+        system_prompt: &str,
+        nl_prompt: &str,
+        scope_hint: Option<&str>,
+        peek_text: Option<&str>,
+        accept: &dyn Fn(&str) -> Result<()>,
+    ) -> Result<(usize, String)> {
+        let mut built: Vec<(&RedactionConfig, Vec<Message>)> = Vec::new();
+        let (tx, rx) = mpsc::channel();
+        for (idx, ai) in ais.iter().enumerate() {
+            let messages = match built.iter().find(|(r, _)| **r == ai.redaction) {
+                Some((_, messages)) => messages.clone(),
+                None => {
+                    let messages = build_generate_messages(
+                        &ai.redaction,
+                        system_prompt,
+                        nl_prompt,
+                        scope_hint,
+                        peek_text,
+                    )?;
+                    built.push((&ai.redaction, messages.clone()));
+                    messages
+                }
+            };
+            let (tx, ai) = (tx.clone(), ai.clone());
+            let cassette = self.cassette.clone();
+            thread::spawn(move || {
+                let (cmd, usage) = match HttpCommandGenerator::try_new() {
+                    Ok(racer) => {
+                        let racer = racer.with_cassette(cassette);
+                        let cmd = racer.generate_command(&ai, &messages, false, &mut |_| {});
+                        (cmd, racer.token_usage())
+                    }
+                    Err(err) => (Err(err), TokenUsage::default()),
+                };
+                let _ = tx.send((idx, cmd, usage));
             });
         }
+        drop(tx);
 
-        if let Some(peek) = peek_text {
-            messages.push(Message {
-                role: "user".to_string(),
-                content: format!(
-                    "Here is a sample of the data the tools will operate on. \
+        let mut failures = Vec::new();
+        for (idx, cmd, usage) in rx.iter() {
+            self.record_usage(Some(usage).filter(|u| !u.is_empty()));
+            match cmd.and_then(|cmd| accept(&cmd).map(|()| cmd)) {
+                Ok(cmd) => {
+                    for (_, _, usage) in rx.try_iter() {
+                        self.record_usage(Some(usage).filter(|u| !u.is_empty()));
+                    }
+                    return Ok((idx, cmd));
+                }
+                Err(err) => failures.push((idx, err)),
+            }
+        }
+        Err(race_failure(ais, failures))
+    }
+}
+
+/// Completion tokens assumed per generated command by `--estimate`; a
+/// single command line, or its function call arguments, rarely needs more.
+pub const ESTIMATED_COMMAND_TOKENS: u32 = 60;
+
+/// Rough prompt token count of a command generation request, without sending it.
+pub fn estimate_generate_tokens(
+    system_prompt: &str,
+    nl_prompt: &str,
+    scope_hint: Option<&str>,
+    peek_text: Option<&str>,
+) -> Result<u32> {
+    let messages = build_generate_messages(
+        &RedactionConfig {
+            enabled: false,
+            ..Default::default()
+        },
+        system_prompt,
+        nl_prompt,
+        scope_hint,
+        peek_text,
+    )?;
+    let len = messages.iter().map(|m| m.content.len()).sum();
+    Ok(capabilities::estimate_tokens(len))
+}
+
+/// The messages of a command generation request. Secrets in the prompt, the
+/// scope listing and the peek sample are replaced first, per `redaction`.
+fn build_generate_messages(
+    redaction: &RedactionConfig,
+    system_prompt: &str,
+    nl_prompt: &str,
+    scope_hint: Option<&str>,
+    peek_text: Option<&str>,
+) -> Result<Vec<Message>> {
+    let mut redacted = 0;
+    let mut redact = |text: &str| {
+        let (text, count) = redact::redact(text, redaction);
+        redacted += count;
+        text
+    };
+    let mut messages = vec![
+        Message {
+            role: "system".to_string(),
+            content: system_prompt.to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: redact(nl_prompt),
+        },
+    ];
+
+    if let Some(scope) = scope_hint {
+        let scope_content = redact(&scope_message(scope)?);
+
+        messages.push(Message {
+            role: "user".to_string(),
+            content: scope_content,
+        });
+    }
+
+    if let Some(peek) = peek_text.map(&mut redact) {
+        messages.push(Message {
+            role: "user".to_string(),
+            content: format!(
+                "Here is a sample of the data the tools will operate on. \
                      It may be truncated and is provided only to infer structure and field names, \
                      not to be hard-coded:\n\n{}",
-                    peek
-                ),
-            });
-        }
+                peek
+            ),
+        });
+    }
 
-        let content = self.chat(ai, messages, 0.0)?;
-        extract_first_line_from_text(&content)
+    if redacted > 0 {
+        eprintln!(
+            "Redacted {} likely secret(s) from the request (see `sai help safety`).",
+            redacted
+        );
     }
+    Ok(messages)
 }
 
 impl ChatClient for HttpCommandGenerator {
@@ -121,34 +545,292 @@ impl ChatClient for HttpCommandGenerator {
 
         self.chat(ai, messages, temperature)
     }
+
+    fn respond_streaming(
+        &self,
+        ai: &EffectiveAiConfig,
+        system_prompt: &str,
+        user_prompt: &str,
+        temperature: f32,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        let messages = vec![
+            Message {
+                role: "system".to_string(),
+                content: system_prompt.to_string(),
+            },
+            Message {
+                role: "user".to_string(),
+                content: user_prompt.to_string(),
+            },
+        ];
+
+        if !ai.stream {
+            let text = self.chat(ai, messages, temperature)?;
+            on_token(&text);
+            return Ok(text);
+        }
+
+        self.chat_streaming(ai, messages, temperature, on_token)
+    }
+
+    fn token_usage(&self) -> TokenUsage {
+        self.usage.get()
+    }
+
+    fn cache_hits(&self) -> u32 {
+        self.cache_hits.get()
+    }
+}
+
+/// How the model is asked to return a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommandFormat {
+    /// A call to the `emit_command` function.
+    Function,
+    /// A JSON object shaped like the `emit_command` arguments (JSON mode).
+    Json,
+    /// Free text.
+    Text,
+}
+
+impl CommandFormat {
+    fn label(self) -> &'static str {
+        match self {
+            CommandFormat::Function => "function-calling",
+            CommandFormat::Json => "JSON mode",
+            CommandFormat::Text => "plain text",
+        }
+    }
+}
+
+/// Added to the conversation in JSON mode, which requires the word "JSON".
+const JSON_MODE_INSTRUCTION: &str = "Respond with a JSON object of the form \
+{\"command\": \"<the single shell command>\", \"rationale\": \"<one sentence>\"}.";
+
+/// Per-request knobs that vary between explanation, generation and candidate calls.
+struct RequestOptions {
+    temperature: f32,
+    stream: bool,
+    format: CommandFormat,
+    /// Number of choices to request; 1 omits the field.
+    n: u32,
+    max_tokens: Option<u32>,
+    /// The request asks for a command rather than prose (used by the mock provider).
+    expects_command: bool,
+}
+
+impl RequestOptions {
+    fn text(temperature: f32, stream: bool) -> Self {
+        Self {
+            temperature,
+            stream,
+            format: CommandFormat::Text,
+            n: 1,
+            max_tokens: None,
+            expects_command: false,
+        }
+    }
 }
 
 #[derive(Serialize)]
-struct ChatRequest {
+struct ChatRequest<'a> {
     model: Option<String>,
-    messages: Vec<Message>,
+    messages: &'a [Message],
     temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<u32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+}
+
+/// Name of the function the model is asked to call with the generated command.
+const COMMAND_FUNCTION: &str = "emit_command";
+
+fn command_tools() -> Value {
+    json!([{
+        "type": "function",
+        "function": {
+            "name": COMMAND_FUNCTION,
+            "description": "Return the single shell command that fulfils the user's request.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "command": {
+                        "type": "string",
+                        "description": "Exactly one command line, without markdown or commentary."
+                    },
+                    "rationale": {
+                        "type": "string",
+                        "description": "One sentence on why this command fulfils the request."
+                    }
+                },
+                "required": ["command", "rationale"]
+            }
+        }
+    }])
+}
+
+fn command_tool_choice() -> Value {
+    json!({ "type": "function", "function": { "name": COMMAND_FUNCTION } })
+}
+
+#[derive(Deserialize)]
+struct CommandArguments {
+    command: String,
+    // Requested so the model commits to its reasoning outside the command field.
+    #[serde(default)]
+    #[allow(dead_code)]
+    rationale: String,
 }
 
 #[derive(Serialize)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
+#[derive(Clone, Serialize)]
 struct Message {
     role: String,
     content: String,
 }
 
+/// Token counts reported by the provider, accumulated across calls.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn add(&mut self, other: TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total() == 0
+    }
+}
+
 #[derive(Deserialize)]
 struct ChatResponse {
     choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<TokenUsage>,
 }
 
 #[derive(Deserialize)]
 struct Choice {
     message: ResponseMessage,
+    #[serde(default)]
+    finish_reason: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct ResponseMessage {
-    content: String,
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCall>,
+}
+
+#[derive(Deserialize)]
+struct ToolCall {
+    function: FunctionCall,
+}
+
+#[derive(Deserialize)]
+struct FunctionCall {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    arguments: String,
+}
+
+#[derive(Deserialize)]
+struct StreamChunk {
+    // Azure sends an initial chunk with content filter results and no choices.
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    // Only present on the final chunk when stream_options.include_usage is set.
+    #[serde(default)]
+    usage: Option<TokenUsage>,
+}
+
+#[derive(Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: StreamDelta,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct StreamDelta {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<StreamToolCall>,
+}
+
+#[derive(Deserialize)]
+struct StreamToolCall {
+    #[serde(default)]
+    function: Option<StreamFunction>,
+}
+
+#[derive(Deserialize)]
+struct StreamFunction {
+    #[serde(default)]
+    arguments: Option<String>,
+}
+
+/// One completion: the text content and, when function calling was requested
+/// and used, the raw JSON arguments of the `emit_command` call.
+struct Completion {
+    text: String,
+    tool_arguments: Option<String>,
+    /// The provider stopped because max_tokens was reached.
+    truncated: bool,
+}
+
+impl Completion {
+    /// In JSON mode the text has the shape of the `emit_command` arguments;
+    /// a server that ignored `response_format` leaves plain text to extract.
+    fn in_format(mut self, format: CommandFormat) -> Self {
+        if format == CommandFormat::Json
+            && self.tool_arguments.is_none()
+            && serde_json::from_str::<Value>(&self.text).is_ok_and(|v| v.is_object())
+        {
+            self.tool_arguments = Some(std::mem::take(&mut self.text));
+        }
+        self
+    }
+}
+
+/// Result of reading a server-sent event stream.
+struct StreamedReply {
+    text: String,
+    tool_arguments: Option<String>,
+    truncated: bool,
+    usage: Option<TokenUsage>,
 }
 
 impl HttpCommandGenerator {
@@ -158,89 +840,862 @@ impl HttpCommandGenerator {
         messages: Vec<Message>,
         temperature: f32,
     ) -> Result<String> {
-        let resp = match ai {
-            EffectiveAiConfig::OpenAI {
-                api_key,
-                base_url,
-                model,
+        let opts = RequestOptions {
+            max_tokens: ai.max_tokens,
+            ..RequestOptions::text(temperature, false)
+        };
+        let completion = self.complete(ai, &messages, &opts, &mut |_| {})?;
+        warn_if_truncated(&completion, ai);
+        Ok(completion.text)
+    }
+
+    fn chat_streaming(
+        &self,
+        ai: &EffectiveAiConfig,
+        messages: Vec<Message>,
+        temperature: f32,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        let opts = RequestOptions {
+            max_tokens: ai.max_tokens,
+            ..RequestOptions::text(temperature, true)
+        };
+        let completion = self.complete(ai, &messages, &opts, on_token)?;
+        warn_if_truncated(&completion, ai);
+        Ok(completion.text)
+    }
+
+    /// Asks for the command through the `emit_command` function (or JSON mode
+    /// when the model lacks function calling) so the answer arrives as
+    /// structured JSON instead of free text.
+    fn generate_command(
+        &self,
+        ai: &EffectiveAiConfig,
+        messages: &[Message],
+        stream: bool,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        self.cached_command(ai, messages, || {
+            self.request_command(ai, messages, stream, on_token)
+        })
+    }
+
+    fn request_command(
+        &self,
+        ai: &EffectiveAiConfig,
+        messages: &[Message],
+        stream: bool,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<String> {
+        self.with_text_fallback(ai, |format| {
+            let opts = RequestOptions {
+                format,
+                max_tokens: command_max_tokens(ai),
+                expects_command: true,
+                // Partial JSON objects make poor provisional output.
+                ..RequestOptions::text(
+                    ai.temperature.unwrap_or(0.0),
+                    stream && format != CommandFormat::Json,
+                )
+            };
+            let completion = self.complete(ai, messages, &opts, &mut *on_token)?;
+            command_from_completion(completion.in_format(format))
+        })
+    }
+
+    /// Runs `request` with the best structured format the model supports when
+    /// function calling is enabled, and again as plain text when the provider
+    /// rejects the structured request.
+    fn with_text_fallback<T>(
+        &self,
+        ai: &EffectiveAiConfig,
+        mut request: impl FnMut(CommandFormat) -> Result<T>,
+    ) -> Result<T> {
+        let caps = ai.capabilities();
+        let structured = if !ai.function_calling {
+            None
+        } else if caps.tools {
+            Some(CommandFormat::Function)
+        } else if caps.json_mode {
+            Some(CommandFormat::Json)
+        } else {
+            None
+        };
+        if let Some(format) = structured {
+            match request(format) {
+                Err(err) if is_rejected_request(&err) => {
+                    eprintln!(
+                        "{} rejected the {} request; retrying with plain text output",
+                        provider_label(ai),
+                        format.label()
+                    );
+                }
+                other => return other,
+            }
+        }
+        request(CommandFormat::Text)
+    }
+
+    fn complete(
+        &self,
+        ai: &EffectiveAiConfig,
+        messages: &[Message],
+        opts: &RequestOptions,
+        on_token: &mut dyn FnMut(&str),
+    ) -> Result<Completion> {
+        let label = provider_label(ai);
+        let (event_stream, body) = match self.fetch(ai, messages, opts)? {
+            Reply::Live(resp) => (is_event_stream(&resp), ReplyBody::Live(resp)),
+            Reply::Recorded(i) => (i.event_stream, ReplyBody::Recorded(i.body)),
+        };
+
+        // Providers without streaming support ignore the flag and answer with
+        // a regular JSON body; handle that transparently.
+        if !opts.stream || !event_stream {
+            let resp: ChatResponse = body
+                .json(&self.runtime)
+                .map_err(|err| LlmError::parse(label, &err))?;
+            self.record_usage(resp.usage);
+            let completion = extract_completion(&resp)?;
+            if opts.stream && !completion.text.is_empty() {
+                on_token(&completion.text);
+            }
+            return Ok(completion);
+        }
+
+        let reply = read_sse_stream(BufReader::new(body.into_reader(&self.runtime)), on_token)
+            .with_context(|| format!("Failed to read {} response stream", label))?;
+        self.record_usage(reply.usage);
+        Ok(Completion {
+            text: strip_code_fences(&reply.text),
+            tool_arguments: reply.tool_arguments,
+            truncated: reply.truncated,
+        })
+    }
+
+    /// Requests `opts.n` choices in one non-streaming call.
+    fn complete_choices(
+        &self,
+        ai: &EffectiveAiConfig,
+        messages: &[Message],
+        opts: &RequestOptions,
+    ) -> Result<Vec<Completion>> {
+        let label = provider_label(ai);
+        let body = match self.fetch(ai, messages, opts)? {
+            Reply::Live(resp) => ReplyBody::Live(resp),
+            Reply::Recorded(i) => ReplyBody::Recorded(i.body),
+        };
+        let resp: ChatResponse = body
+            .json(&self.runtime)
+            .map_err(|err| LlmError::parse(label, &err))?;
+        self.record_usage(resp.usage);
+        extract_completions(&resp)
+    }
+
+    /// Sends the request, or answers it from the cassette when SAI_REPLAY is
+    /// set. When recording, the response is read in full and saved first.
+    fn fetch(
+        &self,
+        ai: &EffectiveAiConfig,
+        messages: &[Message],
+        opts: &RequestOptions,
+    ) -> Result<Reply> {
+        if let ProviderConfig::Mock { responses } = &ai.provider {
+            return mock_reply(responses, messages, opts);
+        }
+        check_context_size(ai, messages, opts)?;
+        let body = request_body(ai, messages, opts)?;
+        let Some(cassette) = &self.cassette else {
+            return Ok(Reply::Live(
+                self.block_on(self.send_with_retry(ai, &body))??,
+            ));
+        };
+        if cassette.is_replay() {
+            return Ok(Reply::Recorded(cassette.take(&body)?));
+        }
+
+        let resp = self.block_on(self.send_with_retry(ai, &body))??;
+        let event_stream = is_event_stream(&resp);
+        let text = self
+            .block_on(resp.text())?
+            .with_context(|| format!("Failed to read {} response", provider_label(ai)))?;
+        let interaction = Interaction {
+            request: body,
+            event_stream,
+            body: text,
+        };
+        cassette.record(&interaction)?;
+        Ok(Reply::Recorded(interaction))
+    }
+
+    /// Runs `fut` to completion on the runtime, cancelling it on Ctrl-C.
+    fn block_on<F: Future>(&self, fut: F) -> Result<F::Output> {
+        self.runtime.block_on(interrupt::cancellable(fut))
+    }
+
+    fn record_usage(&self, usage: Option<TokenUsage>) {
+        if let Some(usage) = usage {
+            let mut total = self.usage.get();
+            total.add(usage);
+            self.usage.set(total);
+        }
+    }
+
+    /// Sends the chat request, retrying transient failures (429, 5xx,
+    /// connection errors and timeouts) with jittered exponential backoff.
+    async fn send_with_retry(&self, ai: &EffectiveAiConfig, body: &Value) -> Result<Response> {
+        let label = provider_label(ai);
+        let max_attempts = ai.retry.max_attempts.max(1);
+        let mut attempt = 1;
+
+        let client = self.client_for(ai)?;
+        loop {
+            let request = self.build_request(&client, ai, body);
+            let (err, retry_after, retryable) = match request.send().await {
+                Ok(resp) if resp.status().is_success() => return Ok(resp),
+                Ok(resp) => {
+                    let status = resp.status();
+                    let wait = retry_after(&resp);
+                    let body = resp.text().await.unwrap_or_default();
+                    let err = LlmError::from_status(label, status, &body);
+                    let retryable = err.is_retryable();
+                    (err, wait, retryable)
+                }
+                // Only connection failures and timeouts may clear up; a
+                // request that could not be built or followed would fail
+                // the same way again.
+                Err(err) => (
+                    LlmError::Network {
+                        provider: label,
+                        detail: err.to_string(),
+                    },
+                    None,
+                    err.is_connect() || err.is_timeout(),
+                ),
+            };
+
+            if !retryable || attempt >= max_attempts {
+                let err = anyhow::Error::new(err);
+                return Err(if attempt > 1 {
+                    err.context(format!("Giving up after {} attempts", attempt))
+                } else {
+                    err
+                });
+            }
+
+            let delay = retry_after
+                .unwrap_or_else(|| backoff_delay(ai.retry.base_delay_ms, attempt, jitter_seed()));
+            eprintln!(
+                "{} request failed ({}); retrying in {} ms (attempt {}/{})",
+                label,
+                err.reason(),
+                delay.as_millis(),
+                attempt + 1,
+                max_attempts
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Returns the HTTP client to use, honoring an explicitly configured proxy.
+    /// Without one, reqwest applies HTTP_PROXY/HTTPS_PROXY/NO_PROXY itself.
+    fn client_for(&self, ai: &EffectiveAiConfig) -> Result<Client> {
+        let Some(proxy_url) = ai.proxy.as_deref() else {
+            return Ok(self.client.clone());
+        };
+
+        let proxy = Proxy::all(proxy_url)
+            .with_context(|| format!("Invalid proxy URL '{}'", proxy_url))?
+            .no_proxy(NoProxy::from_env());
+        Client::builder()
+            .proxy(proxy)
+            .build()
+            .context("Failed to build HTTP client with proxy")
+    }
+
+    fn build_request(
+        &self,
+        client: &Client,
+        ai: &EffectiveAiConfig,
+        body: &Value,
+    ) -> RequestBuilder {
+        match &ai.provider {
+            ProviderConfig::OpenAI {
+                api_key, base_url, ..
             } => {
-                let req = ChatRequest {
-                    model: Some(model.clone()),
-                    messages,
-                    temperature,
-                };
                 let url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
-                self.client
-                    .post(&url)
-                    .bearer_auth(api_key)
-                    .json(&req)
-                    .send()
-                    .context("HTTP error calling OpenAI")?
-                    .error_for_status()
-                    .context("Non-success status from OpenAI")?
-                    .json()
-                    .context("Failed to parse OpenAI response JSON")?
+                client.post(&url).bearer_auth(api_key).json(body)
             }
-            EffectiveAiConfig::Azure {
+            ProviderConfig::Azure {
                 api_key,
                 endpoint,
                 deployment,
                 api_version,
             } => {
-                let req = ChatRequest {
-                    model: None,
-                    messages,
-                    temperature,
-                };
                 let url = format!(
                     "{}/openai/deployments/{}/chat/completions?api-version={}",
                     endpoint.trim_end_matches('/'),
                     deployment,
                     api_version
                 );
-                self.client
-                    .post(&url)
-                    .header("api-key", api_key)
-                    .json(&req)
-                    .send()
-                    .context("HTTP error calling Azure OpenAI")?
-                    .error_for_status()
-                    .context("Non-success status from Azure OpenAI")?
-                    .json()
-                    .context("Failed to parse Azure OpenAI response JSON")?
+                client.post(&url).header("api-key", api_key).json(body)
+            }
+            ProviderConfig::Mock { .. } => {
+                unreachable!("the mock provider never sends HTTP requests")
+            }
+        }
+    }
+}
+
+/// JSON body of a chat completions request.
+fn request_body(
+    ai: &EffectiveAiConfig,
+    messages: &[Message],
+    opts: &RequestOptions,
+) -> Result<Value> {
+    // Features the model lacks are left out; a non-streamed answer is handed
+    // to the caller in one piece.
+    let caps = ai.capabilities();
+    let stream = opts.stream && caps.streaming;
+    let (model, stream_options) = match &ai.provider {
+        // Ask OpenAI to report usage on the final stream chunk.
+        ProviderConfig::OpenAI { model, .. } => (
+            Some(model.clone()),
+            stream.then_some(StreamOptions {
+                include_usage: true,
+            }),
+        ),
+        ProviderConfig::Azure { .. } | ProviderConfig::Mock { .. } => (None, None),
+    };
+    let function_call = opts.format == CommandFormat::Function;
+    let json_mode = opts.format == CommandFormat::Json;
+    let mut messages = messages.to_vec();
+    if json_mode {
+        messages.push(Message {
+            role: "system".to_string(),
+            content: JSON_MODE_INSTRUCTION.to_string(),
+        });
+    }
+    let req = ChatRequest {
+        model,
+        messages: &messages,
+        temperature: opts.temperature,
+        n: (opts.n > 1).then_some(opts.n),
+        stream,
+        stream_options,
+        tools: function_call.then(command_tools),
+        tool_choice: function_call.then(command_tool_choice),
+        response_format: json_mode.then(|| json!({ "type": "json_object" })),
+        seed: ai.seed.filter(|_| caps.seed),
+        max_tokens: opts.max_tokens,
+    };
+    serde_json::to_value(&req).context("Failed to serialize chat request")
+}
+
+/// Fails early when the prompt clearly exceeds the model's context window,
+/// which providers otherwise report as a bare 400.
+fn check_context_size(
+    ai: &EffectiveAiConfig,
+    messages: &[Message],
+    opts: &RequestOptions,
+) -> Result<()> {
+    let Some(limit) = ai.capabilities().max_context_tokens else {
+        return Ok(());
+    };
+    let prompt_len: usize = messages.iter().map(|m| m.content.len()).sum();
+    let needed =
+        capabilities::estimate_tokens(prompt_len).saturating_add(opts.max_tokens.unwrap_or(0));
+    if needed > limit {
+        return Err(LlmError::ContextTooLong {
+            detail: format!(
+                "The request needs about {} tokens but {} accepts {}",
+                needed,
+                ai.model_name(),
+                limit
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Provider failures sai can name, each with a hint on what to do about it.
+/// Other errors (config, I/O) stay plain anyhow chains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LlmError {
+    /// The API key or credentials were refused (401/403).
+    Auth { provider: &'static str, status: u16 },
+    /// The account is out of credit or over its quota; waiting does not help.
+    Quota {
+        provider: &'static str,
+        detail: String,
+    },
+    /// The prompt does not fit the model's context window.
+    ContextTooLong { detail: String },
+    /// The provider could not be reached.
+    Network {
+        provider: &'static str,
+        detail: String,
+    },
+    /// The reply could not be understood.
+    Parse {
+        provider: &'static str,
+        detail: String,
+    },
+    /// Any other non-success status (rate limits, outages, bad requests).
+    Status {
+        provider: &'static str,
+        status: u16,
+        detail: String,
+    },
+}
+
+impl LlmError {
+    /// Classifies a non-success response from its status and error body.
+    fn from_status(provider: &'static str, status: StatusCode, body: &str) -> Self {
+        let (code, message) = error_body_fields(body);
+        let is = |name: &str| code.iter().any(|c| c == name);
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            LlmError::Auth {
+                provider,
+                status: status.as_u16(),
+            }
+        } else if status == StatusCode::PAYMENT_REQUIRED || is("insufficient_quota") {
+            LlmError::Quota {
+                provider,
+                detail: message,
+            }
+        } else if is("context_length_exceeded") {
+            LlmError::ContextTooLong { detail: message }
+        } else {
+            LlmError::Status {
+                provider,
+                status: status.as_u16(),
+                detail: message,
+            }
+        }
+    }
+
+    fn parse(provider: &'static str, err: &anyhow::Error) -> Self {
+        LlmError::Parse {
+            provider,
+            detail: format!("{:#}", err),
+        }
+    }
+
+    /// Whether sending the same request again may succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            LlmError::Network { .. } => true,
+            LlmError::Status { status, .. } => StatusCode::from_u16(*status)
+                .map(is_transient_status)
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    /// What the user can do about it.
+    pub fn guidance(&self) -> &'static str {
+        match self {
+            LlmError::Auth { .. } => {
+                "check the API key in the ai section of the config (SAI_OPENAI_API_KEY, SAI_AZURE_API_KEY)"
+            }
+            LlmError::Quota { .. } => {
+                "the account has no quota left; add credit or switch ai.provider, retrying will not help"
+            }
+            LlmError::ContextTooLong { .. } => {
+                "send less --peek data or use a narrower --scope"
+            }
+            LlmError::Network { .. } => {
+                "check the network, the base URL or endpoint and ai.proxy; raise ai.max_attempts for flaky links"
             }
+            LlmError::Parse { .. } => {
+                "the provider answered in an unexpected format; check the base URL and that the model is a chat model"
+            }
+            LlmError::Status { status: 404, .. } => "check the model or Azure deployment name",
+            LlmError::Status { status: 429, .. } => {
+                "the provider is rate limiting; wait a moment or raise ai.max_attempts"
+            }
+            LlmError::Status { status, .. } if *status >= 500 => {
+                "the provider is having trouble; try again later"
+            }
+            LlmError::Status { .. } => "the provider refused the request",
+        }
+    }
+
+    /// Short reason for retry messages.
+    fn reason(&self) -> String {
+        match self {
+            LlmError::Status { status, .. } => format!("status {}", status),
+            LlmError::Network { detail, .. } => detail.clone(),
+            other => other.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LlmError::Auth { provider, status } => {
+                write!(
+                    f,
+                    "{} rejected the credentials (status {})",
+                    provider, status
+                )
+            }
+            LlmError::Quota { provider, detail } => {
+                write!(f, "{} quota exceeded", provider)?;
+                write_detail(f, detail)
+            }
+            LlmError::ContextTooLong { detail } => {
+                f.write_str("The request is too long for the model")?;
+                write_detail(f, detail)
+            }
+            LlmError::Network { provider, detail } => {
+                write!(f, "Could not reach {}: {}", provider, detail)
+            }
+            LlmError::Parse { provider, detail } => {
+                write!(f, "Failed to parse {} response: {}", provider, detail)
+            }
+            LlmError::Status {
+                provider,
+                status,
+                detail,
+            } => {
+                write!(f, "Non-success status {} from {}", status, provider)?;
+                write_detail(f, detail)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+fn write_detail(f: &mut fmt::Formatter<'_>, detail: &str) -> fmt::Result {
+    if detail.is_empty() {
+        Ok(())
+    } else {
+        write!(f, ": {}", detail)
+    }
+}
+
+/// `error.code`/`error.type` and `error.message` of an OpenAI-style error
+/// body; the message falls back to the first line of a non-JSON body.
+fn error_body_fields(body: &str) -> (Vec<String>, String) {
+    let Ok(value) = serde_json::from_str::<Value>(body) else {
+        let line = body.lines().next().unwrap_or("").trim();
+        return (Vec::new(), line.chars().take(200).collect());
+    };
+    let error = &value["error"];
+    let codes = ["code", "type"]
+        .iter()
+        .filter_map(|k| error[*k].as_str().map(str::to_string))
+        .collect();
+    let message = error["message"].as_str().unwrap_or("").trim().to_string();
+    (codes, message)
+}
+
+/// The hint for the first [`LlmError`] in `err`'s chain, if any.
+pub fn error_guidance(err: &anyhow::Error) -> Option<&'static str> {
+    err.chain()
+        .find_map(|e| e.downcast_ref::<LlmError>())
+        .map(LlmError::guidance)
+}
+
+/// max_tokens for command generation: the configured limit, but never so low
+/// that an ordinary command gets cut off.
+fn command_max_tokens(ai: &EffectiveAiConfig) -> Option<u32> {
+    ai.max_tokens.map(|limit| limit.max(MIN_COMMAND_MAX_TOKENS))
+}
+
+fn warn_if_truncated(completion: &Completion, ai: &EffectiveAiConfig) {
+    if let (true, Some(limit)) = (completion.truncated, ai.max_tokens) {
+        eprintln!();
+        eprintln!("(Response cut off at ai.max_tokens = {})", limit);
+    }
+}
+
+fn provider_label(ai: &EffectiveAiConfig) -> &'static str {
+    match ai.provider {
+        ProviderConfig::OpenAI { .. } => "OpenAI",
+        ProviderConfig::Azure { .. } => "Azure OpenAI",
+        ProviderConfig::Mock { .. } => "Mock provider",
+    }
+}
+
+/// Answers a request from the mock provider's canned responses, shaped like a
+/// non-streaming chat completion so the regular parsing applies.
+fn mock_reply(responses: &Path, messages: &[Message], opts: &RequestOptions) -> Result<Reply> {
+    let mock = MockResponses::load(responses)?;
+    let mut user_messages = messages.iter().filter(|m| m.role == "user");
+    let content = if opts.expects_command {
+        let prompt = user_messages
+            .next()
+            .map(|m| m.content.as_str())
+            .unwrap_or("");
+        mock.command_for(prompt)?.to_string()
+    } else {
+        let text: Vec<&str> = user_messages.map(|m| m.content.as_str()).collect();
+        mock.explanation_for(&text.join("\n"))
+    };
+    let body = json!({
+        "choices": [{ "message": { "content": content }, "finish_reason": "stop" }]
+    });
+    Ok(Reply::Recorded(Interaction {
+        request: Value::Null,
+        event_stream: false,
+        body: body.to_string(),
+    }))
+}
+
+/// Upper bound for a single backoff delay.
+const MAX_BACKOFF_MS: u64 = 30_000;
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// True when the provider refused the request itself (400/422), which is how
+/// models and gateways without function calling answer a `tools` request.
+fn is_rejected_request(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        matches!(
+            e.downcast_ref::<LlmError>(),
+            Some(LlmError::Status {
+                status: 400 | 422,
+                ..
+            })
+        )
+    })
+}
+
+fn retry_after(resp: &Response) -> Option<Duration> {
+    resp.headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(|secs| Duration::from_millis(secs.saturating_mul(1000).min(MAX_BACKOFF_MS)))
+}
+
+/// Exponential backoff (`base * 2^(attempt-1)`) with up to 50% added jitter.
+fn backoff_delay(base_ms: u64, attempt: u32, jitter_seed: u64) -> Duration {
+    let exp = base_ms.saturating_mul(1u64 << attempt.saturating_sub(1).min(16));
+    let capped = exp.min(MAX_BACKOFF_MS);
+    let jitter = if capped == 0 {
+        0
+    } else {
+        jitter_seed % (capped / 2 + 1)
+    };
+    Duration::from_millis((capped + jitter).min(MAX_BACKOFF_MS))
+}
+
+fn jitter_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0)
+}
+
+fn is_event_stream(resp: &Response) -> bool {
+    resp.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("text/event-stream"))
+        .unwrap_or(false)
+}
+
+/// Reads an OpenAI-style server-sent event stream, forwarding each content
+/// delta to `on_token` and returning the concatenated text. Streamed function
+/// arguments are collected separately; their `command` value is forwarded as
+/// provisional text.
+fn read_sse_stream<R: BufRead>(reader: R, on_token: &mut dyn FnMut(&str)) -> Result<StreamedReply> {
+    let mut full = String::new();
+    let mut arguments = PartialCommand::default();
+    let mut usage = None;
+    let mut truncated = false;
+    for line in reader.lines() {
+        interrupt::check()?;
+        let line = line?;
+        let Some(data) = line.strip_prefix("data:") else {
+            continue;
         };
+        let data = data.trim();
+        if data == "[DONE]" {
+            break;
+        }
+        if data.is_empty() {
+            continue;
+        }
 
-        extract_content(&resp)
+        let chunk: StreamChunk =
+            serde_json::from_str(data).context("Malformed chunk in LLM response stream")?;
+        if chunk.usage.is_some() {
+            usage = chunk.usage;
+        }
+        for choice in chunk.choices {
+            truncated |= is_length_finish(choice.finish_reason.as_deref());
+            if let Some(content) = choice.delta.content {
+                if !content.is_empty() {
+                    on_token(&content);
+                    full.push_str(&content);
+                }
+            }
+            for call in choice.delta.tool_calls {
+                if let Some(fragment) = call.function.and_then(|f| f.arguments) {
+                    if let Some(new_text) = arguments.push(&fragment) {
+                        on_token(&new_text);
+                    }
+                }
+            }
+        }
+    }
+
+    let tool_arguments = (!arguments.raw.is_empty()).then_some(arguments.raw);
+    if full.trim().is_empty() && tool_arguments.is_none() {
+        return Err(anyhow!("No content in streamed LLM response"));
+    }
+    Ok(StreamedReply {
+        text: full.trim().to_string(),
+        tool_arguments,
+        truncated,
+        usage,
+    })
+}
+
+/// Accumulates streamed function arguments and reports newly arrived text of
+/// their `command` value, so provisional output shows the command, not JSON.
+#[derive(Default)]
+struct PartialCommand {
+    raw: String,
+    emitted: usize,
+}
+
+impl PartialCommand {
+    fn push(&mut self, fragment: &str) -> Option<String> {
+        self.raw.push_str(fragment);
+        let value = partial_string_field(&self.raw, "command")?;
+        if value.len() <= self.emitted {
+            return None;
+        }
+        let new_text = value[self.emitted..].to_string();
+        self.emitted = value.len();
+        Some(new_text)
     }
 }
 
-fn extract_content(resp: &ChatResponse) -> Result<String> {
-    let content = resp
+/// Decodes the (possibly still incomplete) string value of `field` in a JSON
+/// object prefix. Stops before an escape sequence that has not fully arrived.
+fn partial_string_field(json: &str, field: &str) -> Option<String> {
+    let key = format!("\"{}\"", field);
+    let start = json.find(&key)? + key.len();
+    let rest = json[start..]
+        .trim_start()
+        .strip_prefix(':')?
+        .trim_start()
+        .strip_prefix('"')?;
+
+    let mut out = String::new();
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                        Some(decoded) if hex.len() == 4 => out.push(decoded),
+                        _ => break,
+                    }
+                }
+                Some(other) => out.push(other),
+                None => break,
+            },
+            _ => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+fn parse_command_arguments(arguments: &str) -> Result<String> {
+    let args: CommandArguments = serde_json::from_str(arguments)
+        .with_context(|| format!("Malformed {} arguments from LLM", COMMAND_FUNCTION))?;
+    extract_command_from_text(&args.command)
+}
+
+fn extract_completion(resp: &ChatResponse) -> Result<Completion> {
+    let choice = resp
         .choices
         .first()
-        .ok_or_else(|| anyhow!("No choices in LLM response"))?
-        .message
-        .content
-        .trim()
-        .to_string();
+        .ok_or_else(|| anyhow!("No choices in LLM response"))?;
+    completion_from_message(choice).ok_or_else(|| anyhow!("No content in LLM response"))
+}
+
+/// Every usable choice of a multi-choice (`n > 1`) response.
+fn extract_completions(resp: &ChatResponse) -> Result<Vec<Completion>> {
+    let completions: Vec<Completion> = resp
+        .choices
+        .iter()
+        .filter_map(completion_from_message)
+        .collect();
+    if completions.is_empty() {
+        return Err(anyhow!("No content in LLM response"));
+    }
+    Ok(completions)
+}
+
+fn completion_from_message(choice: &Choice) -> Option<Completion> {
+    let message = &choice.message;
+    let text = strip_code_fences(message.content.as_deref().unwrap_or("").trim());
+    let tool_arguments = message
+        .tool_calls
+        .iter()
+        .find(|call| call.function.name == COMMAND_FUNCTION)
+        .map(|call| call.function.arguments.clone());
 
-    Ok(strip_code_fences(&content))
+    if text.is_empty() && tool_arguments.is_none() {
+        return None;
+    }
+    Some(Completion {
+        text,
+        tool_arguments,
+        truncated: is_length_finish(choice.finish_reason.as_deref()),
+    })
 }
 
-fn extract_first_line_from_text(text: &str) -> Result<String> {
-    let first_line = text
-        .lines()
-        .next()
-        .ok_or_else(|| anyhow!("Empty content from LLM"))?
-        .trim()
-        .to_string();
+fn is_length_finish(finish_reason: Option<&str>) -> bool {
+    finish_reason == Some("length")
+}
 
-    if first_line.is_empty() {
-        return Err(anyhow!("LLM returned an empty command line"));
+/// The command of one completion: the function arguments when the model called
+/// `emit_command`, otherwise the first line of its text.
+fn command_from_completion(completion: Completion) -> Result<String> {
+    if completion.truncated {
+        return Err(anyhow!(
+            "LLM output was cut off at the max_tokens limit before the command was complete; \
+             raise ai.max_tokens"
+        ));
     }
+    match completion.tool_arguments {
+        Some(args) => parse_command_arguments(&args),
+        None => extract_command_from_text(&completion.text),
+    }
+}
 
-    Ok(first_line)
+/// Picks the command out of free text, telling the user about any prose that
+/// was cut off around it.
+fn extract_command_from_text(text: &str) -> Result<String> {
+    let extracted = extract_command(text)?;
+    if let Some(stripped) = &extracted.stripped {
+        progress::note(&format!(
+            "Note: stripped non-command text from LLM output: {}",
+            stripped
+        ));
+    }
+    Ok(extracted.command)
 }
 
 fn strip_code_fences(text: &str) -> String {
@@ -259,3 +1714,288 @@ fn strip_code_fences(text: &str) -> String {
     }
     cleaned.trim().to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn sse_stream_collects_deltas_in_order() {
+        let body = "data: {\"choices\":[]}\n\n\
+data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n\
+data: {\"choices\":[{\"delta\":{\"content\":\"Lists \"}}]}\n\n\
+data: {\"choices\":[{\"delta\":{\"content\":\"files.\"}}]}\n\n\
+data: [DONE]\n\n";
+        let mut seen = Vec::new();
+        let reply = read_sse_stream(Cursor::new(body), &mut |t| seen.push(t.to_string())).unwrap();
+        assert_eq!(reply.text, "Lists files.");
+        assert_eq!(reply.usage, None);
+        assert_eq!(seen, vec!["Lists ", "files."]);
+    }
+
+    #[test]
+    fn sse_stream_reads_usage_from_final_chunk() {
+        let body = "data: {\"choices\":[{\"delta\":{\"content\":\"ls\"}}]}\n\n\
+data: {\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":3,\"total_tokens\":15}}\n\n\
+data: [DONE]\n\n";
+        let reply = read_sse_stream(Cursor::new(body), &mut |_| {}).unwrap();
+        let usage = reply.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, 12);
+        assert_eq!(usage.total(), 15);
+    }
+
+    #[test]
+    fn length_finish_reason_rejects_truncated_commands() {
+        let body = "data: {\"choices\":[{\"delta\":{\"content\":\"find . -name\"},\"finish_reason\":\"length\"}]}\n\n\
+data: [DONE]\n\n";
+        let reply = read_sse_stream(Cursor::new(body), &mut |_| {}).unwrap();
+        assert!(reply.truncated);
+
+        let completion = Completion {
+            text: reply.text,
+            tool_arguments: None,
+            truncated: reply.truncated,
+        };
+        let err = command_from_completion(completion).unwrap_err();
+        assert!(err.to_string().contains("max_tokens"));
+    }
+
+    #[test]
+    fn sse_stream_without_content_is_an_error() {
+        let body = "data: {\"choices\":[]}\n\ndata: [DONE]\n\n";
+        assert!(read_sse_stream(Cursor::new(body), &mut |_| {}).is_err());
+    }
+
+    #[test]
+    fn sse_stream_shows_command_from_streamed_function_arguments() {
+        let body = "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"name\":\"emit_command\",\"arguments\":\"\"}}]}}]}\n\n\
+data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"command\\\": \\\"ls \"}}]}}]}\n\n\
+data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"-la\\\", \\\"rationale\\\": \\\"x\\\"}\"}}]}}]}\n\n\
+data: [DONE]\n\n";
+        let mut seen = Vec::new();
+        let reply = read_sse_stream(Cursor::new(body), &mut |t| seen.push(t.to_string())).unwrap();
+        assert_eq!(seen.concat(), "ls -la");
+        let args = reply.tool_arguments.unwrap();
+        assert_eq!(parse_command_arguments(&args).unwrap(), "ls -la");
+    }
+
+    #[test]
+    fn function_call_arguments_take_precedence_over_text() {
+        let resp: ChatResponse = serde_json::from_str(
+            r#"{"choices":[{"message":{"content":null,"tool_calls":[{"type":"function","function":{"name":"emit_command","arguments":"{\"command\":\"grep -rn TODO src\",\"rationale\":\"search\"}"}}]}}]}"#,
+        )
+        .unwrap();
+        let completion = extract_completion(&resp).unwrap();
+        assert_eq!(completion.text, "");
+        let args = completion.tool_arguments.unwrap();
+        assert_eq!(parse_command_arguments(&args).unwrap(), "grep -rn TODO src");
+
+        assert!(parse_command_arguments("{\"rationale\":\"x\"}").is_err());
+    }
+
+    #[test]
+    fn partial_command_waits_for_complete_escapes() {
+        assert_eq!(
+            partial_string_field(r#"{"command": "echo \"hi"#, "command").as_deref(),
+            Some("echo \"hi")
+        );
+        assert_eq!(
+            partial_string_field(r#"{"command": "echo \"#, "command").as_deref(),
+            Some("echo ")
+        );
+        assert_eq!(partial_string_field(r#"{"rationale": "x"#, "command"), None);
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_with_bounded_jitter() {
+        assert_eq!(backoff_delay(500, 1, 0), Duration::from_millis(500));
+        assert_eq!(backoff_delay(500, 3, 0), Duration::from_millis(2000));
+        let jittered = backoff_delay(500, 2, 123_456);
+        assert!(jittered >= Duration::from_millis(1000));
+        assert!(jittered <= Duration::from_millis(1500));
+        assert_eq!(
+            backoff_delay(500, 30, 0),
+            Duration::from_millis(MAX_BACKOFF_MS)
+        );
+    }
+
+    #[test]
+    fn transient_statuses_are_retried() {
+        assert!(is_transient_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_transient_status(StatusCode::BAD_GATEWAY));
+        assert!(!is_transient_status(StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn provider_failures_are_classified_with_retry_hints() {
+        let quota = r#"{"error":{"message":"You exceeded your current quota","type":"insufficient_quota"}}"#;
+        let err = LlmError::from_status("OpenAI", StatusCode::TOO_MANY_REQUESTS, quota);
+        assert!(matches!(err, LlmError::Quota { .. }));
+        assert!(!err.is_retryable());
+
+        let busy = LlmError::from_status("OpenAI", StatusCode::TOO_MANY_REQUESTS, "slow down");
+        assert!(busy.is_retryable());
+        assert_eq!(
+            busy.to_string(),
+            "Non-success status 429 from OpenAI: slow down"
+        );
+
+        let long = r#"{"error":{"message":"too many tokens","code":"context_length_exceeded"}}"#;
+        let err = LlmError::from_status("Azure OpenAI", StatusCode::BAD_REQUEST, long);
+        assert_eq!(
+            err,
+            LlmError::ContextTooLong {
+                detail: "too many tokens".to_string()
+            }
+        );
+        assert!(!is_rejected_request(&anyhow::Error::new(err)));
+
+        let auth = LlmError::from_status("OpenAI", StatusCode::UNAUTHORIZED, "");
+        assert!(!auth.is_retryable());
+        let chained = anyhow::Error::new(auth).context("Giving up after 3 attempts");
+        assert!(error_guidance(&chained).unwrap().contains("API key"));
+
+        let rejected = LlmError::from_status("OpenAI", StatusCode::BAD_REQUEST, "{}");
+        assert!(is_rejected_request(&anyhow::Error::new(rejected)));
+    }
+
+    #[test]
+    fn request_body_leaves_out_unsupported_features() {
+        let mut ai = resolve_test_ai("o1-mini");
+        ai.seed = Some(7);
+        ai.capability_overrides.seed = Some(false);
+        let messages = vec![Message {
+            role: "user".to_string(),
+            content: "list files".to_string(),
+        }];
+        let opts = RequestOptions {
+            format: CommandFormat::Text,
+            ..RequestOptions::text(0.0, true)
+        };
+        let body = request_body(&ai, &messages, &opts).unwrap();
+        assert!(body.get("stream").is_none());
+        assert!(body.get("seed").is_none());
+
+        let ai = resolve_test_ai("gpt-4o");
+        let opts = RequestOptions {
+            format: CommandFormat::Json,
+            ..RequestOptions::text(0.0, false)
+        };
+        let body = request_body(&ai, &messages, &opts).unwrap();
+        assert_eq!(body["response_format"]["type"], "json_object");
+        assert!(body["messages"][1]["content"]
+            .as_str()
+            .unwrap()
+            .contains("JSON"));
+    }
+
+    #[test]
+    fn request_body_sends_the_seed() {
+        let mut ai = resolve_test_ai("gpt-4o");
+        let messages = vec![Message {
+            role: "user".to_string(),
+            content: "list files".to_string(),
+        }];
+        let opts = RequestOptions::text(0.0, false);
+        assert!(request_body(&ai, &messages, &opts)
+            .unwrap()
+            .get("seed")
+            .is_none());
+        ai.seed = Some(7);
+        assert_eq!(request_body(&ai, &messages, &opts).unwrap()["seed"], 7);
+    }
+
+    #[test]
+    fn oversized_prompts_fail_before_sending() {
+        let mut ai = resolve_test_ai("gpt-4");
+        ai.capability_overrides.max_context_tokens = Some(100);
+        let messages = vec![Message {
+            role: "user".to_string(),
+            content: "x".repeat(1000),
+        }];
+        let err =
+            check_context_size(&ai, &messages, &RequestOptions::text(0.0, false)).unwrap_err();
+        assert!(err.to_string().contains("about 250 tokens"));
+        assert!(error_guidance(&err).unwrap().contains("--scope"));
+    }
+
+    fn resolve_test_ai(model: &str) -> EffectiveAiConfig {
+        EffectiveAiConfig {
+            provider: ProviderConfig::OpenAI {
+                api_key: "key".to_string(),
+                base_url: "http://localhost".to_string(),
+                model: model.to_string(),
+            },
+            stream: true,
+            retry: crate::config::RetryPolicy::default(),
+            proxy: None,
+            function_calling: true,
+            temperature: None,
+            seed: None,
+            max_tokens: None,
+            capability_overrides: Default::default(),
+            redaction: Default::default(),
+        }
+    }
+
+    #[test]
+    fn response_cache_keys_on_sampling_and_skips_secrets() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let generator = HttpCommandGenerator::try_new()
+            .unwrap()
+            .with_response_cache(Some(ResponseCache::new(temp.path())));
+        let messages = [Message {
+            role: "user".to_string(),
+            content: "count lines".to_string(),
+        }];
+        let mut ai = resolve_test_ai("model");
+        let cmd = generator
+            .cached_command(&ai, &messages, || Ok("wc -l f.txt".to_string()))
+            .unwrap();
+        let again = generator
+            .cached_command(&ai, &messages, || panic!("should be cached"))
+            .unwrap();
+        assert_eq!((cmd.as_str(), generator.cache_hits()), (again.as_str(), 1));
+
+        ai.seed = Some(7);
+        generator
+            .cached_command(&ai, &messages, || Ok("API_KEY=abc123 wc -l".to_string()))
+            .unwrap();
+        let fresh = generator
+            .cached_command(&ai, &messages, || Ok("asked again".to_string()))
+            .unwrap();
+        assert_eq!(fresh, "asked again");
+    }
+
+    #[test]
+    fn invalid_proxy_url_is_reported() {
+        let ai = EffectiveAiConfig {
+            provider: ProviderConfig::OpenAI {
+                api_key: "key".to_string(),
+                base_url: "http://localhost".to_string(),
+                model: "model".to_string(),
+            },
+            stream: false,
+            retry: crate::config::RetryPolicy::default(),
+            proxy: Some("not a url".to_string()),
+            function_calling: true,
+            temperature: None,
+            seed: None,
+            max_tokens: None,
+            capability_overrides: Default::default(),
+            redaction: Default::default(),
+        };
+
+        let err = HttpCommandGenerator::try_new()
+            .unwrap()
+            .client_for(&ai)
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid proxy URL"));
+    }
+
+    #[test]
+    fn strips_code_fences_from_content() {
+        assert_eq!(strip_code_fences("```sh\nls -la\n```"), "ls -la");
+    }
+}
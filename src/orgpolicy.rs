@@ -0,0 +1,192 @@
+//! Organization policy: a read-only file managed by administrators
+//! (`/etc/sai/policy.yaml`, or `%ProgramData%\sai\policy.yaml` on Windows)
+//! that applies on top of the user's config and cannot be loosened by it or by
+//! command-line flags.
+
+use crate::config::{EffectiveAiConfig, PromptConfig, ProviderConfig};
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Contents of the organization policy file. Unknown keys are an error, so
+/// a misspelled restriction is not silently ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OrgPolicy {
+    /// Reject `--unsafe`.
+    #[serde(default)]
+    pub forbid_unsafe: bool,
+    /// Ask before every command, as if `--confirm` was given.
+    #[serde(default)]
+    pub force_confirm: bool,
+    /// Providers that may be used (openai, azure, mock); all when unset.
+    #[serde(default)]
+    pub providers: Option<Vec<String>>,
+    /// Tools commands may use; prompt tools outside the list are dropped.
+    #[serde(default)]
+    pub tools: Option<Vec<String>>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+/// Where the organization policy is read from.
+pub fn org_policy_path() -> PathBuf {
+    if cfg!(windows) {
+        std::env::var_os("ProgramData")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"))
+            .join("sai")
+            .join("policy.yaml")
+    } else {
+        PathBuf::from("/etc/sai/policy.yaml")
+    }
+}
+
+/// Name of a provider as written in `ai.provider` and `providers:`.
+pub fn provider_name(provider: &ProviderConfig) -> &'static str {
+    match provider {
+        ProviderConfig::OpenAI { .. } => "openai",
+        ProviderConfig::Azure { .. } => "azure",
+        ProviderConfig::Mock { .. } => "mock",
+    }
+}
+
+impl OrgPolicy {
+    /// The policy at `path`, or `None` when there is no such file. A file that
+    /// cannot be read or parsed is an error rather than no policy.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read organization policy {}", path.display()))?;
+        let mut policy: OrgPolicy = serde_yaml::from_str(&content).with_context(|| {
+            format!(
+                "Failed to parse organization policy YAML {}",
+                path.display()
+            )
+        })?;
+        policy.path = path.to_path_buf();
+        Ok(Some(policy))
+    }
+
+    /// Rejects `--unsafe` when the policy forbids it.
+    pub fn check_unsafe(&self, unsafe_mode: bool) -> Result<()> {
+        if self.forbid_unsafe && unsafe_mode {
+            return Err(anyhow!(
+                "--unsafe is forbidden by the organization policy {}",
+                self.path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Rejects a provider outside `providers`.
+    pub fn check_provider(&self, ai: &EffectiveAiConfig) -> Result<()> {
+        let name = provider_name(&ai.provider);
+        match &self.providers {
+            Some(allowed) if !allowed.iter().any(|p| p.eq_ignore_ascii_case(name)) => Err(anyhow!(
+                "Provider '{}' is not allowed by the organization policy {} (allowed: {})",
+                name,
+                self.path.display(),
+                allowed.join(", ")
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Drops prompt tools outside `tools` and returns their names. Each
+    /// alias counts as a tool of its own, so an alias outside the list is
+    /// dropped too. Tools requested with `--allow-tool` must be in the list.
+    pub fn pin_tools(
+        &self,
+        prompt_cfg: &mut PromptConfig,
+        requested: &[String],
+    ) -> Result<Vec<String>> {
+        let Some(pinned) = &self.tools else {
+            return Ok(Vec::new());
+        };
+        if let Some(name) = requested.iter().find(|name| !pinned.contains(name)) {
+            return Err(anyhow!(
+                "--allow-tool {} is not allowed by the organization policy {}",
+                name,
+                self.path.display()
+            ));
+        }
+        let mut dropped = Vec::new();
+        prompt_cfg.tools.retain_mut(|tool| {
+            let keep = pinned.contains(&tool.name);
+            if !keep {
+                dropped.push(tool.name.clone());
+            }
+            tool.aliases.retain(|alias| {
+                let keep_alias = pinned.contains(alias);
+                if keep && !keep_alias {
+                    dropped.push(alias.clone());
+                }
+                keep_alias
+            });
+            keep
+        });
+        if prompt_cfg.tools.is_empty() {
+            return Err(anyhow!(
+                "No tool of the prompt config is allowed by the organization policy {}",
+                self.path.display()
+            ));
+        }
+        Ok(dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ToolConfig;
+    use tempfile::TempDir;
+
+    fn tool(name: &str) -> ToolConfig {
+        serde_yaml::from_str(&format!("name: {}\nconfig: \"{}\"\n", name, name)).unwrap()
+    }
+
+    #[test]
+    fn policy_restricts_flags_and_tools() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("policy.yaml");
+        assert!(OrgPolicy::load(&path).unwrap().is_none());
+
+        fs::write(
+            &path,
+            "forbid_unsafe: true\nforce_confirm: true\nproviders: [azure]\ntools: [ls, grep]\n",
+        )
+        .unwrap();
+        let policy = OrgPolicy::load(&path).unwrap().unwrap();
+        assert!(policy.force_confirm);
+        assert!(policy.check_unsafe(true).is_err());
+        policy.check_unsafe(false).unwrap();
+
+        let mut prompt = PromptConfig {
+            tools: vec![tool("ls"), tool("rm"), tool("grep")],
+            ..Default::default()
+        };
+        assert_eq!(policy.pin_tools(&mut prompt, &[]).unwrap(), ["rm"]);
+        let names: Vec<&str> = prompt.tools.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["ls", "grep"]);
+        assert!(policy
+            .pin_tools(&mut prompt, &["curl".to_string()])
+            .is_err());
+
+        // An alias is a tool of its own: `rm` may not run as `ls`.
+        let mut prompt = PromptConfig {
+            tools: vec![
+                serde_yaml::from_str("name: ls\naliases: [rm, grep]\nconfig: ls\n").unwrap(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(policy.pin_tools(&mut prompt, &[]).unwrap(), ["rm"]);
+        assert_eq!(prompt.tools[0].aliases, ["grep"]);
+
+        fs::write(&path, "forbid_unsafe: true\nforce_confrim: true\n").unwrap();
+        assert!(OrgPolicy::load(&path).is_err());
+    }
+}
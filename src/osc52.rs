@@ -0,0 +1,107 @@
+//! `--osc52`: copies the generated command into the local clipboard through
+//! the terminal, using the OSC 52 escape sequence. This works over SSH where
+//! sai has no clipboard of its own, in terminals that support OSC 52 (and
+//! through tmux or screen, whose passthrough wrappers are added).
+
+use anyhow::{anyhow, Context, Result};
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+/// Longest sequence sent; several terminals ignore longer OSC 52 payloads.
+const MAX_PAYLOAD: usize = 74_994;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with padding.
+fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, b)| acc | u32::from(*b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Multiplexer that needs the sequence wrapped to reach the outer terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplexer {
+    None,
+    Tmux,
+    Screen,
+}
+
+impl Multiplexer {
+    pub fn detect() -> Self {
+        if env::var_os("TMUX").is_some() {
+            Multiplexer::Tmux
+        } else if env::var("TERM").is_ok_and(|term| term.starts_with("screen")) {
+            Multiplexer::Screen
+        } else {
+            Multiplexer::None
+        }
+    }
+}
+
+/// The escape sequence that puts `text` on the clipboard.
+pub fn sequence(text: &str, mux: Multiplexer) -> String {
+    let osc = format!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+    match mux {
+        Multiplexer::None => osc,
+        Multiplexer::Tmux => format!("\x1bPtmux;{}\x1b\\", osc.replace('\x1b', "\x1b\x1b")),
+        Multiplexer::Screen => format!("\x1bP{}\x1b\\", osc),
+    }
+}
+
+/// Writes the sequence for `text` to the terminal: the controlling tty when
+/// there is one, so it also works with stdout redirected, else stderr.
+pub fn copy_to_clipboard(text: &str) -> Result<()> {
+    let seq = sequence(text, Multiplexer::detect());
+    if seq.len() > MAX_PAYLOAD {
+        return Err(anyhow!("The command is too long to copy with OSC 52"));
+    }
+    let tty = if cfg!(unix) {
+        OpenOptions::new().write(true).open("/dev/tty").ok()
+    } else {
+        None
+    };
+    match tty {
+        Some(mut tty) => tty.write_all(seq.as_bytes()),
+        None => io::stderr().write_all(seq.as_bytes()),
+    }
+    .context("Failed to write the OSC 52 sequence to the terminal")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence_encodes_the_command_and_wraps_it_for_multiplexers() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(
+            sequence("ls -la", Multiplexer::None),
+            "\x1b]52;c;bHMgLWxh\x07"
+        );
+        assert_eq!(
+            sequence("ls -la", Multiplexer::Tmux),
+            "\x1bPtmux;\x1b\x1b]52;c;bHMgLWxh\x07\x1b\\"
+        );
+        assert_eq!(
+            sequence("ls -la", Multiplexer::Screen),
+            "\x1bP\x1b]52;c;bHMgLWxh\x07\x1b\\"
+        );
+    }
+}
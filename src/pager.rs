@@ -0,0 +1,103 @@
+//! Paging of long command output (`pager:` in the global config). When sai's
+//! stdout is a terminal, output longer than the screen goes through `$PAGER`.
+//! `execution.max_output_lines` caps chatty commands: past the limit their
+//! output is handed to the pager on a terminal, and cut off otherwise.
+
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::{self, IsTerminal};
+use std::process::{Child, Command, Stdio};
+
+/// `pager:` section of the global config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PagerConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Pager command line; defaults to `$PAGER`, then `less -R`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+/// What happens to output past `execution.max_output_lines`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputOverflow {
+    /// Hand it to the pager when stdout is a terminal, else truncate.
+    #[default]
+    Page,
+    /// Stop printing it, with a notice once the command ends.
+    Truncate,
+}
+
+/// `execution:` section of the global config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExecutionConfig {
+    /// Lines of stdout printed before the overflow handling applies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_lines: Option<usize>,
+    #[serde(default)]
+    pub overflow: OutputOverflow,
+}
+
+impl ExecutionConfig {
+    /// The output limit for a run; none with `--full-output`.
+    pub fn output_limit(&self, full_output: bool) -> Option<usize> {
+        self.max_output_lines.filter(|n| *n > 0 && !full_output)
+    }
+}
+
+/// Pager for one run: output reaching `rows` lines is sent through `command`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pager {
+    pub command: Vec<String>,
+    pub rows: usize,
+}
+
+impl Pager {
+    /// The pager to use, if paging is enabled and stdout is a terminal.
+    pub fn for_terminal(cfg: &PagerConfig) -> Option<Pager> {
+        if !cfg.enabled || !io::stdout().is_terminal() {
+            return None;
+        }
+        // Terminals that report no size are not paged.
+        let (_, rows) = crossterm::terminal::size().ok().filter(|(_, r)| *r > 1)?;
+        Some(Pager {
+            command: pager_command(Some(cfg))?,
+            // One line stays free for the shell prompt, as with `less -F`.
+            rows: usize::from(rows) - 1,
+        })
+    }
+
+    /// The pager taking over output that reaches `execution.max_output_lines`,
+    /// if the overflow goes to the pager and stdout is a terminal.
+    pub fn for_output_limit(
+        cfg: Option<&PagerConfig>,
+        execution: &ExecutionConfig,
+        limit: usize,
+    ) -> Option<Pager> {
+        if execution.overflow != OutputOverflow::Page || !io::stdout().is_terminal() {
+            return None;
+        }
+        Some(Pager {
+            command: pager_command(cfg)?,
+            rows: limit,
+        })
+    }
+
+    pub(crate) fn spawn(&self) -> io::Result<Child> {
+        Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .stdin(Stdio::piped())
+            .spawn()
+    }
+}
+
+/// `pager.command`, then `$PAGER`, then `less -R`.
+fn pager_command(cfg: Option<&PagerConfig>) -> Option<Vec<String>> {
+    let line = cfg
+        .and_then(|cfg| cfg.command.clone())
+        .or_else(|| env::var("PAGER").ok())
+        .filter(|line| !line.trim().is_empty())
+        .unwrap_or_else(|| "less -R".to_string());
+    shell_words::split(&line).ok().filter(|c| !c.is_empty())
+}
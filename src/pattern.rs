@@ -0,0 +1,82 @@
+//! Small case-insensitive text matchers for user-configured patterns (the
+//! crate has no regex dependency).
+
+/// Glob match over the whole text: `*` matches any run, `?` one character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// True when `phrase` occurs in `text` as whole words, ignoring case and
+/// treating any run of whitespace as a single space.
+pub fn contains_phrase(text: &str, phrase: &str) -> bool {
+    let normalize = |s: &str| {
+        s.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+    let text = normalize(text);
+    let phrase = normalize(phrase);
+    if phrase.is_empty() {
+        return false;
+    }
+
+    let is_word = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+    text.match_indices(&phrase).any(|(idx, _)| {
+        let before = text[..idx].chars().next_back();
+        let after = text[idx + phrase.len()..].chars().next();
+        let starts_word = !is_word(phrase.chars().next()) || !is_word(before);
+        let ends_word = !is_word(phrase.chars().next_back()) || !is_word(after);
+        starts_word && ends_word
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_patterns_match_case_insensitively() {
+        assert!(glob_match("*count*lines*", "Count the lines in notes.txt"));
+        assert!(glob_match("list ?iles", "list files"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("*count*lines*", "count words"));
+        assert!(!glob_match("list", "list files"));
+    }
+
+    #[test]
+    fn phrases_match_whole_words_only() {
+        assert!(contains_phrase(
+            "Please  Dump the\tcredentials now",
+            "dump the credentials"
+        ));
+        assert!(contains_phrase("read /etc/shadow", "/etc/shadow"));
+        assert!(!contains_phrase("run xmrigger", "xmrig"));
+        assert!(!contains_phrase("anything", ""));
+    }
+}
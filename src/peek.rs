@@ -1,41 +1,654 @@
-use anyhow::{Context, Result};
+use crate::humanize;
+use crate::pattern::glob_match;
+use crate::scope::glob_safe_name;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /// Maximum number of bytes to read from each --peek file.
 pub const PEEK_MAX_BYTES: usize = 16 * 1024;
 
-pub fn build_peek_context(peek_files: &[String]) -> Result<Option<String>> {
+/// Maximum number of bytes parsed when projecting JSON files with --fields.
+pub const PEEK_FIELDS_MAX_PARSE_BYTES: usize = 1024 * 1024;
+
+pub fn build_peek_context<P: AsRef<Path>>(
+    peek_files: &[P],
+    fields: &[String],
+) -> Result<Option<String>> {
     if peek_files.is_empty() {
         return Ok(None);
     }
 
     let mut out = String::new();
-    for (idx, path_str) in peek_files.iter().enumerate() {
-        let path = Path::new(path_str);
+    for (idx, path) in peek_files.iter().enumerate() {
+        let path = path.as_ref();
         let data = fs::read(path)
             .with_context(|| format!("Failed to read peek file {}", path.display()))?;
 
-        let truncated = if data.len() > PEEK_MAX_BYTES {
-            &data[..PEEK_MAX_BYTES]
-        } else {
-            &data[..]
-        };
+        // The model sees the name the way --scope . lists it, so a non-UTF-8
+        // name can be used as a glob in the generated command.
+        let name = glob_safe_name(path.as_os_str());
+        if !fields.is_empty() {
+            let projected = project_fields(path, &data, fields)?;
+            out.push_str(&format!(
+                "=== Sample {}: {} (fields: {}) ===\n",
+                idx + 1,
+                name,
+                fields.join(", ")
+            ));
+            push_sample(&mut out, projected.as_bytes());
+            continue;
+        }
+
+        out.push_str(&format!("=== Sample {}: {} ===\n", idx + 1, name));
+        push_sample(&mut out, &data);
+    }
+
+    Ok(Some(out))
+}
+
+/// File names never attached automatically, whatever the config says.
+const SENSITIVE_NAMES: &[&str] = &[
+    ".env",
+    ".env.*",
+    "*.pem",
+    "*.key",
+    "*.p12",
+    "*.pfx",
+    "id_rsa*",
+    "id_dsa*",
+    "id_ecdsa*",
+    "id_ed25519*",
+    ".netrc",
+    ".pgpass",
+    "*.kdbx",
+    "*secret*",
+    "*credential*",
+    "*password*",
+    "*token*",
+];
+
+/// `auto_peek:` section of the global config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoPeekConfig {
+    /// Attach files named in the prompt without --peek (also `--auto-peek`).
+    #[serde(default)]
+    pub enabled: bool,
+    /// Most files attached per run.
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    /// Extra file name globs never attached, on top of the built-in list of
+    /// keys, credentials and dotenv files.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
 
-        let text = String::from_utf8_lossy(truncated);
+impl Default for AutoPeekConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_files: default_max_files(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+fn default_max_files() -> usize {
+    3
+}
+
+/// Existing regular files the prompt names by path, in order of mention,
+/// skipping `already` (the --peek files) and names matching the exclusions.
+pub fn files_named_in_prompt(
+    prompt: &str,
+    cfg: &AutoPeekConfig,
+    already: &[PathBuf],
+) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = Vec::new();
+    for word in prompt.split_whitespace() {
+        if found.len() >= cfg.max_files {
+            break;
+        }
+        let word = word
+            .trim_matches(['"', '\'', '`', '(', ')', '[', ']', '<', '>'])
+            .trim_end_matches(['.', ',', ';', ':', '!', '?']);
+        // Bare words like "notes" are more often English than file names.
+        if !word.contains(['.', '/']) {
+            continue;
+        }
+        let path = PathBuf::from(word);
+        if !path.is_file() || found.contains(&path) || already.contains(&path) {
+            continue;
+        }
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy()) else {
+            continue;
+        };
+        let excluded = SENSITIVE_NAMES
+            .iter()
+            .copied()
+            .chain(cfg.exclude.iter().map(String::as_str))
+            .any(|pattern| glob_match(pattern, &name));
+        if !excluded {
+            found.push(path);
+        }
+    }
+    found
+}
 
-        out.push_str(&format!("=== Sample {}: {} ===\n", idx + 1, path.display()));
-        if data.len() > PEEK_MAX_BYTES {
-            out.push_str(&format!("(truncated after {} bytes)\n", PEEK_MAX_BYTES));
+/// Formats a command's captured output like peek samples (same truncation),
+/// for asking the model about it.
+pub fn build_output_context(stdout: &[u8], stderr: &[u8]) -> String {
+    let mut out = String::new();
+    for (name, data) in [("stdout", stdout), ("stderr", stderr)] {
+        if data.is_empty() {
+            out.push_str(&format!("=== {} (empty) ===\n\n", name));
+            continue;
         }
-        out.push_str("```text\n");
-        out.push_str(&text);
-        out.push_str("\n```\n\n");
+        out.push_str(&format!("=== {} ===\n", name));
+        push_sample(&mut out, data);
     }
+    out
+}
+
+fn push_sample(out: &mut String, data: &[u8]) {
+    let truncated = if data.len() > PEEK_MAX_BYTES {
+        &data[..PEEK_MAX_BYTES]
+    } else {
+        data
+    };
+
+    let text = String::from_utf8_lossy(truncated);
+
+    if data.len() > PEEK_MAX_BYTES {
+        out.push_str(&format!(
+            "(truncated after {})\n",
+            humanize::bytes(PEEK_MAX_BYTES as u64)
+        ));
+    }
+    out.push_str("```text\n");
+    out.push_str(&text);
+    out.push_str("\n```\n\n");
+}
+
+/// Rows (or lines) shown per category in a --peek-diff comparison.
+const DIFF_SAMPLE_ROWS: usize = 5;
 
+/// Longest cell value shown in a --peek-diff comparison.
+const DIFF_MAX_CELL_CHARS: usize = 80;
+
+/// Appends the `--peek-diff BEFORE AFTER` comparison to the peek context.
+pub fn with_diff_context(context: Option<String>, diff: &[PathBuf]) -> Result<Option<String>> {
+    let [before, after] = diff else {
+        return Ok(context);
+    };
+    let mut out = context.unwrap_or_default();
+    out.push_str(&build_diff_context(before, after)?);
     Ok(Some(out))
 }
 
+/// Structural comparison of two versions of a data file, so the model sees
+/// the transformation it is asked for: columns added and removed, the row
+/// count change and samples of changed, removed and added rows. CSV/TSV and
+/// JSON/NDJSON records are compared by column; other files line by line.
+pub fn build_diff_context(before: &Path, after: &Path) -> Result<String> {
+    let read = |path: &Path| {
+        fs::read(path).with_context(|| format!("Failed to read peek file {}", path.display()))
+    };
+    let (before_data, after_data) = (read(before)?, read(after)?);
+
+    let mut out = format!(
+        "=== Desired transformation: {} -> {} ===\n\
+         The command should turn the first file into the second.\n",
+        glob_safe_name(before.as_os_str()),
+        glob_safe_name(after.as_os_str())
+    );
+    match (
+        read_table(before, &before_data),
+        read_table(after, &after_data),
+    ) {
+        (Some(old), Some(new)) => push_table_diff(&mut out, &old, &new),
+        _ => push_line_diff(&mut out, &before_data, &after_data),
+    }
+    out.push('\n');
+    Ok(out)
+}
+
+/// Records of a CSV/TSV or JSON/NDJSON file, cells rendered as text.
+#[derive(Debug)]
+struct Table {
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    fn cell(&self, row: &[String], column: &str) -> String {
+        self.columns
+            .iter()
+            .position(|c| c == column)
+            .and_then(|i| row.get(i).cloned())
+            .unwrap_or_default()
+    }
+
+    fn render(&self, row: &[String]) -> String {
+        self.columns
+            .iter()
+            .zip(row)
+            .map(|(column, value)| format!("{}={}", column, shorten(value)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+fn read_table(path: &Path, data: &[u8]) -> Option<Table> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+    let window = &data[..data.len().min(PEEK_FIELDS_MAX_PARSE_BYTES)];
+    match ext.as_str() {
+        "csv" => delimited_table(window, ','),
+        "tsv" => delimited_table(window, '\t'),
+        "json" | "jsonl" | "ndjson" => json_table(window),
+        _ => None,
+    }
+}
+
+fn delimited_table(data: &[u8], delim: char) -> Option<Table> {
+    let text = String::from_utf8_lossy(data);
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+    let unquote = |cell: &str| cell.trim().trim_matches('"').to_string();
+    let columns = split_delimited(lines.next()?, delim)
+        .into_iter()
+        .map(unquote)
+        .collect();
+    let rows = lines
+        .map(|line| {
+            split_delimited(line, delim)
+                .into_iter()
+                .map(unquote)
+                .collect()
+        })
+        .collect();
+    Some(Table { columns, rows })
+}
+
+fn json_table(data: &[u8]) -> Option<Table> {
+    let records: Vec<Value> = match serde_json::from_slice::<Value>(data) {
+        Ok(Value::Array(items)) => items,
+        Ok(value) => vec![value],
+        Err(_) => String::from_utf8_lossy(data)
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect(),
+    };
+    let objects: Vec<&Map<String, Value>> = records.iter().filter_map(Value::as_object).collect();
+    if objects.is_empty() {
+        return None;
+    }
+    let mut columns: Vec<String> = Vec::new();
+    for key in objects.iter().flat_map(|o| o.keys()) {
+        if !columns.contains(key) {
+            columns.push(key.clone());
+        }
+    }
+    let rows = objects
+        .iter()
+        .map(|object| {
+            columns
+                .iter()
+                .map(|column| match object.get(column) {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(value) => value.to_string(),
+                    None => String::new(),
+                })
+                .collect()
+        })
+        .collect();
+    Some(Table { columns, rows })
+}
+
+fn push_table_diff(out: &mut String, old: &Table, new: &Table) {
+    let removed: Vec<&str> = old
+        .columns
+        .iter()
+        .filter(|c| !new.columns.contains(c))
+        .map(String::as_str)
+        .collect();
+    let added: Vec<&str> = new
+        .columns
+        .iter()
+        .filter(|c| !old.columns.contains(c))
+        .map(String::as_str)
+        .collect();
+    let shared: Vec<&String> = old
+        .columns
+        .iter()
+        .filter(|c| new.columns.contains(c))
+        .collect();
+
+    out.push_str(&format!("Columns before: {}\n", old.columns.join(", ")));
+    out.push_str(&format!("Columns after: {}\n", new.columns.join(", ")));
+    if !removed.is_empty() {
+        out.push_str(&format!("Columns removed: {}\n", removed.join(", ")));
+    }
+    if !added.is_empty() {
+        out.push_str(&format!("Columns added: {}\n", added.join(", ")));
+    }
+    out.push_str(&format!(
+        "Rows: {} -> {}{}\n",
+        old.rows.len(),
+        new.rows.len(),
+        count_change(old.rows.len(), new.rows.len())
+    ));
+
+    // Rows are paired by the first column both files have.
+    if let Some(key) = shared.first() {
+        let old_keys: Vec<String> = old.rows.iter().map(|r| old.cell(r, key)).collect();
+        let new_keys: Vec<String> = new.rows.iter().map(|r| new.cell(r, key)).collect();
+        let mut old_index: HashMap<&str, usize> = HashMap::new();
+        for (i, k) in old_keys.iter().enumerate() {
+            old_index.entry(k).or_insert(i);
+        }
+        let new_index: HashSet<&str> = new_keys.iter().map(String::as_str).collect();
+        let mut changed = Vec::new();
+        for (row, row_key) in new.rows.iter().zip(&new_keys) {
+            let Some(&i) = old_index.get(row_key.as_str()) else {
+                continue;
+            };
+            let before = &old.rows[i];
+            if shared
+                .iter()
+                .any(|c| old.cell(before, c) != new.cell(row, c))
+            {
+                changed.push((before, row));
+            }
+        }
+        if !changed.is_empty() {
+            out.push_str(&format!(
+                "Changed rows, paired by '{}' ({} in total):\n",
+                key,
+                changed.len()
+            ));
+            for (before, after) in changed.iter().take(DIFF_SAMPLE_ROWS) {
+                out.push_str(&format!("  before: {}\n", old.render(before)));
+                out.push_str(&format!("  after:  {}\n", new.render(after)));
+            }
+        }
+        let gone: Vec<&Vec<String>> = old
+            .rows
+            .iter()
+            .zip(&old_keys)
+            .filter(|(_, k)| !new_index.contains(k.as_str()))
+            .map(|(row, _)| row)
+            .collect();
+        push_rows(out, "Rows only before", old, &gone);
+        let new_rows: Vec<&Vec<String>> = new
+            .rows
+            .iter()
+            .zip(&new_keys)
+            .filter(|(_, k)| !old_index.contains_key(k.as_str()))
+            .map(|(row, _)| row)
+            .collect();
+        push_rows(out, "Rows only after", new, &new_rows);
+    }
+
+    for (label, table) in [("before", old), ("after", new)] {
+        out.push_str(&format!("First rows {}:\n", label));
+        for row in table.rows.iter().take(DIFF_SAMPLE_ROWS) {
+            out.push_str(&format!("  {}\n", table.render(row)));
+        }
+    }
+}
+
+fn push_rows(out: &mut String, label: &str, table: &Table, rows: &[&Vec<String>]) {
+    if rows.is_empty() {
+        return;
+    }
+    out.push_str(&format!("{} ({} in total):\n", label, rows.len()));
+    for row in rows.iter().take(DIFF_SAMPLE_ROWS) {
+        out.push_str(&format!("  {}\n", table.render(row)));
+    }
+}
+
+fn push_line_diff(out: &mut String, before: &[u8], after: &[u8]) {
+    let before = String::from_utf8_lossy(before);
+    let after = String::from_utf8_lossy(after);
+    let old: Vec<&str> = before.lines().collect();
+    let new: Vec<&str> = after.lines().collect();
+    out.push_str(&format!(
+        "Lines: {} -> {}{}\n",
+        old.len(),
+        new.len(),
+        count_change(old.len(), new.len())
+    ));
+    for (label, lines, other) in [
+        ("Lines only before", &old, &new),
+        ("Lines only after", &new, &old),
+    ] {
+        let other: HashSet<&str> = other.iter().copied().collect();
+        let only: Vec<&&str> = lines.iter().filter(|l| !other.contains(*l)).collect();
+        if only.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("{} ({} in total):\n", label, only.len()));
+        for line in only.iter().take(DIFF_SAMPLE_ROWS) {
+            out.push_str(&format!("  {}\n", shorten(line)));
+        }
+    }
+}
+
+fn count_change(old: usize, new: usize) -> String {
+    match new.cmp(&old) {
+        std::cmp::Ordering::Equal => String::new(),
+        std::cmp::Ordering::Greater => format!(" (+{})", new - old),
+        std::cmp::Ordering::Less => format!(" (-{})", old - new),
+    }
+}
+
+fn shorten(value: &str) -> String {
+    if value.chars().count() <= DIFF_MAX_CELL_CHARS {
+        return value.to_string();
+    }
+    let cut: String = value.chars().take(DIFF_MAX_CELL_CHARS).collect();
+    format!("{}...", cut)
+}
+
+/// Restricts a CSV/TSV or JSON/NDJSON sample to the named columns or dot-separated JSON paths.
+fn project_fields(path: &Path, data: &[u8], fields: &[String]) -> Result<String> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match ext.as_str() {
+        "csv" => project_delimited(path, data, ',', fields),
+        "tsv" => project_delimited(path, data, '\t', fields),
+        "json" | "jsonl" | "ndjson" => project_json(path, data, fields),
+        _ => Err(anyhow!(
+            "--fields only supports CSV/TSV and JSON/NDJSON peek files, not {}",
+            path.display()
+        )),
+    }
+}
+
+fn project_delimited(path: &Path, data: &[u8], delim: char, fields: &[String]) -> Result<String> {
+    let sample = &data[..data.len().min(PEEK_MAX_BYTES)];
+    let text = String::from_utf8_lossy(sample);
+    let mut lines: Vec<&str> = text.lines().collect();
+    // Drop a partial trailing record cut off by the byte limit.
+    if data.len() > PEEK_MAX_BYTES && lines.len() > 1 {
+        lines.pop();
+    }
+
+    let header = lines
+        .first()
+        .ok_or_else(|| anyhow!("Peek file {} is empty", path.display()))?;
+    let columns: Vec<String> = split_delimited(header, delim)
+        .into_iter()
+        .map(|c| c.trim().trim_matches('"').to_string())
+        .collect();
+
+    let mut indices = Vec::new();
+    for field in fields {
+        let idx = columns.iter().position(|c| c == field).ok_or_else(|| {
+            anyhow!(
+                "Field '{}' not found in {}. Available columns: {}",
+                field,
+                path.display(),
+                columns.join(", ")
+            )
+        })?;
+        indices.push(idx);
+    }
+
+    let mut out = String::new();
+    for line in lines {
+        let cells = split_delimited(line, delim);
+        let selected: Vec<&str> = indices
+            .iter()
+            .map(|&i| cells.get(i).copied().unwrap_or(""))
+            .collect();
+        out.push_str(&selected.join(&delim.to_string()));
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Splits one delimited record into raw cells, keeping quoted cells intact.
+fn split_delimited(line: &str, delim: char) -> Vec<&str> {
+    let mut cells = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+
+    for (i, c) in line.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        } else if c == delim && !in_quotes {
+            cells.push(&line[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+    cells.push(&line[start..]);
+    cells
+}
+
+fn project_json(path: &Path, data: &[u8], fields: &[String]) -> Result<String> {
+    let parse_window = &data[..data.len().min(PEEK_FIELDS_MAX_PARSE_BYTES)];
+    let paths: Vec<Vec<&str>> = fields.iter().map(|f| f.split('.').collect()).collect();
+
+    if let Ok(value) = serde_json::from_slice::<Value>(parse_window) {
+        let projected = project_value(&value, &paths);
+        ensure_fields_found(path, &projected, fields)?;
+        return serde_json::to_string_pretty(&projected)
+            .context("Failed to serialize projected peek sample");
+    }
+
+    // Fall back to newline-delimited JSON, skipping a truncated last line.
+    let text = String::from_utf8_lossy(parse_window);
+    let mut out = String::new();
+    let mut any = false;
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        let Ok(value) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        let projected = project_value(&value, &paths);
+        any |= !is_empty_projection(&projected);
+        out.push_str(&serde_json::to_string(&projected)?);
+        out.push('\n');
+    }
+
+    if out.is_empty() {
+        return Err(anyhow!(
+            "Peek file {} is not valid JSON or NDJSON",
+            path.display()
+        ));
+    }
+    if !any {
+        return Err(anyhow!(
+            "None of the fields {} were found in {}",
+            fields.join(", "),
+            path.display()
+        ));
+    }
+    Ok(out)
+}
+
+fn project_value(value: &Value, paths: &[Vec<&str>]) -> Value {
+    if let Value::Array(items) = value {
+        return Value::Array(items.iter().map(|v| project_value(v, paths)).collect());
+    }
+
+    let mut out = Value::Object(Map::new());
+    for path in paths {
+        if let Some(selected) = select_path(value, path) {
+            insert_path(&mut out, path, selected);
+        }
+    }
+    out
+}
+
+fn select_path(value: &Value, path: &[&str]) -> Option<Value> {
+    let Some((head, rest)) = path.split_first() else {
+        return Some(value.clone());
+    };
+
+    match value {
+        Value::Object(map) => select_path(map.get(*head)?, rest),
+        Value::Array(items) => Some(Value::Array(
+            items.iter().filter_map(|v| select_path(v, path)).collect(),
+        )),
+        _ => None,
+    }
+}
+
+fn insert_path(target: &mut Value, path: &[&str], value: Value) {
+    let Some((head, rest)) = path.split_first() else {
+        return;
+    };
+    let Value::Object(map) = target else {
+        return;
+    };
+
+    if rest.is_empty() {
+        map.insert(head.to_string(), value);
+        return;
+    }
+
+    let child = map
+        .entry(head.to_string())
+        .or_insert_with(|| Value::Object(Map::new()));
+    if child.is_array() {
+        // Arrays were projected as a whole by select_path.
+        *child = value;
+        return;
+    }
+    insert_path(child, rest, value);
+}
+
+fn is_empty_projection(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => map.is_empty(),
+        Value::Array(items) => items.iter().all(is_empty_projection),
+        _ => false,
+    }
+}
+
+fn ensure_fields_found(path: &Path, projected: &Value, fields: &[String]) -> Result<()> {
+    if is_empty_projection(projected) {
+        return Err(anyhow!(
+            "None of the fields {} were found in {}",
+            fields.join(", "),
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -50,10 +663,119 @@ mod tests {
         let mut file = File::create(&path).unwrap();
         writeln!(file, "hello world").unwrap();
 
-        let peek = build_peek_context(&[path.to_string_lossy().to_string()])
+        let peek = build_peek_context(&[path.to_string_lossy().to_string()], &[])
             .unwrap()
             .unwrap();
         assert!(peek.contains("Sample 1"));
         assert!(peek.contains("hello world"));
     }
+
+    #[test]
+    fn files_named_in_prompt_skip_sensitive_and_missing_ones() {
+        let dir = tempdir().unwrap();
+        let log = dir.path().join("access.log.csv");
+        let env = dir.path().join(".env");
+        let extra = dir.path().join("notes.txt");
+        for path in [&log, &env, &extra] {
+            fs::write(path, "x\n").unwrap();
+        }
+        let prompt = format!(
+            "summarize columns in {}, compare with '{}' and {} and {}/missing.csv",
+            log.display(),
+            env.display(),
+            extra.display(),
+            dir.path().display()
+        );
+
+        let cfg = AutoPeekConfig::default();
+        assert_eq!(
+            files_named_in_prompt(&prompt, &cfg, &[]),
+            vec![log.clone(), extra.clone()]
+        );
+
+        let cfg = AutoPeekConfig {
+            exclude: vec!["*.txt".to_string()],
+            ..AutoPeekConfig::default()
+        };
+        assert!(files_named_in_prompt(&prompt, &cfg, &[log]).is_empty());
+    }
+
+    #[test]
+    fn peek_diff_reports_column_row_and_value_changes() {
+        let dir = tempdir().unwrap();
+        let before = dir.path().join("before.csv");
+        let after = dir.path().join("after.csv");
+        fs::write(&before, "id,name,price\n1,apple,10\n2,pear,20\n3,plum,30\n").unwrap();
+        fs::write(&after, "id,price,total\n1,10,10\n2,25,25\n").unwrap();
+
+        let diff = build_diff_context(&before, &after).unwrap();
+        assert!(diff.contains("Columns removed: name\n"));
+        assert!(diff.contains("Columns added: total\n"));
+        assert!(diff.contains("Rows: 3 -> 2 (-1)\n"));
+        assert!(diff
+            .contains("  before: id=2, name=pear, price=20\n  after:  id=2, price=25, total=25\n"));
+        assert!(diff.contains("Rows only before (1 in total):\n  id=3, name=plum, price=30\n"));
+
+        let json = dir.path().join("before.json");
+        let ndjson = dir.path().join("after.ndjson");
+        fs::write(&json, r#"[{"id": 1, "tags": ["a"]}, {"id": 2}]"#).unwrap();
+        fs::write(&ndjson, "{\"id\": 1, \"tags\": [\"a\", \"b\"]}\n").unwrap();
+        let diff = build_diff_context(&json, &ndjson).unwrap();
+        assert!(diff.contains("Rows: 2 -> 1 (-1)\n"));
+        assert!(diff.contains("  after:  id=1, tags=[\"a\",\"b\"]\n"));
+
+        let text = dir.path().join("notes.txt");
+        fs::write(&text, "a\nb\n").unwrap();
+        let diff = build_diff_context(&before, &text).unwrap();
+        assert!(diff.contains("Lines: 4 -> 2 (-2)\n"));
+        assert!(diff.contains("Lines only after (2 in total):\n  a\n  b\n"));
+    }
+
+    #[test]
+    fn fields_restrict_csv_columns() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("sales.csv");
+        fs::write(
+            &path,
+            "user_id,email,amount\n1,\"a@x.com, b\",10\n2,c@x.com,20\n",
+        )
+        .unwrap();
+
+        let fields = vec!["user_id".to_string(), "amount".to_string()];
+        let peek = build_peek_context(&[path.to_string_lossy().to_string()], &fields)
+            .unwrap()
+            .unwrap();
+        assert!(peek.contains("user_id,amount\n1,10\n2,20\n"));
+        assert!(!peek.contains("@x.com"));
+    }
+
+    #[test]
+    fn fields_project_json_paths() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("users.json");
+        fs::write(
+            &path,
+            r#"[{"user":{"id":1,"ssn":"x"},"amount":5},{"user":{"id":2,"ssn":"y"},"amount":7}]"#,
+        )
+        .unwrap();
+
+        let fields = vec!["user.id".to_string(), "amount".to_string()];
+        let peek = build_peek_context(&[path.to_string_lossy().to_string()], &fields)
+            .unwrap()
+            .unwrap();
+        assert!(peek.contains("\"id\": 1"));
+        assert!(peek.contains("\"amount\": 7"));
+        assert!(!peek.contains("ssn"));
+    }
+
+    #[test]
+    fn unknown_csv_field_lists_columns() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a.csv");
+        fs::write(&path, "a,b\n1,2\n").unwrap();
+
+        let err = build_peek_context(&[path.to_string_lossy().to_string()], &["c".to_string()])
+            .unwrap_err();
+        assert!(err.to_string().contains("Available columns: a, b"));
+    }
 }
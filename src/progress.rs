@@ -0,0 +1,125 @@
+use crate::humanize;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const FRAMES: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const TICK: Duration = Duration::from_millis(100);
+/// Maximum characters of provisional output shown next to the spinner.
+const PARTIAL_MAX_CHARS: usize = 60;
+
+/// Spinner with elapsed time shown on stderr while waiting for the provider.
+/// Streamed command tokens are shown as provisional until validated.
+/// Does nothing when stderr is not a terminal.
+pub struct Spinner {
+    partial: Arc<Mutex<String>>,
+    done: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    pub fn start(label: &str) -> Self {
+        let partial = Arc::new(Mutex::new(String::new()));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let handle = if io::stderr().is_terminal() {
+            let label = label.to_string();
+            let partial = Arc::clone(&partial);
+            let done = Arc::clone(&done);
+            Some(thread::spawn(move || {
+                let started = Instant::now();
+                let mut frame = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let text = partial.lock().map(|p| p.clone()).unwrap_or_default();
+                    let line = render_status(FRAMES[frame], &label, started.elapsed(), &text);
+                    eprint!("\r\x1b[2K{}", line);
+                    io::stderr().flush().ok();
+                    frame = (frame + 1) % FRAMES.len();
+                    thread::sleep(TICK);
+                }
+                eprint!("\r\x1b[2K");
+                io::stderr().flush().ok();
+            }))
+        } else {
+            None
+        };
+
+        Self {
+            partial,
+            done,
+            handle,
+        }
+    }
+
+    /// Appends streamed text to the provisional output.
+    pub fn push_partial(&self, token: &str) {
+        if let Ok(mut partial) = self.partial.lock() {
+            partial.push_str(token);
+        }
+    }
+
+    pub fn stop(mut self) {
+        self.finish();
+    }
+
+    fn finish(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Prints a message on its own line even while a spinner is drawing on stderr.
+pub fn note(message: &str) {
+    if io::stderr().is_terminal() {
+        eprint!("\r\x1b[2K");
+    }
+    eprintln!("{}", message);
+}
+
+fn render_status(frame: char, label: &str, elapsed: Duration, partial: &str) -> String {
+    let mut line = format!("{} {} ({})", frame, label, humanize::duration(elapsed));
+
+    let last_line = partial.lines().last().unwrap_or("").trim();
+    if !last_line.is_empty() {
+        let chars: Vec<char> = last_line.chars().collect();
+        let shown: String = if chars.len() > PARTIAL_MAX_CHARS {
+            let tail: String = chars[chars.len() - PARTIAL_MAX_CHARS..].iter().collect();
+            format!("…{}", tail)
+        } else {
+            last_line.to_string()
+        };
+        line.push_str(&format!(" [provisional] {}", shown));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_shows_elapsed_and_provisional_tail() {
+        let line = render_status('⠋', "Generating", Duration::from_millis(2300), "ls -la");
+        assert_eq!(line, "⠋ Generating (2.3s) [provisional] ls -la");
+
+        let long = "x".repeat(PARTIAL_MAX_CHARS + 10);
+        let line = render_status('⠋', "Generating", Duration::ZERO, &long);
+        assert!(line.ends_with(&format!("…{}", "x".repeat(PARTIAL_MAX_CHARS))));
+    }
+
+    #[test]
+    fn status_without_tokens_has_no_provisional_marker() {
+        let line = render_status('⠙', "Generating", Duration::from_secs(1), "");
+        assert!(!line.contains("provisional"));
+    }
+}
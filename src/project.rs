@@ -0,0 +1,181 @@
+//! Project-local tool directories: `node_modules/.bin`, `.venv/bin` and, on
+//! request, the PATH direnv would export. A pinned `jq` or `prettier` there
+//! is listed and run in preference to the one on PATH, for the tools named in
+//! `only:`.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// `project_tools:` section of the global config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectToolsConfig {
+    /// Off unless set: a checked-out repository decides what these run.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Directories looked up in the working directory and its ancestors; the
+    /// nearest match of each is used.
+    #[serde(default = "default_dirs")]
+    pub dirs: Vec<String>,
+    /// The tools that may come from project directories; required when
+    /// enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub only: Vec<String>,
+    /// Also use the PATH entries `direnv export json` adds for this directory
+    /// (for shells without the direnv hook).
+    #[serde(default)]
+    pub direnv: bool,
+}
+
+impl Default for ProjectToolsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dirs: default_dirs(),
+            only: Vec::new(),
+            direnv: false,
+        }
+    }
+}
+
+fn default_dirs() -> Vec<String> {
+    ["node_modules/.bin", ".venv/bin", ".venv/Scripts"]
+        .iter()
+        .map(|d| d.to_string())
+        .collect()
+}
+
+/// Project directories found for the working directory, searched before PATH.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProjectPath {
+    pub dirs: Vec<PathBuf>,
+    pub only: Vec<String>,
+}
+
+impl ProjectPath {
+    pub fn discover(cfg: &ProjectToolsConfig, cwd: &Path) -> Result<Self> {
+        if !cfg.enabled {
+            return Ok(Self::default());
+        }
+        if cfg.only.is_empty() {
+            bail!("project_tools.only must list the tools that may come from project directories");
+        }
+        let mut dirs: Vec<PathBuf> = cfg
+            .dirs
+            .iter()
+            .filter_map(|dir| cwd.ancestors().map(|a| a.join(dir)).find(|p| p.is_dir()))
+            .collect();
+        if cfg.direnv {
+            dirs.extend(direnv_dirs(cwd));
+        }
+        dirs.dedup();
+        Ok(Self {
+            dirs,
+            only: cfg.only.clone(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dirs.is_empty()
+    }
+
+    /// The project copy of `tool`, if there is one and `only` allows it.
+    pub fn resolve(&self, tool: &str) -> Option<PathBuf> {
+        if tool.contains(['/', '\\']) || !self.only.iter().any(|t| t == tool) {
+            return None;
+        }
+        self.dirs.iter().find_map(|dir| executable_in(dir, tool))
+    }
+}
+
+fn executable_in(dir: &Path, tool: &str) -> Option<PathBuf> {
+    let candidate = dir.join(tool);
+    if candidate.is_file() {
+        return Some(candidate);
+    }
+    if cfg!(windows) {
+        for ext in ["exe", "cmd", "bat"] {
+            let candidate = dir.join(format!("{}.{}", tool, ext));
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
+
+/// PATH entries direnv would add here that the current PATH lacks.
+fn direnv_dirs(cwd: &Path) -> Vec<PathBuf> {
+    let Ok(output) = Command::new("direnv")
+        .args(["export", "json"])
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+    else {
+        return Vec::new();
+    };
+    let current = env::var_os("PATH").unwrap_or_default();
+    new_path_entries(&String::from_utf8_lossy(&output.stdout), &current)
+}
+
+fn new_path_entries(export_json: &str, current: &OsString) -> Vec<PathBuf> {
+    let Ok(vars) = serde_json::from_str::<serde_json::Value>(export_json) else {
+        return Vec::new();
+    };
+    let Some(path) = vars.get("PATH").and_then(|p| p.as_str()) else {
+        return Vec::new();
+    };
+    let existing: Vec<PathBuf> = env::split_paths(current).collect();
+    env::split_paths(path)
+        .filter(|dir| !existing.contains(dir))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn nearest_project_dirs_are_found_and_restricted() {
+        let temp = TempDir::new().unwrap();
+        let bin = temp.path().join("node_modules/.bin");
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(bin.join("prettier"), "").unwrap();
+        let nested = temp.path().join("src/components");
+        fs::create_dir_all(&nested).unwrap();
+
+        let cfg: ProjectToolsConfig =
+            serde_yaml::from_str("enabled: true\nonly: [prettier]\n").unwrap();
+        let project = ProjectPath::discover(&cfg, &nested).unwrap();
+        assert_eq!(project.dirs, vec![bin.clone()]);
+        assert_eq!(project.resolve("prettier"), Some(bin.join("prettier")));
+        assert_eq!(project.resolve("jq"), None);
+
+        let cfg: ProjectToolsConfig = serde_yaml::from_str("enabled: true\nonly: [jq]\n").unwrap();
+        let restricted = ProjectPath::discover(&cfg, &nested).unwrap();
+        assert_eq!(restricted.resolve("prettier"), None);
+
+        let off: ProjectToolsConfig = serde_yaml::from_str("only: [prettier]\n").unwrap();
+        assert!(ProjectPath::discover(&off, &nested).unwrap().is_empty());
+        let unlisted: ProjectToolsConfig = serde_yaml::from_str("enabled: true\n").unwrap();
+        assert!(ProjectPath::discover(&unlisted, &nested).is_err());
+    }
+
+    #[test]
+    fn direnv_export_adds_only_new_path_entries() {
+        let current = env::join_paths(["/usr/bin", "/bin"]).unwrap();
+        let exported = env::join_paths(["/work/bin", "/usr/bin", "/bin"]).unwrap();
+        let json = serde_json::json!({ "PATH": exported.to_str().unwrap() }).to_string();
+        assert_eq!(
+            new_path_entries(&json, &current),
+            vec![PathBuf::from("/work/bin")]
+        );
+        assert!(new_path_entries("", &current).is_empty());
+    }
+}
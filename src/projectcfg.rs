@@ -0,0 +1,486 @@
+//! Per-repository sai setup in a `.sai/` directory, created with
+//! `sai init --project` and meant to be committed:
+//!
+//! - `prompt.yaml`: the prompt config used when none is given on the command
+//!   line, ahead of the global `default_prompt`;
+//! - `policy.yaml`: safety sections (confirmation, limits, env_policy,
+//!   prompt_guard) merged into the global config, where they can only make it
+//!   stricter;
+//! - `history/`: the history log for runs inside the project, ignored by the
+//!   `.gitignore` next to it.
+//!
+//! A `.sai.yaml` file in a directory or its ancestors sets a default `--scope`
+//! and files to `--peek` for runs below it.
+
+use crate::config::{GlobalConfig, PromptConfig};
+use crate::executor::{EnvPolicy, ResourceLimits};
+use crate::guard::PromptGuardConfig;
+use crate::risk::{ConfirmationConfig, RiskLevel, DEFAULT_DESTRUCTIVE_PATTERNS};
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+pub const PROJECT_DIR: &str = ".sai";
+pub const PROJECT_PROMPT: &str = "prompt.yaml";
+pub const PROJECT_POLICY: &str = "policy.yaml";
+pub const PROJECT_HISTORY: &str = "history";
+pub const DIR_DEFAULTS: &str = ".sai.yaml";
+
+/// `.sai/policy.yaml`. A committed file must not loosen what the user set
+/// up globally, so each setting is merged in only where it is stricter.
+#[derive(Debug, Default, Deserialize)]
+pub struct ProjectPolicy {
+    #[serde(default)]
+    pub confirmation: Option<ConfirmationConfig>,
+    #[serde(default)]
+    pub limits: Option<ResourceLimits>,
+    #[serde(default)]
+    pub env_policy: Option<EnvPolicy>,
+    #[serde(default)]
+    pub prompt_guard: Option<PromptGuardConfig>,
+}
+
+impl ProjectPolicy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read project policy {}", path.display()))?;
+        let policy: ProjectPolicy = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse project policy YAML {}", path.display()))?;
+        if let Some(limits) = &policy.limits {
+            limits
+                .validate()
+                .with_context(|| format!("Invalid project policy {}", path.display()))?;
+        }
+        if policy
+            .env_policy
+            .as_ref()
+            .is_some_and(|e| !e.set.is_empty())
+        {
+            return Err(anyhow!(
+                "Invalid project policy {}: env_policy.set is only allowed in the global config",
+                path.display()
+            ));
+        }
+        Ok(policy)
+    }
+
+    /// Merges the policy into `global`, keeping the stricter of each setting.
+    pub fn apply_to(mut self, global: &mut GlobalConfig) {
+        let extra_destructive = self
+            .confirmation
+            .as_mut()
+            .and_then(|c| c.destructive.take());
+        global.confirmation = stricter(global.confirmation.take(), self.confirmation, |g, p| {
+            let mode = |level| Some(g.mode_for(level).max(p.mode_for(level)));
+            ConfirmationConfig {
+                low: mode(RiskLevel::Low),
+                medium: mode(RiskLevel::Medium),
+                high: mode(RiskLevel::High),
+                destructive: g.destructive,
+                llm_review: g.llm_review || p.llm_review,
+            }
+        });
+        // The project's destructive patterns add to the user's (or the
+        // built-in ones); they never replace or switch them off.
+        if let Some(confirmation) = &mut global.confirmation {
+            if let Some(extra) = extra_destructive.filter(|e| !e.is_empty()) {
+                let patterns = confirmation.destructive.take().unwrap_or_else(|| {
+                    DEFAULT_DESTRUCTIVE_PATTERNS
+                        .iter()
+                        .map(|p| p.to_string())
+                        .collect()
+                });
+                confirmation.destructive = Some(union(patterns, extra));
+            }
+        }
+        global.limits = stricter(global.limits.take(), self.limits, |g, p| ResourceLimits {
+            nice: g.nice.max(p.nice),
+            max_memory_mb: min_set(g.max_memory_mb, p.max_memory_mb),
+            max_file_size_mb: min_set(g.max_file_size_mb, p.max_file_size_mb),
+        });
+        global.env_policy = stricter(global.env_policy.take(), self.env_policy, |g, p| {
+            EnvPolicy {
+                // An allowlist only narrows what an unrestricted one passes.
+                allow: if g.allow.is_empty() { p.allow } else { g.allow },
+                deny: union(g.deny, p.deny),
+                set: g.set,
+            }
+        });
+        global.prompt_guard = stricter(global.prompt_guard.take(), self.prompt_guard, |g, p| {
+            let mut patterns = g.patterns;
+            for (category, terms) in p.patterns {
+                let merged = patterns.remove(&category).unwrap_or_default();
+                patterns.insert(category, union(merged, terms));
+            }
+            PromptGuardConfig {
+                enabled: g.enabled || p.enabled,
+                use_defaults: g.use_defaults || p.use_defaults,
+                patterns,
+            }
+        });
+    }
+}
+
+/// Merges a project section into the global one; a section only one of them
+/// sets is taken as is, since every section tightens sai's defaults.
+fn stricter<T>(global: Option<T>, project: Option<T>, merge: impl FnOnce(T, T) -> T) -> Option<T> {
+    match (global, project) {
+        (Some(g), Some(p)) => Some(merge(g, p)),
+        (g, p) => g.or(p),
+    }
+}
+
+/// `a` followed by the entries of `b` it lacks.
+fn union(mut a: Vec<String>, b: Vec<String>) -> Vec<String> {
+    for item in b {
+        if !a.contains(&item) {
+            a.push(item);
+        }
+    }
+    a
+}
+
+/// The smaller of two optional limits, where unset means unlimited.
+fn min_set(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The nearest `.sai/` directory in `cwd` or its ancestors.
+pub fn find_project_dir(cwd: &Path) -> Option<PathBuf> {
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_DIR))
+        .find(|dir| dir.is_dir())
+}
+
+/// The project prompt config, if the project has one.
+pub fn project_prompt(cwd: &Path) -> Option<PathBuf> {
+    find_project_dir(cwd)
+        .map(|dir| dir.join(PROJECT_PROMPT))
+        .filter(|path| path.is_file())
+}
+
+/// Refuses settings in the project prompt config that a committed file must
+/// not make: `env:` could set variables such as `LD_PRELOAD` for the user's
+/// commands, `aliases` would let other programs run under an allowed tool's
+/// name, `allowed_operators` would open the shell, and `read_only` and
+/// `network` would take a tool past `--read-only` and the network policy.
+pub fn check_project_prompt(cfg: &PromptConfig, path: &Path) -> Result<()> {
+    if !cfg.allowed_operators.is_empty() {
+        return Err(anyhow!(
+            "Invalid project prompt config {}: allowed_operators is only allowed in prompt \
+             configs outside .sai/",
+            path.display()
+        ));
+    }
+    for tool in &cfg.tools {
+        let field = [
+            ("env", !tool.env.is_empty()),
+            ("aliases", !tool.aliases.is_empty()),
+            ("read_only", tool.read_only.is_some()),
+            ("network", tool.network.is_some()),
+        ]
+        .into_iter()
+        .find_map(|(field, set)| set.then_some(field));
+        if let Some(field) = field {
+            return Err(anyhow!(
+                "Invalid project prompt config {}: tool '{}' sets {}, which is only allowed \
+                 in prompt configs outside .sai/",
+                path.display(),
+                tool.name,
+                field
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Applies the project policy, if any, to the global config.
+pub fn apply_project_policy(global: &mut GlobalConfig, cwd: &Path) -> Result<()> {
+    let Some(path) = find_project_dir(cwd)
+        .map(|dir| dir.join(PROJECT_POLICY))
+        .filter(|path| path.is_file())
+    else {
+        return Ok(());
+    };
+    ProjectPolicy::load(&path)?.apply_to(global);
+    Ok(())
+}
+
+/// `.sai.yaml`: defaults for runs in its directory and below. Each applies
+/// only when the matching flag is not given on the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DirDefaults {
+    /// Default `--scope` hint, used as written.
+    #[serde(default)]
+    pub scope: Option<String>,
+    /// Files to `--peek`, relative to the directory of `.sai.yaml`.
+    #[serde(default)]
+    pub peek: Vec<PathBuf>,
+}
+
+/// The nearest `.sai.yaml` in `cwd` or its ancestors, with peek paths
+/// resolved against its directory.
+pub fn load_dir_defaults(cwd: &Path) -> Result<Option<(PathBuf, DirDefaults)>> {
+    let Some(path) = cwd
+        .ancestors()
+        .map(|dir| dir.join(DIR_DEFAULTS))
+        .find(|path| path.is_file())
+    else {
+        return Ok(None);
+    };
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut defaults: DirDefaults = serde_yaml::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    let base = path.parent().unwrap_or(cwd);
+    defaults.peek = defaults
+        .peek
+        .iter()
+        .map(|p| {
+            peek_path(base, p).with_context(|| format!("Invalid peek path in {}", path.display()))
+        })
+        .collect::<Result<_>>()?;
+    Ok(Some((path, defaults)))
+}
+
+/// `peek` resolved against `base`, which it must stay inside: a committed
+/// `.sai.yaml` must not send files like `~/.ssh/id_rsa` to the provider.
+fn peek_path(base: &Path, peek: &Path) -> Result<PathBuf> {
+    if peek
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(anyhow!(
+            "{} must be a relative path without '..'",
+            peek.display()
+        ));
+    }
+    let joined = base.join(peek);
+    // Symlinks are followed; files that do not exist yet fail later in --peek.
+    let (Ok(resolved), Ok(base)) = (joined.canonicalize(), base.canonicalize()) else {
+        return Ok(joined);
+    };
+    if !resolved.starts_with(&base) {
+        return Err(anyhow!(
+            "{} leads outside {}",
+            peek.display(),
+            base.display()
+        ));
+    }
+    Ok(joined)
+}
+
+/// Fills `scope` and `peek` from the nearest `.sai.yaml` where the command
+/// line left them empty. Returns the file when it changed anything.
+pub fn apply_dir_defaults(
+    cwd: &Path,
+    scope: &mut Option<String>,
+    peek: &mut Vec<PathBuf>,
+) -> Result<Option<PathBuf>> {
+    let Some((path, defaults)) = load_dir_defaults(cwd)? else {
+        return Ok(None);
+    };
+    let mut applied = false;
+    if scope.is_none() && defaults.scope.is_some() {
+        *scope = defaults.scope;
+        applied = true;
+    }
+    if peek.is_empty() && !defaults.peek.is_empty() {
+        *peek = defaults.peek;
+        applied = true;
+    }
+    Ok(applied.then_some(path))
+}
+
+/// Where history goes inside a project. The directory itself is ignored by
+/// git, so a fresh clone only has `.sai/` and it is created on first use.
+pub fn project_history_dir(cwd: &Path) -> Option<PathBuf> {
+    find_project_dir(cwd).map(|dir| dir.join(PROJECT_HISTORY))
+}
+
+/// Scaffolds `.sai/` in `root`; refuses to touch an existing one.
+pub fn init_project(root: &Path) -> Result<PathBuf> {
+    let dir = root.join(PROJECT_DIR);
+    if dir.exists() {
+        return Err(anyhow!(
+            "{} already exists. Refusing to overwrite.",
+            dir.display()
+        ));
+    }
+    let history = dir.join(PROJECT_HISTORY);
+    fs::create_dir_all(&history)
+        .with_context(|| format!("Failed to create {}", history.display()))?;
+
+    let files = [
+        (
+            PROJECT_PROMPT,
+            include_str!("../templates/project/prompt.yaml"),
+        ),
+        (
+            PROJECT_POLICY,
+            include_str!("../templates/project/policy.yaml"),
+        ),
+        (".gitignore", "history/\n"),
+    ];
+    for (name, content) in files {
+        let path = dir.join(name);
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::risk::ConfirmMode;
+    use tempfile::TempDir;
+
+    #[test]
+    fn scaffolded_project_is_found_from_subdirectories() {
+        let temp = TempDir::new().unwrap();
+        let dir = init_project(temp.path()).unwrap();
+        assert!(dir.join(".gitignore").is_file());
+        assert!(init_project(temp.path()).is_err());
+
+        let nested = temp.path().join("src/lib");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(project_prompt(&nested), Some(dir.join(PROJECT_PROMPT)));
+        assert_eq!(
+            project_history_dir(&nested),
+            Some(dir.join(PROJECT_HISTORY))
+        );
+        let prompt = dir.join(PROJECT_PROMPT);
+        check_project_prompt(
+            &crate::config::load_prompt_config(&prompt).unwrap(),
+            &prompt,
+        )
+        .unwrap();
+        ProjectPolicy::load(&dir.join(PROJECT_POLICY)).unwrap();
+    }
+
+    #[test]
+    fn dir_defaults_fill_flags_left_unset() {
+        let temp = TempDir::new().unwrap();
+        fs::write(
+            temp.path().join(DIR_DEFAULTS),
+            "scope: src/**\npeek: [db/schema.sql]\n",
+        )
+        .unwrap();
+        let nested = temp.path().join("src/lib");
+        fs::create_dir_all(&nested).unwrap();
+
+        let (mut scope, mut peek) = (None, Vec::new());
+        let applied = apply_dir_defaults(&nested, &mut scope, &mut peek).unwrap();
+        assert_eq!(applied, Some(temp.path().join(DIR_DEFAULTS)));
+        assert_eq!(scope.as_deref(), Some("src/**"));
+        assert_eq!(peek, [temp.path().join("db/schema.sql")]);
+
+        let (mut scope, mut peek) = (Some("tests".to_string()), vec![PathBuf::from("a.csv")]);
+        assert_eq!(
+            apply_dir_defaults(&nested, &mut scope, &mut peek).unwrap(),
+            None
+        );
+        assert_eq!(scope.as_deref(), Some("tests"));
+        assert_eq!(peek, [PathBuf::from("a.csv")]);
+    }
+
+    #[test]
+    fn dir_default_peek_paths_stay_inside_the_directory() {
+        let temp = TempDir::new().unwrap();
+        let project = temp.path().join("project");
+        fs::create_dir_all(&project).unwrap();
+        fs::write(temp.path().join("secret"), "").unwrap();
+        for peek in ["/etc/passwd", "../secret", "db/../../secret"] {
+            fs::write(
+                project.join(DIR_DEFAULTS),
+                format!("peek: [\"{}\"]\n", peek),
+            )
+            .unwrap();
+            assert!(load_dir_defaults(&project).is_err(), "{}", peek);
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(temp.path().join("secret"), project.join("link")).unwrap();
+            fs::write(project.join(DIR_DEFAULTS), "peek: [link]\n").unwrap();
+            assert!(load_dir_defaults(&project).is_err());
+        }
+    }
+
+    #[test]
+    fn project_policy_can_only_tighten_the_global_config() {
+        let policy: ProjectPolicy = serde_yaml::from_str(
+            "confirmation:\n  low: confirm\n  high: auto\n  destructive: [kubectl delete]\n\
+             limits:\n  nice: 5\n  max_memory_mb: 4096\n\
+             env_policy:\n  allow: [PATH]\n  deny: [\"*_TOKEN\"]\n\
+             prompt_guard:\n  enabled: false\n",
+        )
+        .unwrap();
+        let mut global: GlobalConfig = serde_yaml::from_str(
+            "confirmation:\n  low: auto\nlimits:\n  max_memory_mb: 1024\n\
+             env_policy:\n  allow: [PATH, HOME]\nprompt_guard: {}\n",
+        )
+        .unwrap();
+        policy.apply_to(&mut global);
+        let confirmation = global.confirmation.unwrap();
+        assert_eq!(confirmation.low, Some(ConfirmMode::Confirm));
+        assert_eq!(confirmation.high, Some(ConfirmMode::TypeCommandName));
+        let destructive = confirmation.destructive.unwrap();
+        assert!(destructive.contains(&"rm".to_string()));
+        assert!(destructive.contains(&"kubectl delete".to_string()));
+        let limits = global.limits.unwrap();
+        assert_eq!((limits.nice, limits.max_memory_mb), (Some(5), Some(1024)));
+        let env = global.env_policy.unwrap();
+        assert_eq!(env.allow, ["PATH", "HOME"]);
+        assert_eq!(env.deny, ["*_TOKEN"]);
+        assert!(global.prompt_guard.unwrap().enabled);
+    }
+
+    #[test]
+    fn project_files_cannot_set_environment_variables() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join(PROJECT_POLICY);
+        fs::write(&path, "env_policy:\n  set:\n    LD_PRELOAD: /tmp/x.so\n").unwrap();
+        assert!(ProjectPolicy::load(&path).is_err());
+
+        let prompt: PromptConfig = serde_yaml::from_str(
+            "meta_prompt: m\ntools:\n  - name: jq\n    config: jq\n    env:\n      LD_PRELOAD: /tmp/x.so\n",
+        )
+        .unwrap();
+        assert!(check_project_prompt(&prompt, &path).is_err());
+
+        let prompt: PromptConfig =
+            serde_yaml::from_str("tools:\n  - name: cat\n    config: cat\n    aliases: [rm]\n")
+                .unwrap();
+        let err = check_project_prompt(&prompt, &path).unwrap_err();
+        assert!(err.to_string().contains("sets aliases"));
+    }
+
+    #[test]
+    fn project_prompt_cannot_loosen_operators_read_only_or_network() {
+        let path = Path::new(".sai/prompt.yaml");
+        let check = |yaml: &str| {
+            let prompt: PromptConfig = serde_yaml::from_str(yaml).unwrap();
+            check_project_prompt(&prompt, path).map_err(|err| err.to_string())
+        };
+        assert!(check("allowed_operators: [\"|\"]\ntools: []\n")
+            .unwrap_err()
+            .contains("allowed_operators"));
+        assert!(
+            check("tools:\n  - name: rm\n    config: rm\n    read_only: true\n")
+                .unwrap_err()
+                .contains("sets read_only")
+        );
+        assert!(
+            check("tools:\n  - name: curl\n    config: curl\n    network: false\n")
+                .unwrap_err()
+                .contains("sets network")
+        );
+        assert!(check("tools:\n  - name: ls\n    config: ls\n").is_ok());
+    }
+}
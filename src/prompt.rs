@@ -1,4 +1,6 @@
 use crate::config::{PromptConfig, ToolConfig};
+use crate::envinfo;
+use crate::template;
 use anyhow::{anyhow, Result};
 
 pub fn build_system_prompt(prompt_cfg: &PromptConfig) -> Result<(String, Vec<String>)> {
@@ -8,6 +10,7 @@ pub fn build_system_prompt(prompt_cfg: &PromptConfig) -> Result<(String, Vec<Str
         ));
     }
 
+    template::check_tool_templates(&prompt_cfg.tools)?;
     let meta_prompt = prompt_cfg.meta_prompt.clone().unwrap_or_default();
 
     let mut allowed_names = Vec::new();
@@ -20,7 +23,29 @@ pub fn build_system_prompt(prompt_cfg: &PromptConfig) -> Result<(String, Vec<Str
             ));
         }
         allowed_names.push(tool.name.clone());
-        tool_texts.push(tool.config.clone());
+        let mut text = match template::describe(tool) {
+            Some(template_text) => format!("{}\n{}", tool.config, template_text),
+            None => tool.config.clone(),
+        };
+        if !tool.allowed_flags.is_empty() {
+            text.push_str(&format!(
+                "\nOnly these flags are accepted: {}",
+                tool.allowed_flags.join(" ")
+            ));
+        }
+        if !tool.denied_flags.is_empty() {
+            text.push_str(&format!(
+                "\nNever use these flags: {}",
+                tool.denied_flags.join(" ")
+            ));
+        }
+        if let Some(chdir) = &tool.chdir {
+            text.push_str(&format!(
+                "\nRuns in the {} subdirectory; its relative paths start there.",
+                chdir
+            ));
+        }
+        tool_texts.push(text);
     }
 
     let mut tools_listing = String::from("You may ONLY use the following tools:\n");
@@ -34,11 +59,30 @@ pub fn build_system_prompt(prompt_cfg: &PromptConfig) -> Result<(String, Vec<Str
     }
     system_parts.push(tools_listing);
     system_parts.push(format!("\nTool details:\n\n{}", tool_texts.join("\n\n")));
+    if !prompt_cfg.allowed_operators.is_empty() {
+        system_parts.push(format!(
+            "These shell operators may be used: {}. Every command they connect must \
+             use one of the tools above; no other shell operators are allowed.",
+            prompt_cfg.allowed_operators.join(" ")
+        ));
+    }
+    if let Some(program) = envinfo::powershell_program() {
+        system_parts.push(powershell_note(program));
+    }
 
     let full_prompt = system_parts.join("\n\n").trim().to_string();
     Ok((full_prompt, allowed_names))
 }
 
+fn powershell_note(program: &str) -> String {
+    format!(
+        "The user is in PowerShell ({}) on Windows. Write the command in PowerShell \
+         syntax: $env:NAME for environment variables, single quotes for literal \
+         strings, backtick escapes; not cmd.exe or POSIX sh syntax.",
+        program
+    )
+}
+
 /// Checks if the generated command uses a tool that requires forced explain mode.
 /// Returns true if the first token of the command matches a tool with force_explain set to true.
 pub fn should_force_explain(tools: &[ToolConfig], command: &str) -> bool {
@@ -46,5 +90,5 @@ pub fn should_force_explain(tools: &[ToolConfig], command: &str) -> bool {
 
     tools
         .iter()
-        .any(|t| t.name == first_token && t.force_explain == Some(true))
+        .any(|t| t.matches(first_token) && t.force_explain == Some(true))
 }
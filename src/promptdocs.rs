@@ -0,0 +1,314 @@
+//! Human-readable documentation rendered from a prompt config, so teams can
+//! check in a description of what a shared prompt allows next to the YAML.
+
+use crate::config::{load_global_config, load_prompt_config, PromptConfig, ToolConfig};
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use std::fmt::Write as _;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DocsFormat {
+    /// Markdown with headings, lists and code blocks
+    Md,
+    /// Plain text
+    Text,
+}
+
+/// Section headings whose items are command shapes, rendered as code.
+const CODE_SECTIONS: &[&str] = &["output format", "example", "examples", "usage"];
+
+const FORCE_EXPLAIN_NOTE: &str = "always explained and confirmed before running";
+
+/// A tool config split into the "Tool:/Role:/Rules:" layout the templates use.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ToolDoc {
+    title: Option<String>,
+    role: Option<String>,
+    /// Heading and items of each list section, in order of appearance.
+    sections: Vec<(String, Vec<String>)>,
+}
+
+/// Prints documentation for `prompt_path`, or for the global default prompt.
+pub fn print_prompt_docs(
+    global_config_path: &Path,
+    prompt_path: Option<&str>,
+    format: DocsFormat,
+) -> Result<()> {
+    let (title, prompt) = match prompt_path {
+        Some(path) => {
+            let path = Path::new(path);
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.display().to_string());
+            (name, load_prompt_config(path)?)
+        }
+        None => {
+            let global = load_global_config(global_config_path)?;
+            let prompt = global.default_prompt.ok_or_else(|| {
+                anyhow!(
+                    "No default_prompt in {}; pass a prompt config file",
+                    global_config_path.display()
+                )
+            })?;
+            ("default prompt".to_string(), prompt)
+        }
+    };
+
+    print!("{}", render_prompt_docs(&title, &prompt, format));
+    Ok(())
+}
+
+pub fn render_prompt_docs(title: &str, prompt: &PromptConfig, format: DocsFormat) -> String {
+    let mut out = String::new();
+    let md = format == DocsFormat::Md;
+
+    heading(&mut out, md, 1, &format!("Prompt: {}", title));
+    let names: Vec<&str> = prompt.tools.iter().map(|t| t.name.as_str()).collect();
+    if names.is_empty() {
+        out.push_str("No tools are allowed.\n\n");
+    } else {
+        let list = if md {
+            names
+                .iter()
+                .map(|n| format!("`{}`", n))
+                .collect::<Vec<_>>()
+                .join(", ")
+        } else {
+            names.join(", ")
+        };
+        let _ = writeln!(out, "Allowed tools: {}\n", list);
+    }
+
+    heading(&mut out, md, 2, "Meta prompt");
+    match prompt.meta_prompt.as_deref().map(str::trim) {
+        Some(meta) if !meta.is_empty() => code_block(&mut out, md, meta.lines()),
+        _ => out.push_str("None; only the tool descriptions are sent.\n\n"),
+    }
+
+    heading(&mut out, md, 2, "Tools");
+    for tool in &prompt.tools {
+        render_tool(&mut out, md, tool);
+    }
+
+    while out.ends_with("\n\n") {
+        out.pop();
+    }
+    out
+}
+
+fn render_tool(out: &mut String, md: bool, tool: &ToolConfig) {
+    heading(out, md, 3, &tool.name);
+    let Some(doc) = parse_tool_config(&tool.config) else {
+        if tool.force_explain == Some(true) {
+            field(out, md, "Explain", FORCE_EXPLAIN_NOTE);
+        }
+        if let Some(template) = &tool.template {
+            field(out, md, "Template", template);
+        }
+        if tool.force_explain == Some(true) || tool.template.is_some() {
+            out.push('\n');
+        }
+        code_block(out, md, tool.config.trim().lines());
+        return;
+    };
+
+    if let Some(title) = doc.title.as_deref().filter(|t| *t != tool.name) {
+        field(out, md, "Tool", title);
+    }
+    if let Some(role) = &doc.role {
+        field(out, md, "Role", role);
+    }
+    if tool.force_explain == Some(true) {
+        field(out, md, "Explain", FORCE_EXPLAIN_NOTE);
+    }
+    if let Some(template) = &tool.template {
+        field(out, md, "Template", template);
+    }
+    if doc.title.is_some()
+        || doc.role.is_some()
+        || tool.force_explain == Some(true)
+        || tool.template.is_some()
+    {
+        out.push('\n');
+    }
+
+    for (name, items) in &doc.sections {
+        if md {
+            let _ = writeln!(out, "**{}:**\n", name);
+        } else {
+            let _ = writeln!(out, "{}:", name);
+        }
+        if CODE_SECTIONS.contains(&name.to_lowercase().as_str()) {
+            code_block(out, md, items.iter().map(String::as_str));
+        } else {
+            for item in items {
+                let _ = writeln!(out, "- {}", item);
+            }
+            out.push('\n');
+        }
+    }
+}
+
+/// Splits a tool config into title, role and list sections. Returns `None` for
+/// free-form configs without any "Label:" line; those are shown verbatim.
+fn parse_tool_config(config: &str) -> Option<ToolDoc> {
+    let mut doc = ToolDoc::default();
+    let mut structured = false;
+
+    for line in config.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(item) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+            push_item(&mut doc, item.trim());
+            continue;
+        }
+
+        match line.split_once(':') {
+            Some((label, value)) if is_label(label) => {
+                structured = true;
+                let value = value.trim();
+                match label.to_lowercase().as_str() {
+                    "tool" if !value.is_empty() => doc.title = Some(value.to_string()),
+                    "role" if !value.is_empty() => doc.role = Some(value.to_string()),
+                    _ => {
+                        doc.sections.push((label.to_string(), Vec::new()));
+                        if !value.is_empty() {
+                            push_item(&mut doc, value);
+                        }
+                    }
+                }
+            }
+            _ => push_item(&mut doc, line),
+        }
+    }
+
+    structured.then_some(doc)
+}
+
+/// Appends to the current section, opening a "Notes" section if there is none.
+fn push_item(doc: &mut ToolDoc, item: &str) {
+    if doc.sections.is_empty() {
+        doc.sections.push(("Notes".to_string(), Vec::new()));
+    }
+    if let Some((_, items)) = doc.sections.last_mut() {
+        items.push(item.to_string());
+    }
+}
+
+/// A label is a short capitalized phrase like "Rules" or "Output format".
+fn is_label(label: &str) -> bool {
+    let words = label.split_whitespace().count();
+    (1..=3).contains(&words)
+        && label.starts_with(char::is_uppercase)
+        && label.chars().all(|c| c.is_alphanumeric() || c == ' ')
+}
+
+fn heading(out: &mut String, md: bool, level: usize, text: &str) {
+    if md {
+        let _ = writeln!(out, "{} {}\n", "#".repeat(level), text);
+    } else {
+        let underline = if level == 1 { '=' } else { '-' };
+        let _ = writeln!(
+            out,
+            "{}\n{}\n",
+            text,
+            underline.to_string().repeat(text.chars().count())
+        );
+    }
+}
+
+fn field(out: &mut String, md: bool, name: &str, value: &str) {
+    if md {
+        let _ = writeln!(out, "**{}:** {}  ", name, value);
+    } else {
+        let _ = writeln!(out, "{}: {}", name, value);
+    }
+}
+
+fn code_block<'a>(out: &mut String, md: bool, lines: impl Iterator<Item = &'a str>) {
+    if md {
+        out.push_str("```text\n");
+    }
+    for line in lines {
+        if md {
+            let _ = writeln!(out, "{}", line);
+        } else {
+            let _ = writeln!(out, "    {}", line);
+        }
+    }
+    if md {
+        out.push_str("```\n");
+    }
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool(name: &str, config: &str) -> ToolConfig {
+        ToolConfig {
+            name: name.to_string(),
+            force_explain: None,
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
+            config: config.to_string(),
+        }
+    }
+
+    #[test]
+    fn template_layout_is_split_into_sections() {
+        let doc = parse_tool_config(
+            "Tool: ripgrep (rg)\nRole: fast search.\nRules:\n- Start with \"rg\".\n- No pipes.\nOutput format:\n- rg 'pattern' [path]\n",
+        )
+        .unwrap();
+        assert_eq!(doc.title.as_deref(), Some("ripgrep (rg)"));
+        assert_eq!(doc.role.as_deref(), Some("fast search."));
+        assert_eq!(
+            doc.sections,
+            vec![
+                (
+                    "Rules".to_string(),
+                    vec!["Start with \"rg\".".to_string(), "No pipes.".to_string()]
+                ),
+                (
+                    "Output format".to_string(),
+                    vec!["rg 'pattern' [path]".to_string()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn free_form_config_is_not_structured() {
+        assert_eq!(parse_tool_config("list directory contents"), None);
+    }
+
+    #[test]
+    fn markdown_lists_meta_prompt_tools_and_examples() {
+        let prompt = PromptConfig {
+            meta_prompt: Some("Output one command.".to_string()),
+            tools: vec![
+                tool("jq", "Tool: jq\nRole: filter JSON.\nRules:\n- Single filter.\nExamples:\n- jq '.name' data.json\n"),
+                tool("ls", "list directory contents"),
+            ],
+            allowed_operators: Vec::new(),
+        };
+        let md = render_prompt_docs("team.yaml", &prompt, DocsFormat::Md);
+
+        assert!(md.starts_with("# Prompt: team.yaml\n\nAllowed tools: `jq`, `ls`\n"));
+        assert!(md.contains("## Meta prompt\n\n```text\nOutput one command.\n```"));
+        assert!(md.contains("### jq\n\n**Role:** filter JSON.  \n"));
+        assert!(md.contains("**Rules:**\n\n- Single filter.\n"));
+        assert!(md.contains("**Examples:**\n\n```text\njq '.name' data.json\n```"));
+        assert!(md.contains("### ls\n\n```text\nlist directory contents\n```"));
+    }
+}
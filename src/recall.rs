@@ -0,0 +1,277 @@
+//! `sai history find QUERY`: past runs ranked by how well their prompt and
+//! generated command match the query. Ranking is BM25 over word stems, so
+//! "deleting temp files" finds "delete tmp file" runs without any API call.
+//! `sai history search QUERY` is the literal variant: runs whose prompt,
+//! command or working directory contain the query, as a table.
+
+use crate::cli::Cli;
+use crate::history::{self, HistoryEntry};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// BM25 term frequency saturation.
+const BM25_K1: f64 = 1.2;
+/// BM25 document length normalization.
+const BM25_B: f64 = 0.75;
+
+/// One ranked past run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    pub entry: &'a HistoryEntry,
+    pub prompt: Option<String>,
+    pub score: f64,
+}
+
+/// The natural language prompt of a recorded run, recovered from its argv.
+pub fn entry_prompt(entry: &HistoryEntry) -> Option<String> {
+    Cli::try_parse_from(&entry.argv)
+        .ok()
+        .and_then(|cli| cli.prompt_and_configs().1)
+}
+
+/// Entries with a prompt or command, best match first; entries sharing no
+/// term with the query are left out.
+pub fn find_runs<'a>(entries: &'a [HistoryEntry], query: &str, limit: usize) -> Vec<Match<'a>> {
+    let query_terms: BTreeSet<String> = terms(query).into_iter().collect();
+    let docs: Vec<(&HistoryEntry, Option<String>, Vec<String>)> = entries
+        .iter()
+        .filter(|e| e.generated_command.is_some())
+        .map(|entry| {
+            let prompt = entry_prompt(entry);
+            let text = format!(
+                "{} {}",
+                prompt.as_deref().unwrap_or(""),
+                entry.generated_command.as_deref().unwrap_or("")
+            );
+            (entry, prompt, terms(&text))
+        })
+        .collect();
+    if docs.is_empty() || query_terms.is_empty() {
+        return Vec::new();
+    }
+
+    let avg_len = docs.iter().map(|(_, _, t)| t.len()).sum::<usize>() as f64 / docs.len() as f64;
+    let mut doc_freq: BTreeMap<&str, usize> = BTreeMap::new();
+    for (_, _, doc_terms) in &docs {
+        let unique: BTreeSet<&str> = doc_terms.iter().map(String::as_str).collect();
+        for term in unique {
+            *doc_freq.entry(term).or_default() += 1;
+        }
+    }
+
+    let n = docs.len() as f64;
+    let mut matches: Vec<Match> = docs
+        .iter()
+        .map(|(entry, prompt, doc_terms)| {
+            let len = doc_terms.len() as f64;
+            let score = query_terms
+                .iter()
+                .map(|term| {
+                    let tf = doc_terms.iter().filter(|t| *t == term).count() as f64;
+                    if tf == 0.0 {
+                        return 0.0;
+                    }
+                    let df = doc_freq.get(term.as_str()).copied().unwrap_or(0) as f64;
+                    let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+                    idf * tf * (BM25_K1 + 1.0)
+                        / (tf + BM25_K1 * (1.0 - BM25_B + BM25_B * len / avg_len.max(1.0)))
+                })
+                .sum();
+            Match {
+                entry,
+                prompt: prompt.clone(),
+                score,
+            }
+        })
+        .filter(|m| m.score > 0.0)
+        .collect();
+    // Newer runs first among equal scores.
+    matches.reverse();
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    matches.truncate(limit);
+    matches
+}
+
+/// Lowercased word stems; single characters and common plural/verb endings
+/// are dropped so "files"/"file" and "deleting"/"delete" meet.
+fn terms(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1)
+        .map(|word| stem(&word.to_lowercase()))
+        .collect()
+}
+
+fn stem(word: &str) -> String {
+    for suffix in ["ing", "ed", "es", "s", "e"] {
+        if let Some(base) = word.strip_suffix(suffix) {
+            if base.chars().count() >= 3 {
+                return base.to_string();
+            }
+        }
+    }
+    word.to_string()
+}
+
+/// Prints the best matches for `sai history find`.
+pub fn print_matches(query: &str, limit: usize) -> Result<()> {
+    let entries = history::read_all_entries()?;
+    let matches = find_runs(&entries, query, limit);
+    if matches.is_empty() {
+        return Err(anyhow!("No past runs match '{}'", query));
+    }
+    for m in matches {
+        let ts = m
+            .entry
+            .ts
+            .get(..16)
+            .unwrap_or(&m.entry.ts)
+            .replace('T', " ");
+        println!(
+            "{}  [exit {}]  {}",
+            ts,
+            m.entry.exit_code,
+            m.prompt.as_deref().unwrap_or("(prompt not recorded)")
+        );
+        println!(
+            "    {}",
+            m.entry.generated_command.as_deref().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// Entries whose prompt, generated command or working directory contain
+/// `query` (ignoring case), newest first, with their prompts.
+pub fn search_runs<'a>(
+    entries: &'a [HistoryEntry],
+    query: &str,
+    limit: usize,
+) -> Vec<(&'a HistoryEntry, Option<String>)> {
+    let query = query.to_lowercase();
+    entries
+        .iter()
+        .rev()
+        .map(|entry| (entry, entry_prompt(entry)))
+        .filter(|(entry, prompt)| {
+            [
+                prompt.as_deref(),
+                entry.generated_command.as_deref(),
+                Some(entry.cwd.as_str()),
+            ]
+            .into_iter()
+            .flatten()
+            .any(|field| field.to_lowercase().contains(&query))
+        })
+        .take(limit)
+        .collect()
+}
+
+/// `text` cut to `width` characters, ending in `…` when shortened.
+pub(crate) fn fit(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(width - 1).collect();
+    cut.push('…');
+    cut
+}
+
+/// The `sai history search` table.
+pub fn format_search(rows: &[(&HistoryEntry, Option<String>)]) -> String {
+    const CWD_WIDTH: usize = 24;
+    const PROMPT_WIDTH: usize = 32;
+    let mut out = format!(
+        "{:<16}  {:>4}  {:<CWD_WIDTH$}  {:<PROMPT_WIDTH$}  {}\n",
+        "Time", "Exit", "Directory", "Prompt", "Command"
+    );
+    for (entry, prompt) in rows {
+        let ts = entry.ts.get(..16).unwrap_or(&entry.ts).replace('T', " ");
+        out.push_str(&format!(
+            "{:<16}  {:>4}  {:<CWD_WIDTH$}  {:<PROMPT_WIDTH$}  {}\n",
+            ts,
+            entry.exit_code,
+            fit(&entry.cwd, CWD_WIDTH),
+            fit(prompt.as_deref().unwrap_or("-"), PROMPT_WIDTH),
+            entry.generated_command.as_deref().unwrap_or("-")
+        ));
+    }
+    out
+}
+
+/// Prints the table for `sai history search`.
+pub fn print_search(query: &str, limit: usize) -> Result<()> {
+    let entries = history::read_all_entries()?;
+    let rows = search_runs(&entries, query, limit);
+    if rows.is_empty() {
+        return Err(anyhow!("No past runs contain '{}'", query));
+    }
+    print!("{}", format_search(&rows));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(prompt: &str, command: &str) -> HistoryEntry {
+        HistoryEntry {
+            ts: "2024-05-01T10:00:00Z".to_string(),
+            cwd: "/tmp".to_string(),
+            argv: vec!["sai".to_string(), "-c".to_string(), prompt.to_string()],
+            generated_command: Some(command.to_string()),
+            confirm: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn runs_are_ranked_by_prompt_and_command_terms() {
+        let entries = vec![
+            entry("delete temp files", "find /tmp -name '*.tmp' -delete"),
+            entry(
+                "sum the third column",
+                "awk '{s+=$3} END {print s}' data.txt",
+            ),
+            entry("list files", "ls -la"),
+        ];
+        assert_eq!(
+            entry_prompt(&entries[0]).as_deref(),
+            Some("delete temp files")
+        );
+
+        let found = find_runs(&entries, "deleting tmp file", 5);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].prompt.as_deref(), Some("delete temp files"));
+
+        let found = find_runs(&entries, "that awk column thing", 5);
+        assert_eq!(
+            found[0].entry.generated_command.as_deref(),
+            Some("awk '{s+=$3} END {print s}' data.txt")
+        );
+        assert!(find_runs(&entries, "kubernetes", 5).is_empty());
+    }
+
+    #[test]
+    fn search_matches_prompt_command_or_directory_newest_first() {
+        let mut entries = vec![
+            entry("delete temp files", "find /tmp -name '*.tmp' -delete"),
+            entry("list files", "ls -la"),
+            entry("count lines", "wc -l notes.txt"),
+        ];
+        entries[2].cwd = "/home/me/projects/website-redesign".to_string();
+
+        let rows = search_runs(&entries, "FILES", 10);
+        let prompts: Vec<_> = rows.iter().map(|(_, p)| p.as_deref()).collect();
+        assert_eq!(prompts, [Some("list files"), Some("delete temp files")]);
+        assert_eq!(search_runs(&entries, "-delete", 10).len(), 1);
+        assert_eq!(search_runs(&entries, "FILES", 1).len(), 1);
+
+        let rows = search_runs(&entries, "website", 10);
+        assert_eq!(
+            format_search(&rows),
+            "Time              Exit  Directory                 Prompt                            Command\n\
+             2024-05-01 10:00     0  /home/me/projects/websi…  count lines                       wc -l notes.txt\n"
+        );
+    }
+}
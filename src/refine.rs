@@ -0,0 +1,143 @@
+//! `--refine`: the prompt is feedback on the previous command ("make it
+//! recursive"). The request carries the earlier prompt and command, so the
+//! model adjusts that command instead of starting over.
+
+use crate::history::{self, HistoryEntry};
+use crate::recall::entry_prompt;
+use crate::rerun;
+use anyhow::{anyhow, Result};
+
+/// Note a refinement leaves in its history entry, followed by the time of
+/// the run it refined.
+const REFINE_NOTE: &str = "refines ";
+
+/// The run `--refine` adjusts and how it came about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refinement {
+    pub ts: String,
+    /// The original prompt, then the feedback of earlier refinements.
+    pub prompts: Vec<String>,
+    pub command: String,
+    /// Prompt config paths of the original run (see
+    /// [`rerun::prompt_config_paths`]).
+    pub config_args: Vec<String>,
+    pub with: Vec<String>,
+}
+
+impl Refinement {
+    /// The request sent to the model for `feedback`.
+    pub fn request_prompt(&self, feedback: &str) -> String {
+        let mut prompt = String::from(
+            "Adjust the command generated earlier to the feedback below instead of starting over.",
+        );
+        if let Some((first, later)) = self.prompts.split_first() {
+            prompt.push_str(&format!("\nEarlier request: {}", first));
+            for followup in later {
+                prompt.push_str(&format!("\nThen: {}", followup));
+            }
+        }
+        prompt.push_str(&format!(
+            "\nEarlier command: {}\n\nFeedback: {}",
+            self.command, feedback
+        ));
+        prompt
+    }
+
+    /// The whole request in one line, e.g. for a task comment.
+    pub fn full_prompt(&self, feedback: &str) -> String {
+        let mut prompts = self.prompts.clone();
+        prompts.push(feedback.to_string());
+        prompts.join("; ")
+    }
+
+    /// Note for the history entry of the refined run.
+    pub fn note(&self) -> String {
+        format!("{}{}", REFINE_NOTE, self.ts)
+    }
+}
+
+/// The latest run with a command, with the prompts that led to it: earlier
+/// refinements and reruns are followed back to the original request.
+pub fn pick(entries: &[HistoryEntry]) -> Result<Refinement> {
+    let entry = entries
+        .iter()
+        .rev()
+        .find(|e| e.generated_command.is_some())
+        .ok_or_else(|| anyhow!("No earlier command in history to refine"))?;
+
+    let mut prompts = Vec::new();
+    let mut current = rerun::original(entries, entry);
+    loop {
+        prompts.extend(entry_prompt(current));
+        match history::noted_entry(entries, current, REFINE_NOTE) {
+            Some(earlier) => current = rerun::original(entries, earlier),
+            None => break,
+        }
+    }
+    prompts.reverse();
+    let (config_args, with) = rerun::prompt_config_paths(current);
+    Ok(Refinement {
+        ts: entry.ts.clone(),
+        prompts,
+        command: entry.generated_command.clone().unwrap_or_default(),
+        config_args,
+        with,
+    })
+}
+
+/// Reads the history and picks the run to refine.
+pub fn load() -> Result<Refinement> {
+    pick(&history::read_all_entries()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(ts: &str, argv: &[&str], command: Option<&str>, notes: Option<&str>) -> HistoryEntry {
+        HistoryEntry {
+            ts: ts.to_string(),
+            cwd: "/srv/app".to_string(),
+            argv: argv.iter().map(|a| a.to_string()).collect(),
+            generated_command: command.map(str::to_string),
+            notes: notes.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn refinements_carry_the_earlier_requests_and_command() {
+        assert!(pick(&[]).is_err());
+        let entries = vec![
+            entry(
+                "2024-05-01T10:00:00Z",
+                &["sai", "files.yml", "list log files"],
+                Some("ls *.log"),
+                None,
+            ),
+            // Runs often share a second.
+            entry(
+                "2024-05-01T10:00:00Z",
+                &["sai", "--refine", "make it recursive"],
+                Some("find . -name '*.log'"),
+                Some("refines 2024-05-01T10:00:00Z"),
+            ),
+            entry("2024-05-01T10:01:00Z", &["sai", "--usage"], None, None),
+        ];
+
+        let refinement = pick(&entries).unwrap();
+        assert_eq!(refinement.command, "find . -name '*.log'");
+        assert_eq!(refinement.prompts, ["list log files", "make it recursive"]);
+        assert_eq!(refinement.config_args, ["/srv/app/files.yml"]);
+        assert_eq!(refinement.note(), "refines 2024-05-01T10:00:00Z");
+
+        let request = refinement.request_prompt("only the last day");
+        assert!(request.contains("\nEarlier request: list log files\nThen: make it recursive\n"));
+        assert!(request
+            .ends_with("\nEarlier command: find . -name '*.log'\n\nFeedback: only the last day"));
+        assert_eq!(
+            refinement.full_prompt("only the last day"),
+            "list log files; make it recursive; only the last day"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! `--rerun [INDEX]`: the command of a past run, run again without asking
+//! the model. It is validated against the prompt config of the original run
+//! as it reads now, shown and confirmed before it runs.
+
+use crate::cli::Cli;
+use crate::history::{self, HistoryEntry};
+use crate::recall::entry_prompt;
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::path::Path;
+
+/// Placeholder left by secret scrubbing (see [`history::scrub_entry`]).
+const SCRUBBED: &str = "[REDACTED";
+
+/// The past run picked with `--rerun`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rerun {
+    pub index: usize,
+    pub ts: String,
+    pub cwd: String,
+    /// The original prompt, or a description when it cannot be recovered.
+    pub prompt: String,
+    pub command: String,
+    /// Prompt config files of the original run, resolved against its working
+    /// directory: positional config paths, then `--with` files.
+    pub config_args: Vec<String>,
+    pub with: Vec<String>,
+}
+
+/// Note a rerun leaves in its history entry, followed by the original's time.
+const RERUN_NOTE: &str = "rerun of ";
+
+/// The run whose command `entry` ran: `entry` itself, or for a rerun the
+/// original run, found by the time in its notes.
+pub fn original<'a>(entries: &'a [HistoryEntry], entry: &'a HistoryEntry) -> &'a HistoryEntry {
+    let mut entry = entry;
+    while let Some(earlier) = history::noted_entry(entries, entry, RERUN_NOTE) {
+        entry = earlier;
+    }
+    entry
+}
+
+/// The prompt config paths of a recorded run, resolved against its working
+/// directory: the positional config paths and the `--with` files.
+pub fn prompt_config_paths(entry: &HistoryEntry) -> (Vec<String>, Vec<String>) {
+    let cwd = Path::new(&entry.cwd);
+    let resolve = |arg: &String| cwd.join(arg).display().to_string();
+    match Cli::try_parse_from(&entry.argv) {
+        Ok(cli) => (
+            cli.prompt_and_configs().0.iter().map(resolve).collect(),
+            cli.with.iter().map(resolve).collect(),
+        ),
+        Err(_) => (Vec::new(), Vec::new()),
+    }
+}
+
+/// Note for the history entry of a rerun of `rerun`.
+pub fn note(rerun: &Rerun) -> String {
+    format!("{}{}", RERUN_NOTE, rerun.ts)
+}
+
+/// The run `index` back (1 is the latest) with a command that can be run
+/// again as recorded.
+pub fn pick(entries: &[HistoryEntry], index: usize) -> Result<Rerun> {
+    let entry = history::entry_at(entries, index).ok_or_else(|| {
+        anyhow!(
+            "No run #{} in history ({} recorded); see `sai history list`",
+            index,
+            entries.len()
+        )
+    })?;
+    let command = entry
+        .generated_command
+        .clone()
+        .ok_or_else(|| anyhow!("Run #{} has no generated command to run again", index))?;
+    if command.contains(SCRUBBED) {
+        return Err(anyhow!(
+            "The command of run #{} had secrets scrubbed from it before it was written to \
+             history (history.scrub_secrets), so it cannot be run again as recorded",
+            index
+        ));
+    }
+
+    let source = original(entries, entry);
+    let (config_args, with) = prompt_config_paths(source);
+    Ok(Rerun {
+        index,
+        ts: entry.ts.clone(),
+        cwd: entry.cwd.clone(),
+        prompt: entry_prompt(source).unwrap_or_else(|| format!("(rerun of run #{})", index)),
+        command,
+        config_args,
+        with,
+    })
+}
+
+/// Reads the history and picks run `index`.
+pub fn load(index: usize) -> Result<Rerun> {
+    pick(&history::read_all_entries()?, index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(argv: &[&str], command: Option<&str>) -> HistoryEntry {
+        HistoryEntry {
+            ts: "2024-05-01T10:00:00Z".to_string(),
+            cwd: "/srv/app".to_string(),
+            argv: argv.iter().map(|a| a.to_string()).collect(),
+            generated_command: command.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reruns_pick_a_runnable_command_and_its_prompt_configs() {
+        let entries = vec![
+            entry(
+                &[
+                    "sai",
+                    "git.yml",
+                    "--with",
+                    "/etc/sai/jq.yml",
+                    "show the last tag",
+                ],
+                Some("git describe --tags --abbrev=0"),
+            ),
+            HistoryEntry {
+                ts: "2024-05-01T11:00:00Z".to_string(),
+                ..entry(&["sai", "--usage"], None)
+            },
+            HistoryEntry {
+                ts: "2024-05-02T10:00:00Z".to_string(),
+                notes: Some("rerun of 2024-05-01T10:00:00Z".to_string()),
+                ..entry(
+                    &["sai", "--rerun", "2"],
+                    Some("git describe --tags --abbrev=0"),
+                )
+            },
+            entry(
+                &["sai", "call the api with token=[REDACTED]"],
+                Some("curl -H 'Authorization: Bearer [REDACTED]' api"),
+            ),
+        ];
+
+        let rerun = pick(&entries, 4).unwrap();
+        assert_eq!(rerun.command, "git describe --tags --abbrev=0");
+        assert_eq!(rerun.prompt, "show the last tag");
+        assert_eq!(rerun.config_args, ["/srv/app/git.yml"]);
+        assert_eq!(rerun.with, ["/etc/sai/jq.yml"]);
+        // A rerun of a rerun still uses the original prompt configs.
+        let again = pick(&entries, 2).unwrap();
+        assert_eq!(again.config_args, rerun.config_args);
+        assert_eq!(again.ts, "2024-05-02T10:00:00Z");
+
+        let err = pick(&entries, 3).unwrap_err().to_string();
+        assert!(err.contains("no generated command"));
+        let err = pick(&entries, 1).unwrap_err().to_string();
+        assert!(err.contains("scrub_secrets"));
+        assert!(pick(&entries, 5).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! Generated commands cached on disk, so repeating a request skips the API
+//! call. The key hashes the model, the provider endpoint and the messages
+//! sent, which hold the system prompt, the prompt, the scope listing and the
+//! peek sample; any change to them asks the provider again. Entries are
+//! stored as JSON under `cache/responses` in the config dir and `--no-cache`
+//! bypasses them.
+
+use crate::config;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How long a cached command is reused.
+const RESPONSE_CACHE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Serialize, Deserialize)]
+struct CachedResponse {
+    key: String,
+    cached_at: SystemTime,
+    command: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+}
+
+impl ResponseCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The cache under `cache/responses` in the config dir.
+    pub fn in_config_dir() -> Self {
+        Self::new(config::config_root_dir().join("cache").join("responses"))
+    }
+
+    /// Key for a request made of `parts` (model, endpoint, then each message).
+    pub fn key(parts: &[&str]) -> String {
+        fnv1a_hex(parts.join("\0").as_bytes())
+    }
+
+    /// The command cached under `key`, unless it has expired.
+    pub fn get(&self, key: &str) -> Option<String> {
+        read_cached(&self.path(key), key, SystemTime::now())
+    }
+
+    /// Caches `command` under `key`. Cache failures are ignored; the next
+    /// run just asks the provider again.
+    pub fn put(&self, key: &str, command: &str) {
+        let now = SystemTime::now();
+        prune(&self.dir, now);
+        let cached = CachedResponse {
+            key: key.to_string(),
+            cached_at: now,
+            command: command.to_string(),
+        };
+        if let Ok(json) = serde_json::to_string(&cached) {
+            let _ = fs::create_dir_all(&self.dir).and_then(|_| fs::write(self.path(key), json));
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+/// FNV-1a, stable across runs and Rust releases unlike `DefaultHasher`.
+fn fnv1a_hex(data: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in data {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+fn read_cached(path: &Path, key: &str, now: SystemTime) -> Option<String> {
+    let cached: CachedResponse = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    let age = now.duration_since(cached.cached_at).ok()?;
+    (cached.key == key && age < RESPONSE_CACHE_TTL).then_some(cached.command)
+}
+
+/// Removes cached commands that have expired.
+fn prune(dir: &Path, now: SystemTime) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let expired = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .is_some_and(|age| age >= RESPONSE_CACHE_TTL);
+        if expired {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn cached_commands_are_found_by_request_until_they_expire() {
+        let dir = tempdir().unwrap();
+        let cache = ResponseCache::new(dir.path());
+        let key = ResponseCache::key(&["gpt-4o", "system", "list files"]);
+        assert_ne!(
+            key,
+            ResponseCache::key(&["gpt-4o-mini", "system", "list files"])
+        );
+        assert_ne!(
+            key,
+            ResponseCache::key(&["gpt-4o", "system", "list files", "scope"])
+        );
+
+        assert_eq!(cache.get(&key), None);
+        cache.put(&key, "ls -la");
+        assert_eq!(cache.get(&key).as_deref(), Some("ls -la"));
+
+        let path = cache.path(&key);
+        let later = SystemTime::now() + RESPONSE_CACHE_TTL;
+        assert_eq!(read_cached(&path, &key, later), None);
+        assert_eq!(read_cached(&path, "other", SystemTime::now()), None);
+    }
+}
@@ -0,0 +1,303 @@
+//! Risk classification of generated commands and the per-level confirmation
+//! behavior configured under `confirmation:` in the global config.
+
+use crate::config::ToolConfig;
+use crate::safety::{command_words, detect_forbidden_operator};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskLevel {
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for RiskLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RiskLevel::Low => "low",
+            RiskLevel::Medium => "medium",
+            RiskLevel::High => "high",
+        })
+    }
+}
+
+/// What happens before a command of a given risk level runs, ordered from
+/// least to most strict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ConfirmMode {
+    /// Run without asking (unless a flag asks for confirmation).
+    #[serde(rename = "auto")]
+    Auto,
+    /// Ask `[y/N]` before running.
+    #[serde(rename = "confirm")]
+    Confirm,
+    /// Require typing the command's first token, like GitHub's repo deletion.
+    #[serde(rename = "confirm+type-command-name", alias = "type-command-name")]
+    TypeCommandName,
+}
+
+/// `confirmation:` section of the global config. Without it, only the
+/// --confirm/--explain/--unsafe flags and force_explain decide whether to ask.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ConfirmationConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low: Option<ConfirmMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub medium: Option<ConfirmMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub high: Option<ConfirmMode>,
+    /// Commands that always need their tool name typed back; replaces
+    /// `DEFAULT_DESTRUCTIVE_PATTERNS` (an empty list turns this off).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destructive: Option<Vec<String>>,
+    /// Ask the model to review each command before it runs (like --review).
+    #[serde(default)]
+    pub llm_review: bool,
+}
+
+impl ConfirmationConfig {
+    /// Mode for `level`; unset levels default to auto / confirm / type-command-name.
+    pub fn mode_for(&self, level: RiskLevel) -> ConfirmMode {
+        match level {
+            RiskLevel::Low => self.low.unwrap_or(ConfirmMode::Auto),
+            RiskLevel::Medium => self.medium.unwrap_or(ConfirmMode::Confirm),
+            RiskLevel::High => self.high.unwrap_or(ConfirmMode::TypeCommandName),
+        }
+    }
+}
+
+/// What a command does according to the model's review (--review).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommandEffect {
+    ReadOnly,
+    Modifying,
+    Destructive,
+}
+
+impl fmt::Display for CommandEffect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CommandEffect::ReadOnly => "read-only",
+            CommandEffect::Modifying => "modifying",
+            CommandEffect::Destructive => "destructive",
+        })
+    }
+}
+
+/// The model's review of a command, shown at confirmation and kept in history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LlmReview {
+    pub effect: CommandEffect,
+    pub reason: String,
+}
+
+pub const REVIEW_SYSTEM_PROMPT: &str = "You review shell commands before they run. \
+Classify the command as read-only (only reads or prints), modifying (creates or changes \
+files, processes or settings in a recoverable way) or destructive (deletes or overwrites \
+data irrecoverably). Answer with exactly one line: the category, a colon and a \
+justification of at most 15 words, e.g. `modifying: renames report.txt to report.bak`.";
+
+/// Parses a reply in the `category: justification` form asked for by
+/// [`REVIEW_SYSTEM_PROMPT`]; surrounding prose and markup are tolerated.
+pub fn parse_review(reply: &str) -> Option<LlmReview> {
+    let line = reply.lines().map(str::trim).find(|l| !l.is_empty())?;
+    let line = line.trim_matches(|c: char| c == '`' || c == '*');
+    let (category, reason) = line.split_once(':')?;
+    let effect = match category
+        .trim()
+        .trim_matches(|c: char| c == '`' || c == '*')
+        .to_ascii_lowercase()
+        .replace(' ', "-")
+        .as_str()
+    {
+        "read-only" | "readonly" => CommandEffect::ReadOnly,
+        "modifying" => CommandEffect::Modifying,
+        "destructive" => CommandEffect::Destructive,
+        _ => return None,
+    };
+    Some(LlmReview {
+        effect,
+        reason: reason.trim().trim_end_matches(['`', '*']).to_string(),
+    })
+}
+
+/// Destructive commands: the first word is the tool (`mkfs` also covers
+/// `mkfs.ext4`), any further words must all be among its arguments.
+pub const DEFAULT_DESTRUCTIVE_PATTERNS: &[&str] =
+    &["rm", "dd", "mkfs", "truncate", "git reset --hard"];
+
+/// Tools that delete or overwrite data irrecoverably.
+const HIGH_RISK_TOOLS: &[&str] = &[
+    "rm", "rmdir", "dd", "shred", "truncate", "wipefs", "fdisk", "mkfs", "unlink",
+];
+
+/// Tools that modify files or run other programs.
+const MEDIUM_RISK_TOOLS: &[&str] = &[
+    "mv", "cp", "ln", "chmod", "chown", "chgrp", "touch", "mkdir", "tee", "xargs", "rsync", "tar",
+    "kill", "pkill",
+];
+
+/// Arguments that make an otherwise read-only tool destructive.
+const HIGH_RISK_ARGS: &[(&str, &str)] = &[
+    ("find", "-delete"),
+    ("find", "-exec"),
+    ("find", "-execdir"),
+    ("find", "-ok"),
+    ("sed", "-i"),
+    ("sed", "--in-place"),
+    ("perl", "-i"),
+];
+
+/// Classifies a validated command. Shell operators make a command at least
+/// medium risk, and tools with force_explain are treated as medium as well.
+pub fn classify(cmd_line: &str, tools: &[ToolConfig]) -> RiskLevel {
+    let mut level = if detect_forbidden_operator(cmd_line, &[]).is_some() {
+        RiskLevel::Medium
+    } else {
+        RiskLevel::Low
+    };
+
+    for words in command_words(cmd_line) {
+        let Some((tool, args)) = words.split_first() else {
+            continue;
+        };
+        level = level.max(classify_invocation(tool, args));
+        if tools
+            .iter()
+            .any(|t| t.matches(tool) && t.force_explain == Some(true))
+        {
+            level = level.max(RiskLevel::Medium);
+        }
+    }
+
+    level
+}
+
+/// The tool to type back when a command segment matches a destructive
+/// pattern. This applies with or without a `confirmation:` section.
+pub fn destructive_tool(cfg: Option<&ConfirmationConfig>, cmd_line: &str) -> Option<String> {
+    let patterns: Vec<&str> = match cfg.and_then(|c| c.destructive.as_ref()) {
+        Some(patterns) => patterns.iter().map(String::as_str).collect(),
+        None => DEFAULT_DESTRUCTIVE_PATTERNS.to_vec(),
+    };
+    command_words(cmd_line).into_iter().find_map(|words| {
+        let (tool, args) = words.split_first()?;
+        let name = tool.rsplit('/').next().unwrap_or(tool);
+        let matched = patterns.iter().any(|pattern| {
+            let mut words = pattern.split_whitespace();
+            let Some(pattern_tool) = words.next() else {
+                return false;
+            };
+            (name == pattern_tool
+                || name
+                    .strip_prefix(pattern_tool)
+                    .is_some_and(|rest| rest.starts_with('.')))
+                && words.all(|word| args.iter().any(|a| a == word))
+        });
+        matched.then(|| tool.clone())
+    })
+}
+
+fn classify_invocation(tool: &str, args: &[String]) -> RiskLevel {
+    let name = tool.rsplit('/').next().unwrap_or(tool);
+    let is_mkfs_variant = name.starts_with("mkfs.");
+
+    if HIGH_RISK_TOOLS.contains(&name) || is_mkfs_variant {
+        return RiskLevel::High;
+    }
+    let risky_arg = HIGH_RISK_ARGS.iter().any(|(t, flag)| {
+        *t == name
+            && args
+                .iter()
+                .any(|a| a == flag || (flag.len() == 2 && a.starts_with(flag)))
+    });
+    if risky_arg {
+        return RiskLevel::High;
+    }
+    if MEDIUM_RISK_TOOLS.contains(&name) {
+        return RiskLevel::Medium;
+    }
+    RiskLevel::Low
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(cmd: &str) -> RiskLevel {
+        classify(cmd, &[])
+    }
+
+    #[test]
+    fn commands_are_classified_by_tool_and_arguments() {
+        assert_eq!(level("grep -rn TODO src"), RiskLevel::Low);
+        assert_eq!(level("find . -name '*.log'"), RiskLevel::Low);
+        assert_eq!(level("mv a.txt b.txt"), RiskLevel::Medium);
+        assert_eq!(level("find . -name '*.log' -delete"), RiskLevel::High);
+        assert_eq!(level("sed -i.bak 's/a/b/' notes.txt"), RiskLevel::High);
+        assert_eq!(level("/bin/rm -rf build"), RiskLevel::High);
+    }
+
+    #[test]
+    fn shell_operators_raise_risk_and_every_segment_counts() {
+        assert_eq!(level("grep foo a.txt > out.txt"), RiskLevel::Medium);
+        assert_eq!(level("ls build ; rm -r build"), RiskLevel::High);
+        assert_eq!(level("ls x;rm -rf ~"), RiskLevel::High);
+        assert_eq!(level("echo 'a;rm -rf ~'"), RiskLevel::Low);
+    }
+
+    #[test]
+    fn destructive_patterns_name_the_tool_to_type() {
+        let tool = |cfg, cmd| destructive_tool(cfg, cmd);
+        assert_eq!(
+            tool(None, "git reset --hard HEAD~1").as_deref(),
+            Some("git")
+        );
+        assert_eq!(tool(None, "git reset --soft HEAD~1"), None);
+        assert_eq!(
+            tool(None, "ls build ; mkfs.ext4 /dev/sdb1").as_deref(),
+            Some("mkfs.ext4")
+        );
+        assert_eq!(tool(None, "ls build&&rm -rf build").as_deref(), Some("rm"));
+
+        let cfg: ConfirmationConfig =
+            serde_yaml::from_str("destructive: [\"docker system prune\"]\n").unwrap();
+        assert_eq!(
+            tool(Some(&cfg), "docker system prune -af").as_deref(),
+            Some("docker")
+        );
+        assert_eq!(tool(Some(&cfg), "rm -rf build"), None);
+    }
+
+    #[test]
+    fn model_reviews_are_parsed_leniently() {
+        assert_eq!(
+            parse_review("Destructive: removes the build directory recursively.\n"),
+            Some(LlmReview {
+                effect: CommandEffect::Destructive,
+                reason: "removes the build directory recursively.".to_string(),
+            })
+        );
+        let review = parse_review("**Read-only**: lists files").unwrap();
+        assert_eq!(review.effect, CommandEffect::ReadOnly);
+        assert_eq!(review.reason, "lists files");
+        assert_eq!(parse_review("It looks harmless."), None);
+    }
+
+    #[test]
+    fn unset_levels_use_defaults() {
+        let cfg: ConfirmationConfig = serde_yaml::from_str("low: confirm\n").unwrap();
+        assert_eq!(cfg.mode_for(RiskLevel::Low), ConfirmMode::Confirm);
+        assert_eq!(cfg.mode_for(RiskLevel::Medium), ConfirmMode::Confirm);
+        assert_eq!(cfg.mode_for(RiskLevel::High), ConfirmMode::TypeCommandName);
+
+        let cfg: ConfirmationConfig =
+            serde_yaml::from_str("medium: auto\nhigh: confirm+type-command-name\n").unwrap();
+        assert_eq!(cfg.mode_for(RiskLevel::Medium), ConfirmMode::Auto);
+        assert_eq!(cfg.mode_for(RiskLevel::High), ConfirmMode::TypeCommandName);
+    }
+}
@@ -1,9 +1,19 @@
+use crate::config::{command_name, PromptConfig, ToolConfig};
 use anyhow::{anyhow, Context, Result};
 
+/// Operators a prompt config may list under `allowed_operators:`. Command
+/// substitution and process substitution would run commands the tool
+/// allowlist never sees, so they stay reserved for --unsafe.
+pub const ALLOWABLE_OPERATORS: &[&str] = &["|", "||", "|&", "&&", "&", ";", ">", ">>", "<", "<<"];
+
+/// Operators that separate one command from the next.
+pub(crate) const COMMAND_SEPARATORS: &[&str] = &["|", "||", "|&", "&&", "&", ";"];
+
 pub fn validate_and_split_command(
     cmd_line: &str,
     allowed_tools: &[String],
     unsafe_mode: bool,
+    prompt_cfg: &PromptConfig,
 ) -> Result<Vec<String>> {
     let tokens =
         shell_words::split(cmd_line).context("Failed to split command line from LLM output")?;
@@ -13,7 +23,7 @@ pub fn validate_and_split_command(
     }
 
     let first = &tokens[0];
-    if !allowed_tools.iter().any(|t| t == first) {
+    if resolve_tool(first, allowed_tools, &prompt_cfg.tools).is_none() {
         return Err(anyhow!(
             "Disallowed command '{}'. Allowed tools: {}",
             first,
@@ -22,25 +32,356 @@ pub fn validate_and_split_command(
     }
 
     if !unsafe_mode {
-        if let Some(op) = detect_forbidden_operator(cmd_line) {
+        if let Some(op) = detect_forbidden_operator(cmd_line, &prompt_cfg.allowed_operators) {
             return Err(anyhow!(
                 "Disallowed shell operator or construct '{}' in generated command. \
                  Re-run with --unsafe if you really want to execute it.",
                 op
             ));
         }
+        // Allowed operators may chain commands; each one must use a tool.
+        for segment in command_segments(cmd_line).iter().skip(1) {
+            let words = shell_words::split(segment)
+                .context("Failed to split command line from LLM output")?;
+            let Some(tool) = words.first() else {
+                continue;
+            };
+            if resolve_tool(tool, allowed_tools, &prompt_cfg.tools).is_none() {
+                return Err(anyhow!(
+                    "Disallowed command '{}'. Allowed tools: {}",
+                    tool,
+                    allowed_tools.join(", ")
+                ));
+            }
+        }
+    }
+
+    for segment in command_segments(cmd_line) {
+        let words =
+            shell_words::split(segment).context("Failed to split command line from LLM output")?;
+        let Some((tool, args)) = words.split_first() else {
+            continue;
+        };
+        if let Some(tool) = prompt_cfg.tools.iter().find(|t| t.matches(tool)) {
+            check_flags(tool, args)?;
+        }
     }
 
     Ok(tokens)
 }
 
-pub fn detect_forbidden_operator(cmd_line: &str) -> Option<String> {
-    let mut chars = cmd_line.chars().peekable();
+/// The allowed tool the command word `word` invokes: by name or by one of
+/// the tool's `aliases`, either possibly spelled as a path into a PATH
+/// directory (`/usr/bin/jq`).
+pub fn resolve_tool<'a>(
+    word: &str,
+    allowed_tools: &'a [String],
+    tools: &[ToolConfig],
+) -> Option<&'a str> {
+    let name = command_name(word)?;
+    allowed_tools
+        .iter()
+        .find(|allowed| {
+            *allowed == name
+                || tools
+                    .iter()
+                    .any(|t| &t.name == *allowed && t.aliases.iter().any(|a| a == name))
+        })
+        .map(String::as_str)
+}
+
+/// Enforces a tool's `allowed_flags` and `denied_flags` on its arguments.
+/// Arguments after `--` are operands, not flags.
+fn check_flags(tool: &ToolConfig, args: &[String]) -> Result<()> {
+    if tool.allowed_flags.is_empty() && tool.denied_flags.is_empty() {
+        return Ok(());
+    }
+    for arg in args.iter().take_while(|a| *a != "--") {
+        if !arg.starts_with('-') || arg == "-" {
+            continue;
+        }
+        if let Some(flag) = tool.denied_flags.iter().find(|f| flag_matches(f, arg)) {
+            return Err(anyhow!(
+                "Flag '{}' is not allowed for '{}' (denied_flags: {})",
+                flag,
+                tool.name,
+                tool.denied_flags.join(", ")
+            ));
+        }
+        if !tool.allowed_flags.is_empty() && !is_allowed_flag(&tool.allowed_flags, arg) {
+            return Err(anyhow!(
+                "Flag '{}' is not allowed for '{}' (allowed_flags: {})",
+                arg,
+                tool.name,
+                tool.allowed_flags.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// True when `arg` passes `flag`: exactly, as `--flag=VALUE`, or for a
+/// one-letter flag inside a cluster like `-rf`.
+fn flag_matches(flag: &str, arg: &str) -> bool {
+    if arg == flag {
+        return true;
+    }
+    if flag.starts_with("--") {
+        return arg
+            .strip_prefix(flag)
+            .is_some_and(|rest| rest.starts_with('='));
+    }
+    match (short_flag(flag), short_cluster(arg)) {
+        (Some(letter), Some(cluster)) => cluster.contains(letter),
+        _ => false,
+    }
+}
+
+/// True when every flag in `arg` is allowed. In a short cluster such as
+/// `-sL` each letter must be allowed; anything after the letters (`-n5`) is
+/// an attached value, and `-5` has no flags at all.
+fn is_allowed_flag(allowed: &[String], arg: &str) -> bool {
+    if allowed
+        .iter()
+        .any(|f| f == arg || (f.starts_with("--") && flag_matches(f, arg)))
+    {
+        return true;
+    }
+    let Some(cluster) = short_cluster(arg) else {
+        return false;
+    };
+    cluster
+        .chars()
+        .take_while(char::is_ascii_alphabetic)
+        .all(|letter| allowed.iter().any(|f| short_flag(f) == Some(letter)))
+}
+
+/// The letter of a one-letter flag like `-r`.
+fn short_flag(flag: &str) -> Option<char> {
+    let mut chars = flag.strip_prefix('-')?.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c != '-' => Some(c),
+        _ => None,
+    }
+}
+
+/// The letters of a short option argument (`-rf` gives `rf`).
+fn short_cluster(arg: &str) -> Option<&str> {
+    arg.strip_prefix('-')
+        .filter(|rest| !rest.is_empty() && !rest.starts_with('-'))
+}
+
+/// Checks that every `allowed_flags`/`denied_flags` entry looks like a flag.
+pub fn validate_flag_lists(tool: &ToolConfig) -> Result<()> {
+    for flag in tool.allowed_flags.iter().chain(&tool.denied_flags) {
+        if !flag.starts_with('-') || flag == "-" || flag == "--" {
+            return Err(anyhow!(
+                "Tool '{}': '{}' in allowed_flags/denied_flags is not a flag",
+                tool.name,
+                flag
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Rejects `allowed_operators` entries that are unknown or reserved for --unsafe.
+pub fn validate_allowed_operators(operators: &[String]) -> Result<()> {
+    for op in operators {
+        if !ALLOWABLE_OPERATORS.contains(&op.as_str()) {
+            return Err(anyhow!(
+                "allowed_operators: '{}' cannot be allowed; choose from {}",
+                op,
+                ALLOWABLE_OPERATORS.join(" ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Tools `--read-only` allows when their config does not set `read_only`.
+pub const DEFAULT_READ_ONLY_TOOLS: &[&str] = &[
+    "ls", "cat", "head", "tail", "less", "grep", "rg", "find", "fd", "wc", "sort", "uniq", "cut",
+    "tr", "column", "jq", "yq", "du", "df", "stat", "file", "diff", "tree", "ps", "pwd", "date",
+    "echo", "printf", "which", "uname", "hostname", "id", "whoami", "env",
+];
+
+/// Flags that make an otherwise read-only tool write files or run commands.
+const WRITE_FLAGS: &[(&str, &str)] = &[
+    ("find", "-delete"),
+    ("find", "-exec"),
+    ("find", "-execdir"),
+    ("find", "-ok"),
+    ("find", "-okdir"),
+    ("find", "-fprint"),
+    ("find", "-fprint0"),
+    ("find", "-fprintf"),
+    ("find", "-fls"),
+    ("fd", "-x"),
+    ("fd", "--exec"),
+    ("fd", "-X"),
+    ("fd", "--exec-batch"),
+    ("rg", "--pre"),
+    ("sort", "-o"),
+    ("sort", "--output"),
+    ("yq", "-i"),
+    ("yq", "--inplace"),
+    ("sed", "-i"),
+    ("sed", "--in-place"),
+    ("perl", "-i"),
+];
+
+/// Whether `--read-only` allows `name`: its config's `read_only`, or
+/// membership in [`DEFAULT_READ_ONLY_TOOLS`] when that is unset. An alias
+/// is judged as a tool of its own: it must be on the built-in list as well.
+pub fn is_read_only_tool(tools: &[ToolConfig], name: &str) -> bool {
+    let listed = |n: &str| DEFAULT_READ_ONLY_TOOLS.contains(&n);
+    let invoked = command_name(name);
+    let Some(tool) = tools.iter().find(|t| t.matches(name)) else {
+        return invoked.is_some_and(listed);
+    };
+    let via_alias = invoked != Some(tool.name.as_str());
+    tool.read_only.unwrap_or_else(|| listed(&tool.name))
+        && (!via_alias || invoked.is_some_and(listed))
+}
+
+/// Read-only mode: every command must use a read-only tool without
+/// write-like flags, and output redirection is rejected.
+pub fn check_read_only(cmd_line: &str, tools: &[ToolConfig]) -> Result<()> {
+    if let Some((_, op)) = shell_operators(cmd_line)
+        .into_iter()
+        .find(|(_, op)| op.starts_with('>'))
+    {
+        return Err(anyhow!(
+            "Output redirection '{}' is not allowed in read-only mode",
+            op
+        ));
+    }
+    for segment in command_segments(cmd_line) {
+        let words =
+            shell_words::split(segment).context("Failed to split command line from LLM output")?;
+        let Some((tool, args)) = words.split_first() else {
+            continue;
+        };
+        if !is_read_only_tool(tools, tool) {
+            return Err(anyhow!(
+                "'{}' is not a read-only tool (set `read_only: true` on it to allow it in \
+                 read-only mode)",
+                tool
+            ));
+        }
+        // Write flags of the tool and of the alias it was invoked by.
+        let names = [
+            tools
+                .iter()
+                .find(|t| t.matches(tool))
+                .map(|t| t.name.as_str()),
+            command_name(tool),
+        ];
+        let write_flag = args.iter().take_while(|a| *a != "--").find(|arg| {
+            WRITE_FLAGS
+                .iter()
+                .any(|(t, flag)| names.contains(&Some(*t)) && flag_matches(flag, arg))
+        });
+        if let Some(arg) = write_flag {
+            return Err(anyhow!(
+                "Flag '{}' lets '{}' write or run commands and is not allowed in read-only mode",
+                arg,
+                tool
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// True when the command needs a shell to run: always with --unsafe, and
+/// otherwise when it uses (allowed) shell operators.
+pub fn runs_in_shell(cmd_line: &str, unsafe_mode: bool) -> bool {
+    unsafe_mode || !shell_operators(cmd_line).is_empty()
+}
+
+/// The first shell operator or construct in `cmd_line` that is not in
+/// `allowed`.
+pub fn detect_forbidden_operator(cmd_line: &str, allowed: &[String]) -> Option<String> {
+    shell_operators(cmd_line)
+        .into_iter()
+        .map(|(_, op)| op)
+        .find(|op| !allowed.contains(op))
+}
+
+/// The words of every command on the line, split at unquoted command
+/// separators whether or not spaces surround them (`ls x;rm -rf ~`).
+pub fn command_words(cmd_line: &str) -> Vec<Vec<String>> {
+    command_segments(cmd_line)
+        .into_iter()
+        .map(|segment| {
+            shell_words::split(segment)
+                .unwrap_or_else(|_| segment.split_whitespace().map(str::to_string).collect())
+        })
+        .filter(|words| !words.is_empty())
+        .collect()
+}
+
+/// The command line split at unquoted command separators.
+fn command_segments(cmd_line: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for (offset, op) in shell_operators(cmd_line) {
+        if COMMAND_SEPARATORS.contains(&op.as_str()) {
+            segments.push(&cmd_line[start..offset]);
+            start = offset + op.len();
+        }
+    }
+    segments.push(&cmd_line[start..]);
+    segments
+}
+
+/// The file each unquoted `>`, `>>` or `<` redirects to or from, also when
+/// written without a space (`f>/tmp/x`) or after a descriptor (`2>x`).
+/// Duplications like `2>&1` name no file and are skipped.
+pub(crate) fn redirect_targets(cmd_line: &str) -> Vec<String> {
+    let mut targets = Vec::new();
+    for (offset, op) in shell_operators(cmd_line) {
+        if !matches!(op.as_str(), ">" | ">>" | "<") {
+            continue;
+        }
+        let rest = cmd_line[offset + op.len()..].trim_start();
+        if rest.starts_with('&') {
+            continue;
+        }
+        let mut end = rest.len();
+        let (mut in_single, mut in_double, mut escaped) = (false, false, false);
+        for (i, c) in rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if !in_single => escaped = true,
+                '\'' if !in_double => in_single = !in_single,
+                '"' if !in_single => in_double = !in_double,
+                _ if in_single || in_double => {}
+                c if c.is_whitespace() || "|&;<>()".contains(c) => {
+                    end = i;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let word = shell_words::split(&rest[..end])
+            .ok()
+            .and_then(|words| words.into_iter().next());
+        targets.extend(word);
+    }
+    targets
+}
+
+/// Every unquoted shell operator or construct with its byte offset.
+fn shell_operators(cmd_line: &str) -> Vec<(usize, String)> {
+    let mut found = Vec::new();
+    let mut chars = cmd_line.char_indices().peekable();
     let mut in_single = false;
     let mut in_double = false;
     let mut escaped = false;
 
-    while let Some(c) = chars.next() {
+    while let Some((offset, c)) = chars.next() {
         if escaped {
             escaped = false;
             continue;
@@ -66,92 +407,196 @@ pub fn detect_forbidden_operator(cmd_line: &str) -> Option<String> {
             continue;
         }
 
-        match c {
-            '$' => {
-                if let Some(&next) = chars.peek() {
-                    if next == '(' {
-                        return Some("$(...)".to_string());
-                    }
-                    if next == '{' {
-                        return Some("${...}".to_string());
-                    }
-                }
+        let next = chars.peek().map(|&(_, n)| n);
+        let op = match (c, next) {
+            ('$', Some('(')) => Some("$(...)"),
+            ('$', Some('{')) => Some("${...}"),
+            ('`', _) => Some("`...`"),
+            _ if in_double => None,
+            ('|', Some('|')) => Some("||"),
+            ('|', Some('&')) => Some("|&"),
+            ('|', _) => Some("|"),
+            ('&', Some('&')) => Some("&&"),
+            ('&', _) => Some("&"),
+            (';', _) => Some(";"),
+            ('>', Some('>')) => Some(">>"),
+            ('>', Some('(')) => Some(">("),
+            ('>', _) => Some(">"),
+            ('<', Some('<')) => Some("<<"),
+            ('<', Some('(')) => Some("<("),
+            ('<', _) => Some("<"),
+            _ => None,
+        };
+        if let Some(op) = op {
+            // Two-character operators are consumed whole; `$(` and `${`
+            // leave their bracket for nested constructs.
+            if matches!(op, "||" | "|&" | "&&" | ">>" | "<<") {
+                chars.next();
             }
-            '`' => {
-                return Some("`...`".to_string());
-            }
-            _ => {}
-        }
-
-        if in_double {
-            continue;
-        }
-
-        match c {
-            '|' => {
-                if let Some(&next) = chars.peek() {
-                    if next == '|' {
-                        return Some("||".to_string());
-                    }
-                    if next == '&' {
-                        return Some("|&".to_string());
-                    }
-                }
-                return Some("|".to_string());
-            }
-            '&' => {
-                if let Some(&next) = chars.peek() {
-                    if next == '&' {
-                        return Some("&&".to_string());
-                    }
-                }
-                return Some("&".to_string());
-            }
-            ';' => {
-                return Some(";".to_string());
-            }
-            '>' => {
-                if let Some(&next) = chars.peek() {
-                    if next == '>' {
-                        return Some(">>".to_string());
-                    }
-                    if next == '(' {
-                        return Some(">(".to_string());
-                    }
-                }
-                return Some(">".to_string());
-            }
-            '<' => {
-                if let Some(&next) = chars.peek() {
-                    if next == '<' {
-                        return Some("<<".to_string());
-                    }
-                    if next == '(' {
-                        return Some("<(".to_string());
-                    }
-                }
-                return Some("<".to_string());
-            }
-            _ => {}
+            found.push((offset, op.to_string()));
         }
     }
 
-    None
+    found
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn read_only_mode_rejects_mutating_tools_flags_and_redirects() {
+        let tools: Vec<ToolConfig> =
+            serde_yaml::from_str("- name: git\n  read_only: true\n  config: git\n- name: cat\n  read_only: false\n  config: cat\n").unwrap();
+        check_read_only("grep -rn TODO src | sort | uniq -c", &tools).unwrap();
+        check_read_only("git log --oneline", &tools).unwrap();
+        check_read_only("find . -name '*.log' -print", &tools).unwrap();
+        assert!(check_read_only("cat notes.txt", &tools).is_err());
+        assert!(check_read_only("ls | xargs rm", &tools).is_err());
+        assert!(check_read_only("find . -name '*.tmp' -delete", &tools).is_err());
+        assert!(check_read_only("sort -uo out.txt in.txt", &tools).is_err());
+        assert!(check_read_only("grep x f > out.txt", &tools).is_err());
+        check_read_only("grep '>' f", &tools).unwrap();
+    }
+
+    #[test]
+    fn aliases_are_read_only_only_on_their_own_merits() {
+        let tools: Vec<ToolConfig> = serde_yaml::from_str(
+            r#"
+- name: cat
+  aliases: [rm, bat]
+  config: cat
+- name: git
+  read_only: true
+  aliases: [sh]
+  config: git
+"#,
+        )
+        .unwrap();
+        check_read_only("cat notes.txt", &tools).unwrap();
+        assert!(check_read_only("rm -rf notes", &tools).is_err());
+        assert!(check_read_only("bat notes.txt", &tools).is_err());
+        assert!(check_read_only("sh -c 'rm -rf x'", &tools).is_err());
+        check_read_only("git log", &tools).unwrap();
+    }
+
     #[test]
     fn detects_pipe_even_without_spaces() {
-        assert_eq!(detect_forbidden_operator("ls|wc"), Some("|".to_string()));
+        assert_eq!(
+            detect_forbidden_operator("ls|wc", &[]),
+            Some("|".to_string())
+        );
+    }
+
+    #[test]
+    fn allowed_operators_permit_pipes_between_allowed_tools() {
+        let tools = vec!["jq".to_string(), "sort".to_string()];
+        let pipes = PromptConfig {
+            allowed_operators: vec!["|".to_string()],
+            ..PromptConfig::default()
+        };
+        let cmd = "jq -r '.a | .b' data.json | sort";
+        assert_eq!(
+            validate_and_split_command(cmd, &tools, false, &pipes).unwrap()[0],
+            "jq"
+        );
+        assert!(runs_in_shell(cmd, false));
+        assert!(!runs_in_shell("jq '.a | .b' data.json", false));
+
+        let err = validate_and_split_command("jq . f | rm -rf x", &tools, false, &pipes)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("'rm'"));
+        let err = validate_and_split_command("jq . f; sort f", &tools, false, &pipes)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("';'"));
+        assert_eq!(
+            detect_forbidden_operator("jq . f || `x`", &["||".to_string()]),
+            Some("`...`".to_string())
+        );
+
+        assert!(validate_allowed_operators(&[">>".to_string()]).is_ok());
+        assert!(validate_allowed_operators(&["$(...)".to_string()]).is_err());
     }
 
     #[test]
     fn allows_safe_command() {
-        let tokens =
-            validate_and_split_command("jq '.foo' file.json", &["jq".to_string()], false).unwrap();
+        let tokens = validate_and_split_command(
+            "jq '.foo' file.json",
+            &["jq".to_string()],
+            false,
+            &PromptConfig::default(),
+        )
+        .unwrap();
         assert_eq!(tokens[0], "jq");
     }
+
+    #[test]
+    fn tools_resolve_through_aliases_and_path_directories() {
+        let prompt: PromptConfig = serde_yaml::from_str(
+            "tools:\n  - name: python\n    aliases: [python3]\n    denied_flags: [-c]\n    config: python\n  - name: jq\n    config: jq\n",
+        )
+        .unwrap();
+        let allowed = vec!["python".to_string(), "jq".to_string()];
+        let path_dir = std::env::split_paths(&std::env::var_os("PATH").unwrap())
+            .find(|p| p.is_absolute())
+            .unwrap();
+        let jq = path_dir.join("jq").display().to_string();
+
+        validate_and_split_command("python3 script.py", &allowed, false, &prompt).unwrap();
+        validate_and_split_command(&format!("{} . f.json", jq), &allowed, false, &prompt).unwrap();
+        assert_eq!(
+            resolve_tool("python3", &allowed, &prompt.tools),
+            Some("python")
+        );
+        assert!(
+            validate_and_split_command("python3 -c 'print(1)'", &allowed, false, &prompt).is_err()
+        );
+        assert!(validate_and_split_command("./jq . f.json", &allowed, false, &prompt).is_err());
+        assert!(
+            validate_and_split_command("/nonexistent/dir/jq . f", &allowed, false, &prompt)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn tool_flag_lists_are_enforced_per_command() {
+        let prompt: PromptConfig = serde_yaml::from_str(
+            r#"
+allowed_operators: ["|"]
+tools:
+  - name: rm
+    denied_flags: ["-r", "-R", "--recursive"]
+    config: rm
+  - name: curl
+    allowed_flags: ["-s", "-L", "--max-time"]
+    config: curl
+"#,
+        )
+        .unwrap();
+        let tools = vec!["rm".to_string(), "curl".to_string()];
+        let check = |cmd: &str| validate_and_split_command(cmd, &tools, false, &prompt);
+
+        assert!(check("rm -f old.log").is_ok());
+        assert!(check("rm -- -r").is_ok());
+        let err = check("rm -rf build").unwrap_err().to_string();
+        assert!(err.contains("Flag '-r' is not allowed for 'rm'"));
+        assert!(check("rm --recursive=yes x").is_err());
+
+        assert!(check("curl -sL --max-time=5 https://example.com").is_ok());
+        let err = check("curl -s https://x | curl -o out https://y")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Flag '-o' is not allowed for 'curl'"));
+
+        let bad: ToolConfig = serde_yaml::from_str(
+            "name: rm
+denied_flags: [r]
+config: rm
+",
+        )
+        .unwrap();
+        assert!(validate_flag_lists(&bad).is_err());
+    }
 }
@@ -1,26 +1,177 @@
-use anyhow::{Context, Result};
+use crate::config;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
 use std::env;
+use std::ffi::OsStr;
 use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime};
 
 pub const SCOPE_DOT_MAX_BYTES: usize = 8 * 1024;
 const TRUNCATION_NOTE: &str = "(truncated directory listing)";
+const NON_UTF8_NOTE: &str =
+    "(names containing '?' are not valid UTF-8; use them as globs, '?' stands for one byte)";
+
+/// Scope hints starting with this carry a ready-made directory listing
+/// (see [`remote_scope_hint`]) instead of a pattern from the user.
+const PREPARED_LISTING: &str = "\u{1}listing\n";
+
+/// How long a `--scope .` listing is reused while its directory is unchanged.
+const SCOPE_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// The message sent to the model for a `--scope` hint.
+pub fn scope_message(scope: &str) -> Result<String> {
+    if let Some(prepared) = scope.strip_prefix(PREPARED_LISTING) {
+        return Ok(prepared.to_string());
+    }
+    if scope == "." {
+        let listing = cached_scope_dot_listing()?;
+        return Ok(format!(
+            "Scope: current directory.\nHere is a non-recursive listing of the working directory:\n{}",
+            listing
+        ));
+    }
+    Ok(format!(
+        "Focus your command on files or paths matching this scope:\n{}",
+        scope
+    ))
+}
+
+/// `--scope .` for `--host`: lists the login directory on the host over ssh,
+/// where the command will run, and returns it as a prepared scope hint.
+pub fn remote_scope_hint(host: &str) -> Result<String> {
+    let output = Command::new("ssh")
+        .args(["-o", "BatchMode=yes", "--", host, "ls -1Ap"])
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run ssh to '{}'", host))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!(
+            "Failed to list the remote directory on '{}': {}",
+            host,
+            stderr.lines().next().unwrap_or("ssh failed").trim()
+        ));
+    }
+    let entries = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    Ok(format!(
+        "{}Scope: the login directory on {}, where the command will run.\nHere is a non-recursive listing of it:\n{}",
+        PREPARED_LISTING,
+        host,
+        format_listing(entries, false)
+    ))
+}
 
 pub fn build_scope_dot_listing() -> Result<String> {
     let cwd = env::current_dir().context("Failed to determine current directory")?;
     let mut entries = Vec::new();
+    let mut has_non_utf8 = false;
     let dir_iter = fs::read_dir(&cwd)
         .with_context(|| format!("Failed to list directory {}", cwd.display()))?;
 
     for entry in dir_iter {
         let entry = entry?;
-        let mut name = entry.file_name().to_string_lossy().into_owned();
+        let file_name = entry.file_name();
+        has_non_utf8 |= file_name.to_str().is_none();
+        let mut name = glob_safe_name(&file_name);
         if entry.file_type()?.is_dir() {
             name.push('/');
         }
         entries.push(name);
     }
 
+    Ok(format_listing(entries, has_non_utf8))
+}
+
+/// One cached listing, stored as JSON under `cache/scope` in the config dir.
+#[derive(Serialize, Deserialize)]
+struct CachedListing {
+    dir: PathBuf,
+    /// Modification time of `dir` when it was listed; any entry added,
+    /// removed or renamed since changes it.
+    dir_modified: SystemTime,
+    cached_at: SystemTime,
+    listing: String,
+}
+
+/// [`build_scope_dot_listing`], reused for [`SCOPE_CACHE_TTL`] while the
+/// working directory's mtime is unchanged, so successive runs in huge
+/// directories do not list them again. Cache failures just list afresh.
+fn cached_scope_dot_listing() -> Result<String> {
+    let cwd = env::current_dir().context("Failed to determine current directory")?;
+    let Ok(dir_modified) = fs::metadata(&cwd).and_then(|m| m.modified()) else {
+        return build_scope_dot_listing();
+    };
+    let cache_dir = config::config_root_dir().join("cache").join("scope");
+    let path = cache_dir.join(scope_cache_name(&cwd));
+    let now = SystemTime::now();
+    if let Some(listing) = read_cached_listing(&path, &cwd, dir_modified, now) {
+        return Ok(listing);
+    }
+
+    let listing = build_scope_dot_listing()?;
+    prune_scope_cache(&cache_dir, now);
+    let cached = CachedListing {
+        dir: cwd,
+        dir_modified,
+        cached_at: now,
+        listing,
+    };
+    if let Ok(json) = serde_json::to_string(&cached) {
+        let _ = fs::create_dir_all(&cache_dir).and_then(|_| fs::write(&path, json));
+    }
+    Ok(cached.listing)
+}
+
+fn scope_cache_name(dir: &Path) -> String {
+    let mut hasher = DefaultHasher::new();
+    dir.hash(&mut hasher);
+    format!("{:016x}.json", hasher.finish())
+}
+
+fn read_cached_listing(
+    path: &Path,
+    dir: &Path,
+    dir_modified: SystemTime,
+    now: SystemTime,
+) -> Option<String> {
+    let cached: CachedListing = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    let age = now.duration_since(cached.cached_at).ok()?;
+    (cached.dir == dir && cached.dir_modified == dir_modified && age < SCOPE_CACHE_TTL)
+        .then_some(cached.listing)
+}
+
+/// Removes cached listings that have expired.
+fn prune_scope_cache(cache_dir: &Path, now: SystemTime) {
+    let Ok(entries) = fs::read_dir(cache_dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let expired = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .is_some_and(|age| age >= SCOPE_CACHE_TTL);
+        if expired {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Sorts names (directories end in `/`) and joins them, truncated to
+/// [`SCOPE_DOT_MAX_BYTES`].
+fn format_listing(mut entries: Vec<String>, has_non_utf8: bool) -> String {
     entries.sort();
+    if has_non_utf8 {
+        entries.insert(0, NON_UTF8_NOTE.to_string());
+    }
 
     let max_content_len = SCOPE_DOT_MAX_BYTES.saturating_sub(TRUNCATION_NOTE.len() + 1);
     let mut listing = String::new();
@@ -45,7 +196,29 @@ pub fn build_scope_dot_listing() -> Result<String> {
         listing.push_str(TRUNCATION_NOTE);
     }
 
-    Ok(listing)
+    listing
+}
+
+/// File name as text the model can put in a command. Bytes that are not valid
+/// UTF-8 become `?`, which the executor's glob expansion matches back to the
+/// real name instead of a lossy, non-existent one.
+pub fn glob_safe_name(name: &OsStr) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut out = String::new();
+        for chunk in name.as_bytes().utf8_chunks() {
+            out.push_str(chunk.valid());
+            out.extend(std::iter::repeat_n('?', chunk.invalid().len()));
+        }
+        out
+    }
+
+    #[cfg(not(unix))]
+    {
+        name.to_string_lossy().replace('\u{FFFD}', "?")
+    }
 }
 
 #[cfg(test)]
@@ -87,6 +260,16 @@ mod tests {
         assert!(listing.contains("subdir/"));
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_are_listed_as_globs() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"caf\xe9 \xff\xfe.txt");
+        assert_eq!(glob_safe_name(name), "caf? ??.txt");
+        assert_eq!(glob_safe_name(OsStr::new("café.txt")), "café.txt");
+    }
+
     #[test]
     fn directory_listing_truncates() {
         let dir = tempdir().unwrap();
@@ -101,4 +284,50 @@ mod tests {
         assert!(listing.contains(TRUNCATION_NOTE));
         assert!(listing.len() <= SCOPE_DOT_MAX_BYTES);
     }
+
+    #[test]
+    fn cached_listings_expire_and_follow_the_directory() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        let listed_at = SystemTime::now();
+        let dir_modified = fs::metadata(dir.path()).unwrap().modified().unwrap();
+        let cached = CachedListing {
+            dir: dir.path().to_path_buf(),
+            dir_modified,
+            cached_at: listed_at,
+            listing: "a.txt".to_string(),
+        };
+        fs::write(&path, serde_json::to_string(&cached).unwrap()).unwrap();
+
+        let read = |dir: &Path, modified: SystemTime, later: Duration| {
+            read_cached_listing(&path, dir, modified, listed_at + later)
+        };
+        let soon = Duration::from_secs(1);
+        assert_eq!(
+            read(dir.path(), dir_modified, soon).as_deref(),
+            Some("a.txt")
+        );
+        assert_eq!(read(dir.path(), dir_modified, SCOPE_CACHE_TTL), None);
+        assert_eq!(read(dir.path(), dir_modified + soon, soon), None);
+        assert_eq!(read(Path::new("/elsewhere"), dir_modified, soon), None);
+    }
+
+    #[test]
+    fn prepared_listings_are_sent_as_is() {
+        let hint = format!(
+            "{}Scope: the login directory on box.\na.txt",
+            PREPARED_LISTING
+        );
+        assert_eq!(
+            scope_message(&hint).unwrap(),
+            "Scope: the login directory on box.\na.txt"
+        );
+        assert!(scope_message("src/*.rs")
+            .unwrap()
+            .starts_with("Focus your command on files or paths matching this scope:"));
+        assert_eq!(
+            format_listing(vec!["b/".to_string(), "a.txt".to_string()], false),
+            "a.txt\nb/"
+        );
+    }
 }
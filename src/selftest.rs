@@ -0,0 +1,239 @@
+//! `sai selftest`: an end-to-end run through a throwaway config in a temporary
+//! directory, using the mock provider so no API key or network is needed.
+//! Each subsystem reports pass/fail, which makes the output useful after an
+//! upgrade and worth pasting into bug reports.
+
+use crate::config::{load_global_config, resolve_ai_config, GlobalConfig};
+use crate::executor::{CommandExecutor, ExecOptions, ShellCommandExecutor};
+use crate::history::{self, HistoryEntry};
+use crate::llm::{CommandGenerator, HttpCommandGenerator};
+use crate::prompt::build_system_prompt;
+use crate::safety::validate_and_split_command;
+use anyhow::{anyhow, Context, Result};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+const SELFTEST_PROMPT: &str = "selftest: print the word sai-selftest";
+const SELFTEST_COMMAND: &str = "echo sai-selftest";
+/// A command the default safety rules must reject.
+const SELFTEST_REJECTED: &str = "echo ok; rm -rf selftest";
+
+/// Outcome of one subsystem check; `None` when an earlier check failed.
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub result: Option<Result<String>>,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        matches!(self.result, Some(Ok(_)))
+    }
+}
+
+/// Runs every check inside `dir`, stopping at the first failure.
+pub fn run_checks(dir: &Path) -> Vec<Check> {
+    let mut state = State::default();
+    let steps: [(&'static str, Step); 6] = [
+        ("config", check_config),
+        ("prompt", check_prompt),
+        ("generator", check_generator),
+        ("validation", check_validation),
+        ("execution", check_execution),
+        ("history", check_history),
+    ];
+    let mut failed = false;
+    steps
+        .into_iter()
+        .map(|(name, step)| {
+            let result = (!failed).then(|| step(dir, &mut state));
+            failed |= matches!(result, Some(Err(_)));
+            Check { name, result }
+        })
+        .collect()
+}
+
+type Step = fn(&Path, &mut State) -> Result<String>;
+
+/// What one check hands to the next.
+#[derive(Default)]
+struct State {
+    global: Option<GlobalConfig>,
+    system_prompt: String,
+    allowed_tools: Vec<String>,
+    command: String,
+    tokens: Vec<String>,
+}
+
+fn check_config(dir: &Path, state: &mut State) -> Result<String> {
+    let mock = dir.join("mock.yaml");
+    fs::write(
+        &mock,
+        format!(
+            "responses:\n  - pattern: \"*selftest*\"\n    command: {}\n",
+            SELFTEST_COMMAND
+        ),
+    )?;
+    let config = dir.join("config.yaml");
+    fs::write(
+        &config,
+        format!(
+            "ai:\n  provider: mock\n  mock_responses: {}\n\
+             default_prompt:\n  meta_prompt: Self-test prompt.\n  tools:\n\
+             \x20   - name: echo\n      config: \"echo: prints its arguments\"\n",
+            serde_json::to_string(&mock.display().to_string())?
+        ),
+    )?;
+    state.global = Some(load_global_config(&config)?);
+    Ok(format!("loaded {}", config.display()))
+}
+
+fn check_prompt(_: &Path, state: &mut State) -> Result<String> {
+    let prompt = state
+        .global
+        .as_ref()
+        .and_then(|g| g.default_prompt.clone())
+        .ok_or_else(|| anyhow!("The config has no default_prompt"))?;
+    let (system_prompt, allowed_tools) = build_system_prompt(&prompt)?;
+    if allowed_tools != ["echo"] || !system_prompt.contains("echo") {
+        return Err(anyhow!(
+            "Expected only 'echo' to be allowed, got: {}",
+            allowed_tools.join(", ")
+        ));
+    }
+    state.system_prompt = system_prompt;
+    state.allowed_tools = allowed_tools;
+    Ok("system prompt built, allowed tools: echo".to_string())
+}
+
+fn check_generator(_: &Path, state: &mut State) -> Result<String> {
+    let ai = resolve_ai_config(state.global.as_ref().and_then(|g| g.ai.clone()))?;
+    let command = HttpCommandGenerator::try_new()?.generate(
+        &ai,
+        &state.system_prompt,
+        SELFTEST_PROMPT,
+        None,
+        None,
+    )?;
+    if command != SELFTEST_COMMAND {
+        return Err(anyhow!(
+            "Expected '{}', got '{}'",
+            SELFTEST_COMMAND,
+            command
+        ));
+    }
+    state.command = command;
+    Ok(format!("mock provider answered '{}'", state.command))
+}
+
+fn check_validation(_: &Path, state: &mut State) -> Result<String> {
+    let prompt = state
+        .global
+        .as_ref()
+        .and_then(|g| g.default_prompt.clone())
+        .unwrap_or_default();
+    state.tokens =
+        validate_and_split_command(&state.command, &state.allowed_tools, false, &prompt)?;
+    if validate_and_split_command(SELFTEST_REJECTED, &state.allowed_tools, false, &prompt).is_ok() {
+        return Err(anyhow!("'{}' was not rejected", SELFTEST_REJECTED));
+    }
+    Ok(format!(
+        "accepted '{}', rejected '{}'",
+        state.command, SELFTEST_REJECTED
+    ))
+}
+
+fn check_execution(_: &Path, state: &mut State) -> Result<String> {
+    let captured = ShellCommandExecutor.execute_captured(
+        &state.command,
+        &state.tokens,
+        false,
+        &ExecOptions::default(),
+    )?;
+    let stdout = String::from_utf8_lossy(&captured.stdout);
+    if captured.exit_code != 0 || stdout.trim() != "sai-selftest" {
+        return Err(anyhow!(
+            "Expected exit code 0 and 'sai-selftest', got exit code {} and '{}'",
+            captured.exit_code,
+            stdout.trim()
+        ));
+    }
+    Ok("echo ran without a shell and exited 0".to_string())
+}
+
+fn check_history(dir: &Path, state: &mut State) -> Result<String> {
+    let path = dir.join("history.log");
+    let entry = HistoryEntry {
+        ts: history::now_iso_ts(),
+        cwd: dir.display().to_string(),
+        argv: vec!["sai".to_string(), SELFTEST_PROMPT.to_string()],
+        exit_code: 0,
+        generated_command: Some(state.command.clone()),
+        unsafe_mode: false,
+        confirm: false,
+        explain: false,
+        scope: None,
+        peek_files: Vec::new(),
+        notes: Some("selftest".to_string()),
+        usage: None,
+        model: None,
+        seed: None,
+        extra_tools: Vec::new(),
+        environment: None,
+        output: None,
+        review: None,
+    };
+    history::write_entry_to(&path, entry.clone(), &history::Retention::default())?;
+    let read = history::read_entries_from_file(&path)?;
+    if read != [entry] {
+        return Err(anyhow!("The entry read back differs from the one written"));
+    }
+    Ok(format!("wrote and read back {}", path.display()))
+}
+
+/// Prints the report for `sai selftest`; the exit code is 1 when a check failed.
+pub fn run_selftest() -> Result<i32> {
+    let dir = env::temp_dir().join(format!("sai-selftest-{}", process::id()));
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    println!(
+        "sai selftest: sai-cli {} on {}/{}",
+        env!("CARGO_PKG_VERSION"),
+        env::consts::OS,
+        env::consts::ARCH
+    );
+    let checks = run_checks(&dir);
+    fs::remove_dir_all(&dir).ok();
+    for check in &checks {
+        match &check.result {
+            Some(Ok(detail)) => println!("  PASS  {:<11} {}", check.name, detail),
+            Some(Err(err)) => println!("  FAIL  {:<11} {:#}", check.name, err),
+            None => println!("  SKIP  {:<11} (an earlier check failed)", check.name),
+        }
+    }
+    let passed = checks.iter().filter(|c| c.passed()).count();
+    if passed == checks.len() {
+        println!("All {} checks passed.", passed);
+        Ok(0)
+    } else {
+        println!("{} of {} checks passed.", passed, checks.len());
+        Ok(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn every_subsystem_passes_in_a_clean_directory() {
+        let temp = TempDir::new().unwrap();
+        let checks = run_checks(temp.path());
+        for check in &checks {
+            assert!(check.passed(), "{}: {:?}", check.name, check.result);
+        }
+        assert_eq!(checks.len(), 6);
+    }
+}
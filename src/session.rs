@@ -0,0 +1,546 @@
+//! Embedding API for the sai pipeline.
+//!
+//! A [`Session`] bundles the resolved AI settings, the active prompt config and
+//! the generator/executor backends. Callers drive it step by step:
+//!
+//! 1. [`Session::configure`] (or [`Session::with_backends`]) loads configuration,
+//! 2. [`Session::generate`] asks the LLM for a command line,
+//! 3. [`Session::validate`] applies the tool allowlist and operator checks,
+//! 4. [`Session::execute`] runs it after consulting a [`ConfirmationHook`].
+//!
+//! Validation and execution go through the same policy as the CLI: the
+//! organization policy, read-only mode, the path jail, the network guard,
+//! the tools' `cwd_must_match:` and `chdir:`, `env_policy` and `limits`.
+//!
+//! [`Session::run`] chains all steps for the common case.
+
+use crate::app::{
+    explain_request, load_run_config, policy_exec_options, read_only_mode, restrict_tools,
+    validate_command, EXPLAIN_SYSTEM_PROMPT,
+};
+use crate::config::{
+    find_global_config_path, load_prompt_config, resolve_ai_config, EffectiveAiConfig,
+    GlobalConfig, PromptConfig,
+};
+use crate::envinfo::invoked_tools;
+use crate::executor::{CommandExecutor, ExecOptions, ShellCommandExecutor};
+use crate::jail::{jail_for_run, PathJail};
+use crate::llm::{ChatClient, CommandGenerator, HttpCommandGenerator};
+use crate::network::NetworkGuard;
+use crate::orgpolicy::{self, OrgPolicy};
+use crate::peek::build_peek_context;
+use crate::prompt::{build_system_prompt, should_force_explain};
+use crate::risk::{self, ConfirmMode, RiskLevel};
+use crate::safety::runs_in_shell;
+use crate::workdir;
+use anyhow::{anyhow, Context, Result};
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Per-session settings, mirroring the corresponding CLI flags.
+#[derive(Debug, Clone, Default)]
+pub struct SessionOptions {
+    /// Prompt config file to use instead of the global `default_prompt`.
+    pub prompt_config: Option<PathBuf>,
+    /// Disable operator-level safety checks (always requires confirmation).
+    pub unsafe_mode: bool,
+    /// Path or glob hint to narrow the LLM response.
+    pub scope: Option<String>,
+    /// Sample data files sent to the LLM for schema inference.
+    pub peek: Vec<PathBuf>,
+    /// Columns or JSON paths the peek samples are restricted to.
+    pub peek_fields: Vec<String>,
+    /// Only allow read-only tools (also on with SAI_READ_ONLY).
+    pub read_only: bool,
+    /// Confine path arguments to the working directory (`--jail`).
+    pub jail: bool,
+    /// Let network tools run without confirmation (`--allow-network`).
+    pub allow_network: bool,
+}
+
+/// A command that passed validation and is ready to be confirmed and executed.
+/// Only [`Session::validate`] creates one, so [`Session::execute`] never runs
+/// a command that skipped the checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatedCommand {
+    command: String,
+    tokens: Vec<String>,
+    dir: Option<PathBuf>,
+    confirmation_required: bool,
+    explain_required: bool,
+}
+
+impl ValidatedCommand {
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn tokens(&self) -> &[String] {
+        &self.tokens
+    }
+
+    /// Directory the command runs in when one of its tools sets `chdir:`;
+    /// the working directory otherwise.
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    /// True when sai policy requires explicit confirmation: unsafe mode, a
+    /// `force_explain` or destructive tool, a network tool, the organization
+    /// policy or a `confirmation` mode. Hooks may still ask otherwise.
+    pub fn confirmation_required(&self) -> bool {
+        self.confirmation_required
+    }
+
+    /// True when the tool is configured with `force_explain`.
+    pub fn explain_required(&self) -> bool {
+        self.explain_required
+    }
+}
+
+/// Result of [`Session::execute`] and [`Session::run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Executed(i32),
+    Declined,
+}
+
+/// Decides whether a validated command may run. Implemented for closures.
+pub trait ConfirmationHook {
+    fn confirm(&mut self, command: &ValidatedCommand) -> Result<bool>;
+}
+
+impl<F> ConfirmationHook for F
+where
+    F: FnMut(&ValidatedCommand) -> Result<bool>,
+{
+    fn confirm(&mut self, command: &ValidatedCommand) -> Result<bool> {
+        self(command)
+    }
+}
+
+pub struct Session<G = HttpCommandGenerator, E = ShellCommandExecutor> {
+    ai: EffectiveAiConfig,
+    prompt: PromptConfig,
+    system_prompt: String,
+    allowed_tools: Vec<String>,
+    options: SessionOptions,
+    global_cfg: GlobalConfig,
+    org_policy: OrgPolicy,
+    read_only: bool,
+    jail: Option<PathJail>,
+    network: Option<NetworkGuard>,
+    cwd: PathBuf,
+    generator: G,
+    executor: E,
+}
+
+impl Session {
+    /// Loads the global config from the standard location, with the project
+    /// policy of the working directory applied, and uses the HTTP generator
+    /// and shell executor.
+    pub fn configure(options: SessionOptions) -> Result<Self> {
+        let cwd = env::current_dir().context("Failed to determine current directory")?;
+        let global_cfg = load_run_config(&find_global_config_path(), &cwd)?;
+        Self::with_backends(
+            options,
+            global_cfg,
+            HttpCommandGenerator::try_new()?,
+            ShellCommandExecutor,
+        )
+    }
+}
+
+impl<G, E> Session<G, E>
+where
+    G: CommandGenerator + ChatClient,
+    E: CommandExecutor,
+{
+    /// Builds a session from an already loaded global config and custom
+    /// backends. The organization policy is loaded from its standard location.
+    pub fn with_backends(
+        options: SessionOptions,
+        global_cfg: GlobalConfig,
+        generator: G,
+        executor: E,
+    ) -> Result<Self> {
+        let org_policy = OrgPolicy::load(&orgpolicy::org_policy_path())?.unwrap_or_default();
+        Self::with_policy(options, global_cfg, org_policy, generator, executor)
+    }
+
+    fn with_policy(
+        options: SessionOptions,
+        global_cfg: GlobalConfig,
+        org_policy: OrgPolicy,
+        generator: G,
+        executor: E,
+    ) -> Result<Self> {
+        org_policy.check_unsafe(options.unsafe_mode)?;
+        let mut prompt = match options.prompt_config.as_ref() {
+            Some(path) => load_prompt_config(path)?,
+            None => global_cfg
+                .default_prompt
+                .clone()
+                .ok_or_else(|| anyhow!("No default_prompt found in global config"))?,
+        };
+        let read_only = read_only_mode(options.read_only);
+        restrict_tools(
+            &mut prompt,
+            &org_policy,
+            &[],
+            read_only,
+            options.unsafe_mode,
+        )?;
+        let (system_prompt, allowed_tools) = build_system_prompt(&prompt)?;
+        let mut ai = resolve_ai_config(global_cfg.ai.clone())?;
+        ai.redaction = global_cfg.redaction.clone().unwrap_or_default();
+        org_policy.check_provider(&ai)?;
+        let cwd = env::current_dir().context("Failed to determine current directory")?;
+        let jail = jail_for_run(global_cfg.path_jail.as_ref(), options.jail, &cwd)?;
+        let network = NetworkGuard::for_run(global_cfg.network.as_ref(), options.allow_network);
+
+        Ok(Self {
+            ai,
+            prompt,
+            system_prompt,
+            allowed_tools,
+            options,
+            global_cfg,
+            org_policy,
+            read_only,
+            jail,
+            network,
+            cwd,
+            generator,
+            executor,
+        })
+    }
+
+    pub fn allowed_tools(&self) -> &[String] {
+        &self.allowed_tools
+    }
+
+    /// Asks the LLM for a command line satisfying the natural language prompt.
+    pub fn generate(&self, nl_prompt: &str) -> Result<String> {
+        let peek_context = build_peek_context(&self.options.peek, &self.options.peek_fields)?;
+        self.generator
+            .generate(
+                &self.ai,
+                &self.system_prompt,
+                nl_prompt,
+                self.options.scope.as_deref(),
+                peek_context.as_deref(),
+            )
+            .context("Failed to obtain command from LLM")
+    }
+
+    /// Applies the same checks as a CLI run: the tool allowlist, operators,
+    /// templates, read-only mode, the path jail, the network guard and the
+    /// tools' `cwd_must_match:` and `chdir:`.
+    pub fn validate(&self, cmd_line: &str) -> Result<ValidatedCommand> {
+        let tokens = validate_command(
+            cmd_line,
+            &self.allowed_tools,
+            &self.prompt,
+            self.jail.as_ref(),
+            self.network.as_ref(),
+            self.options.unsafe_mode,
+            self.read_only,
+        )?;
+        let shell = runs_in_shell(cmd_line, self.options.unsafe_mode);
+        let dir = workdir::run_dir(
+            &self.prompt.tools,
+            &invoked_tools(cmd_line, shell),
+            &self.cwd,
+        )?;
+        let explain_required = should_force_explain(&self.prompt.tools, cmd_line);
+        let destructive = risk::destructive_tool(self.global_cfg.confirmation.as_ref(), cmd_line);
+        let risk = match destructive {
+            Some(_) => RiskLevel::High,
+            None => risk::classify(cmd_line, &self.prompt.tools),
+        };
+        let risk_mode = self
+            .global_cfg
+            .confirmation
+            .as_ref()
+            .map(|c| c.mode_for(risk))
+            .unwrap_or(ConfirmMode::Auto);
+        let network_tools = self
+            .network
+            .as_ref()
+            .map(|n| n.confirm_tools(cmd_line, &self.prompt.tools))
+            .unwrap_or_default();
+
+        Ok(ValidatedCommand {
+            command: cmd_line.to_string(),
+            tokens,
+            dir,
+            confirmation_required: self.options.unsafe_mode
+                || explain_required
+                || destructive.is_some()
+                || self.org_policy.force_confirm
+                || !network_tools.is_empty()
+                || risk_mode != ConfirmMode::Auto,
+            explain_required,
+        })
+    }
+
+    /// Asks the LLM to explain a command in concise technical terms.
+    pub fn explain(&self, command: &ValidatedCommand) -> Result<String> {
+        self.generator.respond(
+            &self.ai,
+            EXPLAIN_SYSTEM_PROMPT,
+            &explain_request(&command.command),
+            0.0,
+        )
+    }
+
+    /// Runs a validated command if the hook approves it.
+    pub fn execute(
+        &self,
+        command: &ValidatedCommand,
+        hook: &mut dyn ConfirmationHook,
+    ) -> Result<Outcome> {
+        if !hook.confirm(command)? {
+            return Ok(Outcome::Declined);
+        }
+
+        let shell = runs_in_shell(&command.command, self.options.unsafe_mode);
+        let opts = ExecOptions {
+            dir: command.dir.clone(),
+            ..policy_exec_options(
+                &self.global_cfg,
+                &self.prompt.tools,
+                &command.command,
+                shell,
+                None,
+            )
+        };
+        let status = self
+            .executor
+            .execute_with(&command.command, &command.tokens, shell, &opts)?;
+        Ok(Outcome::Executed(status))
+    }
+
+    /// Generates, validates and executes in one call.
+    pub fn run(&self, nl_prompt: &str, hook: &mut dyn ConfirmationHook) -> Result<Outcome> {
+        let cmd_line = self.generate(nl_prompt)?;
+        let command = self.validate(&cmd_line)?;
+        self.execute(&command, hook)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct FixedGenerator(&'static str);
+
+    impl CommandGenerator for FixedGenerator {
+        fn generate(
+            &self,
+            _ai: &EffectiveAiConfig,
+            _system_prompt: &str,
+            _nl_prompt: &str,
+            _scope_hint: Option<&str>,
+            _peek_text: Option<&str>,
+        ) -> Result<String> {
+            Ok(self.0.to_string())
+        }
+    }
+
+    impl ChatClient for FixedGenerator {
+        fn respond(
+            &self,
+            _ai: &EffectiveAiConfig,
+            _system_prompt: &str,
+            _user_prompt: &str,
+            _temperature: f32,
+        ) -> Result<String> {
+            Ok("explained".to_string())
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingExecutor {
+        ran: RefCell<Vec<String>>,
+        dirs: RefCell<Vec<Option<PathBuf>>>,
+    }
+
+    impl CommandExecutor for RecordingExecutor {
+        fn execute(&self, cmd_line: &str, _tokens: &[String], _unsafe_mode: bool) -> Result<i32> {
+            self.ran.borrow_mut().push(cmd_line.to_string());
+            Ok(0)
+        }
+
+        fn execute_with(
+            &self,
+            cmd_line: &str,
+            tokens: &[String],
+            unsafe_mode: bool,
+            opts: &ExecOptions,
+        ) -> Result<i32> {
+            self.dirs.borrow_mut().push(opts.dir.clone());
+            self.execute(cmd_line, tokens, unsafe_mode)
+        }
+    }
+
+    fn global_config() -> GlobalConfig {
+        serde_yaml::from_str(
+            r#"
+ai:
+  provider: openai
+  openai_api_key: test-key
+  openai_model: test-model
+default_prompt:
+  tools:
+    - name: echo
+      config: "echo tool"
+"#,
+        )
+        .unwrap()
+    }
+
+    fn session(cmd: &'static str) -> Session<FixedGenerator, RecordingExecutor> {
+        Session::with_policy(
+            SessionOptions::default(),
+            global_config(),
+            OrgPolicy::default(),
+            FixedGenerator(cmd),
+            RecordingExecutor::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn run_executes_after_hook_approves() {
+        let session = session("echo hi");
+        let mut seen = Vec::new();
+        let outcome = session
+            .run("say hi", &mut |cmd: &ValidatedCommand| {
+                seen.push(cmd.command.clone());
+                Ok(true)
+            })
+            .unwrap();
+
+        assert_eq!(outcome, Outcome::Executed(0));
+        assert_eq!(seen, vec!["echo hi".to_string()]);
+        assert_eq!(*session.executor.ran.borrow(), vec!["echo hi".to_string()]);
+    }
+
+    #[test]
+    fn declined_command_does_not_run() {
+        let session = session("echo hi");
+        let outcome = session
+            .run("say hi", &mut |_: &ValidatedCommand| Ok(false))
+            .unwrap();
+
+        assert_eq!(outcome, Outcome::Declined);
+        assert!(session.executor.ran.borrow().is_empty());
+    }
+
+    #[test]
+    fn validation_rejects_unlisted_tools() {
+        let session = session("rm -rf /");
+        let err = session
+            .run("clean up", &mut |_: &ValidatedCommand| Ok(true))
+            .unwrap_err();
+        assert!(err.to_string().contains("Disallowed command 'rm'"));
+    }
+
+    #[test]
+    fn read_only_session_rejects_modifying_tools() {
+        let mut cfg = global_config();
+        cfg.default_prompt = Some(
+            serde_yaml::from_str(
+                "tools:\n  - name: ls\n    config: list\n    read_only: true\n  - name: rm\n    config: remove\n",
+            )
+            .unwrap(),
+        );
+        let session = Session::with_policy(
+            SessionOptions {
+                read_only: true,
+                ..Default::default()
+            },
+            cfg,
+            OrgPolicy::default(),
+            FixedGenerator("rm notes.txt"),
+            RecordingExecutor::default(),
+        )
+        .unwrap();
+        assert_eq!(session.allowed_tools(), ["ls"]);
+        assert!(session.validate("rm notes.txt").is_err());
+        assert!(session.validate("ls").is_ok());
+    }
+
+    #[test]
+    fn tool_directory_rules_apply_to_session_runs() {
+        let mut cfg = global_config();
+        cfg.default_prompt = Some(
+            serde_yaml::from_str(
+                "tools:\n  - name: ls\n    config: list\n    chdir: src\n  - name: rm\n    config: remove\n    cwd_must_match: /nowhere/**\n",
+            )
+            .unwrap(),
+        );
+        let session = Session::with_policy(
+            SessionOptions::default(),
+            cfg,
+            OrgPolicy::default(),
+            FixedGenerator("ls"),
+            RecordingExecutor::default(),
+        )
+        .unwrap();
+        let err = session.validate("rm notes.txt").unwrap_err();
+        assert!(err.to_string().contains("cwd_must_match"));
+
+        let src = env::current_dir().unwrap().join("src");
+        let outcome = session
+            .run("list", &mut |cmd: &ValidatedCommand| {
+                Ok(cmd.dir() == Some(&*src))
+            })
+            .unwrap();
+        assert_eq!(outcome, Outcome::Executed(0));
+        assert_eq!(*session.executor.dirs.borrow(), vec![Some(src)]);
+    }
+
+    #[test]
+    fn org_policy_forces_confirmation_and_forbids_unsafe() {
+        let policy: OrgPolicy =
+            serde_yaml::from_str("forbid_unsafe: true\nforce_confirm: true\n").unwrap();
+        let session = Session::with_policy(
+            SessionOptions::default(),
+            global_config(),
+            policy.clone(),
+            FixedGenerator("echo hi"),
+            RecordingExecutor::default(),
+        )
+        .unwrap();
+        assert!(session.validate("echo hi").unwrap().confirmation_required);
+
+        let unsafe_session = Session::with_policy(
+            SessionOptions {
+                unsafe_mode: true,
+                ..Default::default()
+            },
+            global_config(),
+            policy,
+            FixedGenerator("echo hi"),
+            RecordingExecutor::default(),
+        );
+        assert!(unsafe_session.is_err());
+    }
+
+    #[test]
+    fn missing_default_prompt_is_an_error() {
+        let mut cfg = global_config();
+        cfg.default_prompt = None;
+        let result = Session::with_backends(
+            SessionOptions::default(),
+            cfg,
+            FixedGenerator("echo"),
+            RecordingExecutor::default(),
+        );
+        assert!(result.is_err());
+    }
+}
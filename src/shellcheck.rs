@@ -0,0 +1,156 @@
+//! ShellCheck for generated commands (`shellcheck:` in the global config).
+//! When `shellcheck` is on PATH, the command line is checked as a POSIX sh
+//! script before it runs; findings are shown at confirmation, or block the
+//! command with `blocking: true`.
+
+use crate::envinfo::{find_on_path, powershell_program};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// `shellcheck:` section of the global config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShellcheckConfig {
+    /// Check commands when shellcheck is installed.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Refuse to run commands with findings instead of only showing them.
+    #[serde(default)]
+    pub blocking: bool,
+}
+
+impl Default for ShellcheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            blocking: false,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// One ShellCheck finding (warning or error severity).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub column: usize,
+    pub level: String,
+    pub message: String,
+    pub code: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, column {}): {}",
+            self.code, self.level, self.column, self.message
+        )
+    }
+}
+
+/// Findings for `cmd_line`, or `None` when checking is off, shellcheck is
+/// not installed or commands are PowerShell rather than sh.
+pub fn check_command(cfg: &ShellcheckConfig, cmd_line: &str) -> Result<Option<Vec<Finding>>> {
+    if !cfg.enabled || powershell_program().is_some() {
+        return Ok(None);
+    }
+    let Some(program) = find_on_path("shellcheck") else {
+        return Ok(None);
+    };
+    let mut child = Command::new(program)
+        .args(["-s", "sh", "-S", "warning", "-f", "gcc", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to start shellcheck")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        writeln!(stdin, "{}", cmd_line).context("Failed to send the command to shellcheck")?;
+    }
+    let output = child
+        .wait_with_output()
+        .context("Failed to run shellcheck")?;
+    // Exit code 1 means findings; anything else besides 0 is a failure.
+    if !matches!(output.status.code(), Some(0 | 1)) {
+        return Err(anyhow!(
+            "shellcheck failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(Some(parse_gcc_output(&String::from_utf8_lossy(
+        &output.stdout,
+    ))))
+}
+
+/// Parses `-f gcc` lines such as
+/// `-:1:5: warning: Double quote to prevent globbing. [SC2086]`.
+fn parse_gcc_output(text: &str) -> Vec<Finding> {
+    text.lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(5, ':');
+            let (_file, _line) = (parts.next()?, parts.next()?);
+            let column = parts.next()?.trim().parse().ok()?;
+            let level = parts.next()?.trim().to_string();
+            let rest = parts.next()?.trim();
+            let (message, code) = match rest.rsplit_once(" [") {
+                Some((message, code)) => (message, code.trim_end_matches(']')),
+                None => (rest, ""),
+            };
+            Some(Finding {
+                column,
+                level,
+                message: message.to_string(),
+                code: code.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The error for `blocking: true`.
+pub fn blocking_error(findings: &[Finding]) -> anyhow::Error {
+    let list: Vec<String> = findings.iter().map(|f| format!("  {}", f)).collect();
+    anyhow!(
+        "ShellCheck reported {} issue(s) in the generated command (shellcheck.blocking is on):\n{}",
+        findings.len(),
+        list.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gcc_output_is_parsed_into_findings() {
+        let text = concat!(
+            "-:1:6: warning: Double quote to prevent globbing and word splitting. [SC2086]\n",
+            "-:1:20: error: Couldn't parse this test expression. [SC1073]\n",
+            "garbage\n",
+        );
+        let findings = parse_gcc_output(text);
+        assert_eq!(findings.len(), 2);
+        assert_eq!(
+            findings[0],
+            Finding {
+                column: 6,
+                level: "warning".to_string(),
+                message: "Double quote to prevent globbing and word splitting.".to_string(),
+                code: "SC2086".to_string(),
+            }
+        );
+        assert_eq!(
+            findings[1].to_string(),
+            "SC1073 (error, column 20): Couldn't parse this test expression."
+        );
+        let off = ShellcheckConfig {
+            enabled: false,
+            blocking: true,
+        };
+        assert_eq!(check_command(&off, "echo $x").unwrap(), None);
+    }
+}
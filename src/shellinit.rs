@@ -0,0 +1,71 @@
+//! `sai shell-init [bash|zsh|fish]`: shell functions that put sai on a key
+//! binding. Ctrl-X Ctrl-A sends the request typed on the command line to
+//! `sai --fill` and replaces it with the validated command, which the user
+//! reviews, edits and runs like anything typed by hand.
+
+use crate::favorites::AliasShell;
+
+const BASH: &str = r#"# sai shell integration: eval "$(sai shell-init bash)" in ~/.bashrc
+_sai_fill() {
+  [[ -n $READLINE_LINE ]] || return
+  local cmd
+  cmd=$(sai --fill -- "$READLINE_LINE" </dev/tty) && [[ -n $cmd ]] || return
+  READLINE_LINE=$cmd
+  READLINE_POINT=${#cmd}
+}
+bind -x '"\C-x\C-a": _sai_fill'
+"#;
+
+const ZSH: &str = r#"# sai shell integration: eval "$(sai shell-init zsh)" in ~/.zshrc
+_sai_fill() {
+  [[ -n $BUFFER ]] || return
+  local cmd
+  zle -I
+  if cmd=$(sai --fill -- "$BUFFER" </dev/tty) && [[ -n $cmd ]]; then
+    BUFFER=$cmd
+    CURSOR=${#BUFFER}
+  fi
+  zle reset-prompt
+}
+zle -N _sai_fill
+bindkey '^X^A' _sai_fill
+"#;
+
+const FISH: &str = r#"# sai shell integration: sai shell-init fish | source in config.fish
+function _sai_fill
+    set -l request (commandline)
+    test -n "$request"; or return
+    set -l cmd (sai --fill -- "$request" </dev/tty)
+    and test -n "$cmd"
+    and commandline -r -- "$cmd"
+    commandline -f repaint
+end
+bind \cx\ca _sai_fill
+"#;
+
+/// The functions and key binding for `shell`.
+pub fn init_script(shell: AliasShell) -> &'static str {
+    match shell {
+        AliasShell::Bash => BASH,
+        AliasShell::Zsh => ZSH,
+        AliasShell::Fish => FISH,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_fill_the_command_line_from_a_key_binding() {
+        for (shell, line, bind) in [
+            (AliasShell::Bash, "\"$READLINE_LINE\"", "bind -x"),
+            (AliasShell::Zsh, "\"$BUFFER\"", "bindkey '^X^A'"),
+            (AliasShell::Fish, "\"$request\"", "bind \\cx\\ca"),
+        ] {
+            let script = init_script(shell);
+            assert!(script.contains(&format!("sai --fill -- {} </dev/tty", line)));
+            assert!(script.contains(bind));
+        }
+    }
+}
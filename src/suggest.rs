@@ -0,0 +1,459 @@
+use crate::config::{load_global_config, PromptConfig};
+use crate::envinfo;
+use crate::history::{self, HistoryEntry};
+use crate::ops::default_tool_config;
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Unused tools are only proposed for removal once history has at least this many generated commands.
+pub const MIN_COMMANDS_FOR_REMOVALS: usize = 20;
+const DIFF_CONTEXT_LINES: usize = 3;
+const REJECTION_PREFIX: &str = "Disallowed command '";
+
+/// Tool usage statistics gathered from history.
+#[derive(Debug, Default)]
+pub struct ToolStats {
+    /// Executed commands per tool, counting every segment of a pipeline.
+    pub used: BTreeMap<String, usize>,
+    /// Validation rejections per tool that is not in the allowlist.
+    pub rejected: BTreeMap<String, usize>,
+    /// Number of entries whose command was executed.
+    pub commands: usize,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Suggestions {
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+pub fn collect_tool_stats(entries: &[HistoryEntry]) -> ToolStats {
+    let mut stats = ToolStats::default();
+
+    for entry in entries {
+        // Only executed runs record their environment; a declined or
+        // rejected command does not show that a tool is used.
+        let executed = entry
+            .generated_command
+            .as_deref()
+            .filter(|_| entry.environment.is_some());
+        if let Some(cmd) = executed {
+            stats.commands += 1;
+            for tool in envinfo::invoked_tools(cmd, entry.unsafe_mode) {
+                *stats.used.entry(tool).or_default() += 1;
+            }
+        }
+
+        if let Some(tool) = entry.notes.as_deref().and_then(rejected_tool) {
+            *stats.rejected.entry(tool).or_default() += 1;
+        }
+    }
+
+    stats
+}
+
+pub fn suggest_changes(
+    prompt: &PromptConfig,
+    stats: &ToolStats,
+    min_rejections: usize,
+) -> Suggestions {
+    let configured: Vec<&str> = prompt.tools.iter().map(|t| t.name.as_str()).collect();
+
+    let add = stats
+        .rejected
+        .iter()
+        .filter(|(tool, count)| **count >= min_rejections && !configured.contains(&tool.as_str()))
+        .map(|(tool, _)| tool.clone())
+        .collect();
+
+    let remove = if stats.commands >= MIN_COMMANDS_FOR_REMOVALS {
+        configured
+            .iter()
+            .filter(|name| !stats.used.contains_key(**name))
+            .map(|name| name.to_string())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    Suggestions { add, remove }
+}
+
+/// Analyzes history and prints a unified diff against the global config that
+/// applies the suggested tool additions and removals.
+pub fn print_prompt_suggestions(global_path: &Path, min_rejections: usize) -> Result<()> {
+    let global_cfg = load_global_config(global_path)?;
+    let prompt = global_cfg
+        .default_prompt
+        .ok_or_else(|| anyhow!("No default_prompt found in {}", global_path.display()))?;
+
+    let entries = history::read_all_entries()?;
+    let stats = collect_tool_stats(&entries);
+    let suggestions = suggest_changes(&prompt, &stats, min_rejections);
+
+    if suggestions.add.is_empty() && suggestions.remove.is_empty() {
+        eprintln!(
+            "No suggestions: analyzed {} history entries ({} generated commands).",
+            entries.len(),
+            stats.commands
+        );
+        return Ok(());
+    }
+
+    for tool in &suggestions.add {
+        eprintln!(
+            "+ {}: rejected {} time(s) because it is not in the allowlist",
+            tool, stats.rejected[tool]
+        );
+    }
+    for tool in &suggestions.remove {
+        eprintln!(
+            "- {}: unused in the last {} generated commands",
+            tool, stats.commands
+        );
+    }
+
+    let original = fs::read_to_string(global_path).with_context(|| {
+        format!(
+            "Failed to read global config file {}",
+            global_path.display()
+        )
+    })?;
+    let updated = apply_suggestions_to_text(&original, &suggestions)?;
+    let label = global_path.display().to_string();
+    print!("{}", unified_diff(&original, &updated, &label));
+
+    eprintln!();
+    eprintln!(
+        "Apply with: sai prompts suggest > sai.diff && patch {} sai.diff",
+        label
+    );
+    Ok(())
+}
+
+/// Edits the YAML text of a global config, preserving formatting and comments.
+pub fn apply_suggestions_to_text(text: &str, suggestions: &Suggestions) -> Result<String> {
+    let mut lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+
+    for tool in &suggestions.remove {
+        if let Some((start, end)) = find_tool_block(&lines, tool) {
+            lines.drain(start..end);
+        }
+    }
+
+    if !suggestions.add.is_empty() {
+        let last = lines
+            .iter()
+            .rposition(|l| parse_tool_name(l).is_some())
+            .ok_or_else(|| anyhow!("No tool entries found in the config to extend"))?;
+        let indent = leading_spaces(&lines[last]);
+        let name = parse_tool_name(&lines[last]).unwrap_or_default();
+        let (_, mut insert_at) = find_tool_block(&lines, &name).unwrap_or((last, last + 1));
+        while insert_at > last + 1 && lines[insert_at - 1].trim().is_empty() {
+            insert_at -= 1;
+        }
+
+        let pad = " ".repeat(indent);
+        let mut block = Vec::new();
+        for tool in &suggestions.add {
+            block.push(String::new());
+            block.push(format!("{}- name: {}", pad, tool));
+            block.push(format!("{}  config: |", pad));
+            for line in default_tool_config(tool).lines() {
+                block.push(format!("{}    {}", pad, line));
+            }
+        }
+        lines.splice(insert_at..insert_at, block);
+    }
+
+    let mut out = lines.join("\n");
+    if text.ends_with('\n') {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Builds a unified diff of two texts that `patch` can apply.
+pub fn unified_diff(old: &str, new: &str, label: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&a, &b);
+
+    if ops.iter().all(|op| matches!(op, DiffOp::Equal(..))) {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", label, label);
+    let mut idx = 0;
+    while idx < ops.len() {
+        let Some(change) = ops[idx..]
+            .iter()
+            .position(|op| !matches!(op, DiffOp::Equal(..)))
+        else {
+            break;
+        };
+        let start = (idx + change).saturating_sub(DIFF_CONTEXT_LINES).max(idx);
+
+        // Extend the hunk while changes are within 2*context of each other.
+        let mut end = idx + change;
+        let mut equal_run = 0;
+        let mut cursor = end;
+        while cursor < ops.len() {
+            if matches!(ops[cursor], DiffOp::Equal(..)) {
+                equal_run += 1;
+                if equal_run > DIFF_CONTEXT_LINES * 2 {
+                    break;
+                }
+            } else {
+                equal_run = 0;
+                end = cursor;
+            }
+            cursor += 1;
+        }
+        let end = (end + 1 + DIFF_CONTEXT_LINES).min(ops.len());
+
+        let hunk = &ops[start..end];
+        let (old_start, new_start) = op_position(&ops, start);
+        let old_len = hunk
+            .iter()
+            .filter(|op| !matches!(op, DiffOp::Insert(_)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|op| !matches!(op, DiffOp::Delete(_)))
+            .count();
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_len),
+            hunk_range(new_start, new_len)
+        ));
+        for op in hunk {
+            match op {
+                DiffOp::Equal(i) => out.push_str(&format!(" {}\n", a[*i])),
+                DiffOp::Delete(i) => out.push_str(&format!("-{}\n", a[*i])),
+                DiffOp::Insert(j) => out.push_str(&format!("+{}\n", b[*j])),
+            }
+        }
+        idx = end;
+    }
+
+    out
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DiffOp {
+    Equal(usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+fn diff_lines(a: &[&str], b: &[&str]) -> Vec<DiffOp> {
+    let (n, m) = (a.len(), b.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && a[i] == b[j] {
+            ops.push(DiffOp::Equal(i));
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            ops.push(DiffOp::Insert(j));
+            j += 1;
+        } else {
+            ops.push(DiffOp::Delete(i));
+            i += 1;
+        }
+    }
+    ops
+}
+
+/// Returns the 1-based old/new line numbers at which `ops[idx]` starts.
+fn op_position(ops: &[DiffOp], idx: usize) -> (usize, usize) {
+    let old = ops[..idx]
+        .iter()
+        .filter(|op| !matches!(op, DiffOp::Insert(_)))
+        .count();
+    let new = ops[..idx]
+        .iter()
+        .filter(|op| !matches!(op, DiffOp::Delete(_)))
+        .count();
+    (old + 1, new + 1)
+}
+
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start - 1),
+        1 => start.to_string(),
+        _ => format!("{},{}", start, len),
+    }
+}
+
+/// Returns the `[start, end)` line range of the `- name: <tool>` block.
+fn find_tool_block(lines: &[String], tool: &str) -> Option<(usize, usize)> {
+    let start = lines
+        .iter()
+        .position(|l| parse_tool_name(l).as_deref() == Some(tool))?;
+    let indent = leading_spaces(&lines[start]);
+
+    let mut end = start + 1;
+    while end < lines.len() {
+        let line = &lines[end];
+        if !line.trim().is_empty() && leading_spaces(line) <= indent {
+            break;
+        }
+        end += 1;
+    }
+    Some((start, end))
+}
+
+fn parse_tool_name(line: &str) -> Option<String> {
+    let rest = line.trim_start().strip_prefix("- name:")?;
+    let name = rest.trim().trim_matches(|c| c == '"' || c == '\'');
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+fn leading_spaces(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+fn rejected_tool(notes: &str) -> Option<String> {
+    let rest = &notes[notes.find(REJECTION_PREFIX)? + REJECTION_PREFIX.len()..];
+    let end = rest.find('\'')?;
+    Some(rest[..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ToolConfig;
+
+    const CONFIG: &str = "ai:\n  provider: openai\ndefault_prompt:\n  tools:\n  - name: ls\n    config: |\n      list\n\n  - name: stat\n    config: |\n      stat\n";
+
+    fn entry(cmd: Option<&str>, notes: Option<&str>) -> HistoryEntry {
+        HistoryEntry {
+            ts: "2024-01-01T00:00:00Z".to_string(),
+            cwd: "/tmp".to_string(),
+            argv: vec!["sai".to_string()],
+            generated_command: cmd.map(str::to_string),
+            notes: notes.map(str::to_string),
+            // Runs that got past validation executed and recorded one.
+            environment: cmd.filter(|_| notes.is_none()).map(|_| Default::default()),
+            ..Default::default()
+        }
+    }
+
+    fn tool(name: &str) -> ToolConfig {
+        ToolConfig {
+            name: name.to_string(),
+            config: "x".to_string(),
+            force_explain: None,
+            timeout: None,
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
+        }
+    }
+
+    #[test]
+    fn suggests_frequently_rejected_tools() {
+        let notes = "Disallowed command 'jq'. Allowed tools: ls";
+        let entries = vec![
+            entry(Some("jq . a.json"), Some(notes)),
+            entry(Some("jq . b.json"), Some(notes)),
+            entry(Some("ls"), None),
+        ];
+        let prompt = PromptConfig {
+            meta_prompt: None,
+            tools: vec![tool("ls"), tool("stat")],
+            allowed_operators: Vec::new(),
+        };
+
+        let stats = collect_tool_stats(&entries);
+        let suggestions = suggest_changes(&prompt, &stats, 2);
+        assert_eq!(suggestions.add, vec!["jq".to_string()]);
+        // Not enough history to judge unused tools yet.
+        assert!(suggestions.remove.is_empty());
+    }
+
+    #[test]
+    fn suggests_removing_unused_tools_with_enough_history() {
+        let entries: Vec<_> = (0..MIN_COMMANDS_FOR_REMOVALS)
+            .map(|_| entry(Some("ls -la"), None))
+            .collect();
+        let prompt = PromptConfig {
+            meta_prompt: None,
+            tools: vec![tool("ls"), tool("stat")],
+            allowed_operators: Vec::new(),
+        };
+
+        let suggestions = suggest_changes(&prompt, &collect_tool_stats(&entries), 2);
+        assert_eq!(suggestions.remove, vec!["stat".to_string()]);
+    }
+
+    #[test]
+    fn pipeline_segments_count_and_unexecuted_commands_do_not() {
+        let mut piped = entry(Some("ls | stat x"), None);
+        piped.unsafe_mode = true;
+        let entries = vec![piped, entry(Some("rm x"), Some("declined"))];
+
+        let stats = collect_tool_stats(&entries);
+        assert_eq!(stats.commands, 1);
+        assert_eq!(stats.used.get("ls"), Some(&1));
+        assert_eq!(stats.used.get("stat"), Some(&1));
+        assert!(!stats.used.contains_key("rm"));
+    }
+
+    #[test]
+    fn text_edit_and_diff_round_trip() {
+        let suggestions = Suggestions {
+            add: vec!["jq".to_string()],
+            remove: vec!["ls".to_string()],
+        };
+        let updated = apply_suggestions_to_text(CONFIG, &suggestions).unwrap();
+        assert!(!updated.contains("- name: ls"));
+        assert!(updated.contains("  - name: jq\n    config: |\n      Accept"));
+
+        let parsed: crate::config::GlobalConfig = serde_yaml::from_str(&updated).unwrap();
+        let names: Vec<_> = parsed
+            .default_prompt
+            .unwrap()
+            .tools
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(names, vec!["stat".to_string(), "jq".to_string()]);
+
+        let diff = unified_diff(CONFIG, &updated, "config.yaml");
+        assert!(diff.starts_with("--- config.yaml\n+++ config.yaml\n@@ -"));
+        assert!(diff.contains("-  - name: ls\n"));
+        assert!(diff.contains("+  - name: jq\n"));
+    }
+
+    #[test]
+    fn identical_texts_produce_empty_diff() {
+        assert_eq!(unified_diff(CONFIG, CONFIG, "c"), "");
+    }
+}
@@ -0,0 +1,193 @@
+//! Execution targets besides the local machine (`targets:` in the global
+//! config): SSH hosts and running containers. `--list-tools` checks which
+//! configured tools each of them has and renders an availability matrix.
+
+use crate::envinfo::find_on_path;
+use crate::project::ProjectPath;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::process::{Command, Stdio};
+
+/// One entry of the `targets:` list; exactly one of `ssh` and `container`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetConfig {
+    pub name: String,
+    /// `user@host` (or a Host alias from ~/.ssh/config) reached with `ssh`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh: Option<String>,
+    /// Name of a running container, reached with `<engine> exec`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// Container engine for `container` targets; defaults to docker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<String>,
+}
+
+impl TargetConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.ssh.is_some() == self.container.is_some() {
+            return Err(anyhow!(
+                "Target '{}' needs exactly one of 'ssh' and 'container'",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+
+    /// Command that runs `script` with `sh -c` on the target.
+    fn remote_shell(&self, script: &str) -> Result<Command> {
+        self.validate()?;
+        match (&self.ssh, &self.container) {
+            (Some(host), None) => {
+                let mut cmd = Command::new("ssh");
+                cmd.args(["-o", "BatchMode=yes", "-o", "ConnectTimeout=5"])
+                    .arg(host)
+                    .arg(format!("sh -c {}", shell_words::quote(script)));
+                Ok(cmd)
+            }
+            (None, Some(container)) => {
+                let mut cmd = Command::new(self.engine.as_deref().unwrap_or("docker"));
+                cmd.arg("exec").arg(container).args(["sh", "-c", script]);
+                Ok(cmd)
+            }
+            _ => unreachable!("validated above"),
+        }
+    }
+}
+
+/// Which of `tools` the target has, in the same order.
+pub fn probe(target: &TargetConfig, tools: &[String]) -> Result<Vec<bool>> {
+    let output = target
+        .remote_shell(&probe_script(tools))?
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to reach target '{}'", target.name))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!(
+            "Target '{}' could not be checked: {}",
+            target.name,
+            stderr.lines().next().unwrap_or("probe failed").trim()
+        ));
+    }
+    Ok(parse_probe_output(
+        &String::from_utf8_lossy(&output.stdout),
+        tools,
+    ))
+}
+
+/// Prints `+name` or `-name` for every tool, using `command -v`.
+fn probe_script(tools: &[String]) -> String {
+    let names: Vec<String> = tools
+        .iter()
+        .map(|t| shell_words::quote(t).into_owned())
+        .collect();
+    format!(
+        "for t in {}; do if command -v \"$t\" >/dev/null 2>&1; then echo \"+$t\"; else echo \"-$t\"; fi; done",
+        names.join(" ")
+    )
+}
+
+fn parse_probe_output(output: &str, tools: &[String]) -> Vec<bool> {
+    tools
+        .iter()
+        .map(|tool| {
+            output
+                .lines()
+                .any(|line| line.strip_prefix('+') == Some(tool))
+        })
+        .collect()
+}
+
+/// Local availability: project tool directories, then PATH.
+pub fn probe_local(tools: &[String], project: &ProjectPath) -> Vec<bool> {
+    tools
+        .iter()
+        .map(|t| project.resolve(t).is_some() || find_on_path(t).is_some())
+        .collect()
+}
+
+/// Renders a tools × targets table: `x` present, `-` missing, `?` target
+/// could not be checked (the reasons follow the table).
+pub fn render_matrix(tools: &[String], columns: &[(String, Result<Vec<bool>>)]) -> String {
+    let tool_width = tools
+        .iter()
+        .map(|t| t.chars().count())
+        .chain(["Tool".len()])
+        .max()
+        .unwrap_or(4);
+    let widths: Vec<usize> = columns
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .collect();
+
+    let mut out = format!("{:<tool_width$}", "Tool");
+    for ((name, _), width) in columns.iter().zip(&widths) {
+        out.push_str(&format!("  {:<width$}", name));
+    }
+    out = out.trim_end().to_string();
+    out.push('\n');
+
+    for (row, tool) in tools.iter().enumerate() {
+        let mut line = format!("{:<tool_width$}", tool);
+        for ((_, result), width) in columns.iter().zip(&widths) {
+            let mark = match result {
+                Ok(found) if found[row] => "x",
+                Ok(_) => "-",
+                Err(_) => "?",
+            };
+            line.push_str(&format!("  {:<width$}", mark));
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+
+    for (_, result) in columns {
+        if let Err(err) = result {
+            out.push_str(&format!("? {:#}\n", err));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_output_and_matrix_rendering() {
+        let tools = vec!["jq".to_string(), "rsync".to_string()];
+        assert_eq!(
+            parse_probe_output("-jq\n+rsync\n", &tools),
+            vec![false, true]
+        );
+        assert!(probe_script(&tools).starts_with("for t in jq rsync;"));
+
+        let columns = vec![
+            ("local".to_string(), Ok(vec![true, true])),
+            ("web1".to_string(), Ok(vec![false, true])),
+            (
+                "devbox".to_string(),
+                Err(anyhow!("Target 'devbox' could not be checked")),
+            ),
+        ];
+        assert_eq!(
+            render_matrix(&tools, &columns),
+            "Tool   local  web1  devbox\n\
+             jq     x      -     ?\n\
+             rsync  x      x     ?\n\
+             ? Target 'devbox' could not be checked\n"
+        );
+
+        let bad = TargetConfig {
+            name: "both".to_string(),
+            ssh: Some("me@host".to_string()),
+            container: Some("box".to_string()),
+            engine: None,
+        };
+        assert!(probe(&bad, &tools)
+            .unwrap_err()
+            .to_string()
+            .contains("exactly one"));
+    }
+}
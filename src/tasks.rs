@@ -0,0 +1,170 @@
+//! `sai export-task`: a generated and validated command as a Makefile target,
+//! justfile recipe or package.json script, with the prompt as a comment, so
+//! an ad-hoc command can become part of a project's automation.
+
+use crate::cli::Cli;
+use anyhow::{anyhow, Result};
+use clap::{Parser, ValueEnum};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TaskFormat {
+    /// Makefile target
+    Make,
+    /// justfile recipe
+    Just,
+    /// package.json "scripts" entry
+    Npm,
+}
+
+impl TaskFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            TaskFormat::Make => "make",
+            TaskFormat::Just => "just",
+            TaskFormat::Npm => "npm",
+        }
+    }
+}
+
+/// What `sai export-task` asked for; set on the [`Cli`] it runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskExport {
+    pub format: TaskFormat,
+    /// Task name; derived from the prompt when unset.
+    pub name: Option<String>,
+}
+
+/// Words of the prompt used for a derived task name.
+const NAME_WORDS: usize = 4;
+
+/// A task name from the first words of the prompt, e.g. `count-todo-comments`.
+pub fn task_name(prompt: &str) -> String {
+    let words: Vec<String> = prompt
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .take(NAME_WORDS)
+        .map(str::to_ascii_lowercase)
+        .collect();
+    match words.first() {
+        Some(first) if first.starts_with(|c: char| c.is_ascii_alphabetic()) => words.join("-"),
+        Some(_) => format!("task-{}", words.join("-")),
+        None => "task".to_string(),
+    }
+}
+
+/// Names valid for make, just and npm alike: a letter or `_`, then letters,
+/// digits, `_` or `-`.
+fn validate_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Invalid task name '{}': use letters, digits, '_' and '-', starting with a letter",
+            name
+        ))
+    }
+}
+
+/// The sai invocation `sai export-task` runs: `args` with `--print`, so the
+/// command is generated and validated but not executed.
+pub fn export_cli(format: TaskFormat, name: Option<String>, args: &[String]) -> Result<Cli> {
+    if let Some(name) = &name {
+        validate_name(name)?;
+    }
+    let argv = ["sai", "--print"]
+        .into_iter()
+        .map(String::from)
+        .chain(args.iter().cloned());
+    let mut cli = Cli::try_parse_from(argv).map_err(|err| {
+        anyhow!(
+            "export-task arguments are not a valid sai invocation:\n{}",
+            err
+        )
+    })?;
+    cli.export_task = Some(TaskExport { format, name });
+    Ok(cli)
+}
+
+fn comment_lines(prompt: &str, marker: &str) -> String {
+    prompt
+        .trim()
+        .lines()
+        .map(|line| format!("{} {}\n", marker, line.trim_end()))
+        .collect()
+}
+
+/// The stanza to paste into the task runner's file.
+pub fn render_task(format: TaskFormat, name: &str, prompt: &str, command: &str) -> String {
+    match format {
+        TaskFormat::Make => format!(
+            "{}.PHONY: {}\n{}:\n\t{}\n",
+            comment_lines(prompt, "#"),
+            name,
+            name,
+            // make expands $; the shell should see it unchanged.
+            command.replace('$', "$$")
+        ),
+        TaskFormat::Just => format!(
+            "{}{}:\n    {}\n",
+            comment_lines(prompt, "#"),
+            name,
+            command.replace("{{", "{{ \"{{\" }}")
+        ),
+        TaskFormat::Npm => {
+            let json = |text: &str| serde_json::Value::from(text).to_string();
+            format!(
+                "{}: {},\n{}: {}\n",
+                json(&format!("//{}", name)),
+                json(prompt.trim()),
+                json(name),
+                json(command)
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tasks_carry_the_prompt_and_escape_the_runner_syntax() {
+        assert_eq!(
+            task_name("Count TODO comments in src/"),
+            "count-todo-comments-in"
+        );
+        assert_eq!(task_name("2 largest files"), "task-2-largest-files");
+        assert_eq!(task_name("!!"), "task");
+
+        let prompt = "sum the size column";
+        let command = "awk -F, '{ s += $3 } END { print s }' data.csv";
+        assert_eq!(
+            render_task(TaskFormat::Make, "sum-size", prompt, command),
+            "# sum the size column\n.PHONY: sum-size\nsum-size:\n\
+             \tawk -F, '{ s += $$3 } END { print s }' data.csv\n"
+        );
+        assert_eq!(
+            render_task(TaskFormat::Just, "sum-size", prompt, "echo {{x}}"),
+            "# sum the size column\nsum-size:\n    echo {{ \"{{\" }}x}}\n"
+        );
+        let npm = render_task(TaskFormat::Npm, "sum-size", prompt, command);
+        let parsed: serde_json::Value = serde_json::from_str(&format!("{{{}}}", npm)).unwrap();
+        assert_eq!(parsed["sum-size"], command);
+        assert_eq!(parsed["//sum-size"], prompt);
+
+        let cli = export_cli(
+            TaskFormat::Make,
+            None,
+            &["-s".to_string(), ".".to_string(), "list files".to_string()],
+        )
+        .unwrap();
+        assert!(cli.print);
+        assert!(export_cli(TaskFormat::Make, Some("build all".to_string()), &[]).is_err());
+        assert!(export_cli(TaskFormat::Npm, None, &["-c".to_string(), "x".to_string()]).is_err());
+    }
+}
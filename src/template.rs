@@ -0,0 +1,305 @@
+//! Template tools: a tool whose `template` fixes the whole command line, e.g.
+//! `rsync -av {src:dir} {dst:path}`. The model only fills the placeholders and
+//! a generated command using the tool must match the template word for word,
+//! with every placeholder value checked against its type.
+
+use crate::config::ToolConfig;
+use crate::safety::detect_forbidden_operator;
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+
+/// What a placeholder accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Any word.
+    String,
+    /// A whole number.
+    Int,
+    /// A path, existing or not.
+    Path,
+    /// An existing regular file.
+    File,
+    /// An existing directory.
+    Dir,
+}
+
+impl Kind {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "" | "string" => Some(Kind::String),
+            "int" => Some(Kind::Int),
+            "path" => Some(Kind::Path),
+            "file" => Some(Kind::File),
+            "dir" => Some(Kind::Dir),
+            _ => None,
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Kind::String => "a single word",
+            Kind::Int => "a whole number",
+            Kind::Path => "a path",
+            Kind::File => "an existing file",
+            Kind::Dir => "an existing directory",
+        }
+    }
+}
+
+/// One word of a template: literal text around at most one placeholder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Word {
+    prefix: String,
+    placeholder: Option<(String, Kind)>,
+    suffix: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Template {
+    source: String,
+    words: Vec<Word>,
+}
+
+fn parse(tool: &ToolConfig, source: &str) -> Result<Template> {
+    let tokens = shell_words::split(source)
+        .with_context(|| format!("Failed to split the template of tool '{}'", tool.name))?;
+    if tokens.first() != Some(&tool.name) {
+        return Err(anyhow!(
+            "The template of tool '{}' must start with '{}'",
+            tool.name,
+            tool.name
+        ));
+    }
+
+    let mut words = Vec::new();
+    for token in tokens {
+        let Some(open) = token.find('{') else {
+            words.push(Word {
+                prefix: token,
+                placeholder: None,
+                suffix: String::new(),
+            });
+            continue;
+        };
+        let close = token[open..]
+            .find('}')
+            .map(|i| open + i)
+            .ok_or_else(|| anyhow!("Unclosed placeholder in template of '{}'", tool.name))?;
+        let suffix = &token[close + 1..];
+        if suffix.contains(['{', '}']) {
+            return Err(anyhow!(
+                "Template of '{}' has more than one placeholder in '{}'",
+                tool.name,
+                token
+            ));
+        }
+        let inner = &token[open + 1..close];
+        let (name, kind_name) = inner.split_once(':').unwrap_or((inner, ""));
+        let kind = Kind::parse(kind_name.trim()).ok_or_else(|| {
+            anyhow!(
+                "Unknown placeholder type '{}' in template of '{}' (use string, int, path, file or dir)",
+                kind_name,
+                tool.name
+            )
+        })?;
+        words.push(Word {
+            prefix: token[..open].to_string(),
+            placeholder: Some((name.trim().to_string(), kind)),
+            suffix: suffix.to_string(),
+        });
+    }
+    Ok(Template {
+        source: source.to_string(),
+        words,
+    })
+}
+
+/// Checks every tool template, so a typo is reported before the LLM is called.
+pub fn check_tool_templates(tools: &[ToolConfig]) -> Result<()> {
+    for tool in tools {
+        if let Some(source) = &tool.template {
+            parse(tool, source)?;
+        }
+    }
+    Ok(())
+}
+
+/// Instructions added to the tool details for a template tool.
+pub fn describe(tool: &ToolConfig) -> Option<String> {
+    let template = parse(tool, tool.template.as_deref()?).ok()?;
+    let mut text = format!(
+        "Template for {}: {}\nA command using {} must be exactly this template with each \
+         {{name:type}} placeholder replaced by one shell word:",
+        tool.name, template.source, tool.name
+    );
+    for (name, kind) in template.words.iter().filter_map(|w| w.placeholder.as_ref()) {
+        text.push_str(&format!("\n- {}: {}", name, kind.describe()));
+    }
+    text.push_str("\nDo not add, remove or reorder anything else.");
+    Some(text)
+}
+
+/// Fails when the command uses a template tool but does not match its
+/// template. `tokens` are the words of the validated command.
+pub fn check_command(
+    tools: &[ToolConfig],
+    cmd_line: &str,
+    tokens: &[String],
+    unsafe_mode: bool,
+) -> Result<()> {
+    let Some(tool) = tools
+        .iter()
+        .find(|t| t.template.is_some() && tokens.first().is_some_and(|first| t.matches(first)))
+    else {
+        return Ok(());
+    };
+    let template = parse(tool, tool.template.as_deref().unwrap_or_default())?;
+    let mismatch = |reason: String| {
+        anyhow!(
+            "Command for '{}' does not match its template `{}`: {}",
+            tool.name,
+            template.source,
+            reason
+        )
+    };
+
+    // --unsafe runs through a shell, which would reinterpret placeholder values.
+    if unsafe_mode {
+        if let Some(op) = detect_forbidden_operator(cmd_line, &[]) {
+            return Err(mismatch(format!("shell construct '{}' is not allowed", op)));
+        }
+    }
+    if tokens.len() != template.words.len() {
+        return Err(mismatch(format!(
+            "expected {} words, got {}",
+            template.words.len(),
+            tokens.len()
+        )));
+    }
+    for (i, (word, token)) in template.words.iter().zip(tokens).enumerate() {
+        // The tool itself may be invoked by an alias or path.
+        if i == 0 && tool.matches(token) {
+            continue;
+        }
+        let Some((name, kind)) = &word.placeholder else {
+            if &word.prefix != token {
+                return Err(mismatch(format!(
+                    "expected '{}', got '{}'",
+                    word.prefix, token
+                )));
+            }
+            continue;
+        };
+        let value = token
+            .strip_prefix(word.prefix.as_str())
+            .and_then(|rest| rest.strip_suffix(word.suffix.as_str()))
+            .ok_or_else(|| {
+                mismatch(format!(
+                    "'{}' does not have the form '{}{{{}}}{}'",
+                    token, word.prefix, name, word.suffix
+                ))
+            })?;
+        check_value(*kind, value)
+            .map_err(|reason| mismatch(format!("{{{}}} = '{}' {}", name, value, reason)))?;
+    }
+    Ok(())
+}
+
+fn check_value(kind: Kind, value: &str) -> std::result::Result<(), &'static str> {
+    if value.is_empty() {
+        return Err("is empty");
+    }
+    // A leading dash would turn the value into an option.
+    if value.starts_with('-') && !(kind == Kind::Int && value[1..].parse::<u64>().is_ok()) {
+        return Err("may not start with '-'");
+    }
+    // Safe mode expands globs, which would turn one value into many words.
+    if value.contains(['*', '?', '[']) {
+        return Err("may not contain glob characters");
+    }
+    match kind {
+        Kind::String | Kind::Path => Ok(()),
+        Kind::Int if value.parse::<i64>().is_ok() => Ok(()),
+        Kind::Int => Err("is not a whole number"),
+        Kind::File if Path::new(value).is_file() => Ok(()),
+        Kind::File => Err("is not an existing file"),
+        Kind::Dir if Path::new(value).is_dir() => Ok(()),
+        Kind::Dir => Err("is not an existing directory"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn rsync(template: &str) -> ToolConfig {
+        ToolConfig {
+            name: "rsync".to_string(),
+            force_explain: None,
+            timeout: None,
+            template: Some(template.to_string()),
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
+            config: "rsync".to_string(),
+        }
+    }
+
+    fn check(tool: &ToolConfig, cmd: &str) -> Result<()> {
+        let tokens = shell_words::split(cmd).unwrap();
+        check_command(std::slice::from_ref(tool), cmd, &tokens, false)
+    }
+
+    #[test]
+    fn commands_must_match_the_template_and_placeholder_types() {
+        let temp = TempDir::new().unwrap();
+        let src = temp.path().display().to_string();
+        let tool = rsync("rsync -av --bwlimit={kbps:int} {src:dir} {dst:path}");
+
+        assert!(check(&tool, &format!("rsync -av --bwlimit=500 {} /backup/x", src)).is_ok());
+
+        let err = check(&tool, &format!("rsync -av --delete --bwlimit=5 {} /b", src)).unwrap_err();
+        assert!(err.to_string().contains("expected 5 words, got 6"));
+        let err = check(&tool, "rsync -av --bwlimit=5 /no/such/dir /b").unwrap_err();
+        assert!(err.to_string().contains("not an existing directory"));
+        let err = check(&tool, &format!("rsync -av --bwlimit=fast {} /b", src)).unwrap_err();
+        assert!(err.to_string().contains("not a whole number"));
+        let err = check(&tool, &format!("rsync -av --bwlimit=5 {} -e", src)).unwrap_err();
+        assert!(err.to_string().contains("may not start with '-'"));
+        let err = check(&tool, &format!("rsync -av --bwlimit=5 {} /b/*", src)).unwrap_err();
+        assert!(err.to_string().contains("glob characters"));
+
+        let other = ToolConfig {
+            template: None,
+            env: Default::default(),
+            allowed_flags: Vec::new(),
+            denied_flags: Vec::new(),
+            read_only: None,
+            aliases: Vec::new(),
+            network: None,
+            cwd_must_match: None,
+            chdir: None,
+            ..rsync("")
+        };
+        assert!(check(&other, "rsync --anything goes").is_ok());
+    }
+
+    #[test]
+    fn malformed_templates_are_reported() {
+        let err = check_tool_templates(&[rsync("cp {a:path} {b:path}")]).unwrap_err();
+        assert!(err.to_string().contains("must start with 'rsync'"));
+        let err = check_tool_templates(&[rsync("rsync {a:number}")]).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Unknown placeholder type 'number'"));
+        assert!(describe(&rsync("rsync -a {src:file} {dst}"))
+            .unwrap()
+            .contains("- src: an existing file\n- dst: a single word"));
+    }
+}
@@ -0,0 +1,245 @@
+use crate::safety::COMMAND_SEPARATORS;
+use anyhow::{anyhow, Context, Result};
+use std::process::{Command, Stdio};
+
+/// Maximum number of bytes of distilled documentation added to the system prompt per tool.
+pub const TOOL_DOCS_MAX_BYTES: usize = 4 * 1024;
+const TRUNCATION_NOTE: &str = "(truncated documentation)";
+
+/// Man page sections kept when distilling documentation for the system prompt.
+const KEPT_SECTIONS: &[&str] = &["NAME", "SYNOPSIS", "DESCRIPTION", "OPTIONS"];
+
+/// Phrases tools use to report an option they do not know.
+const FLAG_ERROR_MARKERS: &[&str] = &[
+    "unrecognized option",
+    "unrecognised option",
+    "unknown option",
+    "invalid option",
+    "illegal option",
+    "unrecognized argument",
+    "unexpected argument",
+    "unknown flag",
+    "unknown shorthand flag",
+    "no such option",
+];
+
+/// Fetches the local documentation for a tool: the man page when available,
+/// otherwise the output of `<tool> --help`.
+pub fn fetch_tool_docs(tool: &str) -> Result<String> {
+    if let Some(man) = read_man_page(tool) {
+        return Ok(man);
+    }
+
+    read_help_output(tool)
+        .ok_or_else(|| anyhow!("No man page or --help output found for '{}'", tool))
+}
+
+/// Reduces raw documentation to the parts useful for flag selection and caps
+/// its size so it can be embedded into the system prompt.
+pub fn distill_tool_docs(raw: &str) -> String {
+    let kept = keep_man_sections(raw).unwrap_or_else(|| raw.to_string());
+
+    let mut compact = String::new();
+    let mut previous_blank = true;
+    for line in kept.lines() {
+        let line = line.trim_end();
+        let blank = line.trim().is_empty();
+        if blank && previous_blank {
+            continue;
+        }
+        compact.push_str(line);
+        compact.push('\n');
+        previous_blank = blank;
+    }
+
+    truncate_docs(compact.trim_end())
+}
+
+/// Appends distilled documentation for the given tools to a system prompt.
+pub fn append_tool_docs(system_prompt: &str, docs: &[(String, String)]) -> String {
+    let mut out = system_prompt.to_string();
+    for (name, text) in docs {
+        out.push_str(&format!(
+            "\n\nReference documentation for {} (use it to pick correct flags):\n{}",
+            name, text
+        ));
+    }
+    out
+}
+
+/// True when error output says a flag was not recognized.
+pub fn is_flag_error(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    FLAG_ERROR_MARKERS.iter().any(|m| stderr.contains(m))
+}
+
+/// The tool of `cmd_line` that an error output is about: the first one whose
+/// name starts a line of `stderr` (`grep: unrecognized option`), otherwise the
+/// first tool of the command.
+pub fn failing_tool(cmd_line: &str, stderr: &str) -> Option<String> {
+    let tokens = shell_words::split(cmd_line).ok()?;
+    let tools: Vec<&str> = tokens
+        .split(|t| COMMAND_SEPARATORS.contains(&t.as_str()))
+        .filter_map(|segment| segment.first())
+        .map(|tool| tool.as_str())
+        .collect();
+    let mentioned = tools.iter().find(|tool| {
+        let name = tool.rsplit('/').next().unwrap_or(tool);
+        stderr
+            .lines()
+            .any(|line| line.starts_with(&format!("{}:", name)))
+    });
+    mentioned.or(tools.first()).map(|tool| tool.to_string())
+}
+
+/// The distilled `--help` output of a tool, for correcting a flag error.
+pub fn flag_help(tool: &str) -> Option<String> {
+    read_help_output(tool).map(|raw| distill_tool_docs(&raw))
+}
+
+fn read_man_page(tool: &str) -> Option<String> {
+    if cfg!(windows) {
+        return None;
+    }
+
+    let output = Command::new("man")
+        .arg(tool)
+        .env("MANPAGER", "cat")
+        .env("PAGER", "cat")
+        .env("MANWIDTH", "100")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let text = strip_overstrike(&String::from_utf8_lossy(&output.stdout));
+    if text.trim().is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+fn read_help_output(tool: &str) -> Option<String> {
+    let output = Command::new(tool)
+        .arg("--help")
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run '{} --help'", tool))
+        .ok()?;
+
+    // Many tools print usage to stderr, some exit non-zero for --help.
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    if text.trim().is_empty() {
+        text = String::from_utf8_lossy(&output.stderr).into_owned();
+    }
+
+    if text.trim().is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// Removes the backspace overstrike sequences man uses for bold/underline.
+fn strip_overstrike(text: &str) -> String {
+    let mut out: Vec<char> = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '\u{8}' {
+            out.pop();
+        } else {
+            out.push(c);
+        }
+    }
+    out.into_iter().collect()
+}
+
+fn keep_man_sections(raw: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut keeping = false;
+    let mut found = false;
+
+    for line in raw.lines() {
+        let is_heading = !line.is_empty()
+            && !line.starts_with(char::is_whitespace)
+            && line.chars().any(|c| c.is_ascii_uppercase())
+            && !line.chars().any(|c| c.is_ascii_lowercase());
+
+        if is_heading {
+            keeping = KEPT_SECTIONS.contains(&line.trim());
+            found |= keeping;
+        }
+
+        if keeping {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+
+    if found {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+fn truncate_docs(text: &str) -> String {
+    if text.len() <= TOOL_DOCS_MAX_BYTES {
+        return text.to_string();
+    }
+
+    let max_content_len = TOOL_DOCS_MAX_BYTES.saturating_sub(TRUNCATION_NOTE.len() + 1);
+    let mut cut = max_content_len;
+    while !text.is_char_boundary(cut) {
+        cut -= 1;
+    }
+
+    format!("{}\n{}", &text[..cut], TRUNCATION_NOTE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_man_overstrike() {
+        assert_eq!(strip_overstrike("N\u{8}NA\u{8}AM\u{8}ME\u{8}E"), "NAME");
+    }
+
+    #[test]
+    fn distill_keeps_relevant_man_sections() {
+        let raw = "LS(1)\n\nNAME\n       ls - list\n\n\n\nSYNOPSIS\n       ls [OPTION]\n\nAUTHOR\n       Someone\n";
+        let distilled = distill_tool_docs(raw);
+        assert!(distilled.contains("ls - list"));
+        assert!(distilled.contains("ls [OPTION]"));
+        assert!(!distilled.contains("Someone"));
+        assert!(!distilled.contains("\n\n\n"));
+    }
+
+    #[test]
+    fn flag_errors_name_the_failing_tool() {
+        let stderr = "grep: unrecognized option '--recursve'\nUsage: grep [OPTION]...\n";
+        assert!(is_flag_error(stderr));
+        assert!(!is_flag_error("ls: cannot access 'x': No such file"));
+        assert_eq!(
+            failing_tool("find . -name '*.rs' | /usr/bin/grep --recursve x", stderr).as_deref(),
+            Some("/usr/bin/grep")
+        );
+        assert_eq!(
+            failing_tool("tar -xzq a.tgz", "Try 'tar --help'").as_deref(),
+            Some("tar")
+        );
+    }
+
+    #[test]
+    fn distill_truncates_long_help() {
+        let raw = "usage: tool [flags]\n".repeat(1000);
+        let distilled = distill_tool_docs(&raw);
+        assert!(distilled.len() <= TOOL_DOCS_MAX_BYTES);
+        assert!(distilled.ends_with(TRUNCATION_NOTE));
+    }
+}
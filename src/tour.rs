@@ -0,0 +1,326 @@
+//! `sai tour`: a guided walk through simple mode, scope, peek, explain,
+//! confirm and analyze. Each step quotes its help topic, shows the sai
+//! invocation to try later and runs a harmless echo/ls/cat example for real
+//! in a scratch directory, so no API key is needed.
+
+use crate::config::{PromptConfig, ToolConfig};
+use crate::executor::{CommandExecutor, ShellCommandExecutor};
+use crate::help::HelpTopic;
+use crate::peek::build_peek_context;
+use crate::safety::validate_and_split_command;
+use anyhow::{Context, Result};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+
+/// Tools the tour's example commands may use.
+const TOUR_TOOLS: &[&str] = &["echo", "ls", "cat"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    SimpleMode,
+    Scope,
+    Peek,
+    Explain,
+    Confirm,
+    Analyze,
+    Done,
+}
+
+impl Step {
+    const ORDER: [Step; 7] = [
+        Step::SimpleMode,
+        Step::Scope,
+        Step::Peek,
+        Step::Explain,
+        Step::Confirm,
+        Step::Analyze,
+        Step::Done,
+    ];
+
+    fn index(self) -> usize {
+        Self::ORDER.iter().position(|s| *s == self).unwrap_or(0)
+    }
+
+    pub fn next(self) -> Step {
+        Self::ORDER[(self.index() + 1).min(Self::ORDER.len() - 1)]
+    }
+
+    pub fn prev(self) -> Step {
+        Self::ORDER[self.index().saturating_sub(1)]
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Step::SimpleMode => "Simple mode",
+            Step::Scope => "Scope",
+            Step::Peek => "Peek",
+            Step::Explain => "Explain",
+            Step::Confirm => "Confirm",
+            Step::Analyze => "Analyze",
+            Step::Done => "Done",
+        }
+    }
+
+    /// Help topic quoted by the step, and a phrase of the paragraph to quote.
+    fn help(self) -> Option<(HelpTopic, &'static str)> {
+        match self {
+            Step::SimpleMode => Some((HelpTopic::Advanced, "Simple mode:")),
+            Step::Scope => Some((HelpTopic::Scope, "-s/--scope")),
+            Step::Peek => Some((HelpTopic::Peek, "--peek sends")),
+            Step::Explain => Some((HelpTopic::Explain, "-e/--explain")),
+            Step::Confirm => Some((HelpTopic::Safety, "3) Confirmation")),
+            Step::Analyze => Some((HelpTopic::Analyze, "--analyze reads")),
+            Step::Done => None,
+        }
+    }
+
+    /// The invocation to try with a real provider.
+    fn example(self) -> &'static str {
+        match self {
+            Step::SimpleMode => "sai \"say hello\"",
+            Step::Scope => "sai -s . \"list the files here\"",
+            Step::Peek => "sai --peek sample.csv \"print the name column\"",
+            Step::Explain => "sai -e \"list the files here\"",
+            Step::Confirm => "sai -c \"say confirmed\"",
+            Step::Analyze => "sai --analyze",
+            Step::Done => "",
+        }
+    }
+
+    /// The command run for real in this step, in the scratch directory.
+    fn demo(self, dir: &Path) -> String {
+        let quoted = shell_words::quote(&dir.display().to_string()).into_owned();
+        match self {
+            Step::SimpleMode => "echo hello from sai".to_string(),
+            Step::Scope | Step::Explain => format!("ls {}", quoted),
+            Step::Peek => format!("cat {}/sample.csv", quoted),
+            Step::Confirm => "echo confirmed".to_string(),
+            Step::Analyze => format!("ls {}/missing.txt", quoted),
+            Step::Done => String::new(),
+        }
+    }
+
+    /// What the step adds after its demo command.
+    fn note(self) -> &'static str {
+        match self {
+            Step::SimpleMode => {
+                "With a provider configured, sai turned your words into that command, checked \
+                 that `echo` is an allowed tool and ran it without a shell."
+            }
+            Step::Scope => {
+                "`-s .` sends a listing like this one with the prompt, so the model sees the \
+                 real file names."
+            }
+            Step::Peek => "",
+            Step::Explain => {
+                "With -e, sai asks the model to explain the command first, e.g. \"ls: lists \
+                 the entries of the directory, read-only\", then asks before running it."
+            }
+            Step::Confirm => "",
+            Step::Analyze => {
+                "That command failed. `sai --analyze` reads the last history entry and asks \
+                 the model why and what to try next; it never runs anything."
+            }
+            Step::Done => "",
+        }
+    }
+}
+
+/// The paragraph of `topic` containing `needle`.
+fn help_paragraph(topic: HelpTopic, needle: &str) -> &'static str {
+    let text = topic.render();
+    text.split("\n\n")
+        .find(|p| p.contains(needle))
+        .unwrap_or(text)
+        .trim()
+}
+
+/// Runs the tour, reading choices from `reader` and writing the narration to
+/// `out`. Returns the steps shown, in order.
+pub fn run_tour<R, W, E>(reader: &mut R, out: &mut W, executor: &E, dir: &Path) -> Result<Vec<Step>>
+where
+    R: BufRead,
+    W: Write,
+    E: CommandExecutor,
+{
+    fs::write(
+        dir.join("sample.csv"),
+        "id,name,size\n1,alpha,120\n2,beta,64\n",
+    )?;
+    fs::write(dir.join("notes.txt"), "sai tour scratch file\n")?;
+    let prompt = PromptConfig {
+        tools: TOUR_TOOLS
+            .iter()
+            .map(|name| ToolConfig {
+                name: name.to_string(),
+                force_explain: None,
+                timeout: None,
+                template: None,
+                env: Default::default(),
+                allowed_flags: Vec::new(),
+                denied_flags: Vec::new(),
+                read_only: Some(true),
+                aliases: Vec::new(),
+                network: None,
+                cwd_must_match: None,
+                chdir: None,
+                config: format!("{}: tour example", name),
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let allowed: Vec<String> = TOUR_TOOLS.iter().map(|t| t.to_string()).collect();
+
+    writeln!(
+        out,
+        "Welcome to the sai tour. Each step explains a feature and runs a harmless example.\n\
+         Examples run in {}.",
+        dir.display()
+    )?;
+    let mut shown = Vec::new();
+    let mut step = Step::SimpleMode;
+    while step != Step::Done {
+        shown.push(step);
+        writeln!(
+            out,
+            "\n== {} ({}/{}) ==",
+            step.title(),
+            step.index() + 1,
+            Step::ORDER.len() - 1
+        )?;
+        if let Some((topic, needle)) = step.help() {
+            writeln!(
+                out,
+                "{}\n(more: sai help {})",
+                help_paragraph(topic, needle),
+                topic.name()
+            )?;
+        }
+        writeln!(out, "\nTry it later: {}", step.example())?;
+        let demo = step.demo(dir);
+        write!(
+            out,
+            "Example command: {}\n[Enter] run it, s skip, b back, q quit: ",
+            demo
+        )?;
+        out.flush()?;
+
+        match read_choice(reader)?.as_str() {
+            "q" => break,
+            "s" => {
+                step = step.next();
+                continue;
+            }
+            "b" => {
+                step = step.prev();
+                continue;
+            }
+            _ => {}
+        }
+        if step == Step::Peek {
+            if let Some(context) = build_peek_context(&[dir.join("sample.csv")], &[])? {
+                writeln!(
+                    out,
+                    "With --peek, this sample goes with the prompt:\n{}",
+                    context.trim_end()
+                )?;
+            }
+        }
+        if step == Step::Confirm {
+            write!(out, "Execute this command? [y/N] ")?;
+            out.flush()?;
+            if read_choice(reader)? != "y" {
+                writeln!(out, "Not run. This is what -c gives you for every command.")?;
+                step = step.next();
+                continue;
+            }
+        }
+        out.flush()?;
+        let tokens = validate_and_split_command(&demo, &allowed, false, &prompt)?;
+        let exit_code = executor.execute(&demo, &tokens, false)?;
+        writeln!(out, "(exit code {})", exit_code)?;
+        if !step.note().is_empty() {
+            writeln!(out, "{}", step.note())?;
+        }
+        step = step.next();
+    }
+    writeln!(
+        out,
+        "\nThat's the tour. Next: `sai init` for a config, then `sai help quickstart`."
+    )?;
+    Ok(shown)
+}
+
+/// The next line, trimmed and lowercased; "q" at end of input.
+fn read_choice<R: BufRead>(reader: &mut R) -> Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok("q".to_string());
+    }
+    Ok(line.trim().to_ascii_lowercase())
+}
+
+/// `sai tour` on the terminal, in a scratch directory removed afterwards.
+pub fn run_tour_interactive() -> Result<i32> {
+    let dir = env::temp_dir().join(format!("sai-tour-{}", process::id()));
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let result = run_tour(
+        &mut io::stdin().lock(),
+        &mut io::stdout(),
+        &ShellCommandExecutor,
+        &dir,
+    );
+    fs::remove_dir_all(&dir).ok();
+    result.map(|_| 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    #[test]
+    fn tour_walks_every_step_and_honors_back_and_quit() {
+        let temp = TempDir::new().unwrap();
+        let mut out = Vec::new();
+        let input = "\ns\nb\n\n\n\nn\n\n";
+        let shown = run_tour(
+            &mut Cursor::new(input.as_bytes()),
+            &mut out,
+            &ShellCommandExecutor,
+            temp.path(),
+        )
+        .unwrap();
+        assert_eq!(
+            shown,
+            [
+                Step::SimpleMode,
+                Step::Scope,
+                Step::Peek,
+                Step::Scope,
+                Step::Peek,
+                Step::Explain,
+                Step::Confirm,
+                Step::Analyze,
+            ]
+        );
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Simple mode: `sai \"<prompt>\"`"));
+        assert!(out.contains("Not run."));
+        assert!(out.contains("That's the tour."));
+
+        let mut out = Vec::new();
+        let shown = run_tour(
+            &mut Cursor::new(b"q\n".to_vec()),
+            &mut out,
+            &ShellCommandExecutor,
+            temp.path(),
+        )
+        .unwrap();
+        assert_eq!(shown, [Step::SimpleMode]);
+    }
+}
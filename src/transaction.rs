@@ -0,0 +1,400 @@
+//! `--transaction`: the command runs in a staging workspace instead of the
+//! working directory, and the files it writes there are moved into place only
+//! when it succeeds. A command line of several steps (`sort a > b && uniq b >
+//! c`) is one transaction: later steps see what earlier ones wrote, and a
+//! failing step leaves the working directory as it was.
+//!
+//! The workspace is a directory in the working directory, so the final moves
+//! are renames on the same filesystem. It starts with copies of the files and
+//! directories the command names; the command sees nothing else.
+
+use crate::backup::{copy_tree, size_of, MAX_BACKUP_BYTES};
+use crate::humanize;
+use crate::jail::{PathJail, PathJailConfig};
+use anyhow::{anyhow, Context, Result};
+use chrono::Local;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+/// Name prefix of the workspace directory.
+pub const WORKSPACE_PREFIX: &str = ".sai-txn-";
+
+/// A staging workspace, removed when dropped unless a failed commit left
+/// originals in it.
+#[derive(Debug)]
+pub struct Transaction {
+    root: PathBuf,
+    workspace: PathBuf,
+    keep: bool,
+}
+
+impl Transaction {
+    /// Creates the workspace in `root` and copies `inputs` into it at their
+    /// paths relative to `root`. Inputs outside `root` are not copied; the
+    /// command is rejected by [`Transaction::check`] if it names them.
+    pub fn begin(root: &Path, inputs: &[PathBuf]) -> Result<Self> {
+        let inside: Vec<(&PathBuf, &Path)> = inputs
+            .iter()
+            .filter_map(|path| Some((path, relative_inside(path, root)?)))
+            .collect();
+        // A directory also copies everything below it.
+        let inside: Vec<(&PathBuf, &Path)> = inside
+            .iter()
+            .filter(|(_, rel)| {
+                !inside
+                    .iter()
+                    .any(|(_, other)| other != rel && rel.starts_with(other))
+            })
+            .copied()
+            .collect();
+        let mut total = 0;
+        for (path, _) in &inside {
+            total += size_of(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        }
+        if total > MAX_BACKUP_BYTES {
+            return Err(anyhow!(
+                "--transaction would copy {} into its workspace, more than the {} limit",
+                humanize::bytes(total),
+                humanize::bytes(MAX_BACKUP_BYTES)
+            ));
+        }
+
+        let stamp = Local::now().format("%Y%m%d-%H%M%S");
+        let workspace = root.join(format!("{}{}-{}", WORKSPACE_PREFIX, stamp, process::id()));
+        fs::create_dir(&workspace).with_context(|| {
+            format!(
+                "Failed to create transaction workspace {}",
+                workspace.display()
+            )
+        })?;
+        let txn = Self {
+            root: root.to_path_buf(),
+            workspace,
+            keep: false,
+        };
+        for (path, rel) in inside {
+            copy_tree(path, &txn.workspace.join(rel), Some(&txn.workspace))
+                .with_context(|| format!("Failed to stage {}", path.display()))?;
+        }
+        Ok(txn)
+    }
+
+    pub fn workspace(&self) -> &Path {
+        &self.workspace
+    }
+
+    /// Where a command that would run in `dir` runs instead.
+    pub fn dir_for(&self, dir: &Path) -> PathBuf {
+        match relative_inside(dir, &self.root) {
+            Some(rel) => self.workspace.join(rel),
+            None => self.workspace.clone(),
+        }
+    }
+
+    /// Rejects commands whose paths leave the workspace, or would once their
+    /// outputs are moved into place (through a symlink in the working
+    /// directory), with the same rules as `--jail`.
+    pub fn check(&self, cmd_line: &str, tokens: &[String], shell: bool, dir: &Path) -> Result<()> {
+        for (root, cwd) in [
+            (&self.workspace, self.dir_for(dir)),
+            (&self.root, dir.to_path_buf()),
+        ] {
+            let cfg = PathJailConfig {
+                enabled: true,
+                root: Some(root.display().to_string()),
+                allow: Vec::new(),
+            };
+            PathJail::new(&cfg, &cwd)?
+                .check(cmd_line, tokens, shell)
+                .context("--transaction only stages paths inside the working directory")?;
+        }
+        Ok(())
+    }
+
+    /// Moves every file in the workspace that is new or differs from its
+    /// original into place and returns the staged → final mapping, relative
+    /// to the working directory. When a move fails, the files already moved
+    /// are put back, so either every output lands or none does.
+    pub fn commit(mut self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let mut changes = Vec::new();
+        self.collect_changes(&self.workspace, &mut changes)?;
+        for (_, target) in &changes {
+            self.check_target(target)?;
+        }
+
+        let originals = self
+            .workspace
+            .join(format!("{}originals", WORKSPACE_PREFIX));
+        let mut moved: Vec<(&Path, Option<PathBuf>)> = Vec::new();
+        let mut created: Vec<PathBuf> = Vec::new();
+        let mut failure = None;
+        for (idx, (staged, target)) in changes.iter().enumerate() {
+            if let Err(err) = self.move_into_place(
+                staged,
+                target,
+                &originals.join(idx.to_string()),
+                &mut moved,
+                &mut created,
+            ) {
+                failure = Some(err);
+                break;
+            }
+        }
+        let Some(err) = failure else {
+            return Ok(changes
+                .into_iter()
+                .map(|(staged, target)| {
+                    let rel =
+                        |path: &Path| path.strip_prefix(&self.root).unwrap_or(path).to_path_buf();
+                    (rel(&staged), rel(&target))
+                })
+                .collect());
+        };
+
+        let mut restored = true;
+        for (target, original) in moved.into_iter().rev() {
+            restored &= fs::remove_file(target).is_ok();
+            if let Some(original) = original {
+                restored &= fs::rename(&original, target).is_ok();
+            }
+        }
+        for dir in created.iter().rev() {
+            fs::remove_dir(dir).ok();
+        }
+        if !restored {
+            self.keep = true;
+            return Err(err.context(format!(
+                "--transaction could not put every file back; the originals are in {}",
+                originals.display()
+            )));
+        }
+        Err(err.context("--transaction was rolled back; no files were changed"))
+    }
+
+    /// Moves `staged` to `target`, first moving an existing `target` to
+    /// `original`; records both in `moved` and new directories in `created`.
+    fn move_into_place<'a>(
+        &self,
+        staged: &Path,
+        target: &'a Path,
+        original: &Path,
+        moved: &mut Vec<(&'a Path, Option<PathBuf>)>,
+        created: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        if let Some(parent) = target.parent() {
+            let mut missing: Vec<PathBuf> = parent
+                .ancestors()
+                .take_while(|dir| !dir.exists())
+                .map(Path::to_path_buf)
+                .collect();
+            missing.reverse();
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+            created.extend(missing);
+        }
+        let saved = if fs::symlink_metadata(target).is_ok() {
+            fs::create_dir_all(original.parent().unwrap_or(original))?;
+            fs::rename(target, original)
+                .with_context(|| format!("Failed to move {} aside", target.display()))?;
+            Some(original.to_path_buf())
+        } else {
+            None
+        };
+        if let Err(err) = fs::rename(staged, target) {
+            if let Some(saved) = &saved {
+                fs::rename(saved, target).ok();
+            }
+            return Err(err).with_context(|| {
+                format!(
+                    "Failed to move {} to {}",
+                    staged.display(),
+                    target.display()
+                )
+            });
+        }
+        moved.push((target, saved));
+        Ok(())
+    }
+
+    /// Files below `dir` that are new or changed, with their final path.
+    fn collect_changes(&self, dir: &Path, changes: &mut Vec<(PathBuf, PathBuf)>) -> Result<()> {
+        let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read {}", dir.display()))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<_>>()?;
+        entries.sort();
+        for path in entries {
+            if fs::symlink_metadata(&path)?.is_dir() {
+                self.collect_changes(&path, changes)?;
+                continue;
+            }
+            let rel = path.strip_prefix(&self.workspace).unwrap_or(&path);
+            let target = self.root.join(rel);
+            if !same_file(&path, &target)? {
+                changes.push((path, target));
+            }
+        }
+        Ok(())
+    }
+
+    /// Refuses a target whose directory resolves outside the working
+    /// directory, e.g. through a symlink created by the command.
+    fn check_target(&self, target: &Path) -> Result<()> {
+        let root = fs::canonicalize(&self.root)?;
+        let dir = target
+            .parent()
+            .and_then(|parent| {
+                parent
+                    .ancestors()
+                    .find_map(|dir| fs::canonicalize(dir).ok())
+            })
+            .unwrap_or_default();
+        if !dir.starts_with(&root) {
+            return Err(anyhow!(
+                "--transaction will not write {}: it resolves outside {}; no files were changed",
+                target.display(),
+                root.display()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if !self.keep {
+            fs::remove_dir_all(&self.workspace).ok();
+        }
+    }
+}
+
+/// `path` relative to `root`, when it is below it without `..`.
+fn relative_inside<'a>(path: &'a Path, root: &Path) -> Option<&'a Path> {
+    let rel = path.strip_prefix(root).ok()?;
+    rel.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        .then_some(rel)
+}
+
+/// True when `staged` has the same type, permissions and contents as
+/// `original`.
+fn same_file(staged: &Path, original: &Path) -> Result<bool> {
+    let Ok(before) = fs::symlink_metadata(original) else {
+        return Ok(false);
+    };
+    let after = fs::symlink_metadata(staged)?;
+    if after.file_type().is_symlink() || before.file_type().is_symlink() {
+        return Ok(after.file_type().is_symlink()
+            && before.file_type().is_symlink()
+            && fs::read_link(staged)? == fs::read_link(original)?);
+    }
+    if !before.is_file()
+        || after.len() != before.len()
+        || after.permissions() != before.permissions()
+    {
+        return Ok(false);
+    }
+    let mut a = BufReader::new(File::open(staged)?);
+    let mut b = BufReader::new(File::open(original)?);
+    let (mut buf_a, mut buf_b) = ([0u8; 8192], [0u8; 8192]);
+    loop {
+        let n = a.read(&mut buf_a)?;
+        if n == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn commit_moves_only_new_and_changed_files() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        fs::write(root.join("input.txt"), "b\na\n").unwrap();
+        fs::write(root.join("old.txt"), "old").unwrap();
+
+        let inputs = [root.join("input.txt"), root.join("old.txt"), root.join(".")];
+        let txn = Transaction::begin(root, &inputs).unwrap();
+        let ws = txn.workspace().to_path_buf();
+        assert_eq!(entries(&ws), ["input.txt", "old.txt"]);
+        fs::write(ws.join("old.txt"), "new").unwrap();
+        fs::create_dir(ws.join("out")).unwrap();
+        fs::write(ws.join("out/sorted.txt"), "a\nb\n").unwrap();
+        // The working directory is untouched until the commit.
+        assert!(!root.join("out").exists());
+
+        let mapping = txn.commit().unwrap();
+        let staged = ws.strip_prefix(root).unwrap();
+        assert_eq!(
+            mapping,
+            [
+                (staged.join("old.txt"), PathBuf::from("old.txt")),
+                (
+                    staged.join("out/sorted.txt"),
+                    PathBuf::from("out/sorted.txt")
+                ),
+            ]
+        );
+        assert_eq!(fs::read_to_string(root.join("old.txt")).unwrap(), "new");
+        assert_eq!(
+            fs::read_to_string(root.join("out/sorted.txt")).unwrap(),
+            "a\nb\n"
+        );
+        assert_eq!(entries(root), ["input.txt", "old.txt", "out"]);
+    }
+
+    #[test]
+    fn failed_commit_puts_everything_back() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("z"), "a file").unwrap();
+
+        let txn = Transaction::begin(root, &[root.join("a.txt")]).unwrap();
+        fs::write(txn.workspace().join("a.txt"), "changed").unwrap();
+        // z is a file in the working directory, so z/out.txt cannot be
+        // moved into place after a.txt was.
+        fs::create_dir(txn.workspace().join("z")).unwrap();
+        fs::write(txn.workspace().join("z/out.txt"), "out").unwrap();
+
+        let err = txn.commit().unwrap_err();
+        assert!(format!("{:#}", err).contains("rolled back"));
+        assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "a");
+        assert_eq!(entries(root), ["a.txt", "z"]);
+    }
+
+    #[test]
+    fn commands_must_stay_in_the_workspace() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        let txn = Transaction::begin(root, &[]).unwrap();
+        let check = |line: &str| {
+            let tokens = shell_words::split(line).unwrap();
+            txn.check(line, &tokens, true, root)
+        };
+        assert!(check("sort in.txt > out.txt").is_ok());
+        assert!(check("sort in.txt > /tmp/out.txt").is_err());
+        assert!(check(&format!("sort in.txt > {}/out.txt", root.display())).is_err());
+        drop(txn);
+        assert!(entries(root).is_empty());
+    }
+}
@@ -0,0 +1,201 @@
+//! Markdown transcripts of sai runs for tickets and PR descriptions. Between
+//! `sai transcript start FILE` and `sai transcript stop`, or for one run with
+//! `--transcript FILE`, each run appends its prompt, command, explanation,
+//! confirmation decision and the end of its output to the file.
+
+use crate::config::config_root_dir;
+use crate::history::CommandOutput;
+use crate::humanize;
+use crate::redact::{self, RedactionConfig};
+use anyhow::{anyhow, Context, Result};
+use chrono::Local;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Bytes of each output stream kept in a transcript.
+pub const TRANSCRIPT_OUTPUT_BYTES: usize = 2048;
+
+/// File in the config directory naming the active transcript.
+fn state_path() -> PathBuf {
+    config_root_dir().join("transcript")
+}
+
+/// The transcript started with `sai transcript start`, if any.
+pub fn active_transcript() -> Option<PathBuf> {
+    let path = fs::read_to_string(state_path()).ok()?;
+    let path = path.trim();
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+/// `sai transcript start FILE`: later runs append to FILE until `stop`.
+pub fn start(file: &Path) -> Result<PathBuf> {
+    let file = env::current_dir()
+        .context("Failed to determine current directory")?
+        .join(file);
+    if let Some(previous) = active_transcript().filter(|p| *p != file) {
+        eprintln!("Stopped the transcript in {}.", previous.display());
+    }
+    if !file.exists() {
+        fs::write(
+            &file,
+            format!(
+                "# sai transcript\n\nStarted {}.\n",
+                Local::now().format("%Y-%m-%d %H:%M")
+            ),
+        )
+        .with_context(|| format!("Failed to create transcript {}", file.display()))?;
+    }
+    let state = state_path();
+    if let Some(parent) = state.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&state, file.display().to_string())
+        .with_context(|| format!("Failed to write {}", state.display()))?;
+    Ok(file)
+}
+
+/// `sai transcript stop`: the transcript that was active.
+pub fn stop() -> Result<PathBuf> {
+    let file = active_transcript().ok_or_else(|| anyhow!("No transcript is being recorded"))?;
+    fs::remove_file(state_path()).context("Failed to stop the transcript")?;
+    Ok(file)
+}
+
+/// What the user decided at the confirmation prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// The command ran without asking.
+    NotAsked,
+    Confirmed,
+    Declined,
+}
+
+/// One run as it appears in a transcript.
+#[derive(Debug, Clone)]
+pub struct TranscriptRecord {
+    pub prompt: Option<String>,
+    pub command: Option<String>,
+    pub explanation: Option<String>,
+    pub decision: Decision,
+    pub exit_code: Option<i32>,
+    pub notes: Option<String>,
+    pub output: Option<CommandOutput>,
+}
+
+/// A fenced block whose fence is longer than any backtick run in `text`.
+fn fenced(lang: &str, text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!(
+        "{}{}\n{}\n{}\n",
+        fence,
+        lang,
+        text.trim_end_matches('\n'),
+        fence
+    )
+}
+
+/// The Markdown section for one run, headed by `time`.
+pub fn render(record: &TranscriptRecord, time: &str) -> String {
+    let mut out = format!("\n## {}\n\n", time);
+    if let Some(prompt) = &record.prompt {
+        out.push_str(&format!("**Prompt:** {}\n\n", prompt.trim()));
+    }
+    if let Some(command) = &record.command {
+        out.push_str(&format!("**Command:**\n\n{}\n", fenced("sh", command)));
+    }
+    if let Some(explanation) = record.explanation.as_deref().map(str::trim) {
+        if !explanation.is_empty() {
+            out.push_str(&format!("**Explanation:**\n\n{}\n\n", explanation));
+        }
+    }
+    let decision = match record.decision {
+        Decision::NotAsked => None,
+        Decision::Confirmed => Some("confirmed"),
+        Decision::Declined => Some("declined"),
+    };
+    if let Some(decision) = decision {
+        out.push_str(&format!("**Confirmation:** {}\n\n", decision));
+    }
+    if let Some(code) = record.exit_code {
+        out.push_str(&format!("**Exit code:** {}\n\n", code));
+    }
+    if let Some(notes) = &record.notes {
+        out.push_str(&format!("**Notes:** {}\n\n", notes));
+    }
+    if let Some(output) = &record.output {
+        if output.omitted_bytes > 0 {
+            out.push_str(&format!(
+                "**Output** (last part; {} left out):\n\n",
+                humanize::bytes(output.omitted_bytes as u64)
+            ));
+        } else {
+            out.push_str("**Output:**\n\n");
+        }
+        for text in [&output.stdout, &output.stderr] {
+            if !text.is_empty() {
+                out.push_str(&fenced("", text));
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+/// Appends `record` to the transcript at `path`, with likely secrets
+/// redacted as in requests (see [`crate::redact`]).
+pub fn append(path: &Path, record: &TranscriptRecord, redaction: &RedactionConfig) -> Result<()> {
+    let time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let (text, _) = redact::redact(&render(record, &time), redaction);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open transcript {}", path.display()))?;
+    file.write_all(text.as_bytes())
+        .with_context(|| format!("Failed to write transcript {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::set_config_dir_override_for_tests;
+    use tempfile::TempDir;
+
+    #[test]
+    fn runs_are_appended_as_markdown_between_start_and_stop() {
+        let temp = TempDir::new().unwrap();
+        let _guard = set_config_dir_override_for_tests(temp.path().join("config"));
+        let file = temp.path().join("notes.md");
+        assert!(active_transcript().is_none());
+        assert_eq!(start(&file).unwrap(), file);
+        assert_eq!(active_transcript(), Some(file.clone()));
+
+        let record = TranscriptRecord {
+            prompt: Some("find large logs".to_string()),
+            command: Some("find . -name '*.log' -size +1M".to_string()),
+            explanation: Some("Lists log files over 1 MiB.".to_string()),
+            decision: Decision::Confirmed,
+            exit_code: Some(0),
+            notes: None,
+            output: Some(CommandOutput::keep_tail(
+                b"./app.log\n```\nAPI_KEY=abc123\n",
+                b"",
+                TRANSCRIPT_OUTPUT_BYTES,
+            )),
+        };
+        append(&file, &record, &RedactionConfig::default()).unwrap();
+        let text = fs::read_to_string(&file).unwrap();
+        assert!(text.starts_with("# sai transcript\n"));
+        assert!(text.contains("**Prompt:** find large logs\n"));
+        assert!(text.contains("```sh\nfind . -name '*.log' -size +1M\n```\n"));
+        assert!(text.contains("**Confirmation:** confirmed\n"));
+        assert!(text.contains("````\n./app.log\n```\nAPI_KEY=[REDACTED]\n````\n"));
+
+        assert_eq!(stop().unwrap(), file);
+        assert!(active_transcript().is_none());
+        assert!(stop().is_err());
+    }
+}
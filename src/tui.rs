@@ -0,0 +1,607 @@
+//! `sai --tui`: a full-screen front end with panes for the prompt, the
+//! generated command, its explanation and the history. Enter generates a
+//! command, Ctrl-E explains it and Ctrl-R hands it to the usual confirmation
+//! and execution; Tab switches to the history, where Enter recalls a run.
+//!
+//! The screen is drawn with crossterm. [`Tui`] holds the state, handles keys
+//! and renders plain lines, so everything but the terminal is testable.
+
+use crate::history::HistoryEntry;
+use crate::recall::entry_prompt;
+use anyhow::{anyhow, Context, Result};
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::Print,
+    terminal::{
+        self, disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+    },
+};
+use std::io::{self, IsTerminal, Write};
+
+/// What the TUI asks of the rest of sai.
+pub trait TuiBackend {
+    /// A command for `prompt` that passed validation.
+    fn generate(&mut self, prompt: &str) -> Result<String>;
+    /// Checks a recalled command against the active prompt config.
+    fn validate(&mut self, command: &str) -> Result<()>;
+    fn explain(&mut self, command: &str) -> Result<String>;
+}
+
+/// The prompt and command picked to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Choice {
+    pub prompt: String,
+    pub command: String,
+}
+
+/// Lines the command pane grows to before it is cut off.
+const COMMAND_ROWS: usize = 3;
+/// Smallest terminal the panes fit in.
+const MIN_WIDTH: u16 = 30;
+const MIN_HEIGHT: u16 = 13;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Prompt,
+    History,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    None,
+    Generate,
+    Explain,
+    Recall,
+    Run,
+    Quit,
+}
+
+/// A run shown in the history pane.
+struct Item {
+    prompt: Option<String>,
+    command: Option<String>,
+    exit_code: i32,
+}
+
+/// Screen lines and where the cursor goes.
+pub struct Frame {
+    pub lines: Vec<String>,
+    pub cursor: Option<(u16, u16)>,
+}
+
+pub struct Tui {
+    prompt: Vec<char>,
+    /// Position in `prompt`, in characters.
+    cursor: usize,
+    /// The prompt the command was generated for or recalled with.
+    command_prompt: String,
+    command: Option<String>,
+    explanation: Option<String>,
+    explanation_scroll: usize,
+    status: String,
+    /// Newest first, numbered from 1 as in `sai history list`.
+    history: Vec<Item>,
+    selected: usize,
+    focus: Focus,
+}
+
+impl Tui {
+    /// `entries` oldest first, as the history log holds them.
+    pub fn new(prompt: &str, entries: &[HistoryEntry]) -> Self {
+        let history = entries
+            .iter()
+            .rev()
+            .map(|entry| Item {
+                prompt: entry_prompt(entry),
+                command: entry.generated_command.clone(),
+                exit_code: entry.exit_code,
+            })
+            .collect();
+        Self {
+            prompt: prompt.chars().collect(),
+            cursor: prompt.chars().count(),
+            command_prompt: String::new(),
+            command: None,
+            explanation: None,
+            explanation_scroll: 0,
+            status: String::new(),
+            history,
+            selected: 0,
+            focus: Focus::Prompt,
+        }
+    }
+
+    fn prompt_text(&self) -> String {
+        self.prompt.iter().collect()
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('c' | 'q') if ctrl => return Action::Quit,
+            KeyCode::Char('e') if ctrl => return Action::Explain,
+            KeyCode::Char('r') if ctrl => return Action::Run,
+            KeyCode::Tab | KeyCode::BackTab => {
+                self.focus = match self.focus {
+                    Focus::Prompt if !self.history.is_empty() => Focus::History,
+                    _ => Focus::Prompt,
+                };
+                return Action::None;
+            }
+            KeyCode::PageDown => {
+                self.explanation_scroll += 1;
+                return Action::None;
+            }
+            KeyCode::PageUp => {
+                self.explanation_scroll = self.explanation_scroll.saturating_sub(1);
+                return Action::None;
+            }
+            _ => {}
+        }
+        match self.focus {
+            Focus::Prompt => self.edit_prompt(key, ctrl),
+            Focus::History => self.browse_history(key),
+        }
+    }
+
+    fn edit_prompt(&mut self, key: KeyEvent, ctrl: bool) -> Action {
+        match key.code {
+            KeyCode::Enter => return Action::Generate,
+            KeyCode::Char('u') if ctrl => {
+                self.prompt.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.prompt.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.prompt.remove(self.cursor);
+            }
+            KeyCode::Delete if self.cursor < self.prompt.len() => {
+                self.prompt.remove(self.cursor);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.prompt.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.prompt.len(),
+            _ => {}
+        }
+        Action::None
+    }
+
+    fn browse_history(&mut self, key: KeyEvent) -> Action {
+        let last = self.history.len().saturating_sub(1);
+        match key.code {
+            KeyCode::Enter => return Action::Recall,
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected = (self.selected + 1).min(last),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = last,
+            _ => {}
+        }
+        Action::None
+    }
+
+    /// Carries out `action`; `Some` when the TUI should close.
+    fn apply(&mut self, action: Action, backend: &mut dyn TuiBackend) -> Option<Option<Choice>> {
+        match action {
+            Action::None => {}
+            Action::Quit => return Some(None),
+            Action::Generate => {
+                let prompt = self.prompt_text();
+                if prompt.trim().is_empty() {
+                    self.status = "Type a request first.".to_string();
+                    return None;
+                }
+                self.explanation = None;
+                self.explanation_scroll = 0;
+                match backend.generate(prompt.trim()) {
+                    Ok(command) => {
+                        self.command = Some(command);
+                        self.command_prompt = prompt.trim().to_string();
+                        self.status = "Ctrl-R runs it, Ctrl-E explains it.".to_string();
+                    }
+                    Err(err) => {
+                        self.command = None;
+                        self.status = format!("{:#}", err);
+                    }
+                }
+            }
+            Action::Explain => {
+                let Some(command) = &self.command else {
+                    self.status = "Nothing to explain yet.".to_string();
+                    return None;
+                };
+                self.explanation_scroll = 0;
+                match backend.explain(command) {
+                    Ok(text) => {
+                        self.explanation = Some(text);
+                        self.status.clear();
+                    }
+                    Err(err) => self.status = format!("Failed to explain command: {:#}", err),
+                }
+            }
+            Action::Recall => {
+                let item = &self.history[self.selected];
+                let (prompt, command) = (item.prompt.clone(), item.command.clone());
+                let number = self.selected + 1;
+                self.prompt = prompt.unwrap_or_default().chars().collect();
+                self.cursor = self.prompt.len();
+                self.focus = Focus::Prompt;
+                self.explanation = None;
+                self.command = None;
+                match command {
+                    Some(command) => match backend.validate(&command) {
+                        Ok(()) => {
+                            self.command = Some(command);
+                            self.command_prompt = self.prompt_text();
+                            self.status =
+                                format!("Recalled run #{}. Ctrl-R runs it again.", number);
+                        }
+                        Err(err) => {
+                            self.status = format!(
+                                "The command of run #{} is not allowed here: {:#}",
+                                number, err
+                            );
+                        }
+                    },
+                    None => self.status = format!("Run #{} has no command.", number),
+                }
+            }
+            Action::Run => match &self.command {
+                Some(command) => {
+                    return Some(Some(Choice {
+                        prompt: self.command_prompt.clone(),
+                        command: command.clone(),
+                    }))
+                }
+                None => self.status = "Generate a command first (Enter).".to_string(),
+            },
+        }
+        None
+    }
+
+    /// The screen for a terminal of `width` x `height`.
+    pub fn render(&self, width: u16, height: u16) -> Frame {
+        if width < MIN_WIDTH || height < MIN_HEIGHT {
+            return Frame {
+                lines: vec![format!(
+                    "Terminal too small for --tui ({}x{} needed); Esc quits.",
+                    MIN_WIDTH, MIN_HEIGHT
+                )],
+                cursor: None,
+            };
+        }
+        let width = usize::from(width);
+        let inner = width - 4;
+        let mut lines = Vec::new();
+
+        lines.push(border('┌', "Prompt", '┐', width));
+        let visible = inner - 2;
+        let offset = (self.cursor + 1).saturating_sub(visible);
+        let shown: String = self.prompt.iter().skip(offset).take(visible).collect();
+        lines.push(row(&format!("> {}", shown), inner));
+        let cursor =
+            (self.focus == Focus::Prompt).then(|| ((4 + self.cursor - offset) as u16, 1u16));
+
+        lines.push(border('├', "Command", '┤', width));
+        let command = match &self.command {
+            Some(command) => wrap(command, inner),
+            None => vec!["(Enter generates a command for the prompt)".to_string()],
+        };
+        let command_rows = command.len().min(COMMAND_ROWS);
+        lines.extend(clip(&command, 0, command_rows, inner));
+
+        // Borders, prompt, footer and status take 8 rows.
+        let rest = usize::from(height) - 8 - command_rows;
+        let explanation_rows = rest / 2;
+        let history_rows = rest - explanation_rows;
+
+        lines.push(border('├', "Explanation (Ctrl-E)", '┤', width));
+        let explanation: Vec<String> = match &self.explanation {
+            Some(text) => text.lines().flat_map(|line| wrap(line, inner)).collect(),
+            None => Vec::new(),
+        };
+        let scroll = self
+            .explanation_scroll
+            .min(explanation.len().saturating_sub(explanation_rows));
+        lines.extend(clip(&explanation, scroll, explanation_rows, inner));
+
+        let title = match self.focus {
+            Focus::History => "History",
+            Focus::Prompt => "History (Tab)",
+        };
+        lines.push(border('├', title, '┤', width));
+        let first = (self.selected + 1).saturating_sub(history_rows);
+        let items: Vec<String> = self
+            .history
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(history_rows)
+            .map(|(idx, item)| {
+                let marker = if self.focus == Focus::History && idx == self.selected {
+                    '>'
+                } else {
+                    ' '
+                };
+                let exit = match item.exit_code {
+                    0 => String::new(),
+                    code => format!(" [exit {}]", code),
+                };
+                format!(
+                    "{}{:>3} {} -> {}{}",
+                    marker,
+                    idx + 1,
+                    item.prompt.as_deref().unwrap_or("(no prompt)"),
+                    item.command.as_deref().unwrap_or("(no command)"),
+                    exit
+                )
+            })
+            .collect();
+        lines.extend(clip(&items, 0, history_rows, inner));
+        lines.push(border('└', "", '┘', width));
+
+        let keys = match self.focus {
+            Focus::Prompt => "Enter generate  Ctrl-E explain  Ctrl-R run  Tab history  Esc quit",
+            Focus::History => "Up/Down select  Enter recall  Tab prompt  Esc quit",
+        };
+        lines.push(fit(&format!(" {}", keys), width));
+        lines.push(fit(&format!(" {}", self.status), width));
+        Frame { lines, cursor }
+    }
+}
+
+/// `rows` lines of `lines` from `start`, each boxed; blank rows fill the rest.
+fn clip(lines: &[String], start: usize, rows: usize, inner: usize) -> Vec<String> {
+    let mut out: Vec<String> = lines
+        .iter()
+        .skip(start)
+        .take(rows)
+        .map(|line| row(line, inner))
+        .collect();
+    if lines.len() > start + rows {
+        if let Some(last) = out.last_mut() {
+            *last = row("...", inner);
+        }
+    }
+    out.resize(rows, row("", inner));
+    out
+}
+
+fn border(left: char, title: &str, right: char, width: usize) -> String {
+    let title = match title {
+        "" => String::new(),
+        title => format!(" {} ", title),
+    };
+    let fill = width.saturating_sub(title.chars().count() + 3);
+    format!("{}─{}{}{}", left, title, "─".repeat(fill), right)
+}
+
+fn row(text: &str, inner: usize) -> String {
+    format!("│ {} │", fit(text, inner))
+}
+
+/// `text` cut or padded to `width` characters.
+fn fit(text: &str, width: usize) -> String {
+    let mut out: String = text.chars().take(width).collect();
+    let len = out.chars().count();
+    out.extend(std::iter::repeat_n(' ', width - len));
+    out
+}
+
+/// `text` broken into lines of at most `width` characters, at spaces where
+/// possible.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split(' ') {
+        let mut word: Vec<char> = word.chars().collect();
+        let len = line.chars().count();
+        if len > 0 && len + 1 + word.len() > width {
+            lines.push(std::mem::take(&mut line));
+        } else if len > 0 {
+            line.push(' ');
+        }
+        while line.is_empty() && word.len() > width {
+            lines.push(word.drain(..width).collect());
+        }
+        line.extend(word);
+    }
+    lines.push(line);
+    lines
+}
+
+/// Restores the terminal when the TUI ends, also on errors.
+struct Screen;
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        enable_raw_mode().context("Failed to enable raw terminal mode")?;
+        let screen = Screen;
+        crossterm::execute!(io::stdout(), EnterAlternateScreen)
+            .context("Failed to switch to the alternate screen")?;
+        Ok(screen)
+    }
+
+    fn draw(&self, tui: &Tui) -> Result<()> {
+        let (width, height) = terminal::size().context("Failed to read the terminal size")?;
+        let frame = tui.render(width, height);
+        let mut out = io::stdout();
+        queue!(out, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+        for (idx, line) in frame.lines.iter().enumerate() {
+            queue!(out, cursor::MoveTo(0, idx as u16), Print(line))?;
+        }
+        if let Some((col, line)) = frame.cursor {
+            queue!(out, cursor::MoveTo(col, line), cursor::Show)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = crossterm::execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
+        let _ = disable_raw_mode();
+    }
+}
+
+/// Runs the TUI until a command is picked to run (`Some`) or it is closed.
+pub fn run(
+    backend: &mut dyn TuiBackend,
+    prompt: &str,
+    entries: &[HistoryEntry],
+) -> Result<Option<Choice>> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Err(anyhow!("--tui needs a terminal"));
+    }
+    let mut tui = Tui::new(prompt, entries);
+    let screen = Screen::enter()?;
+    loop {
+        screen.draw(&tui)?;
+        let action = match event::read().context("Failed to read key event")? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => tui.handle_key(key),
+            _ => Action::None,
+        };
+        if matches!(action, Action::Generate | Action::Explain) {
+            tui.status = match action {
+                Action::Generate => "Generating command...".to_string(),
+                _ => "Explaining command...".to_string(),
+            };
+            screen.draw(&tui)?;
+        }
+        if let Some(choice) = tui.apply(action, backend) {
+            return Ok(choice);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StubBackend;
+
+    impl TuiBackend for StubBackend {
+        fn generate(&mut self, prompt: &str) -> Result<String> {
+            match prompt {
+                "list files" => Ok("ls -la".to_string()),
+                _ => Err(anyhow!("Disallowed command 'rm'")),
+            }
+        }
+
+        fn validate(&mut self, command: &str) -> Result<()> {
+            match command.starts_with("ls") {
+                true => Ok(()),
+                false => Err(anyhow!("Disallowed command")),
+            }
+        }
+
+        fn explain(&mut self, _command: &str) -> Result<String> {
+            Ok("Lists the files, one per line, with details.".to_string())
+        }
+    }
+
+    fn entry(argv: &[&str], command: &str) -> HistoryEntry {
+        HistoryEntry {
+            ts: "2024-05-01T10:00:00Z".to_string(),
+            cwd: "/tmp".to_string(),
+            argv: argv.iter().map(|a| a.to_string()).collect(),
+            generated_command: Some(command.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn press(tui: &mut Tui, backend: &mut StubBackend, code: KeyCode) -> Option<Option<Choice>> {
+        let action = tui.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+        tui.apply(action, backend)
+    }
+
+    fn ctrl(tui: &mut Tui, backend: &mut StubBackend, c: char) -> Option<Option<Choice>> {
+        let action = tui.handle_key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL));
+        tui.apply(action, backend)
+    }
+
+    #[test]
+    fn prompts_generate_explain_and_run_commands() {
+        let mut backend = StubBackend;
+        let mut tui = Tui::new("list", &[]);
+        assert_eq!(ctrl(&mut tui, &mut backend, 'r'), None);
+        for c in " files".chars() {
+            press(&mut tui, &mut backend, KeyCode::Char(c));
+        }
+        press(&mut tui, &mut backend, KeyCode::Enter);
+        assert_eq!(tui.command.as_deref(), Some("ls -la"));
+        ctrl(&mut tui, &mut backend, 'e');
+
+        let frame = tui.render(40, 14);
+        assert_eq!(frame.lines.len(), 14);
+        assert!(frame.lines.iter().all(|l| l.chars().count() == 40));
+        assert_eq!(
+            frame.lines[1],
+            format!("│ > list files{} │", " ".repeat(24))
+        );
+        assert_eq!(frame.cursor, Some((14, 1)));
+        assert!(frame.lines[3].contains("ls -la"));
+        assert!(frame.lines[5].contains("Lists the files, one per line, with"));
+        assert!(frame.lines[6].contains("details."));
+
+        assert_eq!(
+            ctrl(&mut tui, &mut backend, 'r'),
+            Some(Some(Choice {
+                prompt: "list files".to_string(),
+                command: "ls -la".to_string(),
+            }))
+        );
+
+        // A failed generation leaves no command to run.
+        let mut tui = Tui::new("wipe it", &[]);
+        press(&mut tui, &mut backend, KeyCode::Enter);
+        assert_eq!(tui.command, None);
+        assert!(tui.status.contains("Disallowed"));
+        assert_eq!(press(&mut tui, &mut backend, KeyCode::Esc), Some(None));
+    }
+
+    #[test]
+    fn history_runs_are_recalled_after_validation() {
+        let mut backend = StubBackend;
+        let entries = [
+            entry(&["sai", "show sizes"], "ls -s"),
+            entry(&["sai", "clean up"], "rm -rf build"),
+        ];
+        let mut tui = Tui::new("", &entries);
+        press(&mut tui, &mut backend, KeyCode::Tab);
+        let frame = tui.render(40, 14);
+        assert!(frame
+            .lines
+            .iter()
+            .any(|l| l.starts_with("│ >  1 clean up -> rm -rf build")));
+
+        press(&mut tui, &mut backend, KeyCode::Enter);
+        assert_eq!(tui.prompt_text(), "clean up");
+        assert_eq!(tui.command, None);
+        assert!(tui.status.contains("not allowed here"));
+
+        press(&mut tui, &mut backend, KeyCode::Tab);
+        press(&mut tui, &mut backend, KeyCode::Down);
+        press(&mut tui, &mut backend, KeyCode::Enter);
+        assert_eq!(tui.focus, Focus::Prompt);
+        assert_eq!(tui.command.as_deref(), Some("ls -s"));
+        assert_eq!(
+            ctrl(&mut tui, &mut backend, 'r'),
+            Some(Some(Choice {
+                prompt: "show sizes".to_string(),
+                command: "ls -s".to_string(),
+            }))
+        );
+
+        assert_eq!(wrap("a bb ccc", 4), ["a bb", "ccc"]);
+        assert_eq!(wrap("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+    }
+}
@@ -0,0 +1,206 @@
+//! Execution audit webhook (`hooks.webhook_url` in the global config): after
+//! each executed command, a JSON summary is POSTed so security teams can
+//! stream sai activity into their monitoring.
+
+use crate::risk::RiskLevel;
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::runtime::{Builder, Runtime};
+
+/// `hooks:` section of the global config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HooksConfig {
+    /// Endpoint that receives one POST per executed command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+    /// Seconds each attempt may take.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Further attempts after a failed one (network error or non-2xx status).
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            webhook_url: None,
+            timeout_secs: default_timeout_secs(),
+            retries: default_retries(),
+        }
+    }
+}
+
+fn default_timeout_secs() -> u64 {
+    5
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+/// Body of the webhook request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub ts: String,
+    pub command: String,
+    pub user: String,
+    /// Machine the command ran on: the `--host` target or this machine.
+    pub host: String,
+    pub cwd: String,
+    pub exit_code: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub risk: Option<RiskLevel>,
+    pub unsafe_mode: bool,
+}
+
+/// Name of the user running sai, from USER or USERNAME.
+pub fn current_user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Name of this machine.
+pub fn local_hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } == 0 {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            return String::from_utf8_lossy(&buf[..len]).into_owned();
+        }
+    }
+    env::var("COMPUTERNAME")
+        .or_else(|_| env::var("HOSTNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Runtime shared by every webhook POST of the process (batch runs send one
+/// per prompt), started on first use.
+fn runtime() -> Result<&'static Runtime> {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+    let runtime = Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Failed to start the async runtime")?;
+    Ok(RUNTIME.get_or_init(|| runtime))
+}
+
+/// POSTs `event` to the configured URL, retrying failed attempts. Does
+/// nothing without a `webhook_url`.
+pub fn send(cfg: &HooksConfig, event: &AuditEvent) -> Result<()> {
+    let Some(url) = cfg.webhook_url.as_deref() else {
+        return Ok(());
+    };
+    let client = Client::builder()
+        .timeout(Duration::from_secs(cfg.timeout_secs.max(1)))
+        .build()
+        .context("Failed to build the webhook client")?;
+    let attempts = cfg.retries.saturating_add(1);
+    runtime()?.block_on(async {
+        let mut attempt = 1;
+        loop {
+            let err = match client.post(url).json(event).send().await {
+                Ok(resp) if resp.status().is_success() => return Ok(()),
+                Ok(resp) => anyhow!("HTTP {}", resp.status()),
+                Err(err) => anyhow!(err),
+            };
+            if attempt >= attempts {
+                return Err(err.context(format!(
+                    "Webhook {} failed after {} attempt(s)",
+                    url, attempt
+                )));
+            }
+            tokio::time::sleep(Duration::from_millis(500 * u64::from(attempt))).await;
+            attempt += 1;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answers each connection with the next status and returns the bodies.
+    fn serve(statuses: &'static [u16]) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/audit", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+            bodies
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn event_is_posted_and_failed_attempts_are_retried() {
+        let (url, server) = serve(&[500, 200]);
+        let cfg = HooksConfig {
+            webhook_url: Some(url),
+            timeout_secs: 5,
+            retries: 1,
+        };
+        let event = AuditEvent {
+            ts: "2026-01-01T00:00:00Z".to_string(),
+            command: "rm junk.txt".to_string(),
+            user: "alice".to_string(),
+            host: "build-01".to_string(),
+            cwd: "/work".to_string(),
+            exit_code: 0,
+            risk: Some(RiskLevel::High),
+            unsafe_mode: false,
+        };
+        send(&cfg, &event).unwrap();
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies.len(), 2);
+        let received: AuditEvent = serde_json::from_str(&bodies[1]).unwrap();
+        assert_eq!(received, event);
+        assert!(bodies[1].contains("\"risk\":\"high\""));
+
+        let (url, server) = serve(&[503]);
+        let cfg = HooksConfig {
+            webhook_url: Some(url),
+            retries: 0,
+            ..cfg
+        };
+        assert!(send(&cfg, &event).is_err());
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+//! Per-tool working directory rules: `cwd_must_match` lets a tool run only
+//! from matching directories (terraform only inside a module directory), and
+//! `chdir` runs it in a subdirectory of the working directory.
+
+use crate::config::ToolConfig;
+use anyhow::{anyhow, Result};
+use glob::{MatchOptions, Pattern};
+use std::path::{Component, Path, PathBuf};
+
+/// True when `cwd` matches a `cwd_must_match` glob. `*` stays within one
+/// directory name and `**` spans several; a pattern without a leading `/`
+/// (or `~/`) may match the end of the path.
+pub fn cwd_matches(pattern: &str, cwd: &Path) -> Result<bool> {
+    let pattern = pattern.trim_end_matches('/');
+    let pattern = match pattern.strip_prefix("~/") {
+        Some(rest) => match dirs::home_dir() {
+            Some(home) => format!("{}/{}", Pattern::escape(&home.to_string_lossy()), rest),
+            None => return Ok(false),
+        },
+        None if pattern.starts_with('/') || pattern.starts_with("**") => pattern.to_string(),
+        None => format!("**/{}", pattern),
+    };
+    let glob = Pattern::new(&pattern)
+        .map_err(|err| anyhow!("Invalid cwd_must_match '{}': {}", pattern, err))?;
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    Ok(glob.matches_path_with(cwd, options))
+}
+
+/// Checks the rules of the invoked tools against `cwd` and returns the
+/// directory the command runs in when a tool has `chdir`.
+pub fn run_dir(tools: &[ToolConfig], invoked: &[String], cwd: &Path) -> Result<Option<PathBuf>> {
+    let mut dir: Option<(&str, PathBuf)> = None;
+    for word in invoked {
+        let Some(tool) = tools.iter().find(|t| t.matches(word)) else {
+            continue;
+        };
+        if let Some(pattern) = &tool.cwd_must_match {
+            if !cwd_matches(pattern, cwd)? {
+                return Err(anyhow!(
+                    "'{}' may only run in directories matching '{}' (cwd_must_match), not in {}",
+                    tool.name,
+                    pattern,
+                    cwd.display()
+                ));
+            }
+        }
+        let Some(chdir) = &tool.chdir else {
+            continue;
+        };
+        let relative = Path::new(chdir);
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(anyhow!(
+                "chdir '{}' of '{}' must be a relative path inside the working directory",
+                chdir,
+                tool.name
+            ));
+        }
+        let target = cwd.join(relative);
+        if !target.is_dir() {
+            return Err(anyhow!(
+                "'{}' runs in {} (chdir), which is not a directory",
+                tool.name,
+                target.display()
+            ));
+        }
+        match &dir {
+            Some((other, existing)) if *existing != target => {
+                return Err(anyhow!(
+                    "'{}' and '{}' run in different directories (chdir); run them separately",
+                    other,
+                    tool.name
+                ));
+            }
+            Some(_) => {}
+            None => dir = Some((tool.name.as_str(), target)),
+        }
+    }
+    Ok(dir.map(|(_, dir)| dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn tools_run_only_in_matching_directories_and_chdir_into_subdirectories() {
+        let temp = TempDir::new().unwrap();
+        let module = temp.path().join("infra/modules/vpc");
+        std::fs::create_dir_all(module.join("envs")).unwrap();
+        assert!(cwd_matches("**/infra/modules/*", &module).unwrap());
+        assert!(cwd_matches("modules/*", &module).unwrap());
+        assert!(!cwd_matches("modules/*", &module.join("envs")).unwrap());
+        assert!(!cwd_matches("/infra/*", &module).unwrap());
+
+        let tools: Vec<ToolConfig> = serde_yaml::from_str(
+            "- name: terraform\n  cwd_must_match: \"modules/*\"\n  config: terraform\n\
+             - name: tflint\n  chdir: envs/\n  config: tflint\n\
+             - name: npm\n  chdir: web\n  config: npm\n\
+             - name: ls\n  config: ls\n",
+        )
+        .unwrap();
+        let invoked = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            run_dir(&tools, &invoked(&["terraform", "ls"]), &module).unwrap(),
+            None
+        );
+        let err = run_dir(&tools, &invoked(&["terraform"]), temp.path()).unwrap_err();
+        assert!(err.to_string().contains("cwd_must_match"));
+
+        assert_eq!(
+            run_dir(&tools, &invoked(&["ls", "tflint"]), &module).unwrap(),
+            Some(module.join("envs/"))
+        );
+        let err = run_dir(&tools, &invoked(&["npm"]), &module).unwrap_err();
+        assert!(err.to_string().contains("not a directory"));
+    }
+}